@@ -0,0 +1,490 @@
+use ttf_parser::colr::{self, CompositeMode, GradientExtend, Paint};
+use ttf_parser::{Face, GlyphId, RgbaColor, Tag, Transform};
+
+use crate::document::{FontPalette, FontPaletteKeyword};
+use crate::glyph_outline::{design_glyph_path, vertical_origin};
+use crate::path::PathSegment;
+
+/// Half the side of the square that stands for an unbounded area, in design units
+const UNBOUNDED: f32 = 65536.0;
+
+/// A bounding box: x min, y min, x max, y max
+pub(crate) type Bounds = [f32; 4];
+
+/// A gradient stop, with its offset normalized to the 0..1 range of the gradient geometry
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct GradientStop {
+    pub(crate) offset: f32,
+    pub(crate) color: RgbaColor,
+}
+
+/// A `COLR` paint, with the geometry of gradients resolved so that their stops span 0..1
+pub(crate) enum ColorPaint {
+    Solid(RgbaColor),
+    LinearGradient {
+        start: (f32, f32),
+        end: (f32, f32),
+        stops: Vec<GradientStop>,
+        extend: GradientExtend,
+    },
+    /// From the circle at `start` to the circle at `end`
+    RadialGradient {
+        start: (f32, f32),
+        start_radius: f32,
+        end: (f32, f32),
+        end_radius: f32,
+        stops: Vec<GradientStop>,
+        extend: GradientExtend,
+    },
+    /// Angles in degrees, counter-clockwise in design space
+    SweepGradient {
+        center: (f32, f32),
+        start_angle: f32,
+        end_angle: f32,
+        stops: Vec<GradientStop>,
+        extend: GradientExtend,
+    },
+}
+
+/// A node of the paint graph of a color glyph. Paths are in the coordinate space of the
+/// transforms around them.
+pub(crate) enum ColorNode {
+    /// Fill a path; `bounds` contains it
+    Fill {
+        path: Vec<PathSegment>,
+        bounds: Bounds,
+        paint: ColorPaint,
+    },
+    Clip {
+        path: Vec<PathSegment>,
+        children: Vec<ColorNode>,
+    },
+    Transform {
+        transform: Transform,
+        children: Vec<ColorNode>,
+    },
+    /// Children drawn in a layer of their own and composited onto what is below
+    Layer {
+        mode: CompositeMode,
+        children: Vec<ColorNode>,
+    },
+}
+
+/// The paint graph of a color glyph, in design units with the y axis pointing down like
+/// `GlyphOutlineSource::glyph_path`
+pub(crate) struct ColorGlyph {
+    pub(crate) nodes: Vec<ColorNode>,
+}
+
+/// An open node, with what it replaced in the painter state
+enum Scope {
+    Clip {
+        path: Vec<PathSegment>,
+        outer_clip: Option<Bounds>,
+    },
+    Transform {
+        transform: Transform,
+        outer_transform: Transform,
+    },
+    Layer(CompositeMode),
+}
+
+/// Collects the calls `ttf-parser` makes while walking a `COLR` glyph into a tree
+struct ColorPainter<'a> {
+    face: &'a Face<'a>,
+    palette: u16,
+    outline: Option<(Vec<PathSegment>, Bounds)>,
+    /// Maps the current space to design space
+    transform: Transform,
+    /// Bounds of the area the clips leave, in design space
+    clip: Option<Bounds>,
+    scopes: Vec<(Scope, Vec<ColorNode>)>,
+    nodes: Vec<ColorNode>,
+}
+
+impl ColorPainter<'_> {
+    fn current(&mut self) -> &mut Vec<ColorNode> {
+        match self.scopes.last_mut() {
+            Some((_, children)) => children,
+            None => &mut self.nodes,
+        }
+    }
+
+    fn push_scope(&mut self, scope: Scope) {
+        self.scopes.push((scope, Vec::new()));
+    }
+
+    fn pop_scope(&mut self) {
+        let Some((scope, children)) = self.scopes.pop() else {
+            return;
+        };
+        let node = match scope {
+            Scope::Clip { path, outer_clip } => {
+                self.clip = outer_clip;
+                ColorNode::Clip { path, children }
+            }
+            Scope::Transform {
+                transform,
+                outer_transform,
+            } => {
+                self.transform = outer_transform;
+                ColorNode::Transform {
+                    transform,
+                    children,
+                }
+            }
+            Scope::Layer(mode) => ColorNode::Layer { mode, children },
+        };
+        self.current().push(node);
+    }
+
+    /// Clip to a path in the current space
+    fn push_clip_path(&mut self, path: Vec<PathSegment>, bounds: Bounds) {
+        let [x0, y0, x1, y1] = map_bounds(&self.transform, bounds);
+        let outer_clip = self.clip;
+        self.clip = Some(match outer_clip {
+            Some([a0, b0, a1, b1]) => [x0.max(a0), y0.max(b0), x1.min(a1), y1.min(b1)],
+            None => [x0, y0, x1, y1],
+        });
+        self.push_scope(Scope::Clip { path, outer_clip });
+    }
+
+    /// What a paint fills: the current outline, or else a rectangle covering what the clips leave
+    fn fill_area(&self) -> Option<(Vec<PathSegment>, Bounds)> {
+        if let Some(outline) = &self.outline {
+            return Some(outline.clone());
+        }
+        let clip = self
+            .clip
+            .unwrap_or([-UNBOUNDED, -UNBOUNDED, UNBOUNDED, UNBOUNDED]);
+        let bounds = map_bounds(&invert(&self.transform)?, clip);
+        Some((rect_path(bounds), bounds))
+    }
+
+    fn stops(&self, stops: colr::GradientStopsIter) -> Vec<GradientStop> {
+        let mut stops: Vec<GradientStop> = stops
+            .map(|stop| GradientStop {
+                offset: stop.stop_offset,
+                color: stop.color,
+            })
+            .collect();
+        stops.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+        stops
+    }
+}
+
+impl<'a> colr::Painter<'a> for ColorPainter<'a> {
+    fn outline_glyph(&mut self, glyph_id: GlyphId) {
+        let bounds = self
+            .face
+            .glyph_bounding_box(glyph_id)
+            .map_or([0.0; 4], |b| {
+                [
+                    b.x_min as f32,
+                    b.y_min as f32,
+                    b.x_max as f32,
+                    b.y_max as f32,
+                ]
+            });
+        self.outline = Some((design_glyph_path(self.face, glyph_id.0), bounds));
+    }
+
+    fn paint(&mut self, paint: Paint<'a>) {
+        let coords = self.face.variation_coordinates();
+        let paint = match paint {
+            Paint::Solid(color) => Some(ColorPaint::Solid(color)),
+            Paint::LinearGradient(g) => {
+                linear_gradient(&g, self.stops(g.stops(self.palette, coords)))
+            }
+            Paint::RadialGradient(g) => {
+                radial_gradient(&g, self.stops(g.stops(self.palette, coords)))
+            }
+            Paint::SweepGradient(g) => {
+                sweep_gradient(&g, self.stops(g.stops(self.palette, coords)))
+            }
+        };
+        if let (Some(paint), Some((path, bounds))) = (paint, self.fill_area()) {
+            self.current().push(ColorNode::Fill {
+                path,
+                bounds,
+                paint,
+            });
+        }
+    }
+
+    fn push_clip(&mut self) {
+        let (path, bounds) = self.outline.take().unwrap_or_default();
+        self.push_clip_path(path, bounds);
+    }
+
+    fn push_clip_box(&mut self, clip_box: colr::ClipBox) {
+        let bounds = [
+            clip_box.x_min,
+            clip_box.y_min,
+            clip_box.x_max,
+            clip_box.y_max,
+        ];
+        self.push_clip_path(rect_path(bounds), bounds);
+    }
+
+    fn pop_clip(&mut self) {
+        self.pop_scope();
+    }
+
+    fn push_layer(&mut self, mode: CompositeMode) {
+        self.push_scope(Scope::Layer(mode));
+    }
+
+    fn pop_layer(&mut self) {
+        self.pop_scope();
+    }
+
+    fn push_transform(&mut self, transform: Transform) {
+        let outer_transform = self.transform;
+        self.transform = Transform::combine(outer_transform, transform);
+        self.push_scope(Scope::Transform {
+            transform,
+            outer_transform,
+        });
+    }
+
+    fn pop_transform(&mut self) {
+        self.pop_scope();
+    }
+}
+
+/// Paint graph of a color glyph, `None` when the face has no `COLR` layers for it
+pub(crate) fn color_glyph(
+    face: &Face,
+    glyph_id: u16,
+    is_sideways: bool,
+    palette: Option<&FontPalette>,
+    foreground: &csscolorparser::Color,
+) -> Option<ColorGlyph> {
+    if !face.is_color_glyph(GlyphId(glyph_id)) {
+        return None;
+    }
+    let [red, green, blue, alpha] = foreground.to_rgba8();
+    let mut painter = ColorPainter {
+        face,
+        palette: palette_index(face, palette),
+        outline: None,
+        transform: Transform::default(),
+        clip: None,
+        scopes: Vec::new(),
+        nodes: Vec::new(),
+    };
+    face.paint_color_glyph(
+        GlyphId(glyph_id),
+        painter.palette,
+        RgbaColor::new(red, green, blue, alpha),
+        &mut painter,
+    )?;
+    while !painter.scopes.is_empty() {
+        painter.pop_scope();
+    }
+
+    // Flip the y axis, turning sideways glyphs around their vertical origin like `glyph_path`
+    let transform = match is_sideways {
+        true => {
+            let (x, y) = vertical_origin(face, glyph_id);
+            Transform::new(0.0, -1.0, -1.0, 0.0, y as f32, x as f32)
+        }
+        false => Transform::new(1.0, 0.0, 0.0, -1.0, 0.0, 0.0),
+    };
+    Some(ColorGlyph {
+        nodes: vec![ColorNode::Transform {
+            transform,
+            children: painter.nodes,
+        }],
+    })
+}
+
+fn rect_path([x0, y0, x1, y1]: Bounds) -> Vec<PathSegment> {
+    vec![
+        PathSegment::MoveTo(x0, y0),
+        PathSegment::LineTo(x1, y0),
+        PathSegment::LineTo(x1, y1),
+        PathSegment::LineTo(x0, y1),
+        PathSegment::Close,
+    ]
+}
+
+/// Bounding box of a box mapped by a transform
+fn map_bounds(t: &Transform, [x0, y0, x1, y1]: Bounds) -> Bounds {
+    let corners = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)]
+        .map(|(x, y)| (t.a * x + t.c * y + t.e, t.b * x + t.d * y + t.f));
+    corners.iter().fold(
+        [
+            f32::INFINITY,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NEG_INFINITY,
+        ],
+        |[a0, b0, a1, b1], &(x, y)| [a0.min(x), b0.min(y), a1.max(x), b1.max(y)],
+    )
+}
+
+fn invert(t: &Transform) -> Option<Transform> {
+    let det = t.a * t.d - t.b * t.c;
+    if det.abs() <= f32::EPSILON {
+        return None;
+    }
+    Some(Transform::new(
+        t.d / det,
+        -t.b / det,
+        -t.c / det,
+        t.a / det,
+        (t.c * t.f - t.d * t.e) / det,
+        (t.b * t.e - t.a * t.f) / det,
+    ))
+}
+
+/// Index of the `CPAL` palette that `font-palette` selects, the first one when the font has no
+/// matching palette
+fn palette_index(face: &Face, palette: Option<&FontPalette>) -> u16 {
+    let count = face.color_palettes().map_or(1, |count| count.get());
+    match palette {
+        Some(FontPalette::Index(index)) if *index < count => *index,
+        Some(FontPalette::Keyword(FontPaletteKeyword::Light)) => {
+            palette_with_type(face, 0x1).unwrap_or(0)
+        }
+        Some(FontPalette::Keyword(FontPaletteKeyword::Dark)) => {
+            palette_with_type(face, 0x2).unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+/// First palette whose type flags in a version 1 `CPAL` include `flag`: 1 marks palettes for
+/// light backgrounds, 2 those for dark backgrounds
+fn palette_with_type(face: &Face, flag: u32) -> Option<u16> {
+    let data = face.raw_face().table(Tag::from_bytes(b"CPAL"))?;
+    let read_u16 = |offset: usize| {
+        data.get(offset..offset + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+    };
+    let read_u32 = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if read_u16(0)? < 1 {
+        return None;
+    }
+    let num_palettes = read_u16(4)?;
+    let types_offset = read_u32(12 + 2 * num_palettes as usize)? as usize;
+    if types_offset == 0 {
+        return None;
+    }
+    (0..num_palettes)
+        .find(|&i| read_u32(types_offset + 4 * i as usize).is_some_and(|types| types & flag != 0))
+}
+
+/// Stops of a gradient, rescaled so that they span 0..1
+enum ColorLine {
+    /// Where the first and last stops were on the original color line
+    Span(f32, f32, Vec<GradientStop>),
+    /// All stops at one offset, painting the color of the last one
+    Single(RgbaColor),
+}
+
+fn color_line(stops: Vec<GradientStop>) -> Option<ColorLine> {
+    let first = stops.first()?.offset;
+    let last = stops.last()?.offset;
+    if last - first <= f32::EPSILON {
+        return Some(ColorLine::Single(stops.last()?.color));
+    }
+    let stops = stops
+        .into_iter()
+        .map(|stop| GradientStop {
+            offset: (stop.offset - first) / (last - first),
+            ..stop
+        })
+        .collect();
+    Some(ColorLine::Span(first, last, stops))
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn linear_gradient(g: &colr::LinearGradient, stops: Vec<GradientStop>) -> Option<ColorPaint> {
+    // Colors are constant along lines parallel to p0p2, so the gradient runs from p0 to the
+    // projection of p1 onto the normal of p0p2
+    let (nx, ny) = (g.y0 - g.y2, g.x2 - g.x0);
+    let norm = nx * nx + ny * ny;
+    let (x3, y3) = match norm > 0.0 {
+        true => {
+            let t = ((g.x1 - g.x0) * nx + (g.y1 - g.y0) * ny) / norm;
+            (g.x0 + nx * t, g.y0 + ny * t)
+        }
+        false => (g.x1, g.y1),
+    };
+    let (first, last, stops) = match color_line(stops)? {
+        ColorLine::Span(first, last, stops) => (first, last, stops),
+        ColorLine::Single(color) => return Some(ColorPaint::Solid(color)),
+    };
+    Some(ColorPaint::LinearGradient {
+        start: (lerp(g.x0, x3, first), lerp(g.y0, y3, first)),
+        end: (lerp(g.x0, x3, last), lerp(g.y0, y3, last)),
+        stops,
+        extend: g.extend,
+    })
+}
+
+fn radial_gradient(g: &colr::RadialGradient, stops: Vec<GradientStop>) -> Option<ColorPaint> {
+    let (first, last, stops) = match color_line(stops)? {
+        ColorLine::Span(first, last, stops) => (first, last, stops),
+        ColorLine::Single(color) => return Some(ColorPaint::Solid(color)),
+    };
+    Some(ColorPaint::RadialGradient {
+        start: (lerp(g.x0, g.x1, first), lerp(g.y0, g.y1, first)),
+        start_radius: lerp(g.r0, g.r1, first).max(0.0),
+        end: (lerp(g.x0, g.x1, last), lerp(g.y0, g.y1, last)),
+        end_radius: lerp(g.r0, g.r1, last).max(0.0),
+        stops,
+        extend: g.extend,
+    })
+}
+
+fn sweep_gradient(g: &colr::SweepGradient, stops: Vec<GradientStop>) -> Option<ColorPaint> {
+    let (first, last, stops) = match color_line(stops)? {
+        ColorLine::Span(first, last, stops) => (first, last, stops),
+        ColorLine::Single(color) => return Some(ColorPaint::Solid(color)),
+    };
+    // Angles are stored in half turns
+    let (start_angle, end_angle) = (g.start_angle * 180.0, g.end_angle * 180.0);
+    Some(ColorPaint::SweepGradient {
+        center: (g.center_x, g.center_y),
+        start_angle: lerp(start_angle, end_angle, first),
+        end_angle: lerp(start_angle, end_angle, last),
+        stops,
+        extend: g.extend,
+    })
+}
+
+/// Color of a gradient at `t`, where its stops span 0..1, extended beyond that range
+pub(crate) fn color_at(stops: &[GradientStop], extend: GradientExtend, t: f32) -> RgbaColor {
+    let t = match extend {
+        GradientExtend::Pad => t.clamp(0.0, 1.0),
+        GradientExtend::Repeat => t.rem_euclid(1.0),
+        GradientExtend::Reflect => 1.0 - (t.rem_euclid(2.0) - 1.0).abs(),
+    };
+    let next = stops.iter().position(|stop| stop.offset > t);
+    let (a, b) = match next {
+        Some(0) => (stops[0], stops[0]),
+        Some(i) => (stops[i - 1], stops[i]),
+        None => (stops[stops.len() - 1], stops[stops.len() - 1]),
+    };
+    let s = match b.offset - a.offset > 0.0 {
+        true => (t - a.offset) / (b.offset - a.offset),
+        false => 0.0,
+    };
+    let mix = |x: u8, y: u8| lerp(x as f32, y as f32, s).round() as u8;
+    RgbaColor::new(
+        mix(a.color.red, b.color.red),
+        mix(a.color.green, b.color.green),
+        mix(a.color.blue, b.color.blue),
+        mix(a.color.alpha, b.color.alpha),
+    )
+}
@@ -0,0 +1,866 @@
+use tiny_skia::Transform;
+
+use crate::color_glyph::Bounds;
+use crate::document::GradientBox;
+use crate::error::Result;
+use crate::glyph_run::{join_bounds, GlyphFrame, GlyphOutlineSource, GlyphRun};
+
+/// A length of the gradient syntax, in DIPs or relative to the size it is measured against
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Length {
+    Px(f32),
+    Percent(f32),
+}
+
+impl Length {
+    fn parse(css: &str) -> Option<Self> {
+        if let Some(percent) = css.strip_suffix('%') {
+            return percent.parse().ok().map(Self::Percent);
+        }
+        if let Some(px) = css.strip_suffix("px") {
+            return px.parse().ok().map(Self::Px);
+        }
+        // Only zero may go without a unit
+        (css.parse::<f32>().ok()? == 0.0).then_some(Self::Px(0.0))
+    }
+
+    fn resolve(self, full: f32) -> f32 {
+        match self {
+            Self::Px(px) => px,
+            Self::Percent(percent) => percent / 100.0 * full,
+        }
+    }
+}
+
+/// Direction of a linear gradient
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GradientLine {
+    /// Degrees clockwise from `to top`
+    Angle(f32),
+    /// Towards a corner, as -1 or 1 for left or right and top or bottom. The angle depends on
+    /// the shape of the box.
+    Corner(f32, f32),
+}
+
+/// Size of the ending shape of a radial gradient
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RadialExtent {
+    ClosestSide,
+    FarthestSide,
+    ClosestCorner,
+    FarthestCorner,
+    Radii(Length, Length),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GradientGeometry {
+    Linear(GradientLine),
+    Radial {
+        circle: bool,
+        extent: RadialExtent,
+        center: (Length, Length),
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ColorStop {
+    color: csscolorparser::Color,
+    position: Option<Length>,
+}
+
+/// A CSS `linear-gradient()` or `radial-gradient()` color, before it is placed in a box
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CssGradient {
+    geometry: GradientGeometry,
+    stops: Vec<ColorStop>,
+}
+
+impl CssGradient {
+    /// Parse a gradient function; `None` for anything else, which is a plain color
+    pub(crate) fn parse(css: &str) -> Option<Self> {
+        let css = css.trim();
+        let open = css.find('(')?;
+        let mut args = split_top_level(css[open + 1..].strip_suffix(')')?, |ch| ch == ',');
+        let first = args.first().copied().unwrap_or_default();
+        let geometry = match css[..open].trim().to_ascii_lowercase().as_str() {
+            "linear-gradient" => match parse_line(first) {
+                Some(line) => {
+                    args.remove(0);
+                    GradientGeometry::Linear(line)
+                }
+                None => GradientGeometry::Linear(GradientLine::Angle(180.0)),
+            },
+            "radial-gradient" => match parse_radial(first) {
+                Some(geometry) => {
+                    args.remove(0);
+                    geometry
+                }
+                None => GradientGeometry::Radial {
+                    circle: false,
+                    extent: RadialExtent::FarthestCorner,
+                    center: (Length::Percent(50.0), Length::Percent(50.0)),
+                },
+            },
+            _ => return None,
+        };
+        let mut stops = Vec::new();
+        for arg in args {
+            stops.extend(parse_stop(arg)?);
+        }
+        if stops.is_empty() {
+            return None;
+        }
+        Some(Self { geometry, stops })
+    }
+
+    /// The color that stands for the gradient where a single color is drawn
+    pub(crate) fn first_color(&self) -> csscolorparser::Color {
+        self.stops[0].color.clone()
+    }
+
+    /// The gradient stretched over `bounds`, following the sizing rules of CSS
+    pub(crate) fn place(&self, [x0, y0, x1, y1]: Bounds) -> PlacedGradient {
+        let (width, height) = (x1 - x0, y1 - y0);
+        match self.geometry {
+            GradientGeometry::Linear(line) => {
+                let (dx, dy) = match line {
+                    GradientLine::Angle(angle) => {
+                        let (sin, cos) = angle.to_radians().sin_cos();
+                        (sin, -cos)
+                    }
+                    // Perpendicular to the diagonal between the other two corners
+                    GradientLine::Corner(x, y) => {
+                        let (dx, dy) = (x * height, y * width);
+                        let length = dx.hypot(dy).max(f32::EPSILON);
+                        (dx / length, dy / length)
+                    }
+                };
+                let length = (width * dx).abs() + (height * dy).abs();
+                let (cx, cy) = ((x0 + x1) / 2.0, (y0 + y1) / 2.0);
+                let start = (cx - dx * length / 2.0, cy - dy * length / 2.0);
+                let end = (cx + dx * length / 2.0, cy + dy * length / 2.0);
+                let mut stops = resolve_stops(&self.stops, length);
+                // Stops outside of the line move its ends, which outputs cannot go beyond
+                let (first, last) = (stops[0].0, stops[stops.len() - 1].0);
+                if last > first && (first != 0.0 || last != 1.0) {
+                    for stop in stops.iter_mut() {
+                        stop.0 = (stop.0 - first) / (last - first);
+                    }
+                    let at = |t: f32| {
+                        (
+                            start.0 + (end.0 - start.0) * t,
+                            start.1 + (end.1 - start.1) * t,
+                        )
+                    };
+                    return PlacedGradient {
+                        shape: GradientShape::Linear {
+                            start: at(first),
+                            end: at(last),
+                        },
+                        stops,
+                    };
+                }
+                PlacedGradient {
+                    shape: GradientShape::Linear { start, end },
+                    stops,
+                }
+            }
+            GradientGeometry::Radial {
+                circle,
+                extent,
+                center,
+            } => {
+                let (cx, cy) = (x0 + center.0.resolve(width), y0 + center.1.resolve(height));
+                let (near_x, far_x) = (
+                    (cx - x0).abs().min((x1 - cx).abs()),
+                    (cx - x0).abs().max((x1 - cx).abs()),
+                );
+                let (near_y, far_y) = (
+                    (cy - y0).abs().min((y1 - cy).abs()),
+                    (cy - y0).abs().max((y1 - cy).abs()),
+                );
+                let (rx, ry) = match (extent, circle) {
+                    (RadialExtent::ClosestSide, true) => (near_x.min(near_y), near_x.min(near_y)),
+                    (RadialExtent::ClosestSide, false) => (near_x, near_y),
+                    (RadialExtent::FarthestSide, true) => (far_x.max(far_y), far_x.max(far_y)),
+                    (RadialExtent::FarthestSide, false) => (far_x, far_y),
+                    (RadialExtent::ClosestCorner, true) => {
+                        (near_x.hypot(near_y), near_x.hypot(near_y))
+                    }
+                    // An ellipse through the corner, with the proportions of the sides
+                    (RadialExtent::ClosestCorner, false) => (near_x * SQRT_2, near_y * SQRT_2),
+                    (RadialExtent::FarthestCorner, true) => {
+                        (far_x.hypot(far_y), far_x.hypot(far_y))
+                    }
+                    (RadialExtent::FarthestCorner, false) => (far_x * SQRT_2, far_y * SQRT_2),
+                    (RadialExtent::Radii(rx, ry), _) => (rx.resolve(width), ry.resolve(height)),
+                };
+                let (rx, ry) = (rx.max(MIN_RADIUS), ry.max(MIN_RADIUS));
+                let mut stops = resolve_stops(&self.stops, rx);
+                // Stops past the ending shape grow it; stops before the center are clamped
+                let last = stops[stops.len() - 1].0;
+                let scale = last.max(MIN_RADIUS / rx);
+                for stop in stops.iter_mut() {
+                    stop.0 = (stop.0 / scale).clamp(0.0, 1.0);
+                }
+                PlacedGradient {
+                    shape: GradientShape::Radial {
+                        center: (cx, cy),
+                        radius: (rx * scale, ry * scale),
+                    },
+                    stops,
+                }
+            }
+        }
+    }
+}
+
+const SQRT_2: f32 = std::f32::consts::SQRT_2;
+
+// Radii of zero would leave nothing to interpolate over
+const MIN_RADIUS: f32 = 0.001;
+
+/// Geometry of a gradient on the canvas, in DIPs
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum GradientShape {
+    Linear {
+        start: (f32, f32),
+        end: (f32, f32),
+    },
+    /// Ellipses around `center`, out to the radii along the x and y axes
+    Radial {
+        center: (f32, f32),
+        radius: (f32, f32),
+    },
+}
+
+/// A gradient placed on the canvas, with offsets of its stops in order from 0 to 1
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PlacedGradient {
+    pub(crate) shape: GradientShape,
+    pub(crate) stops: Vec<(f32, csscolorparser::Color)>,
+}
+
+impl PlacedGradient {
+    /// Maps a radial gradient drawn as a circle with the x radius onto its ellipse
+    pub(crate) fn shape_transform(&self) -> Transform {
+        match self.shape {
+            GradientShape::Linear { .. } => Transform::identity(),
+            GradientShape::Radial {
+                center: (cx, cy),
+                radius: (rx, ry),
+            } => Transform::from_translate(cx, cy)
+                .pre_scale(1.0, ry / rx)
+                .pre_translate(-cx, -cy),
+        }
+    }
+}
+
+/// Places the gradient colors of a frame's runs over the glyphs of the box that their
+/// `gradient-box` selects
+pub(crate) struct GradientPlacer<'a> {
+    frame: &'a GlyphFrame,
+    outlines: &'a dyn GlyphOutlineSource,
+    frame_bounds: Option<Option<Bounds>>,
+}
+
+impl<'a> GradientPlacer<'a> {
+    pub(crate) fn new(frame: &'a GlyphFrame, outlines: &'a dyn GlyphOutlineSource) -> Self {
+        Self {
+            frame,
+            outlines,
+            frame_bounds: None,
+        }
+    }
+
+    /// The gradient a run is filled with, `None` when it has a plain color or no glyphs
+    pub(crate) fn place(&mut self, glyph_run: &GlyphRun) -> Result<Option<PlacedGradient>> {
+        let Some(effect) = glyph_run.drawing_effect.as_ref() else {
+            return Ok(None);
+        };
+        let Some(gradient) = effect.gradient.as_ref() else {
+            return Ok(None);
+        };
+        let bounds = match effect.gradient_box {
+            GradientBox::Run => glyph_run.ink_bounds(self.frame, self.outlines)?,
+            GradientBox::Frame => match self.frame_bounds {
+                Some(bounds) => bounds,
+                None => {
+                    let mut bounds = None;
+                    for run in self.frame.runs.iter() {
+                        let run = run.ink_bounds(self.frame, self.outlines)?;
+                        bounds = join_bounds(bounds, run);
+                    }
+                    *self.frame_bounds.insert(bounds)
+                }
+            },
+        };
+        Ok(bounds.map(|bounds| gradient.place(bounds)))
+    }
+}
+
+/// Offsets of color stops along a gradient of `length` DIPs: missing positions are spread evenly
+/// between their neighbours, and no stop goes before the one preceding it
+fn resolve_stops(stops: &[ColorStop], length: f32) -> Vec<(f32, csscolorparser::Color)> {
+    let length = length.max(f32::EPSILON);
+    let mut offsets: Vec<Option<f32>> = stops
+        .iter()
+        .map(|stop| {
+            stop.position
+                .map(|position| position.resolve(length) / length)
+        })
+        .collect();
+    let last = offsets.len() - 1;
+    offsets[0] = offsets[0].or(Some(0.0));
+    offsets[last] = offsets[last].or(Some(1.0));
+    let mut max = f32::MIN;
+    for offset in offsets.iter_mut().flatten() {
+        max = max.max(*offset);
+        *offset = max;
+    }
+    let mut resolved: Vec<f32> = Vec::with_capacity(offsets.len());
+    let mut i = 0;
+    while i < offsets.len() {
+        match offsets[i] {
+            Some(offset) => {
+                resolved.push(offset);
+                i += 1;
+            }
+            None => {
+                let from = resolved[i - 1];
+                let end = (i..offsets.len())
+                    .find(|&j| offsets[j].is_some())
+                    .unwrap_or(last);
+                let to = offsets[end].unwrap_or(from);
+                let steps = (end - i + 1) as f32;
+                for j in i..end {
+                    resolved.push(from + (to - from) * (j - i + 1) as f32 / steps);
+                }
+                i = end;
+            }
+        }
+    }
+    if stops.len() == 1 {
+        resolved.push(resolved[0]);
+    }
+    resolved
+        .into_iter()
+        .zip(stops.iter().chain(stops.last()))
+        .map(|(offset, stop)| (offset, stop.color.clone()))
+        .collect()
+}
+
+/// Split at the separators that are not inside parentheses, dropping empty pieces
+pub(crate) fn split_top_level(css: &str, is_separator: impl Fn(char) -> bool) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, ch) in css.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 && is_separator(ch) => {
+                pieces.push(css[start..i].trim());
+                start = i + ch.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    pieces.push(css[start..].trim());
+    pieces.retain(|piece| !piece.is_empty());
+    pieces
+}
+
+pub(crate) fn parse_angle(css: &str) -> Option<f32> {
+    let units = [
+        ("deg", 1.0),
+        ("grad", 0.9),
+        ("rad", 180.0 / std::f32::consts::PI),
+        ("turn", 360.0),
+    ];
+    for (unit, degrees) in units {
+        if let Some(value) = css.strip_suffix(unit) {
+            return value.parse::<f32>().ok().map(|value| value * degrees);
+        }
+    }
+    (css.parse::<f32>().ok()? == 0.0).then_some(0.0)
+}
+
+/// An angle or a `to <side-or-corner>`
+fn parse_line(css: &str) -> Option<GradientLine> {
+    let Some(sides) = css.strip_prefix("to ") else {
+        return parse_angle(css).map(GradientLine::Angle);
+    };
+    let (mut x, mut y) = (0, 0);
+    for side in sides.split_whitespace() {
+        match side {
+            "left" => x = -1,
+            "right" => x = 1,
+            "top" => y = -1,
+            "bottom" => y = 1,
+            _ => return None,
+        }
+    }
+    match (x, y) {
+        (0, 0) => None,
+        (0, -1) => Some(GradientLine::Angle(0.0)),
+        (0, _) => Some(GradientLine::Angle(180.0)),
+        (1, 0) => Some(GradientLine::Angle(90.0)),
+        (_, 0) => Some(GradientLine::Angle(270.0)),
+        (x, y) => Some(GradientLine::Corner(x as f32, y as f32)),
+    }
+}
+
+/// `[<shape> || <size>] [at <position>]`
+fn parse_radial(css: &str) -> Option<GradientGeometry> {
+    let tokens: Vec<&str> = css.split_whitespace().collect();
+    let at = tokens.iter().position(|token| *token == "at");
+    let (size, position) = match at {
+        Some(at) => (&tokens[..at], Some(&tokens[at + 1..])),
+        None => (&tokens[..], None),
+    };
+    if size.is_empty() && position.is_none() {
+        return None;
+    }
+    let mut circle = None;
+    let mut extent = None;
+    let mut radii = Vec::new();
+    for token in size {
+        match *token {
+            "circle" => circle = Some(true),
+            "ellipse" => circle = Some(false),
+            "closest-side" => extent = Some(RadialExtent::ClosestSide),
+            "farthest-side" => extent = Some(RadialExtent::FarthestSide),
+            "closest-corner" => extent = Some(RadialExtent::ClosestCorner),
+            "farthest-corner" => extent = Some(RadialExtent::FarthestCorner),
+            token => radii.push(Length::parse(token)?),
+        }
+    }
+    let circle = circle.unwrap_or(radii.len() == 1);
+    let extent = match radii[..] {
+        [] => extent.unwrap_or(RadialExtent::FarthestCorner),
+        [radius] if circle && extent.is_none() => RadialExtent::Radii(radius, radius),
+        [rx, ry] if !circle && extent.is_none() => RadialExtent::Radii(rx, ry),
+        _ => return None,
+    };
+    let center = match position {
+        Some(position) => parse_position(position)?,
+        None => (Length::Percent(50.0), Length::Percent(50.0)),
+    };
+    Some(GradientGeometry::Radial {
+        circle,
+        extent,
+        center,
+    })
+}
+
+/// One or two keywords or lengths, horizontal first unless a keyword says otherwise
+fn parse_position(tokens: &[&str]) -> Option<(Length, Length)> {
+    let value = |token: &str| match token {
+        "left" | "top" => Some(Length::Percent(0.0)),
+        "center" => Some(Length::Percent(50.0)),
+        "right" | "bottom" => Some(Length::Percent(100.0)),
+        token => Length::parse(token),
+    };
+    let is_vertical = |token: &str| token == "top" || token == "bottom";
+    let is_horizontal = |token: &str| token == "left" || token == "right";
+    match *tokens {
+        [a] if is_vertical(a) => Some((Length::Percent(50.0), value(a)?)),
+        [a] => Some((value(a)?, Length::Percent(50.0))),
+        [a, b] if is_vertical(a) || is_horizontal(b) => Some((value(b)?, value(a)?)),
+        [a, b] => Some((value(a)?, value(b)?)),
+        _ => None,
+    }
+}
+
+/// A color with up to two positions, which make two stops of the same color
+fn parse_stop(css: &str) -> Option<Vec<ColorStop>> {
+    let tokens = split_top_level(css, char::is_whitespace);
+    let positions = tokens
+        .iter()
+        .rev()
+        .take(2)
+        .take_while(|token| Length::parse(token).is_some())
+        .count()
+        .min(tokens.len().checked_sub(1)?);
+    let (color, positions) = tokens.split_at(tokens.len() - positions);
+    let color = csscolorparser::parse(&color.join(" ")).ok()?;
+    if positions.is_empty() {
+        return Some(vec![ColorStop {
+            color,
+            position: None,
+        }]);
+    }
+    let stops = positions
+        .iter()
+        .map(|position| ColorStop {
+            color: color.clone(),
+            position: Length::parse(position),
+        })
+        .collect();
+    Some(stops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::TextColor;
+    use crate::glyph_run::{test_outlines::Squares, DrawingEffect};
+
+    const CENTER: (Length, Length) = (Length::Percent(50.0), Length::Percent(50.0));
+
+    fn color(css: &str) -> csscolorparser::Color {
+        csscolorparser::parse(css).unwrap()
+    }
+
+    fn assert_near(actual: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-3 && (actual.1 - expected.1).abs() < 1e-3,
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    fn offsets(gradient: &PlacedGradient) -> Vec<f32> {
+        gradient.stops.iter().map(|stop| stop.0).collect()
+    }
+
+    #[test]
+    fn angles_take_css_units() {
+        for (css, degrees) in [
+            ("90deg", Some(90.0)),
+            ("-45deg", Some(-45.0)),
+            ("100grad", Some(90.0)),
+            ("0.25turn", Some(90.0)),
+            ("0", Some(0.0)),
+            ("90", None),
+            ("deg", None),
+            ("ninety", None),
+        ] {
+            assert_eq!(parse_angle(css), degrees, "{css:?}");
+        }
+        assert!((parse_angle("3.1415927rad").unwrap() - 180.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn lines_go_towards_sides_and_corners() {
+        for (css, line) in [
+            ("to top", Some(GradientLine::Angle(0.0))),
+            ("to right", Some(GradientLine::Angle(90.0))),
+            ("to bottom", Some(GradientLine::Angle(180.0))),
+            ("to left", Some(GradientLine::Angle(270.0))),
+            ("to top right", Some(GradientLine::Corner(1.0, -1.0))),
+            ("to right top", Some(GradientLine::Corner(1.0, -1.0))),
+            ("to bottom left", Some(GradientLine::Corner(-1.0, 1.0))),
+            ("45deg", Some(GradientLine::Angle(45.0))),
+            ("to middle", None),
+            ("to ", None),
+            ("top", None),
+        ] {
+            assert_eq!(parse_line(css), line, "{css:?}");
+        }
+    }
+
+    #[test]
+    fn radial_shapes_take_size_keywords_and_radii() {
+        let radial = |circle, extent, center| {
+            Some(GradientGeometry::Radial {
+                circle,
+                extent,
+                center,
+            })
+        };
+        for (css, geometry) in [
+            ("circle", radial(true, RadialExtent::FarthestCorner, CENTER)),
+            (
+                "ellipse closest-side",
+                radial(false, RadialExtent::ClosestSide, CENTER),
+            ),
+            (
+                "farthest-side circle",
+                radial(true, RadialExtent::FarthestSide, CENTER),
+            ),
+            (
+                "10px",
+                radial(
+                    true,
+                    RadialExtent::Radii(Length::Px(10.0), Length::Px(10.0)),
+                    CENTER,
+                ),
+            ),
+            (
+                "10px 20%",
+                radial(
+                    false,
+                    RadialExtent::Radii(Length::Px(10.0), Length::Percent(20.0)),
+                    CENTER,
+                ),
+            ),
+            (
+                "closest-corner at left top",
+                radial(
+                    false,
+                    RadialExtent::ClosestCorner,
+                    (Length::Percent(0.0), Length::Percent(0.0)),
+                ),
+            ),
+            (
+                "at 25% 5px",
+                radial(
+                    false,
+                    RadialExtent::FarthestCorner,
+                    (Length::Percent(25.0), Length::Px(5.0)),
+                ),
+            ),
+            ("circle 10px 20px", None),
+            ("ellipse 10px", None),
+            ("circle farthest-side 10px", None),
+            ("star", None),
+            ("at", None),
+            ("", None),
+        ] {
+            assert_eq!(parse_radial(css), geometry, "{css:?}");
+        }
+    }
+
+    #[test]
+    fn positions_take_keywords_in_either_order() {
+        let percent = |x, y| Some((Length::Percent(x), Length::Percent(y)));
+        for (tokens, position) in [
+            (&["left"][..], percent(0.0, 50.0)),
+            (&["top"][..], percent(50.0, 0.0)),
+            (&["center"][..], percent(50.0, 50.0)),
+            (&["top", "left"][..], percent(0.0, 0.0)),
+            (&["right", "bottom"][..], percent(100.0, 100.0)),
+            (&["bottom", "right"][..], percent(100.0, 100.0)),
+            (&["center", "bottom"][..], percent(50.0, 100.0)),
+            (
+                &["10px", "20%"][..],
+                Some((Length::Px(10.0), Length::Percent(20.0))),
+            ),
+            (&["10", "20%"][..], None),
+            (&["left", "top", "10px"][..], None),
+            (&[][..], None),
+        ] {
+            assert_eq!(parse_position(tokens), position, "{tokens:?}");
+        }
+    }
+
+    #[test]
+    fn stops_take_up_to_two_positions() {
+        let stop = |css: &str, position| ColorStop {
+            color: color(css),
+            position,
+        };
+        for (css, stops) in [
+            ("red", Some(vec![stop("red", None)])),
+            ("red 0", Some(vec![stop("red", Some(Length::Px(0.0)))])),
+            (
+                "red 10%",
+                Some(vec![stop("red", Some(Length::Percent(10.0)))]),
+            ),
+            (
+                "rgb(0 0 255) 10% 20px",
+                Some(vec![
+                    stop("blue", Some(Length::Percent(10.0))),
+                    stop("blue", Some(Length::Px(20.0))),
+                ]),
+            ),
+            ("10%", None),
+            ("red 1", None),
+            ("red 1px 2px 3px", None),
+            ("nocolor 10%", None),
+        ] {
+            assert_eq!(parse_stop(css), stops, "{css:?}");
+        }
+    }
+
+    #[test]
+    fn malformed_gradients_are_rejected() {
+        for css in [
+            "linear-gradient()",
+            "linear-gradient(red, nocolor)",
+            "linear-gradient(to middle, red)",
+            "linear-gradient(45deg, red, blue",
+            "radial-gradient(circle 1px 2px, red)",
+            "conic-gradient(red, blue)",
+            "red",
+        ] {
+            assert_eq!(CssGradient::parse(css), None, "{css:?}");
+        }
+        for css in [
+            "linear-gradient(to middle, red)",
+            "radial-gradient()",
+            "nocolor",
+        ] {
+            let error = TextColor::try_from(String::from(css)).unwrap_err();
+            assert!(error.starts_with("unknown color"), "{error}");
+        }
+        let plain = TextColor::try_from(String::from("red")).unwrap();
+        assert_eq!((plain.color, plain.gradient), (color("red"), None));
+        let gradient = TextColor::try_from(String::from("linear-gradient(blue, red)")).unwrap();
+        assert_eq!(gradient.color, color("blue"));
+        assert!(gradient.gradient.is_some());
+    }
+
+    #[test]
+    fn implicit_stops_are_spread_between_their_neighbours() {
+        let bounds = [0.0, 0.0, 100.0, 100.0];
+        for (css, expected) in [
+            ("linear-gradient(red, green, blue)", vec![0.0, 0.5, 1.0]),
+            (
+                "linear-gradient(red, green 20%, blue, white)",
+                vec![0.0, 0.2, 0.6, 1.0],
+            ),
+            ("linear-gradient(red 50%, blue 20%)", vec![0.5, 0.5]),
+            ("linear-gradient(red 0 30px, blue)", vec![0.0, 0.3, 1.0]),
+            ("linear-gradient(red)", vec![0.0, 0.0]),
+        ] {
+            let placed = CssGradient::parse(css).unwrap().place(bounds);
+            let actual = offsets(&placed);
+            assert_eq!(actual.len(), expected.len(), "{css:?}");
+            for (actual, expected) in actual.iter().zip(expected) {
+                assert!(
+                    (actual - expected).abs() < 1e-5,
+                    "{css:?}: {actual} != {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn linear_gradients_span_the_box_along_their_line() {
+        let bounds = [0.0, 0.0, 200.0, 100.0];
+        let line = |css: &str| match CssGradient::parse(css).unwrap().place(bounds).shape {
+            GradientShape::Linear { start, end } => (start, end),
+            shape => panic!("{shape:?}"),
+        };
+        let (start, end) = line("linear-gradient(90deg, red, blue)");
+        assert_near(start, (0.0, 50.0));
+        assert_near(end, (200.0, 50.0));
+        let (start, end) = line("linear-gradient(red, blue)");
+        assert_near(start, (100.0, 0.0));
+        assert_near(end, (100.0, 100.0));
+        // Perpendicular to the diagonal from the bottom left to the top right corner, so that
+        // both of those corners are half way
+        let (start, end) = line("linear-gradient(to bottom right, red, blue)");
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        assert!((dx * 200.0 - dy * 100.0).abs() < 1e-2);
+        assert_near(
+            ((start.0 + end.0) / 2.0, (start.1 + end.1) / 2.0),
+            (100.0, 50.0),
+        );
+        // Stops past the ends move them
+        let (start, end) = line("linear-gradient(to right, red -50px, blue 100px)");
+        assert_near(start, (-50.0, 50.0));
+        assert_near(end, (100.0, 50.0));
+    }
+
+    #[test]
+    fn radial_gradients_size_their_ending_shape() {
+        let bounds = [0.0, 0.0, 200.0, 100.0];
+        for (css, center, radius) in [
+            (
+                "radial-gradient(circle closest-side at 50px 30px, red, blue)",
+                (50.0, 30.0),
+                (30.0, 30.0),
+            ),
+            (
+                "radial-gradient(closest-side at 50px 30px, red, blue)",
+                (50.0, 30.0),
+                (50.0, 30.0),
+            ),
+            (
+                "radial-gradient(circle farthest-side, red, blue)",
+                (100.0, 50.0),
+                (100.0, 100.0),
+            ),
+            (
+                "radial-gradient(circle closest-corner at 0 0, red, blue)",
+                (0.0, 0.0),
+                (0.0, 0.0),
+            ),
+            (
+                "radial-gradient(red, blue)",
+                (100.0, 50.0),
+                (100.0 * SQRT_2, 50.0 * SQRT_2),
+            ),
+            (
+                "radial-gradient(10px 20%, red, blue)",
+                (100.0, 50.0),
+                (10.0, 20.0),
+            ),
+            // The last stop grows the shape
+            (
+                "radial-gradient(circle 10px, red, blue 20px)",
+                (100.0, 50.0),
+                (20.0, 20.0),
+            ),
+        ] {
+            let placed = CssGradient::parse(css).unwrap().place(bounds);
+            let GradientShape::Radial {
+                center: actual_center,
+                radius: actual_radius,
+            } = placed.shape
+            else {
+                panic!("{css:?}: {:?}", placed.shape);
+            };
+            assert_near(actual_center, center);
+            // A zero radius is kept just above zero
+            assert_near(
+                actual_radius,
+                (radius.0.max(MIN_RADIUS), radius.1.max(MIN_RADIUS)),
+            );
+            assert_eq!(*offsets(&placed).last().unwrap(), 1.0, "{css:?}");
+        }
+    }
+
+    #[test]
+    fn gradients_cover_their_run_or_the_whole_frame() {
+        let run = |x: f32, size: u16, gradient_box| GlyphRun {
+            font_size: 1.0,
+            baseline_origin_x: x,
+            baseline_origin_y: 10.0,
+            glyph_ids: vec![size],
+            advances: vec![size as f32],
+            drawing_effect: Some(DrawingEffect {
+                gradient: CssGradient::parse("linear-gradient(to right, red, blue)"),
+                gradient_box,
+                ..DrawingEffect::default()
+            }),
+            ..GlyphRun::default()
+        };
+        let line = |placed: Option<PlacedGradient>| match placed.unwrap().shape {
+            GradientShape::Linear { start, end } => (start, end),
+            shape => panic!("{shape:?}"),
+        };
+        for (gradient_box, first, second) in [
+            (
+                GradientBox::Run,
+                ((0.0, 5.0), (10.0, 5.0)),
+                ((20.0, 7.5), (25.0, 7.5)),
+            ),
+            (
+                GradientBox::Frame,
+                ((0.0, 5.0), (25.0, 5.0)),
+                ((0.0, 5.0), (25.0, 5.0)),
+            ),
+        ] {
+            let frame = GlyphFrame {
+                runs: vec![run(0.0, 10, gradient_box), run(20.0, 5, gradient_box)],
+                ..GlyphFrame::default()
+            };
+            let mut placer = GradientPlacer::new(&frame, &Squares);
+            for (run, (start, end)) in frame.runs.iter().zip([first, second]) {
+                let (actual_start, actual_end) = line(placer.place(run).unwrap());
+                assert_near(actual_start, start);
+                assert_near(actual_end, end);
+            }
+        }
+        let mut plain = run(0.0, 10, GradientBox::Run);
+        plain.drawing_effect = None;
+        let frame = GlyphFrame::default();
+        assert_eq!(
+            GradientPlacer::new(&frame, &Squares).place(&plain).unwrap(),
+            None
+        );
+    }
+}
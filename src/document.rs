@@ -1,8 +1,16 @@
 use std::collections::BTreeMap;
 
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use tiny_skia::Transform;
+#[cfg(windows)]
 use windows::Win32::Graphics::DirectWrite::*;
 
+use crate::css_gradient::CssGradient;
+use crate::frame_transform::{parse_transform, parse_transform_origin};
+use crate::glyph_run::TextShadow;
+use crate::text_path::flatten_path;
+use crate::text_shadow::parse_text_shadows;
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct Document {
@@ -12,6 +20,9 @@ pub(crate) struct Document {
     pub(crate) height: f32,
     #[serde(default)]
     pub(crate) font_files: Vec<String>,
+    /// Fallback families keyed by locale (`ja`, `zh-Hant`) or ISO 15924 script code (`Hani`, `Zsye`)
+    #[serde(default)]
+    pub(crate) fallback: BTreeMap<String, FontFamilyList>,
     #[serde(default)]
     pub(crate) frames: Vec<DocumentFrame>,
 }
@@ -48,6 +59,20 @@ pub(crate) struct DocumentFrame {
     pub(crate) line_height: f32,
     #[serde(default = "default_baseline")]
     pub(crate) baseline_offset: f32,
+    #[serde(default)]
+    pub(crate) line_break: LineBreak,
+    #[serde(default)]
+    pub(crate) word_break: WordBreak,
+    #[serde(default)]
+    pub(crate) text_orientation: TextOrientation,
+    /// Transform of the laid out frame, applied around `transform-origin`
+    #[serde(default)]
+    pub(crate) transform: Option<FrameTransform>,
+    #[serde(default)]
+    pub(crate) transform_origin: TransformOrigin,
+    /// Path that the text follows instead of lines in the frame box
+    #[serde(default)]
+    pub(crate) text_path: Option<TextPath>,
 
     pub(crate) contents: DocumentContent,
 }
@@ -59,19 +84,81 @@ const fn default_baseline() -> f32 {
     0.80
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub(crate) enum DocumentContent {
     Text(String),
-    Style(TextStyle),
-    Embed(Box<Vec<DocumentContent>>),
+    Object(InlineObject),
+    Style(Box<TextStyle>),
+    Embed(Vec<DocumentContent>),
 }
 
-// Block-level styles
+/// Objects with an `image` or `svg` key are inline objects and other objects are styles. Unlike
+/// an untagged enum, this reports why a style or an object does not parse.
+impl<'de> Deserialize<'de> for DocumentContent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let content = match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::String(text) => Ok(Self::Text(text)),
+            serde_json::Value::Array(items) => items
+                .into_iter()
+                .map(serde_json::from_value)
+                .collect::<Result<_, _>>()
+                .map(Self::Embed),
+            serde_json::Value::Object(object)
+                if object.contains_key("image") || object.contains_key("svg") =>
+            {
+                serde_json::from_value(object.into()).map(Self::Object)
+            }
+            serde_json::Value::Object(object) => {
+                serde_json::from_value(object.into()).map(Self::Style)
+            }
+            _ => {
+                return Err(D::Error::custom(
+                    "expected text, a style, an inline object or a list of them",
+                ))
+            }
+        };
+        content.map_err(D::Error::custom)
+    }
+}
+
+/// An image or SVG fragment set in the text like a character, sized in DIPs. It stays upright in
+/// vertical lines, centred on the central baseline.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct InlineObject {
+    #[serde(flatten)]
+    pub(crate) source: InlineObjectSource,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+    /// Distance from the top of the object down to the baseline; by default the object sits on
+    /// the baseline
+    pub(crate) baseline: Option<f32>,
+    /// Text that describes the object
+    pub(crate) alt: Option<String>,
+}
+
+impl InlineObject {
+    pub(crate) fn baseline(&self) -> f32 {
+        self.baseline.unwrap_or(self.height)
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
+pub(crate) enum InlineObjectSource {
+    /// Path of a PNG, JPEG or SVG file, scaled to fit the object
+    Image(String),
+    /// SVG elements, drawn with the top left corner of the object as their origin
+    Svg(String),
+}
+
+// Block-level styles
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub(crate) enum WritingMode {
+    #[default]
     LrTb,
     LrBt,
     RlTb,
@@ -82,12 +169,7 @@ pub(crate) enum WritingMode {
     BtRl,
 }
 
-impl Default for WritingMode {
-    fn default() -> Self {
-        Self::LrTb
-    }
-}
-
+#[cfg(windows)]
 impl Into<(DWRITE_READING_DIRECTION, DWRITE_FLOW_DIRECTION)> for WritingMode {
     fn into(self) -> (DWRITE_READING_DIRECTION, DWRITE_FLOW_DIRECTION) {
         match self {
@@ -127,9 +209,10 @@ impl Into<(DWRITE_READING_DIRECTION, DWRITE_FLOW_DIRECTION)> for WritingMode {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) enum TextAlign {
+    #[default]
     #[serde(alias = "leading")]
     Left,
     Center,
@@ -138,12 +221,7 @@ pub(crate) enum TextAlign {
     Justify,
 }
 
-impl Default for TextAlign {
-    fn default() -> Self {
-        Self::Left
-    }
-}
-
+#[cfg(windows)]
 impl Into<DWRITE_TEXT_ALIGNMENT> for TextAlign {
     fn into(self) -> DWRITE_TEXT_ALIGNMENT {
         match self {
@@ -155,41 +233,316 @@ impl Into<DWRITE_TEXT_ALIGNMENT> for TextAlign {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) enum HAlign {
+    #[default]
     Left,
     Center,
     Right,
 }
 
-impl Default for HAlign {
-    fn default() -> Self {
-        Self::Left
-    }
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) enum VAlign {
+    #[default]
     Top,
     Center,
     Bottom,
 }
 
-impl Default for VAlign {
+/// Strictness of line breaking, as the CSS `line-break` property
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LineBreak {
+    #[default]
+    Auto,
+    Loose,
+    Normal,
+    Strict,
+    Anywhere,
+}
+
+/// Breaking within words, as the CSS `word-break` property
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum WordBreak {
+    #[default]
+    Normal,
+    BreakAll,
+    KeepAll,
+}
+
+/// Orientation of characters in vertical lines, as the CSS `text-orientation` property
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum TextOrientation {
+    #[default]
+    Mixed,
+    Upright,
+    Sideways,
+}
+
+/// A CSS `transform` list of `matrix`, `translate`, `scale`, `rotate` and `skew` functions,
+/// multiplied into one matrix
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct FrameTransform(pub(crate) Transform);
+
+impl TryFrom<String> for FrameTransform {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match parse_transform(&value) {
+            Some(transform) => Ok(Self(transform)),
+            None => Err(format!("unknown transform \"{}\"", value)),
+        }
+    }
+}
+
+impl From<FrameTransform> for String {
+    fn from(value: FrameTransform) -> Self {
+        let Transform {
+            sx,
+            ky,
+            kx,
+            sy,
+            tx,
+            ty,
+        } = value.0;
+        format!("matrix({}, {}, {}, {}, {}, {})", sx, ky, kx, sy, tx, ty)
+    }
+}
+
+/// A CSS color, or a `linear-gradient()` or `radial-gradient()` drawn in its first color where a
+/// single color is needed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct TextColor {
+    source: String,
+    pub(crate) color: csscolorparser::Color,
+    pub(crate) gradient: Option<CssGradient>,
+}
+
+impl TryFrom<String> for TextColor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (color, gradient) = match CssGradient::parse(&value) {
+            Some(gradient) => (gradient.first_color(), Some(gradient)),
+            None => match csscolorparser::parse(&value) {
+                Ok(color) => (color, None),
+                Err(_) => return Err(format!("unknown color \"{}\"", value)),
+            },
+        };
+        Ok(Self {
+            source: value,
+            color,
+            gradient,
+        })
+    }
+}
+
+impl From<TextColor> for String {
+    fn from(value: TextColor) -> Self {
+        value.source
+    }
+}
+
+/// A CSS `text-shadow` list, the first shadow on top; `none` has no shadows
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct TextShadowList(pub(crate) Vec<TextShadow>);
+
+impl TryFrom<String> for TextShadowList {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match parse_text_shadows(&value) {
+            Some(shadows) => Ok(Self(shadows)),
+            None => Err(format!("unknown text-shadow \"{}\"", value)),
+        }
+    }
+}
+
+impl From<TextShadowList> for String {
+    fn from(value: TextShadowList) -> Self {
+        if value.0.is_empty() {
+            return String::from("none");
+        }
+        let shadows: Vec<String> = value
+            .0
+            .iter()
+            .map(|shadow| {
+                let lengths = format!(
+                    "{}px {}px {}px",
+                    shadow.offset_x, shadow.offset_y, shadow.blur
+                );
+                match &shadow.color {
+                    Some(color) => format!("{} {}", lengths, color.to_hex_string()),
+                    None => lengths,
+                }
+            })
+            .collect();
+        shadows.join(", ")
+    }
+}
+
+/// Point of the frame box that a transform keeps in place, as the CSS `transform-origin`
+/// property: keywords, lengths in DIPs or percentages. The center by default.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct TransformOrigin {
+    pub(crate) x: OriginOffset,
+    pub(crate) y: OriginOffset,
+}
+
+impl Default for TransformOrigin {
     fn default() -> Self {
-        Self::Top
+        Self {
+            x: OriginOffset::Percent(50.0),
+            y: OriginOffset::Percent(50.0),
+        }
+    }
+}
+
+impl TryFrom<String> for TransformOrigin {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        parse_transform_origin(&value)
+            .ok_or_else(|| format!("unknown transform-origin \"{}\"", value))
+    }
+}
+
+impl From<TransformOrigin> for String {
+    fn from(value: TransformOrigin) -> Self {
+        format!("{} {}", value.x, value.y)
+    }
+}
+
+/// A length in DIPs or a percentage: a number, `"10px"` or `"50%"`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "LengthValue", into = "LengthValue")]
+pub(crate) enum OriginOffset {
+    Px(f32),
+    Percent(f32),
+}
+
+impl OriginOffset {
+    /// Offset from the start of a side of the frame box, or of a path, that is `size` long
+    pub(crate) fn resolve(self, size: f32) -> f32 {
+        match self {
+            Self::Px(px) => px,
+            Self::Percent(percent) => percent / 100.0 * size,
+        }
+    }
+}
+
+impl std::fmt::Display for OriginOffset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Px(px) => write!(f, "{}px", px),
+            Self::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+impl TryFrom<LengthValue> for OriginOffset {
+    type Error = String;
+
+    fn try_from(value: LengthValue) -> Result<Self, Self::Error> {
+        let css = match value {
+            LengthValue::Number(px) => return Ok(Self::Px(px)),
+            LengthValue::Css(css) => css,
+        };
+        let css = css.trim();
+        let parse = |number: &str| {
+            number
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("unknown offset \"{}\"", css))
+        };
+        match (css.strip_suffix('%'), css.strip_suffix("px")) {
+            (Some(percent), _) => Ok(Self::Percent(parse(percent)?)),
+            (_, Some(px)) => Ok(Self::Px(parse(px)?)),
+            _ => Ok(Self::Px(parse(css)?)),
+        }
+    }
+}
+
+impl From<OriginOffset> for LengthValue {
+    fn from(value: OriginOffset) -> Self {
+        match value {
+            OriginOffset::Px(px) => Self::Number(px),
+            OriginOffset::Percent(percent) => Self::Css(format!("{}%", percent)),
+        }
+    }
+}
+
+/// Text set along a path, as SVG `<textPath>`. The laid out line is bent onto the path, each
+/// cluster at its distance from the anchor that `text-align` picks, turned to the tangent.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct TextPath {
+    /// SVG path data, in DIPs from the top left corner of the frame box
+    pub(crate) d: PathData,
+    /// Distance along the path of the anchor
+    #[serde(default = "default_start_offset")]
+    pub(crate) start_offset: OriginOffset,
+    #[serde(default)]
+    pub(crate) side: TextPathSide,
+}
+
+const fn default_start_offset() -> OriginOffset {
+    OriginOffset::Px(0.0)
+}
+
+/// SVG path data, kept with the polylines of its subpaths
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct PathData {
+    source: String,
+    pub(crate) subpaths: Vec<Vec<(f32, f32)>>,
+}
+
+impl TryFrom<String> for PathData {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match flatten_path(&value) {
+            Some(subpaths) => Ok(Self {
+                source: value,
+                subpaths,
+            }),
+            None => Err(format!("unknown path data \"{}\"", value)),
+        }
+    }
+}
+
+impl From<PathData> for String {
+    fn from(value: PathData) -> Self {
+        value.source
     }
 }
 
+/// Side of the path that the text is on, as the SVG `side` attribute. On the right side the
+/// text runs along the path backwards.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum TextPathSide {
+    #[default]
+    Left,
+    Right,
+}
+
 // Run-level styles
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct TextStyle {
     #[serde(default)]
-    pub(crate) font_family: Option<String>,
+    pub(crate) font_family: Option<FontFamilyList>,
     #[serde(default)]
     pub(crate) font_weight: Option<i32>,
     #[serde(default)]
@@ -198,32 +551,164 @@ pub(crate) struct TextStyle {
     pub(crate) font_style: Option<FontStyle>,
     #[serde(default)]
     pub(crate) font_size: Option<f32>,
+    /// A CSS color, or a `linear-gradient()` or `radial-gradient()` spread over `gradient-box`
+    #[serde(default)]
+    pub(crate) color: Option<TextColor>,
     #[serde(default)]
-    pub(crate) color: Option<String>,
+    pub(crate) gradient_box: Option<GradientBox>,
     #[serde(default)]
     pub(crate) lang: Option<String>,
+    /// Space added after every character; optional ligatures are turned off when it is not zero
+    #[serde(default)]
+    pub(crate) letter_spacing: Option<SpacingLength>,
+    /// Space added after every word separator, on top of `letter-spacing`
+    #[serde(default)]
+    pub(crate) word_spacing: Option<SpacingLength>,
     #[serde(default)]
     pub(crate) font_feature_settings: BTreeMap<String, u32>,
     #[serde(default)]
     pub(crate) font_variation_settings: BTreeMap<String, FontVariationValue>,
+    #[serde(default)]
+    pub(crate) font_palette: Option<FontPalette>,
+    #[serde(default)]
+    pub(crate) text_decoration_line: Option<TextDecorationLine>,
+    #[serde(default)]
+    pub(crate) text_decoration_color: Option<String>,
+    #[serde(default)]
+    pub(crate) text_decoration_style: Option<TextDecorationStyle>,
+    /// Thickness of decoration lines in DIPs, from the font when not set
+    #[serde(default)]
+    pub(crate) text_decoration_thickness: Option<f32>,
+    /// Distance from the baseline down to the underline in DIPs, from the font when not set
+    #[serde(default)]
+    pub(crate) text_underline_offset: Option<f32>,
+    /// Fill behind the text, covering its line box on each line
+    #[serde(default)]
+    pub(crate) background_color: Option<String>,
+    /// Space between the text and the edges of its background in DIPs
+    #[serde(default)]
+    pub(crate) background_padding: Option<f32>,
+    /// Corner radius of the background in DIPs
+    #[serde(default)]
+    pub(crate) background_radius: Option<f32>,
+    /// Color of the stroke along glyph outlines, the text color when not set
+    #[serde(default)]
+    pub(crate) stroke_color: Option<String>,
+    /// Width of the stroke in DIPs, centred on the outlines
+    #[serde(default)]
+    pub(crate) stroke_width: Option<f32>,
+    #[serde(default)]
+    pub(crate) stroke_linejoin: Option<StrokeLineJoin>,
+    #[serde(default)]
+    pub(crate) paint_order: Option<PaintOrder>,
+    /// A CSS `text-shadow` list of offsets, blur radius and color, the first shadow on top
+    #[serde(default)]
+    pub(crate) text_shadow: Option<TextShadowList>,
+    /// Opacity of the text as a whole, from 0 to 1, fading its decorations and background too
+    #[serde(default)]
+    pub(crate) opacity: Option<f32>,
+    /// How the glyphs of the text and their shadows blend with what is below them
+    #[serde(default)]
+    pub(crate) mix_blend_mode: Option<MixBlendMode>,
 }
 
 impl TextStyle {
     pub(crate) fn merge(&mut self, other: &TextStyle) {
         self.font_family = other.font_family.clone().or(self.font_family.clone());
-        self.font_weight = other.font_weight.clone().or(self.font_weight.clone());
-        self.font_width = other.font_width.clone().or(self.font_width.clone());
+        self.font_weight = other.font_weight.or(self.font_weight);
+        self.font_width = other.font_width.or(self.font_width);
         self.font_style = other.font_style.clone().or(self.font_style.clone());
-        self.font_size = other.font_size.clone().or(self.font_size.clone());
+        self.font_size = other.font_size.or(self.font_size);
         self.color = other.color.clone().or(self.color.clone());
+        self.gradient_box = other.gradient_box.or(self.gradient_box);
         self.lang = other.lang.clone().or(self.lang.clone());
+        self.letter_spacing = other.letter_spacing.or(self.letter_spacing);
+        self.word_spacing = other.word_spacing.or(self.word_spacing);
         self.font_feature_settings
             .extend(other.font_feature_settings.clone());
         self.font_variation_settings
             .extend(other.font_variation_settings.clone());
+        self.font_palette = other.font_palette.clone().or(self.font_palette.clone());
+        self.text_decoration_line = other.text_decoration_line.or(self.text_decoration_line);
+        self.text_decoration_color = other
+            .text_decoration_color
+            .clone()
+            .or(self.text_decoration_color.clone());
+        self.text_decoration_style = other.text_decoration_style.or(self.text_decoration_style);
+        self.text_decoration_thickness = other
+            .text_decoration_thickness
+            .or(self.text_decoration_thickness);
+        self.text_underline_offset = other.text_underline_offset.or(self.text_underline_offset);
+        self.background_color = other
+            .background_color
+            .clone()
+            .or(self.background_color.clone());
+        self.background_padding = other.background_padding.or(self.background_padding);
+        self.background_radius = other.background_radius.or(self.background_radius);
+        self.stroke_color = other.stroke_color.clone().or(self.stroke_color.clone());
+        self.stroke_width = other.stroke_width.or(self.stroke_width);
+        self.stroke_linejoin = other.stroke_linejoin.or(self.stroke_linejoin);
+        self.paint_order = other.paint_order.or(self.paint_order);
+        self.text_shadow = other.text_shadow.clone().or(self.text_shadow.clone());
+        self.opacity = other.opacity.or(self.opacity);
+        self.mix_blend_mode = other.mix_blend_mode.or(self.mix_blend_mode);
     }
 }
 
+/// An ordered list of font families, written as a CSS `font-family` value or as an array
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "FontFamilyValue", into = "FontFamilyValue")]
+pub(crate) struct FontFamilyList(pub(crate) Vec<String>);
+
+impl FontFamilyList {
+    pub(crate) fn first(&self) -> Option<&str> {
+        self.0.first().map(String::as_str)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum FontFamilyValue {
+    Css(String),
+    List(Vec<String>),
+}
+
+impl From<FontFamilyValue> for FontFamilyList {
+    fn from(value: FontFamilyValue) -> Self {
+        match value {
+            FontFamilyValue::Css(css) => Self(parse_font_family_list(&css)),
+            FontFamilyValue::List(list) => Self(list),
+        }
+    }
+}
+
+impl From<FontFamilyList> for FontFamilyValue {
+    fn from(value: FontFamilyList) -> Self {
+        Self::List(value.0)
+    }
+}
+
+/// Split a comma-separated family list, removing quotes around names
+fn parse_font_family_list(css: &str) -> Vec<String> {
+    let mut families = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    for ch in css.chars() {
+        match (quote, ch) {
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(q), _) if q == ch => quote = None,
+            (None, ',') => families.push(std::mem::take(&mut current)),
+            _ => current.push(ch),
+        }
+    }
+    families.push(current);
+    families
+        .into_iter()
+        .map(|family| family.trim().to_string())
+        .filter(|family| !family.is_empty())
+        .collect()
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) enum FontStyle {
@@ -232,6 +717,7 @@ pub(crate) enum FontStyle {
     Italic,
 }
 
+#[cfg(windows)]
 impl Into<DWRITE_FONT_STYLE> for FontStyle {
     fn into(self) -> DWRITE_FONT_STYLE {
         match self {
@@ -242,6 +728,64 @@ impl Into<DWRITE_FONT_STYLE> for FontStyle {
     }
 }
 
+/// A length in DIPs, or in ems of the font size: a number, `"2px"` or `"0.1em"`. `normal` is
+/// zero.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "LengthValue", into = "LengthValue")]
+pub(crate) enum SpacingLength {
+    Px(f32),
+    Em(f32),
+}
+
+impl SpacingLength {
+    pub(crate) fn resolve(self, font_size: f32) -> f32 {
+        match self {
+            Self::Px(px) => px,
+            Self::Em(em) => em * font_size,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum LengthValue {
+    Number(f32),
+    Css(String),
+}
+
+impl TryFrom<LengthValue> for SpacingLength {
+    type Error = String;
+
+    fn try_from(value: LengthValue) -> Result<Self, Self::Error> {
+        let css = match value {
+            LengthValue::Number(px) => return Ok(Self::Px(px)),
+            LengthValue::Css(css) => css,
+        };
+        let css = css.trim();
+        let parse = |number: &str| {
+            number
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("unknown spacing \"{}\"", css))
+        };
+        match (css.strip_suffix("em"), css.strip_suffix("px")) {
+            _ if css == "normal" => Ok(Self::Px(0.0)),
+            (Some(em), _) => Ok(Self::Em(parse(em)?)),
+            (_, Some(px)) => Ok(Self::Px(parse(px)?)),
+            _ => Ok(Self::Px(parse(css)?)),
+        }
+    }
+}
+
+impl From<SpacingLength> for LengthValue {
+    fn from(value: SpacingLength) -> Self {
+        match value {
+            SpacingLength::Px(px) => Self::Number(px),
+            SpacingLength::Em(em) => Self::Css(format!("{}em", em)),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub(crate) enum FontVariationValue {
@@ -249,6 +793,167 @@ pub(crate) enum FontVariationValue {
     Set(f32),
 }
 
+/// `CPAL` palette of color glyphs, as the CSS `font-palette` property: `normal`, `light`, `dark`
+/// or a palette index
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum FontPalette {
+    Keyword(FontPaletteKeyword),
+    Index(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum FontPaletteKeyword {
+    Normal,
+    Light,
+    Dark,
+}
+
+/// Lines drawn by the CSS `text-decoration-line` property: `none`, or any of `underline`,
+/// `overline` and `line-through` separated by spaces
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct TextDecorationLine {
+    pub(crate) underline: bool,
+    pub(crate) overline: bool,
+    pub(crate) line_through: bool,
+}
+
+impl TextDecorationLine {
+    pub(crate) fn is_none(&self) -> bool {
+        !(self.underline || self.overline || self.line_through)
+    }
+}
+
+impl TryFrom<String> for TextDecorationLine {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut line = Self::default();
+        for keyword in value.split_whitespace() {
+            match keyword {
+                "none" => {}
+                "underline" => line.underline = true,
+                "overline" => line.overline = true,
+                "line-through" => line.line_through = true,
+                _ => return Err(format!("unknown text-decoration-line \"{}\"", keyword)),
+            }
+        }
+        Ok(line)
+    }
+}
+
+impl From<TextDecorationLine> for String {
+    fn from(value: TextDecorationLine) -> Self {
+        let keywords = [
+            (value.underline, "underline"),
+            (value.overline, "overline"),
+            (value.line_through, "line-through"),
+        ];
+        let keywords: Vec<&str> = keywords
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, keyword)| *keyword)
+            .collect();
+        match keywords.is_empty() {
+            true => String::from("none"),
+            false => keywords.join(" "),
+        }
+    }
+}
+
+/// Style of decoration lines, as the CSS `text-decoration-style` property
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum TextDecorationStyle {
+    #[default]
+    Solid,
+    Double,
+    Dotted,
+    Dashed,
+    Wavy,
+}
+
+/// The box a gradient `color` stretches over: the glyphs of each run, or all glyphs of the frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum GradientBox {
+    #[default]
+    Run,
+    Frame,
+}
+
+/// Shape of stroke corners, as the SVG `stroke-linejoin` property
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum StrokeLineJoin {
+    #[default]
+    Miter,
+    Round,
+    Bevel,
+}
+
+/// Whether text is filled before it is stroked, as the SVG `paint-order` property. Painting the
+/// stroke first leaves only its outer half visible.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PaintOrder {
+    #[default]
+    #[serde(alias = "fill", alias = "fill stroke")]
+    Normal,
+    #[serde(alias = "stroke fill")]
+    Stroke,
+}
+
+/// The CSS `mix-blend-mode` property
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum MixBlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+impl MixBlendMode {
+    /// The keyword of the mode in CSS and SVG
+    pub(crate) fn css_name(self) -> &'static str {
+        match self {
+            MixBlendMode::Normal => "normal",
+            MixBlendMode::Multiply => "multiply",
+            MixBlendMode::Screen => "screen",
+            MixBlendMode::Overlay => "overlay",
+            MixBlendMode::Darken => "darken",
+            MixBlendMode::Lighten => "lighten",
+            MixBlendMode::ColorDodge => "color-dodge",
+            MixBlendMode::ColorBurn => "color-burn",
+            MixBlendMode::HardLight => "hard-light",
+            MixBlendMode::SoftLight => "soft-light",
+            MixBlendMode::Difference => "difference",
+            MixBlendMode::Exclusion => "exclusion",
+            MixBlendMode::Hue => "hue",
+            MixBlendMode::Saturation => "saturation",
+            MixBlendMode::Color => "color",
+            MixBlendMode::Luminosity => "luminosity",
+        }
+    }
+}
+
+#[cfg(windows)]
 /// Convert a string to DW tag. Note that DW uses little endian.
 pub(crate) fn string_to_tag(tag_str: &str) -> u32 {
     let mut len: usize = 0;
@@ -264,3 +969,62 @@ pub(crate) fn string_to_tag(tag_str: &str) -> u32 {
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_error(json: &str) -> String {
+        serde_json::from_str::<DocumentContent>(json)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn content_reports_why_it_does_not_parse() {
+        assert!(content_error(r#"{"image": "a.png", "height": 10}"#).contains("`width`"));
+        assert!(content_error(r#"{"svg": 1, "width": 10, "height": 10}"#).contains("string"));
+        assert!(content_error(r#"{"text-shadow": "1 2"}"#).contains("unknown text-shadow"));
+        assert!(content_error(r#"["text", {"opacity": "half"}]"#).contains("half"));
+        assert!(content_error("1").contains("expected text, a style"));
+    }
+
+    #[test]
+    fn family_lists_split_at_commas_outside_quotes() {
+        for (css, families) in [
+            ("Fira Mono", vec!["Fira Mono"]),
+            (
+                "\"Fira Mono\", 'Noto Sans, Bold', serif",
+                vec!["Fira Mono", "Noto Sans, Bold", "serif"],
+            ),
+            ("\"It's Sans\",x", vec!["It's Sans", "x"]),
+            (" a ,, b ,", vec!["a", "b"]),
+            ("''", vec![]),
+            ("", vec![]),
+        ] {
+            assert_eq!(parse_font_family_list(css), families, "{css:?}");
+        }
+        let list: FontFamilyList = serde_json::from_str(r#"["A, B", " C"]"#).unwrap();
+        assert_eq!(list.0, ["A, B", " C"]);
+    }
+
+    #[test]
+    fn objects_with_an_image_or_svg_are_inline_objects() {
+        let content: DocumentContent =
+            serde_json::from_str(r#"["a", {"svg": "<g/>", "width": 1, "height": 2}, {}]"#).unwrap();
+        let DocumentContent::Embed(items) = content else {
+            panic!("{content:?}");
+        };
+        assert!(matches!(items[0], DocumentContent::Text(_)));
+        assert!(matches!(items[1], DocumentContent::Object(_)));
+        assert!(matches!(items[2], DocumentContent::Style(_)));
+    }
+
+    #[test]
+    fn blend_mode_names_are_the_json_names() {
+        for name in ["normal", "multiply", "color-dodge", "hard-light", "luminosity"] {
+            let mode: MixBlendMode = serde_json::from_value(serde_json::json!(name)).unwrap();
+            assert_eq!(mode.css_name(), name);
+        }
+    }
+}
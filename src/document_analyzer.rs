@@ -1,15 +1,26 @@
+#[cfg(windows)]
 use windows::{
     core::{IUnknown, Interface, Result, HSTRING, PCWSTR},
-    Win32::Graphics::DirectWrite::*,
+    Win32::{Foundation::BOOL, Graphics::DirectWrite::*},
 };
 
+use crate::document::{DocumentContent, DocumentFrame, HAlign, InlineObject, TextStyle, VAlign};
+#[cfg(windows)]
 use crate::{
     document::{
-        string_to_tag, DocumentContent, DocumentFrame, FontVariationValue, HAlign, TextStyle,
-        VAlign,
+        string_to_tag, FontFamilyList, FontVariationValue, LineBreak, TextOrientation, WordBreak,
     },
+    font_fallback::script_ranges,
+    glyph_run::{Background, DrawingEffect, TextBackground},
+    inline_object::InlineObjectImpl,
     svg_color::{ISvgColor, SvgColorImpl},
+    text_spacing::{is_word_separator, TextSpacing},
 };
+#[cfg(windows)]
+use std::collections::BTreeMap;
+
+/// Stands for an inline object in the text
+pub(crate) const OBJECT_REPLACEMENT_CHARACTER: char = '\u{fffc}';
 
 #[derive(Debug)]
 pub(crate) struct DocumentAnalyzer {
@@ -17,6 +28,7 @@ pub(crate) struct DocumentAnalyzer {
     text: Vec<u16>,
     style_runs: Vec<StyleRun>,
     style_stack: Vec<TextStyle>,
+    object_runs: Vec<ObjectRun>,
 }
 
 impl DocumentAnalyzer {
@@ -25,6 +37,7 @@ impl DocumentAnalyzer {
             text: Vec::new(),
             style_runs: Vec::new(),
             style_stack: Vec::new(),
+            object_runs: Vec::new(),
         }
     }
     fn sync_style_run_length(&mut self) {
@@ -46,6 +59,14 @@ impl DocumentAnalyzer {
                 self.text.extend(ws);
                 self.sync_style_run_length()
             }
+            DocumentContent::Object(object) => {
+                self.object_runs.push(ObjectRun {
+                    wch_position: self.text.len(),
+                    object: object.clone(),
+                });
+                self.text.push(OBJECT_REPLACEMENT_CHARACTER as u16);
+                self.sync_style_run_length()
+            }
             DocumentContent::Style(s) => {
                 if let Some(style) = self.style_stack.last_mut() {
                     style.merge(s);
@@ -69,6 +90,49 @@ impl DocumentAnalyzer {
         }
     }
 
+    pub(crate) fn text(&self) -> &[u16] {
+        &self.text
+    }
+    pub(crate) fn style_runs(&self) -> &[StyleRun] {
+        &self.style_runs
+    }
+    pub(crate) fn object_runs(&self) -> &[ObjectRun] {
+        &self.object_runs
+    }
+
+    pub(crate) fn compute_layout_offset(
+        canvas_width: f32,
+        canvas_height: f32,
+        frame: &DocumentFrame,
+        bounds: &LayoutBounds,
+    ) -> (f32, f32) {
+        let factor_h = match frame.horizontal_align {
+            HAlign::Left => 0.0,
+            HAlign::Center => 0.5,
+            HAlign::Right => 1.0,
+        };
+        let factor_v = match frame.vertical_align {
+            VAlign::Top => 0.0,
+            VAlign::Center => 0.5,
+            VAlign::Bottom => 1.0,
+        };
+        let frame_left = frame.left.unwrap_or(0.0);
+        let frame_top = frame.top.unwrap_or(0.0);
+        let frame_right = frame.right.unwrap_or(canvas_width);
+        let frame_bottom = frame.bottom.unwrap_or(canvas_height);
+
+        let tm_cx = bounds.left + factor_h * bounds.width;
+        let tm_cy = bounds.top + factor_v * bounds.height;
+
+        let frame_cx = frame_left + factor_h * (frame_right - frame_left);
+        let frame_cy = frame_top + factor_v * (frame_bottom - frame_top);
+
+        (frame_cx - tm_cx, frame_cy - tm_cy)
+    }
+}
+
+#[cfg(windows)]
+impl DocumentAnalyzer {
     pub(crate) fn create_text_layout(
         &self,
         factory: IDWriteFactory,
@@ -76,6 +140,7 @@ impl DocumentAnalyzer {
         canvas_width: f32,
         canvas_height: f32,
         frame: &DocumentFrame,
+        fallback: &BTreeMap<String, FontFamilyList>,
     ) -> Result<IDWriteTextLayout1> {
         let layout = unsafe {
             factory.CreateTextLayout(
@@ -100,6 +165,51 @@ impl DocumentAnalyzer {
                 frame.line_height * frame.baseline_offset,
             )?
         }
+        // DirectWrite cannot tailor its line breaking; breaking between any characters is the
+        // closest it has to `line-break: anywhere` and `word-break: break-all`
+        if matches!(frame.line_break, LineBreak::Anywhere)
+            || matches!(frame.word_break, WordBreak::BreakAll)
+        {
+            unsafe { layout.SetWordWrapping(DWRITE_WORD_WRAPPING_CHARACTER)? }
+        }
+        let line_break = match frame.line_break {
+            LineBreak::Loose => Some("loose"),
+            LineBreak::Normal => Some("normal"),
+            LineBreak::Strict => Some("strict"),
+            LineBreak::Auto | LineBreak::Anywhere => None,
+        };
+        if let Some(line_break) = line_break {
+            eprintln!(
+                "warning: DirectWrite does not support \"line-break: {line_break}\", using \"auto\""
+            );
+        }
+        if matches!(frame.word_break, WordBreak::KeepAll) {
+            eprintln!(
+                "warning: DirectWrite does not support \"word-break: keep-all\", using \"normal\""
+            );
+        }
+        // Text on a path only breaks at hard line breaks
+        if frame.text_path.is_some() {
+            unsafe { layout.SetWordWrapping(DWRITE_WORD_WRAPPING_NO_WRAP)? }
+        }
+        // Set font fallback
+        let collection = unsafe { format.GetFontCollection()? };
+        let font_fallback = self.create_font_fallback(&factory, &collection, fallback)?;
+        let layout2: IDWriteTextLayout2 = layout.cast()?;
+        unsafe { layout2.SetFontFallback(&font_fallback)? }
+        // Set glyph orientation in vertical lines
+        let vertical_orientation = match frame.text_orientation {
+            TextOrientation::Mixed => DWRITE_VERTICAL_GLYPH_ORIENTATION_DEFAULT,
+            TextOrientation::Upright => DWRITE_VERTICAL_GLYPH_ORIENTATION_STACKED,
+            TextOrientation::Sideways => {
+                eprintln!(
+                    "warning: DirectWrite does not support \"text-orientation: sideways\", \
+                     using \"mixed\""
+                );
+                DWRITE_VERTICAL_GLYPH_ORIENTATION_DEFAULT
+            }
+        };
+        unsafe { layout2.SetVerticalGlyphOrientation(vertical_orientation)? }
 
         // Set text styles
         for style_run in self.style_runs.iter() {
@@ -112,7 +222,7 @@ impl DocumentAnalyzer {
                 length: (style_run.wch_end - style_run.wch_start) as u32,
             };
             // Apply styles to the layout
-            if let Some(family_name) = &style.font_family {
+            if let Some(family_name) = style.font_family.as_ref().and_then(|f| f.first()) {
                 unsafe {
                     layout.SetFontFamilyName(
                         PCWSTR(HSTRING::from(family_name).as_ptr()),
@@ -132,23 +242,44 @@ impl DocumentAnalyzer {
             if let Some(font_size) = &style.font_size {
                 unsafe { layout.SetFontSize(*font_size, range.clone())? }
             }
-            if let Some(color) = &style.color {
-                let c = csscolorparser::parse(color).unwrap_or_default();
-                let brush: ISvgColor = SvgColorImpl::new(c).into();
+            if let Some(effect) = DrawingEffect::from_style(style) {
+                let brush: ISvgColor = SvgColorImpl::new(effect).into();
                 let brush: IUnknown = brush.cast()?;
                 unsafe { layout.SetDrawingEffect(&brush, range.clone())? }
             }
+            if let Some(line) = style.text_decoration_line {
+                // DirectWrite has no overlines; the renderer draws them along with underlines
+                let underline = line.underline || line.overline;
+                unsafe { layout.SetUnderline(BOOL::from(underline), range.clone())? }
+                unsafe { layout.SetStrikethrough(BOOL::from(line.line_through), range.clone())? }
+            }
             if let Some(lang) = &style.lang {
                 unsafe {
                     layout.SetLocaleName(PCWSTR(HSTRING::from(lang).as_ptr()), range.clone())?
                 }
             }
-            if !style.font_feature_settings.is_empty() {
+            let font_size = style
+                .font_size
+                .unwrap_or_else(|| unsafe { format.GetFontSize() });
+            let spacing = TextSpacing::from_style(style, font_size);
+            if let Some(spacing) = spacing {
+                self.set_spacing(&layout, style_run, spacing)?;
+            }
+            let disabled: Vec<&str> = spacing
+                .iter()
+                .flat_map(|spacing| spacing.disabled_features(style))
+                .collect();
+            if !style.font_feature_settings.is_empty() || !disabled.is_empty() {
                 let typography = unsafe { factory.CreateTypography()? };
-                for (feature, parameter) in style.font_feature_settings.iter() {
+                let features = style
+                    .font_feature_settings
+                    .iter()
+                    .map(|(feature, parameter)| (feature.as_str(), *parameter))
+                    .chain(disabled.into_iter().map(|feature| (feature, 0)));
+                for (feature, parameter) in features {
                     let feature = DWRITE_FONT_FEATURE {
                         nameTag: DWRITE_FONT_FEATURE_TAG(string_to_tag(feature)),
-                        parameter: *parameter,
+                        parameter,
                     };
                     unsafe { typography.AddFontFeature(feature)? }
                 }
@@ -167,48 +298,246 @@ impl DocumentAnalyzer {
                         _ => {}
                     }
                 }
-                if let Ok(layout4) = layout.cast::<IDWriteTextLayout4>() {
-                    unsafe { layout4.SetFontAxisValues(&axis_values, range.clone())? }
+                match layout.cast::<IDWriteTextLayout4>() {
+                    Ok(layout4) => unsafe {
+                        layout4.SetFontAxisValues(&axis_values, range.clone())?
+                    },
+                    Err(_) => eprintln!(
+                        "warning: font-variation-settings needs IDWriteTextLayout4, which this \
+                         version of DirectWrite does not provide"
+                    ),
                 }
             }
         }
+        for object_run in self.object_runs.iter() {
+            let object: IDWriteInlineObject =
+                InlineObjectImpl::new(object_run.object.clone()).into();
+            let range = DWRITE_TEXT_RANGE {
+                startPosition: object_run.wch_position as u32,
+                length: 1,
+            };
+            unsafe { layout.SetInlineObject(&object, range)? }
+        }
         Ok(layout)
     }
 
-    pub(crate) fn compute_layout_offset(
-        canvas_width: f32,
-        canvas_height: f32,
-        frame: &DocumentFrame,
-        metrics: &DWRITE_TEXT_METRICS,
-    ) -> (f32, f32) {
-        let factor_h = match frame.horizontal_align {
-            HAlign::Left => 0.0,
-            HAlign::Center => 0.5,
-            HAlign::Right => 1.0,
-        };
-        let factor_v = match frame.vertical_align {
-            VAlign::Top => 0.0,
-            VAlign::Center => 0.5,
-            VAlign::Bottom => 1.0,
+    /// Trailing spacing of every character of a style run. DirectWrite has no word spacing, so
+    /// word separators get theirs as character spacing of their own.
+    fn set_spacing(
+        &self,
+        layout: &IDWriteTextLayout1,
+        style_run: &StyleRun,
+        spacing: TextSpacing,
+    ) -> Result<()> {
+        let range = DWRITE_TEXT_RANGE {
+            startPosition: style_run.wch_start as u32,
+            length: (style_run.wch_end - style_run.wch_start) as u32,
         };
-        let frame_left = frame.left.unwrap_or(0.0);
-        let frame_top = frame.top.unwrap_or(0.0);
-        let frame_right = frame.right.unwrap_or(canvas_width);
-        let frame_bottom = frame.bottom.unwrap_or(canvas_height);
+        unsafe { layout.SetCharacterSpacing(0.0, spacing.letter, 0.0, range)? }
+        if spacing.word == 0.0 {
+            return Ok(());
+        }
+        let text = &self.text[style_run.wch_start..style_run.wch_end];
+        let mut position = style_run.wch_start;
+        for ch in char::decode_utf16(text.iter().copied()) {
+            let ch = ch.unwrap_or(char::REPLACEMENT_CHARACTER);
+            if is_word_separator(ch) {
+                let range = DWRITE_TEXT_RANGE {
+                    startPosition: position as u32,
+                    length: ch.len_utf16() as u32,
+                };
+                unsafe { layout.SetCharacterSpacing(0.0, spacing.after(ch), 0.0, range)? }
+            }
+            position += ch.len_utf16();
+        }
+        Ok(())
+    }
 
-        let tm_cx = metrics.left + factor_h * metrics.width;
-        let tm_cy = metrics.top + factor_v * metrics.height;
+    /// Rectangles behind the text of styles with a background color, hit-testing what they cover
+    /// of each line without its trailing whitespace. Neighbouring style runs with the same
+    /// background are tested together, so that nested styles do not split the rectangle.
+    pub(crate) fn text_backgrounds(&self, layout: &IDWriteTextLayout1) -> Result<Vec<Background>> {
+        let mut ranges: Vec<(usize, usize, TextBackground)> = Vec::new();
+        for style_run in self.style_runs.iter() {
+            if style_run.wch_end <= style_run.wch_start {
+                continue;
+            }
+            let Some(background) = TextBackground::from_style(&style_run.style) else {
+                continue;
+            };
+            match ranges.last_mut() {
+                Some((_, end, last)) if *end == style_run.wch_start && *last == background => {
+                    *end = style_run.wch_end
+                }
+                _ => ranges.push((style_run.wch_start, style_run.wch_end, background)),
+            }
+        }
+        if ranges.is_empty() {
+            return Ok(Vec::new());
+        }
 
-        let frame_cx = frame_left + factor_h * (frame_right - frame_left);
-        let frame_cy = frame_top + factor_v * (frame_bottom - frame_top);
+        // The first calls only report how many items there are, failing for want of a buffer
+        let mut line_count = 0;
+        let _ = unsafe { layout.GetLineMetrics(None, &mut line_count) };
+        let mut line_metrics = vec![DWRITE_LINE_METRICS::default(); line_count as usize];
+        unsafe { layout.GetLineMetrics(Some(&mut line_metrics), &mut line_count)? }
+        let mut lines = Vec::new();
+        let mut line_start = 0;
+        for metrics in line_metrics.iter() {
+            let visible = (metrics.length - metrics.trailingWhitespaceLength) as usize;
+            lines.push((line_start, line_start + visible));
+            line_start += metrics.length as usize;
+        }
 
-        (frame_cx - tm_cx, frame_cy - tm_cy)
+        let mut backgrounds = Vec::new();
+        for (start, end, background) in ranges.iter() {
+            for (line_start, line_end) in lines.iter() {
+                let start = (*start).max(*line_start) as u32;
+                let end = (*end).min(*line_end) as u32;
+                if end <= start {
+                    continue;
+                }
+                let mut count = 0;
+                let _ = unsafe {
+                    layout.HitTestTextRange(start, end - start, 0.0, 0.0, None, &mut count)
+                };
+                let mut hit_tests = vec![DWRITE_HIT_TEST_METRICS::default(); count as usize];
+                unsafe {
+                    layout.HitTestTextRange(
+                        start,
+                        end - start,
+                        0.0,
+                        0.0,
+                        Some(&mut hit_tests),
+                        &mut count,
+                    )?
+                }
+                backgrounds.extend(hit_tests.iter().map(|hit_test| {
+                    background.around(hit_test.left, hit_test.top, hit_test.width, hit_test.height)
+                }));
+            }
+        }
+        Ok(backgrounds)
     }
+
+    /// Font fallback that walks the `font-family` list of each run, then the document `fallback`
+    /// map (locales before scripts, longest locale first), then the system fallback
+    fn create_font_fallback(
+        &self,
+        factory: &IDWriteFactory,
+        collection: &IDWriteFontCollection,
+        fallback: &BTreeMap<String, FontFamilyList>,
+    ) -> Result<IDWriteFontFallback> {
+        let factory2: IDWriteFactory2 = factory.cast()?;
+        let builder = unsafe { factory2.CreateFontFallbackBuilder()? };
+        let everything = [DWRITE_UNICODE_RANGE {
+            first: 0,
+            last: 0x10ffff,
+        }];
+
+        let mut chains: Vec<&FontFamilyList> = Vec::new();
+        for style_run in self.style_runs.iter() {
+            if let Some(list) = &style_run.style.font_family {
+                if list.0.len() > 1 && !chains.contains(&list) {
+                    chains.push(list);
+                }
+            }
+        }
+        for chain in chains {
+            let base = HSTRING::from(&chain.0[0]);
+            let targets: Vec<HSTRING> = chain.0[1..].iter().map(HSTRING::from).collect();
+            let targets: Vec<*const u16> = targets.iter().map(|t| t.as_ptr()).collect();
+            unsafe {
+                builder.AddMapping(
+                    &everything,
+                    &targets,
+                    collection,
+                    PCWSTR::null(),
+                    PCWSTR(base.as_ptr()),
+                    1.0,
+                )?
+            }
+        }
+
+        let mut keys: Vec<(&String, Option<Vec<(u32, u32)>>)> = fallback
+            .keys()
+            .map(|key| (key, script_ranges(key)))
+            .collect();
+        keys.sort_by_key(|(key, ranges)| (ranges.is_some(), std::cmp::Reverse(key.len())));
+        for (key, ranges) in keys {
+            let targets: Vec<HSTRING> = fallback[key].0.iter().map(HSTRING::from).collect();
+            let targets: Vec<*const u16> = targets.iter().map(|t| t.as_ptr()).collect();
+            match ranges {
+                Some(ranges) => {
+                    let ranges: Vec<DWRITE_UNICODE_RANGE> = ranges
+                        .into_iter()
+                        .map(|(first, last)| DWRITE_UNICODE_RANGE { first, last })
+                        .collect();
+                    unsafe {
+                        builder.AddMapping(
+                            &ranges,
+                            &targets,
+                            collection,
+                            PCWSTR::null(),
+                            PCWSTR::null(),
+                            1.0,
+                        )?
+                    }
+                }
+                None => {
+                    let locale = HSTRING::from(key);
+                    unsafe {
+                        builder.AddMapping(
+                            &everything,
+                            &targets,
+                            collection,
+                            PCWSTR(locale.as_ptr()),
+                            PCWSTR::null(),
+                            1.0,
+                        )?
+                    }
+                }
+            }
+        }
+
+        unsafe {
+            builder.AddMappings(&factory2.GetSystemFontFallback()?)?;
+            builder.CreateFontFallback()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct StyleRun {
+    pub(crate) wch_start: usize,
+    pub(crate) wch_end: usize,
+    pub(crate) style: TextStyle,
 }
 
+/// An inline object and the position of the object replacement character standing for it
 #[derive(Debug)]
-struct StyleRun {
-    wch_start: usize,
-    wch_end: usize,
-    style: TextStyle,
+pub(crate) struct ObjectRun {
+    pub(crate) wch_position: usize,
+    pub(crate) object: InlineObject,
+}
+
+/// Bounding box of the formatted text, relative to the layout box.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct LayoutBounds {
+    pub(crate) left: f32,
+    pub(crate) top: f32,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+}
+
+#[cfg(windows)]
+impl From<&DWRITE_TEXT_METRICS> for LayoutBounds {
+    fn from(metrics: &DWRITE_TEXT_METRICS) -> Self {
+        Self {
+            left: metrics.left,
+            top: metrics.top,
+            width: metrics.width,
+            height: metrics.height,
+        }
+    }
 }
@@ -0,0 +1,618 @@
+use core::ffi::c_void;
+use std::{cell::RefCell, ffi::OsString, fs, os::windows::ffi::OsStringExt, path::PathBuf, rc::Rc};
+use windows::{
+    core::{AsImpl, IUnknown, Interface, Result},
+    Win32::Foundation::BOOL,
+    Win32::Graphics::{Direct2D::Common::*, DirectWrite::*},
+};
+
+use crate::{
+    color_glyph::{color_glyph, ColorGlyph},
+    document::FontPalette,
+    glyph_image::{glyph_image, GlyphImage},
+    glyph_outline,
+    glyph_run::{
+        Decoration, DecorationKind, DrawingEffect, FontId, GlyphOffset, GlyphOrientation,
+        GlyphOutlineSource, GlyphRun, PlacedObject,
+    },
+    inline_object::InlineObjectImpl,
+    path::{PathBuilder, PathSegment},
+    svg_color::{ISvgColor, SvgColorImpl},
+};
+
+/// The file behind a face, with the axis values DirectWrite instanced it with
+struct LocalFontFile {
+    path: PathBuf,
+    data: Vec<u8>,
+    index: u32,
+    coordinates: Vec<(ttf_parser::Tag, f32)>,
+}
+
+/// A font face DirectWrite reported runs against, with the data of its file when it is local
+struct DWriteFace {
+    face: IDWriteFontFace,
+    file: Option<LocalFontFile>,
+}
+
+/// The font faces DirectWrite reported runs against, indexed by `FontId`
+pub(crate) struct DWriteFontTable {
+    faces: RefCell<Vec<DWriteFace>>,
+}
+
+impl DWriteFontTable {
+    pub(crate) fn new() -> Self {
+        Self {
+            faces: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn register(&self, font_face: &IDWriteFontFace) -> FontId {
+        let mut faces = self.faces.borrow_mut();
+        match faces.iter().position(|entry| &entry.face == font_face) {
+            Some(i) => FontId(i),
+            None => {
+                faces.push(DWriteFace {
+                    face: font_face.clone(),
+                    file: local_font_file(font_face),
+                });
+                FontId(faces.len() - 1)
+            }
+        }
+    }
+
+    fn face(&self, font: FontId) -> IDWriteFontFace {
+        self.faces.borrow()[font.0].face.clone()
+    }
+
+    /// Run `f` on the face parsed from its local file, with the axis values of the instance
+    fn with_file_face<T>(&self, font: FontId, f: impl FnOnce(&ttf_parser::Face) -> T) -> Option<T> {
+        let faces = self.faces.borrow();
+        let file = faces[font.0].file.as_ref()?;
+        let mut face = ttf_parser::Face::parse(&file.data, file.index).ok()?;
+        for &(tag, value) in file.coordinates.iter() {
+            face.set_variation(tag, value);
+        }
+        Some(f(&face))
+    }
+
+    /// Outline read from the font file, so that it matches the portable backend byte for byte
+    fn file_glyph_path(&self, font: FontId, glyph_id: u16) -> Option<Vec<PathSegment>> {
+        self.with_file_face(font, |face| {
+            glyph_outline::glyph_path(face, glyph_id, false)
+        })
+    }
+
+    fn outline_glyph_path(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+    ) -> crate::error::Result<Vec<PathSegment>> {
+        // With the em size set to the UPM, one DIP is one design unit
+        let em_size = self.units_per_em(font);
+        let geometry_sink: ID2D1SimplifiedGeometrySink = OutlineGeometrySink::new().into();
+        unsafe {
+            self.face(font).GetGlyphRunOutline(
+                em_size,
+                &glyph_id,
+                None,
+                None,
+                1,
+                is_sideways,
+                false,
+                &geometry_sink,
+            )?;
+        }
+        let sink: &OutlineGeometrySink = unsafe { geometry_sink.as_impl() };
+        Ok(sink.reset())
+    }
+}
+
+impl GlyphOutlineSource for DWriteFontTable {
+    fn units_per_em(&self, font: FontId) -> f32 {
+        let mut metrics = DWRITE_FONT_METRICS::default();
+        unsafe { self.face(font).GetMetrics(&mut metrics) }
+        metrics.designUnitsPerEm as f32
+    }
+
+    fn glyph_path(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+    ) -> crate::error::Result<Vec<PathSegment>> {
+        if !is_sideways {
+            if let Some(path) = self.file_glyph_path(font, glyph_id) {
+                return Ok(path);
+            }
+        }
+        self.outline_glyph_path(font, glyph_id, is_sideways)
+    }
+
+    fn font_file(&self, font: FontId) -> Option<(PathBuf, u32)> {
+        let faces = self.faces.borrow();
+        let file = faces[font.0].file.as_ref()?;
+        Some((file.path.clone(), file.index))
+    }
+
+    /// Color layers come from the font file, as DirectWrite only translates them for drawing
+    fn color_glyph(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+        palette: Option<&FontPalette>,
+        foreground: &csscolorparser::Color,
+    ) -> Option<ColorGlyph> {
+        self.with_file_face(font, |face| {
+            color_glyph(face, glyph_id, is_sideways, palette, foreground)
+        })
+        .flatten()
+    }
+
+    fn glyph_image(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+        pixels_per_em: u16,
+    ) -> Option<GlyphImage> {
+        self.with_file_face(font, |face| {
+            glyph_image(face, glyph_id, is_sideways, pixels_per_em)
+        })
+        .flatten()
+    }
+}
+
+/// The file behind a face, when it is a local file and the face has no simulations that only
+/// DirectWrite can apply
+fn local_font_file(font_face: &IDWriteFontFace) -> Option<LocalFontFile> {
+    unsafe {
+        if font_face.GetSimulations() != DWRITE_FONT_SIMULATIONS_NONE {
+            return None;
+        }
+        let mut num_of_files: u32 = 1;
+        let mut font_file: Option<IDWriteFontFile> = None;
+        font_face
+            .GetFiles(&mut num_of_files, Some(&mut font_file))
+            .ok()?;
+        let font_file = font_file?;
+
+        let mut key: *mut c_void = std::ptr::null_mut();
+        let mut key_size: u32 = 0;
+        font_file.GetReferenceKey(&mut key, &mut key_size).ok()?;
+        let loader: IDWriteLocalFontFileLoader = font_file.GetLoader().ok()?.cast().ok()?;
+        let length = loader.GetFilePathLengthFromKey(key, key_size).ok()?;
+        let mut path = vec![0u16; length as usize + 1];
+        loader.GetFilePathFromKey(key, key_size, &mut path).ok()?;
+        path.truncate(length as usize);
+
+        let path = PathBuf::from(OsString::from_wide(&path));
+        let data = fs::read(&path).ok()?;
+        Some(LocalFontFile {
+            path,
+            data,
+            index: font_face.GetIndex(),
+            coordinates: axis_values(font_face),
+        })
+    }
+}
+
+/// Axis values of a variable font instance, converted from DirectWrite's little-endian tags
+fn axis_values(font_face: &IDWriteFontFace) -> Vec<(ttf_parser::Tag, f32)> {
+    let Ok(face5) = font_face.cast::<IDWriteFontFace5>() else {
+        return Vec::new();
+    };
+    unsafe {
+        if !face5.HasVariations().as_bool() {
+            return Vec::new();
+        }
+        let mut values =
+            vec![DWRITE_FONT_AXIS_VALUE::default(); face5.GetFontAxisValueCount() as usize];
+        if face5.GetFontAxisValues(&mut values).is_err() {
+            return Vec::new();
+        }
+        values
+            .iter()
+            .map(|v| {
+                (
+                    ttf_parser::Tag::from_bytes(&v.axisTag.0.to_le_bytes()),
+                    v.value,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Text renderer that records what `IDWriteTextLayout::Draw` produces as `GlyphRun`s
+#[windows::core::implement(IDWriteTextRenderer1)]
+pub(crate) struct GlyphRunCollector {
+    fonts: Rc<DWriteFontTable>,
+    runs: Rc<RefCell<Vec<GlyphRun>>>,
+    decorations: Rc<RefCell<Vec<Decoration>>>,
+    objects: Rc<RefCell<Vec<PlacedObject>>>,
+}
+
+impl GlyphRunCollector {
+    pub(crate) fn new(
+        fonts: Rc<DWriteFontTable>,
+        runs: Rc<RefCell<Vec<GlyphRun>>>,
+        decorations: Rc<RefCell<Vec<Decoration>>>,
+        objects: Rc<RefCell<Vec<PlacedObject>>>,
+    ) -> Self {
+        Self {
+            fonts,
+            runs,
+            decorations,
+            objects,
+        }
+    }
+
+    fn get_drawing_effect(&self, brush: Option<&IUnknown>) -> Option<DrawingEffect> {
+        let brush = brush?.cast::<ISvgColor>().ok()?;
+        let brush: &SvgColorImpl = unsafe { brush.as_impl() };
+        Some(brush.drawing_effect())
+    }
+
+    /// Record the lines of a DirectWrite underline or strikethrough that the style of its range
+    /// asks for, each with its offset. The thickness comes from the font unless the style sets it.
+    fn add_decorations(
+        &self,
+        (origin_x, origin_y): (f32, f32),
+        orientation_angle: &DWRITE_GLYPH_ORIENTATION_ANGLE,
+        width: f32,
+        thickness: f32,
+        lines: &[(DecorationKind, f32)],
+        effect: &DrawingEffect,
+    ) {
+        let Some(decoration) = effect.decoration.as_ref() else {
+            return;
+        };
+        let mut decorations = self.decorations.borrow_mut();
+        for &(kind, offset) in lines {
+            decorations.push(Decoration {
+                kind,
+                origin_x,
+                origin_y,
+                orientation: dw_orientation(orientation_angle),
+                width,
+                offset,
+                thickness: decoration.thickness.unwrap_or(thickness),
+                style: decoration.style,
+                color: effect.decoration_color(),
+            });
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+impl IDWritePixelSnapping_Impl for GlyphRunCollector_Impl {
+    fn IsPixelSnappingDisabled(&self, _client_drawing_context: *const c_void) -> Result<BOOL> {
+        Ok(false.into())
+    }
+    fn GetCurrentTransform(
+        &self,
+        _client_drawing_context: *const core::ffi::c_void,
+        transform: *mut DWRITE_MATRIX,
+    ) -> windows::core::Result<()> {
+        unsafe {
+            *transform = DWRITE_MATRIX {
+                m11: 1.0,
+                m12: 1.0,
+                m21: 1.0,
+                m22: 1.0,
+                dx: 0.0,
+                dy: 0.0,
+            };
+        }
+        Ok(())
+    }
+    fn GetPixelsPerDip(&self, _client_drawing_context: *const c_void) -> Result<f32> {
+        Ok(1.0)
+    }
+}
+
+#[allow(non_snake_case)]
+impl IDWriteTextRenderer_Impl for GlyphRunCollector_Impl {
+    fn DrawGlyphRun(
+        &self,
+        client_drawing_context: *const c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        measuring_mode: DWRITE_MEASURING_MODE,
+        glyph_run: *const DWRITE_GLYPH_RUN,
+        glyph_run_description: *const DWRITE_GLYPH_RUN_DESCRIPTION,
+        client_drawing_effect: Option<&IUnknown>,
+    ) -> Result<()> {
+        IDWriteTextRenderer1_Impl::DrawGlyphRun(
+            self,
+            client_drawing_context,
+            baseline_origin_x,
+            baseline_origin_y,
+            DWRITE_GLYPH_ORIENTATION_ANGLE_0_DEGREES,
+            measuring_mode,
+            glyph_run,
+            glyph_run_description,
+            client_drawing_effect,
+        )
+    }
+
+    fn DrawInlineObject(
+        &self,
+        client_drawing_context: *const c_void,
+        origin_x: f32,
+        origin_y: f32,
+        inline_object: Option<&IDWriteInlineObject>,
+        is_sideways: BOOL,
+        is_right_to_left: BOOL,
+        client_drawing_effect: Option<&IUnknown>,
+    ) -> Result<()> {
+        IDWriteTextRenderer1_Impl::DrawInlineObject(
+            self,
+            client_drawing_context,
+            origin_x,
+            origin_y,
+            DWRITE_GLYPH_ORIENTATION_ANGLE_0_DEGREES,
+            inline_object,
+            is_sideways,
+            is_right_to_left,
+            client_drawing_effect,
+        )
+    }
+
+    fn DrawUnderline(
+        &self,
+        client_drawing_context: *const c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        underline: *const DWRITE_UNDERLINE,
+        client_drawing_effect: Option<&IUnknown>,
+    ) -> Result<()> {
+        IDWriteTextRenderer1_Impl::DrawUnderline(
+            self,
+            client_drawing_context,
+            baseline_origin_x,
+            baseline_origin_y,
+            DWRITE_GLYPH_ORIENTATION_ANGLE_0_DEGREES,
+            underline,
+            client_drawing_effect,
+        )
+    }
+
+    fn DrawStrikethrough(
+        &self,
+        client_drawing_context: *const c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        strike_through: *const DWRITE_STRIKETHROUGH,
+        client_drawing_effect: Option<&IUnknown>,
+    ) -> Result<()> {
+        IDWriteTextRenderer1_Impl::DrawStrikethrough(
+            self,
+            client_drawing_context,
+            baseline_origin_x,
+            baseline_origin_y,
+            DWRITE_GLYPH_ORIENTATION_ANGLE_0_DEGREES,
+            strike_through,
+            client_drawing_effect,
+        )
+    }
+}
+
+#[allow(non_snake_case)]
+impl IDWriteTextRenderer1_Impl for GlyphRunCollector_Impl {
+    fn DrawGlyphRun(
+        &self,
+        _client_drawing_context: *const c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        orientation_angle: DWRITE_GLYPH_ORIENTATION_ANGLE,
+        _measuring_mode: DWRITE_MEASURING_MODE,
+        glyph_run: *const DWRITE_GLYPH_RUN,
+        glyph_run_description: *const DWRITE_GLYPH_RUN_DESCRIPTION,
+        client_drawing_effect: Option<&IUnknown>,
+    ) -> Result<()> {
+        let glyph_run = unsafe { &*glyph_run };
+        let description = unsafe { &*glyph_run_description };
+        if let Some(font_face) = glyph_run.fontFace.as_ref() {
+            let glyph_count = glyph_run.glyphCount as usize;
+            let text_length = description.stringLength as usize;
+
+            let run = GlyphRun {
+                font: self.fonts.register(font_face),
+                font_size: glyph_run.fontEmSize,
+                baseline_origin_x,
+                baseline_origin_y,
+                glyph_ids: unsafe { slice_or_default(glyph_run.glyphIndices, glyph_count) },
+                advances: unsafe { slice_or_default(glyph_run.glyphAdvances, glyph_count) },
+                offsets: unsafe { slice_or_default(glyph_run.glyphOffsets, glyph_count) }
+                    .into_iter()
+                    .map(|offset| GlyphOffset {
+                        advance_offset: offset.advanceOffset,
+                        ascender_offset: offset.ascenderOffset,
+                    })
+                    .collect(),
+                orientation: dw_orientation(&orientation_angle),
+                is_sideways: glyph_run.isSideways.as_bool(),
+                path_angle: 0.0,
+                bidi_level: glyph_run.bidiLevel as u8,
+                text_position: description.textPosition as usize,
+                source_text: String::from_utf16_lossy(unsafe {
+                    &slice_or_default(description.string.0, text_length)
+                }),
+                cluster_map: unsafe { slice_or_default(description.clusterMap, text_length) },
+                drawing_effect: self.get_drawing_effect(client_drawing_effect),
+            };
+            self.runs.borrow_mut().push(run);
+        }
+
+        Ok(())
+    }
+
+    fn DrawInlineObject(
+        &self,
+        _client_drawing_context: *const c_void,
+        origin_x: f32,
+        origin_y: f32,
+        _orientation_angle: DWRITE_GLYPH_ORIENTATION_ANGLE,
+        inline_object: Option<&IDWriteInlineObject>,
+        _is_sideways: BOOL,
+        _is_right_to_left: BOOL,
+        _client_drawing_effect: Option<&IUnknown>,
+    ) -> Result<()> {
+        // Only objects set by the document analyzer are in the layout; they stay upright, with
+        // the origin at their top left corner
+        let Some(inline_object) = inline_object else {
+            return Ok(());
+        };
+        let inline_object: &InlineObjectImpl = unsafe { inline_object.as_impl() };
+        self.objects.borrow_mut().push(PlacedObject {
+            object: inline_object.object().clone(),
+            x: origin_x,
+            y: origin_y,
+        });
+        Ok(())
+    }
+
+    fn DrawUnderline(
+        &self,
+        _client_drawing_context: *const c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        orientation_angle: DWRITE_GLYPH_ORIENTATION_ANGLE,
+        underline: *const DWRITE_UNDERLINE,
+        client_drawing_effect: Option<&IUnknown>,
+    ) -> Result<()> {
+        let underline = unsafe { &*underline };
+        let Some(effect) = self.get_drawing_effect(client_drawing_effect) else {
+            return Ok(());
+        };
+        let Some(decoration) = effect.decoration.as_ref() else {
+            return Ok(());
+        };
+        let mut lines = Vec::new();
+        if decoration.line.underline {
+            let offset = decoration.underline_offset.unwrap_or(underline.offset);
+            lines.push((DecorationKind::Underline, offset));
+        }
+        if decoration.line.overline {
+            // DirectWrite has no overline metrics: go up from the underline by the run height
+            let offset = underline.offset - underline.runHeight;
+            lines.push((DecorationKind::Overline, offset));
+        }
+        self.add_decorations(
+            (baseline_origin_x, baseline_origin_y),
+            &orientation_angle,
+            underline.width,
+            underline.thickness,
+            &lines,
+            &effect,
+        );
+        Ok(())
+    }
+
+    fn DrawStrikethrough(
+        &self,
+        _client_drawing_context: *const c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        orientation_angle: DWRITE_GLYPH_ORIENTATION_ANGLE,
+        strike_through: *const DWRITE_STRIKETHROUGH,
+        client_drawing_effect: Option<&IUnknown>,
+    ) -> Result<()> {
+        let strike_through = unsafe { &*strike_through };
+        let Some(effect) = self.get_drawing_effect(client_drawing_effect) else {
+            return Ok(());
+        };
+        self.add_decorations(
+            (baseline_origin_x, baseline_origin_y),
+            &orientation_angle,
+            strike_through.width,
+            strike_through.thickness,
+            &[(DecorationKind::LineThrough, strike_through.offset)],
+            &effect,
+        );
+        Ok(())
+    }
+}
+
+/// Geometry sink that collects the segments of a glyph outline
+#[windows::core::implement(ID2D1SimplifiedGeometrySink)]
+pub(crate) struct OutlineGeometrySink {
+    builder: RefCell<PathBuilder>,
+}
+
+impl OutlineGeometrySink {
+    fn new() -> Self {
+        Self {
+            builder: RefCell::new(PathBuilder::new()),
+        }
+    }
+
+    fn reset(&self) -> Vec<PathSegment> {
+        self.builder.borrow_mut().reset()
+    }
+}
+
+#[allow(non_snake_case)]
+impl ID2D1SimplifiedGeometrySink_Impl for OutlineGeometrySink_Impl {
+    fn SetFillMode(&self, _fill_mode: D2D1_FILL_MODE) {}
+    fn SetSegmentFlags(&self, _flags: D2D1_PATH_SEGMENT) {}
+    fn BeginFigure(&self, start_point: &D2D_POINT_2F, _figure_begin: D2D1_FIGURE_BEGIN) {
+        self.builder
+            .borrow_mut()
+            .move_to(start_point.x, start_point.y);
+    }
+    fn AddLines(&self, points: *const D2D_POINT_2F, points_count: u32) {
+        let mut builder = self.builder.borrow_mut();
+        for i in 0..points_count {
+            unsafe {
+                let point = points.offset(i as isize);
+                builder.line_to((*point).x, (*point).y);
+            }
+        }
+    }
+    fn AddBeziers(&self, beziers: *const D2D1_BEZIER_SEGMENT, beziers_count: u32) {
+        let mut builder = self.builder.borrow_mut();
+        for i in 0..beziers_count {
+            unsafe {
+                let curve = beziers.offset(i as isize);
+                builder.cubic_to(
+                    (*curve).point1.x,
+                    (*curve).point1.y,
+                    (*curve).point2.x,
+                    (*curve).point2.y,
+                    (*curve).point3.x,
+                    (*curve).point3.y,
+                );
+            }
+        }
+    }
+    fn EndFigure(&self, figure_end: D2D1_FIGURE_END) {
+        if figure_end == D2D1_FIGURE_END_CLOSED {
+            self.builder.borrow_mut().close();
+        }
+    }
+    fn Close(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn dw_orientation(angle: &DWRITE_GLYPH_ORIENTATION_ANGLE) -> GlyphOrientation {
+    match angle {
+        &DWRITE_GLYPH_ORIENTATION_ANGLE_0_DEGREES => GlyphOrientation::Angle0,
+        &DWRITE_GLYPH_ORIENTATION_ANGLE_90_DEGREES => GlyphOrientation::Angle90,
+        &DWRITE_GLYPH_ORIENTATION_ANGLE_180_DEGREES => GlyphOrientation::Angle180,
+        &DWRITE_GLYPH_ORIENTATION_ANGLE_270_DEGREES => GlyphOrientation::Angle270,
+        _ => unreachable!(),
+    }
+}
+
+/// Copy a DirectWrite array, which may be absent
+unsafe fn slice_or_default<T: Clone + Default>(ptr: *const T, len: usize) -> Vec<T> {
+    if ptr.is_null() {
+        vec![T::default(); len]
+    } else {
+        std::slice::from_raw_parts(ptr, len).to_vec()
+    }
+}
@@ -1,11 +1,15 @@
 #[allow(unused)]
 #[derive(Debug)]
 pub enum AppError {
+    #[cfg(windows)]
     Windows(windows::core::Error),
     IO(std::io::Error),
     Json(serde_json::Error),
+    Font(String),
+    Render(String),
 }
 
+#[cfg(windows)]
 impl From<windows::core::Error> for AppError {
     fn from(value: windows::core::Error) -> Self {
         Self::Windows(value)
@@ -0,0 +1,648 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use glob::glob;
+use ttf_parser::{name_id, Face, Style, Tag};
+
+use crate::color_glyph::{color_glyph, ColorGlyph};
+use crate::document::{
+    Document, FontFamilyList, FontPalette, FontStyle, FontVariationValue, TextStyle,
+};
+use crate::error::{AppError, Result};
+use crate::glyph_image::{glyph_image, GlyphImage};
+use crate::glyph_outline::glyph_path;
+use crate::glyph_run::{FontId, GlyphOutlineSource};
+use crate::path::PathSegment;
+
+struct FontFile {
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+struct FaceEntry {
+    file: usize,
+    index: u32,
+    families: Vec<String>,
+    weight: u16,
+    stretch: u16,
+    style: Style,
+}
+
+/// A face with its variation axes set, clamped to the ranges in `fvar`
+struct FontInstance {
+    face: usize,
+    coordinates: Vec<(Tag, f32)>,
+}
+
+/// Font properties requested by a `TextStyle`, in DirectWrite units
+#[derive(Clone, Copy)]
+pub(crate) struct FontQuery<'a> {
+    pub(crate) family: &'a str,
+    pub(crate) weight: u16,
+    pub(crate) stretch: u16,
+    pub(crate) style: Style,
+    pub(crate) variations: &'a BTreeMap<String, FontVariationValue>,
+}
+
+impl<'a> FontQuery<'a> {
+    pub(crate) fn from_style(style: &'a TextStyle, family: &'a str) -> Self {
+        Self {
+            family,
+            weight: style.font_weight.unwrap_or(400).clamp(1, 999) as u16,
+            stretch: style.font_width.unwrap_or(5).clamp(1, 9) as u16,
+            style: match style.font_style {
+                Some(FontStyle::Italic) => Style::Italic,
+                Some(FontStyle::Oblique) => Style::Oblique,
+                Some(FontStyle::Normal) | None => Style::Normal,
+            },
+            variations: &style.font_variation_settings,
+        }
+    }
+}
+
+/// Portable counterpart of `load_font_collection`: every face of every file matched by `fontFiles`.
+/// `FontId`s name instances of these faces, created as styles ask for them.
+pub(crate) struct FontCollection {
+    files: Vec<FontFile>,
+    faces: Vec<FaceEntry>,
+    instances: RefCell<Vec<FontInstance>>,
+    missing_axes: RefCell<HashSet<(usize, Tag)>>,
+    fallback: BTreeMap<String, FontFamilyList>,
+}
+
+impl FontCollection {
+    pub(crate) fn load(document: &Document) -> Result<Self> {
+        let mut collection = Self {
+            files: Vec::new(),
+            faces: Vec::new(),
+            instances: RefCell::new(Vec::new()),
+            missing_axes: RefCell::new(HashSet::new()),
+            fallback: document.fallback.clone(),
+        };
+
+        for pattern in document.font_files.iter() {
+            let font_paths = glob(pattern).map_err(|err| {
+                AppError::Font(format!(
+                    "invalid fontFiles pattern \"{}\": {}",
+                    pattern, err
+                ))
+            })?;
+            for font_path in font_paths.flatten() {
+                let data = fs::read(&font_path)?;
+                let num_of_faces = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+
+                let file = collection.files.len();
+                for i in 0..num_of_faces {
+                    if let Ok(face) = Face::parse(&data, i) {
+                        collection.faces.push(FaceEntry {
+                            file,
+                            index: i,
+                            families: family_names(&face),
+                            weight: face.weight().to_number(),
+                            stretch: face.width().to_number(),
+                            style: face.style(),
+                        });
+                    }
+                }
+                collection.files.push(FontFile {
+                    path: font_path,
+                    data,
+                });
+            }
+        }
+
+        if collection.faces.is_empty() {
+            return Err(AppError::Font(String::from(
+                "no usable font face found in fontFiles",
+            )));
+        }
+        Ok(collection)
+    }
+
+    /// Find the face of a family that best matches the query, following the CSS font matching
+    /// algorithm: width first, then style, then weight
+    fn match_face(&self, query: &FontQuery) -> Option<usize> {
+        let mut candidates: Vec<usize> = (0..self.faces.len())
+            .filter(|&i| {
+                self.faces[i]
+                    .families
+                    .iter()
+                    .any(|f| f.eq_ignore_ascii_case(query.family))
+            })
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let stretches: Vec<u16> = candidates.iter().map(|&i| self.faces[i].stretch).collect();
+        let stretch = match_stretch(query.stretch, &stretches);
+        candidates.retain(|&i| self.faces[i].stretch == stretch);
+
+        let styles: Vec<Style> = candidates.iter().map(|&i| self.faces[i].style).collect();
+        let style = match_style(query.style, &styles);
+        candidates.retain(|&i| self.faces[i].style == style);
+
+        let weights: Vec<u16> = candidates.iter().map(|&i| self.faces[i].weight).collect();
+        let weight = match_weight(query.weight, &weights);
+        candidates
+            .into_iter()
+            .find(|&i| self.faces[i].weight == weight)
+    }
+
+    /// Find the best match for a query within its family only
+    pub(crate) fn find(&self, query: &FontQuery) -> Option<FontId> {
+        self.match_face(query)
+            .map(|face| self.instantiate(face, query.variations))
+    }
+
+    /// Resolve a query, falling back to the default family and then to the first loaded family
+    pub(crate) fn resolve(&self, query: &FontQuery, default_family: &str) -> FontId {
+        let face = self.resolve_face(query, default_family);
+        self.instantiate(face, query.variations)
+    }
+
+    fn resolve_face(&self, query: &FontQuery, default_family: &str) -> usize {
+        let fallback_family = self.faces[0].families.first().map(String::as_str);
+        [Some(query.family), Some(default_family), fallback_family]
+            .into_iter()
+            .flatten()
+            .find_map(|family| self.match_face(&FontQuery { family, ..*query }))
+            .unwrap_or(0)
+    }
+
+    /// Get the instance of a face for the given axis values. Values are clamped to the axis
+    /// ranges; axes the face does not have are reported once and ignored.
+    fn instantiate(
+        &self,
+        face: usize,
+        variations: &BTreeMap<String, FontVariationValue>,
+    ) -> FontId {
+        let axes = self.parse_face(face).variation_axes();
+        let mut coordinates = Vec::new();
+        for (axis, value) in variations.iter() {
+            let FontVariationValue::Set(value) = value else {
+                continue;
+            };
+            let tag = Tag::from_bytes_lossy(axis.as_bytes());
+            match axes.into_iter().find(|a| a.tag == tag) {
+                Some(a) => coordinates.push((tag, value.max(a.min_value).min(a.max_value))),
+                None => {
+                    if self.missing_axes.borrow_mut().insert((face, tag)) {
+                        let entry = &self.faces[face];
+                        eprintln!(
+                            "warning: font {} (face {}) has no variation axis \"{}\"",
+                            self.files[entry.file].path.display(),
+                            entry.index,
+                            axis
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut instances = self.instances.borrow_mut();
+        let existing = instances
+            .iter()
+            .position(|i| i.face == face && i.coordinates == coordinates);
+        match existing {
+            Some(i) => FontId(i),
+            None => {
+                instances.push(FontInstance { face, coordinates });
+                FontId(instances.len() - 1)
+            }
+        }
+    }
+
+    /// Whether a face has glyphs for every character of a text, ignoring invisible controls
+    pub(crate) fn covers(&self, id: FontId, text: &str) -> bool {
+        let face = self.parse_face(self.instances.borrow()[id.0].face);
+        text.chars()
+            .all(|ch| is_default_ignorable(ch) || face.glyph_index(ch).is_some())
+    }
+
+    /// Families of the document `fallback` map that apply to a locale or a script code. The
+    /// longest matching locale comes first, then the script.
+    pub(crate) fn fallback_families(&self, lang: Option<&str>, script: &str) -> Vec<&str> {
+        let mut locales: Vec<&String> = self
+            .fallback
+            .keys()
+            .filter(|key| lang.is_some_and(|lang| locale_matches(key, lang)))
+            .collect();
+        locales.sort_by_key(|key| std::cmp::Reverse(key.len()));
+        let scripts = self
+            .fallback
+            .keys()
+            .filter(|key| key.eq_ignore_ascii_case(script));
+        locales
+            .into_iter()
+            .chain(scripts)
+            .flat_map(|key| self.fallback[key].0.iter().map(String::as_str))
+            .collect()
+    }
+
+    fn parse_face(&self, face: usize) -> Face<'_> {
+        let entry = &self.faces[face];
+        Face::parse(&self.files[entry.file].data, entry.index)
+            .expect("face was validated when loading")
+    }
+
+    pub(crate) fn face_data(&self, id: FontId) -> (&[u8], u32) {
+        let entry = &self.faces[self.instances.borrow()[id.0].face];
+        (&self.files[entry.file].data, entry.index)
+    }
+
+    /// Axis values of an instance, as clamped when it was created
+    pub(crate) fn variations(&self, id: FontId) -> Vec<(Tag, f32)> {
+        self.instances.borrow()[id.0].coordinates.clone()
+    }
+
+    /// The face of an instance, with its variations applied to outlines and metrics
+    pub(crate) fn face(&self, id: FontId) -> Face<'_> {
+        let instance = &self.instances.borrow()[id.0];
+        let mut face = self.parse_face(instance.face);
+        for &(tag, value) in instance.coordinates.iter() {
+            face.set_variation(tag, value);
+        }
+        face
+    }
+}
+
+impl GlyphOutlineSource for FontCollection {
+    fn units_per_em(&self, font: FontId) -> f32 {
+        self.face(font).units_per_em() as f32
+    }
+
+    fn glyph_path(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+    ) -> Result<Vec<PathSegment>> {
+        Ok(glyph_path(&self.face(font), glyph_id, is_sideways))
+    }
+
+    fn font_file(&self, font: FontId) -> Option<(PathBuf, u32)> {
+        let entry = &self.faces[self.instances.borrow()[font.0].face];
+        Some((self.files[entry.file].path.clone(), entry.index))
+    }
+
+    fn color_glyph(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+        palette: Option<&FontPalette>,
+        foreground: &csscolorparser::Color,
+    ) -> Option<ColorGlyph> {
+        color_glyph(&self.face(font), glyph_id, is_sideways, palette, foreground)
+    }
+
+    fn glyph_image(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+        pixels_per_em: u16,
+    ) -> Option<GlyphImage> {
+        glyph_image(&self.face(font), glyph_id, is_sideways, pixels_per_em)
+    }
+}
+
+fn family_names(face: &Face) -> Vec<String> {
+    let mut families = Vec::new();
+    for name in face.names() {
+        if matches!(
+            name.name_id,
+            name_id::FAMILY | name_id::TYPOGRAPHIC_FAMILY | name_id::WWS_FAMILY
+        ) {
+            if let Some(family) = name.to_string() {
+                if !families.contains(&family) {
+                    families.push(family);
+                }
+            }
+        }
+    }
+    families
+}
+
+fn is_default_ignorable(ch: char) -> bool {
+    ch.is_control()
+        || matches!(
+            ch,
+            '\u{200b}'..='\u{200f}'
+                | '\u{2028}'..='\u{202e}'
+                | '\u{2060}'..='\u{2064}'
+                | '\u{fe00}'..='\u{fe0f}'
+                | '\u{feff}'
+                | '\u{e0100}'..='\u{e01ef}'
+        )
+}
+
+/// Whether a locale key such as `zh` or `zh-Hant` applies to a language tag, ignoring case
+fn locale_matches(key: &str, lang: &str) -> bool {
+    match lang.get(..key.len()) {
+        Some(prefix) => {
+            prefix.eq_ignore_ascii_case(key)
+                && (lang.len() == key.len() || lang.as_bytes()[key.len()] == b'-')
+        }
+        None => false,
+    }
+}
+
+/// CSS `font-stretch` matching: narrower widths are preferred for condensed requests, wider ones
+/// for expanded requests
+fn match_stretch(desired: u16, available: &[u16]) -> u16 {
+    let narrower = available.iter().copied().filter(|&s| s < desired).max();
+    let wider = available.iter().copied().filter(|&s| s > desired).min();
+    if available.contains(&desired) {
+        desired
+    } else if desired <= 5 {
+        narrower.or(wider).unwrap_or(desired)
+    } else {
+        wider.or(narrower).unwrap_or(desired)
+    }
+}
+
+/// CSS `font-style` matching: italic and oblique substitute for each other before normal
+fn match_style(desired: Style, available: &[Style]) -> Style {
+    let order = match desired {
+        Style::Italic => [Style::Italic, Style::Oblique, Style::Normal],
+        Style::Oblique => [Style::Oblique, Style::Italic, Style::Normal],
+        Style::Normal => [Style::Normal, Style::Oblique, Style::Italic],
+    };
+    order
+        .into_iter()
+        .find(|style| available.contains(style))
+        .unwrap_or(desired)
+}
+
+/// CSS `font-weight` matching: requests between 400 and 500 try heavier weights up to 500 first,
+/// lighter requests search downwards first and bolder requests upwards first
+fn match_weight(desired: u16, available: &[u16]) -> u16 {
+    let lighter = available.iter().copied().filter(|&w| w < desired).max();
+    let heavier = available.iter().copied().filter(|&w| w > desired).min();
+    if available.contains(&desired) {
+        desired
+    } else if (400..=500).contains(&desired) {
+        let up_to_500 = available
+            .iter()
+            .copied()
+            .filter(|&w| w > desired && w <= 500)
+            .min();
+        up_to_500.or(lighter).or(heavier).unwrap_or(desired)
+    } else if desired < 400 {
+        lighter.or(heavier).unwrap_or(desired)
+    } else {
+        heavier.or(lighter).unwrap_or(desired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_VARIATIONS: &BTreeMap<String, FontVariationValue> = &BTreeMap::new();
+
+    /// A collection of faces with no files behind them, enough to match queries against
+    fn collection(faces: &[(&str, u16, u16, Style)]) -> FontCollection {
+        FontCollection {
+            files: Vec::new(),
+            faces: faces
+                .iter()
+                .map(|&(family, weight, stretch, style)| FaceEntry {
+                    file: 0,
+                    index: 0,
+                    families: vec![String::from(family)],
+                    weight,
+                    stretch,
+                    style,
+                })
+                .collect(),
+            instances: RefCell::new(Vec::new()),
+            missing_axes: RefCell::new(HashSet::new()),
+            fallback: BTreeMap::new(),
+        }
+    }
+
+    fn query(family: &str, weight: u16, stretch: u16, style: Style) -> FontQuery<'_> {
+        FontQuery {
+            family,
+            weight,
+            stretch,
+            style,
+            variations: NO_VARIATIONS,
+        }
+    }
+
+    #[test]
+    fn weights_between_400_and_500_look_up_to_500_first() {
+        let available = [300, 500, 600];
+        assert_eq!(match_weight(400, &available), 500);
+        assert_eq!(match_weight(450, &available), 500);
+        assert_eq!(match_weight(400, &[300, 600]), 300);
+        assert_eq!(match_weight(400, &[600, 700]), 600);
+        assert_eq!(match_weight(500, &[400, 600]), 400);
+    }
+
+    #[test]
+    fn light_weights_look_lighter_first_and_bold_weights_heavier() {
+        assert_eq!(match_weight(300, &[100, 200, 400]), 200);
+        assert_eq!(match_weight(300, &[400, 500]), 400);
+        assert_eq!(match_weight(600, &[400, 800, 900]), 800);
+        assert_eq!(match_weight(600, &[400, 500]), 500);
+        assert_eq!(match_weight(700, &[700, 800]), 700);
+    }
+
+    #[test]
+    fn condensed_widths_look_narrower_first_and_expanded_wider() {
+        assert_eq!(match_stretch(5, &[3, 4, 7]), 4);
+        assert_eq!(match_stretch(4, &[5, 7]), 5);
+        assert_eq!(match_stretch(6, &[4, 5, 8]), 8);
+        assert_eq!(match_stretch(6, &[4, 5]), 5);
+        assert_eq!(match_stretch(3, &[3, 5]), 3);
+    }
+
+    #[test]
+    fn italic_and_oblique_substitute_for_each_other() {
+        let normal_and_oblique = [Style::Normal, Style::Oblique];
+        assert_eq!(
+            match_style(Style::Italic, &normal_and_oblique),
+            Style::Oblique
+        );
+        let normal_and_italic = [Style::Normal, Style::Italic];
+        assert_eq!(
+            match_style(Style::Oblique, &normal_and_italic),
+            Style::Italic
+        );
+        assert_eq!(match_style(Style::Italic, &[Style::Normal]), Style::Normal);
+        assert_eq!(
+            match_style(Style::Normal, &[Style::Italic, Style::Oblique]),
+            Style::Oblique
+        );
+    }
+
+    #[test]
+    fn width_is_matched_before_style_and_weight() {
+        let fonts = collection(&[
+            ("Sans", 400, 5, Style::Normal),
+            ("Sans", 700, 3, Style::Italic),
+            ("Sans", 400, 3, Style::Normal),
+        ]);
+        // The condensed faces win over the normal width face with the right style and weight
+        assert_eq!(
+            fonts.match_face(&query("Sans", 400, 3, Style::Italic)),
+            Some(1)
+        );
+        assert_eq!(
+            fonts.match_face(&query("Sans", 700, 3, Style::Normal)),
+            Some(2)
+        );
+        assert_eq!(
+            fonts.match_face(&query("Sans", 700, 5, Style::Italic)),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn style_is_matched_before_weight() {
+        let fonts = collection(&[
+            ("Sans", 700, 5, Style::Normal),
+            ("Sans", 300, 5, Style::Italic),
+        ]);
+        assert_eq!(
+            fonts.match_face(&query("Sans", 700, 5, Style::Italic)),
+            Some(1)
+        );
+        assert_eq!(
+            fonts.match_face(&query("sans", 300, 5, Style::Normal)),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn unknown_families_fall_back_to_the_default_family() {
+        let fonts = collection(&[
+            ("Serif", 400, 5, Style::Normal),
+            ("Sans", 400, 5, Style::Normal),
+            ("Sans", 700, 5, Style::Normal),
+        ]);
+        assert_eq!(
+            fonts.match_face(&query("Mono", 400, 5, Style::Normal)),
+            None
+        );
+        let bold_mono = query("Mono", 700, 5, Style::Normal);
+        assert_eq!(fonts.resolve_face(&bold_mono, "Sans"), 2);
+        // Without the default family either, the first loaded family is taken
+        assert_eq!(fonts.resolve_face(&bold_mono, "Fantasy"), 0);
+    }
+
+    #[test]
+    fn fallback_families_put_longer_locales_before_the_script() {
+        let mut fonts = collection(&[("Sans", 400, 5, Style::Normal)]);
+        for (key, family) in [
+            ("zh", "Zh"),
+            ("zh-Hant", "Zh Hant"),
+            ("Hani", "Han"),
+            ("ja", "Ja"),
+        ] {
+            fonts.fallback.insert(
+                String::from(key),
+                FontFamilyList(vec![String::from(family)]),
+            );
+        }
+        for (lang, script, families) in [
+            (Some("zh-Hant-TW"), "Hani", vec!["Zh Hant", "Zh", "Han"]),
+            (Some("ZH-hant"), "hani", vec!["Zh Hant", "Zh", "Han"]),
+            (Some("zh-Hans"), "Hani", vec!["Zh", "Han"]),
+            (Some("zhx"), "Hani", vec!["Han"]),
+            (Some("ja"), "Latn", vec!["Ja"]),
+            (None, "Hani", vec!["Han"]),
+            (None, "Zsye", vec![]),
+        ] {
+            assert_eq!(
+                fonts.fallback_families(lang, script),
+                families,
+                "{lang:?} {script}"
+            );
+        }
+    }
+
+    const TEST_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data");
+
+    /// A `wght` axis from 0 to 1000, and a contrast axis
+    fn variable_font() -> FontCollection {
+        let document: Document = serde_json::from_value(serde_json::json!({
+            "fontFiles": [format!("{TEST_DATA}/AdobeVFPrototype-Subset.otf")],
+            "frames": [],
+        }))
+        .unwrap();
+        FontCollection::load(&document).unwrap()
+    }
+
+    fn variations(values: &[(&str, f32)]) -> BTreeMap<String, FontVariationValue> {
+        values
+            .iter()
+            .map(|&(axis, value)| (String::from(axis), FontVariationValue::Set(value)))
+            .collect()
+    }
+
+    #[test]
+    fn axis_values_are_clamped_to_the_axis_range() {
+        let fonts = variable_font();
+        let wght = Tag::from_bytes(b"wght");
+        for (value, clamped) in [(500.0, 500.0), (0.0, 0.0), (-100.0, 0.0), (2000.0, 1000.0)] {
+            let id = fonts.instantiate(0, &variations(&[("wght", value)]));
+            assert_eq!(fonts.variations(id), [(wght, clamped)], "{value}");
+        }
+        // Values that clamp to the same coordinates share an instance
+        assert_eq!(
+            fonts.instantiate(0, &variations(&[("wght", 1000.0)])),
+            fonts.instantiate(0, &variations(&[("wght", 5000.0)]))
+        );
+        let defaults = BTreeMap::from([(String::from("wght"), FontVariationValue::Default)]);
+        assert_eq!(fonts.variations(fonts.instantiate(0, &defaults)), []);
+
+        // The instances draw differently
+        let light = fonts.instantiate(0, &variations(&[("wght", 0.0)]));
+        let black = fonts.instantiate(0, &variations(&[("wght", 1000.0)]));
+        let dollar = fonts.face(light).glyph_index('$').unwrap().0;
+        assert_ne!(
+            fonts.glyph_path(light, dollar, false).unwrap(),
+            fonts.glyph_path(black, dollar, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_axes_are_ignored_and_reported_once() {
+        let fonts = variable_font();
+        let values = variations(&[("wdth", 50.0), ("wght", 300.0)]);
+        let first = fonts.instantiate(0, &values);
+        let second = fonts.instantiate(0, &values);
+        assert_eq!(first, second);
+        assert_eq!(fonts.variations(first), [(Tag::from_bytes(b"wght"), 300.0)]);
+        assert_eq!(
+            *fonts.missing_axes.borrow(),
+            HashSet::from([(0, Tag::from_bytes(b"wdth"))])
+        );
+        fonts.instantiate(0, &variations(&[("opsz", 12.0), ("wdth", 75.0)]));
+        assert_eq!(fonts.missing_axes.borrow().len(), 2);
+    }
+
+    #[test]
+    fn invalid_patterns_fail_to_load() {
+        let document: Document = serde_json::from_value(serde_json::json!({
+            "fontFiles": [format!("{TEST_DATA}/DejaVuSans.ttf"), "fonts/[*.ttf"],
+            "frames": [],
+        }))
+        .unwrap();
+        match FontCollection::load(&document) {
+            Err(AppError::Font(message)) => {
+                assert!(message.contains("\"fonts/[*.ttf\""), "{message}")
+            }
+            Err(err) => panic!("{err:?}"),
+            Ok(_) => panic!("loaded"),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::ops::Range;
+
+use unicode_linebreak::{break_property, BreakClass};
+use unicode_script::{Script, UnicodeScript};
+
+use crate::document::TextStyle;
+use crate::font_collection::{FontCollection, FontQuery};
+use crate::glyph_run::FontId;
+#[cfg(any(windows, test))]
+use crate::script_table::SCRIPT_RANGES;
+
+/// Split a style run into pieces that each use one face. Every cluster takes the first family of
+/// the `font-family` list that covers it, then the document `fallback` families for its locale
+/// and script, and finally the primary face. Characters common to all scripts stay with the face
+/// before them when it covers them.
+pub(crate) fn itemize_fonts(
+    fonts: &FontCollection,
+    text: &str,
+    range: Range<usize>,
+    style: &TextStyle,
+    default_family: &str,
+) -> Vec<(Range<usize>, FontId)> {
+    let families: Vec<&str> = match &style.font_family {
+        Some(list) => list.0.iter().map(String::as_str).collect(),
+        None => Vec::new(),
+    };
+    let chain: Vec<FontId> = families
+        .iter()
+        .filter_map(|family| fonts.find(&FontQuery::from_style(style, family)))
+        .collect();
+    let primary_family = families.first().copied().unwrap_or(default_family);
+    let primary = fonts.resolve(
+        &FontQuery::from_style(style, primary_family),
+        default_family,
+    );
+
+    let mut pieces: Vec<(Range<usize>, FontId)> = Vec::new();
+    for cluster in clusters(text, range) {
+        let cluster_text = &text[cluster.clone()];
+        let script = cluster_script(cluster_text);
+        let previous = pieces.last().map(|(_, face)| *face);
+
+        let face = previous
+            .filter(|&face| is_common(script) && fonts.covers(face, cluster_text))
+            .or_else(|| {
+                chain
+                    .iter()
+                    .copied()
+                    .find(|&face| fonts.covers(face, cluster_text))
+            })
+            .or_else(|| {
+                fonts
+                    .fallback_families(style.lang.as_deref(), script_code(cluster_text, script))
+                    .into_iter()
+                    .filter_map(|family| fonts.find(&FontQuery::from_style(style, family)))
+                    .find(|&face| fonts.covers(face, cluster_text))
+            })
+            .unwrap_or(primary);
+
+        match pieces.last_mut() {
+            Some((range, last)) if *last == face => range.end = cluster.end,
+            _ => pieces.push((cluster, face)),
+        }
+    }
+    pieces
+}
+
+/// Approximate grapheme clusters: marks, joiners and what follows a joiner stay with their base
+pub(crate) fn clusters(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let mut clusters: Vec<Range<usize>> = Vec::new();
+    let mut after_joiner = false;
+    for (i, ch) in text[range.clone()].char_indices() {
+        let start = range.start + i;
+        let end = start + ch.len_utf8();
+        let class = break_property(ch as u32);
+        let attached = after_joiner
+            || matches!(
+                class,
+                BreakClass::CombiningMark | BreakClass::ZeroWidthJoiner | BreakClass::EmojiModifier
+            );
+        match clusters.last_mut() {
+            Some(cluster) if attached => cluster.end = end,
+            _ => clusters.push(start..end),
+        }
+        after_joiner = class == BreakClass::ZeroWidthJoiner;
+    }
+    clusters
+}
+
+/// Script of the first character that has a specific one
+fn cluster_script(cluster: &str) -> Script {
+    cluster
+        .chars()
+        .map(|ch| ch.script())
+        .find(|script| !is_common(*script))
+        .unwrap_or(Script::Common)
+}
+
+fn is_common(script: Script) -> bool {
+    matches!(script, Script::Common | Script::Inherited | Script::Unknown)
+}
+
+/// ISO 15924 code used to look a cluster up in the `fallback` map; emoji use `Zsye`
+fn script_code(cluster: &str, script: Script) -> &'static str {
+    if is_common(script) && cluster.chars().any(is_emoji) {
+        "Zsye"
+    } else {
+        script.short_name()
+    }
+}
+
+// Emoji presentation selector, miscellaneous symbols and dingbats, and the supplementary
+// symbol blocks
+const EMOJI_RANGES: [(u32, u32); 3] = [(0xfe0f, 0xfe0f), (0x2600, 0x27bf), (0x1f000, 0x1faff)];
+
+fn is_emoji(ch: char) -> bool {
+    EMOJI_RANGES
+        .iter()
+        .any(|&(first, last)| (first..=last).contains(&(ch as u32)))
+}
+
+/// Code point ranges of an ISO 15924 script code, or `None` when the key is not a script code
+#[cfg(any(windows, test))]
+pub(crate) fn script_ranges(code: &str) -> Option<Vec<(u32, u32)>> {
+    if code.len() != 4 {
+        return None;
+    }
+    if code.eq_ignore_ascii_case("Zsye") {
+        return Some(EMOJI_RANGES.to_vec());
+    }
+    let script = Script::from_short_name(&title_case(code))?;
+    let ranges = SCRIPT_RANGES
+        .iter()
+        .filter(|(_, _, range_script)| *range_script == script)
+        .map(|&(first, last, _)| (first, last))
+        .collect();
+    Some(ranges)
+}
+
+#[cfg(any(windows, test))]
+fn title_case(code: &str) -> String {
+    code.char_indices()
+        .map(|(i, ch)| match i {
+            0 => ch.to_ascii_uppercase(),
+            _ => ch.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    const TEST_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data");
+
+    /// Fira Mono, which only has Basic Latin, and DejaVu Sans with the `fallback` map
+    fn fonts(fallback: serde_json::Value) -> FontCollection {
+        let document: Document = serde_json::from_value(serde_json::json!({
+            "fontFiles": [
+                format!("{TEST_DATA}/FiraMono-subset.ttf"),
+                format!("{TEST_DATA}/DejaVuSans.ttf"),
+            ],
+            "fallback": fallback,
+            "frames": [],
+        }))
+        .unwrap();
+        FontCollection::load(&document).unwrap()
+    }
+
+    fn style(style: serde_json::Value) -> TextStyle {
+        serde_json::from_value(style).unwrap()
+    }
+
+    fn itemize(
+        fonts: &FontCollection,
+        text: &str,
+        style: &TextStyle,
+    ) -> Vec<(&'static str, String)> {
+        itemize_fonts(fonts, text, 0..text.len(), style, "Fira Mono")
+            .into_iter()
+            .map(|(range, face)| {
+                let fira = fonts.find(&FontQuery::from_style(style, "Fira Mono"));
+                let family = if Some(face) == fira { "Fira" } else { "DejaVu" };
+                (family, text[range].to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clusters_keep_marks_joiners_and_modifiers_with_their_base() {
+        for (text, expected) in [
+            ("ab", vec!["a", "b"]),
+            ("e\u{301}x", vec!["e\u{301}", "x"]),
+            (
+                "\u{1f469}\u{200d}\u{1f4bb}!",
+                vec!["\u{1f469}\u{200d}\u{1f4bb}", "!"],
+            ),
+            ("\u{1f44d}\u{1f3fd}", vec!["\u{1f44d}\u{1f3fd}"]),
+            ("", vec![]),
+        ] {
+            let clusters: Vec<&str> = clusters(text, 0..text.len())
+                .into_iter()
+                .map(|range| &text[range])
+                .collect();
+            assert_eq!(clusters, expected, "{text:?}");
+        }
+        assert_eq!(clusters("xab", 1..3), [1..2, 2..3]);
+    }
+
+    #[test]
+    fn clusters_fall_back_by_script_and_common_characters_stay() {
+        let fonts = fonts(serde_json::json!({ "Grek": "DejaVu Sans" }));
+        let fira = style(serde_json::json!({ "font-family": "Fira Mono" }));
+        assert_eq!(
+            itemize(&fonts, "ab \u{3b1}\u{3b2} 1", &fira),
+            [
+                ("Fira", String::from("ab ")),
+                ("DejaVu", String::from("\u{3b1}\u{3b2} 1"))
+            ]
+        );
+        // Nothing covers Hebrew without a fallback for it, so it stays with the primary face
+        assert_eq!(
+            itemize(&fonts, "a\u{5e9}", &fira),
+            [("Fira", String::from("a\u{5e9}"))]
+        );
+    }
+
+    #[test]
+    fn clusters_take_the_first_family_of_the_list_that_covers_them() {
+        let fonts = fonts(serde_json::json!({}));
+        let list = style(serde_json::json!({ "font-family": "Fira Mono, DejaVu Sans" }));
+        assert_eq!(
+            itemize(&fonts, "\u{3b1}ae\u{301}", &list),
+            [
+                ("DejaVu", String::from("\u{3b1}")),
+                ("Fira", String::from("a")),
+                // Fira Mono has the base but not the mark
+                ("DejaVu", String::from("e\u{301}")),
+            ]
+        );
+    }
+
+    #[test]
+    fn locale_and_emoji_fallbacks_apply() {
+        let fonts = fonts(serde_json::json!({ "he": "DejaVu Sans", "Zsye": "DejaVu Sans" }));
+        let hebrew = style(serde_json::json!({ "font-family": "Fira Mono", "lang": "he-IL" }));
+        assert_eq!(
+            itemize(&fonts, "a\u{5e9}", &hebrew),
+            [
+                ("Fira", String::from("a")),
+                ("DejaVu", String::from("\u{5e9}"))
+            ]
+        );
+        let fira = style(serde_json::json!({ "font-family": "Fira Mono" }));
+        assert_eq!(
+            itemize(&fonts, "a\u{5e9}\u{1f600}", &fira),
+            [
+                ("Fira", String::from("a\u{5e9}")),
+                ("DejaVu", String::from("\u{1f600}"))
+            ]
+        );
+    }
+
+    #[test]
+    fn script_table_agrees_with_unicode_script() {
+        let mut next = 0;
+        for &(first, last, script) in SCRIPT_RANGES {
+            assert!(first >= next && last >= first, "{first:x}..={last:x}");
+            for cp in next..first {
+                let ch = char::from_u32(cp);
+                assert!(ch.is_none_or(|ch| ch.script() == Script::Unknown), "{cp:x}");
+            }
+            for ch in (first..=last).filter_map(char::from_u32) {
+                assert_eq!(ch.script(), script, "{:x}", ch as u32);
+            }
+            next = last + 1;
+        }
+        for ch in (next..=0x10ffff).filter_map(char::from_u32) {
+            assert_eq!(ch.script(), Script::Unknown, "{:x}", ch as u32);
+        }
+    }
+
+    #[test]
+    fn script_codes_map_to_their_ranges() {
+        let greek = script_ranges("grek").unwrap();
+        assert_eq!(Some(&greek), script_ranges("Grek").as_ref());
+        assert!(greek
+            .iter()
+            .any(|&(first, last)| (first..=last).contains(&0x3b1)));
+        assert!(!greek
+            .iter()
+            .any(|&(first, last)| (first..=last).contains(&0x41)));
+        assert_eq!(script_ranges("Zsye"), Some(EMOJI_RANGES.to_vec()));
+        for key in ["zh", "zh-Hant", "Abcd", ""] {
+            assert_eq!(script_ranges(key), None, "{key:?}");
+        }
+    }
+}
@@ -0,0 +1,594 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::path::PathSegment;
+
+/// Units per em of the fonts written, fine enough that rounding the outlines is not visible
+pub(crate) const FONT_UNITS_PER_EM: u16 = 4096;
+
+/// A glyph of a generated font: its outline y-down
+pub(crate) struct FontGlyph {
+    pub(crate) path: Vec<PathSegment>,
+    pub(crate) advance: f32,
+}
+
+/// A TrueType glyph with its points in font units
+#[derive(Default)]
+struct TrueTypeGlyph {
+    contours: Vec<Vec<(i16, i16, bool)>>,
+    advance: u16,
+}
+
+impl TrueTypeGlyph {
+    fn points(&self) -> impl Iterator<Item = &(i16, i16, bool)> {
+        self.contours.iter().flatten()
+    }
+
+    /// xMin, yMin, xMax, yMax; `None` for empty glyphs
+    fn bounds(&self) -> Option<[i16; 4]> {
+        self.points().fold(None, |bounds, &(x, y, _)| match bounds {
+            None => Some([x, y, x, y]),
+            Some([x0, y0, x1, y1]) => Some([x0.min(x), y0.min(y), x1.max(x), y1.max(y)]),
+        })
+    }
+}
+
+/// Converts glyph outlines into TrueType contours, flipping y and scaling design units
+struct ContourBuilder {
+    scale: f32,
+    contours: Vec<Vec<(i16, i16, bool)>>,
+    current: Vec<(i16, i16, bool)>,
+    last: (f32, f32),
+}
+
+impl ContourBuilder {
+    fn new(scale: f32) -> Self {
+        Self {
+            scale,
+            contours: Vec::new(),
+            current: Vec::new(),
+            last: (0.0, 0.0),
+        }
+    }
+
+    fn push(&mut self, x: f32, y: f32, on_curve: bool) {
+        let round = |v: f32| {
+            (v * self.scale)
+                .round()
+                .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+        };
+        self.current.push((round(x), -round(y), on_curve));
+        if on_curve {
+            self.last = (x, y);
+        }
+    }
+
+    fn close(&mut self) {
+        let mut contour = std::mem::take(&mut self.current);
+        // Contours close implicitly; drop an explicit closing point
+        if contour.len() > 1 && contour.first() == contour.last() {
+            contour.pop();
+        }
+        if contour.len() > 1 {
+            self.contours.push(contour);
+        }
+    }
+
+    fn build(mut self, path: &[PathSegment]) -> Vec<Vec<(i16, i16, bool)>> {
+        for segment in path {
+            match *segment {
+                PathSegment::MoveTo(x, y) => {
+                    self.close();
+                    self.push(x, y, true);
+                }
+                PathSegment::LineTo(x, y) => self.push(x, y, true),
+                PathSegment::QuadTo(x1, y1, x, y) => {
+                    self.push(x1, y1, false);
+                    self.push(x, y, true);
+                }
+                PathSegment::CubicTo(x1, y1, x2, y2, x, y) => {
+                    let tolerance = 0.25 / self.scale;
+                    for (cx, cy, ex, ey) in
+                        cubic_to_quads(self.last, (x1, y1), (x2, y2), (x, y), tolerance)
+                    {
+                        self.push(cx, cy, false);
+                        self.push(ex, ey, true);
+                    }
+                }
+                PathSegment::Close => self.close(),
+            }
+        }
+        self.close();
+        self.contours
+    }
+}
+
+/// Approximate a cubic curve with quadratic ones, returning their control and end points
+fn cubic_to_quads(
+    p0: (f32, f32),
+    p1: (f32, f32),
+    p2: (f32, f32),
+    p3: (f32, f32),
+    tolerance: f32,
+) -> Vec<(f32, f32, f32, f32)> {
+    let point = |t: f32| {
+        let s = 1.0 - t;
+        let (a, b, c, d) = (s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t);
+        (
+            a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+            a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+        )
+    };
+    let tangent = |t: f32| {
+        let s = 1.0 - t;
+        let (a, b, c) = (3.0 * s * s, 6.0 * s * t, 3.0 * t * t);
+        (
+            a * (p1.0 - p0.0) + b * (p2.0 - p1.0) + c * (p3.0 - p2.0),
+            a * (p1.1 - p0.1) + b * (p2.1 - p1.1) + c * (p3.1 - p2.1),
+        )
+    };
+    // The error of one quadratic is bounded by sqrt(3)/36 of the third difference of the points,
+    // and shrinks with the cube of the number of pieces
+    let third_x = p3.0 - 3.0 * p2.0 + 3.0 * p1.0 - p0.0;
+    let third_y = p3.1 - 3.0 * p2.1 + 3.0 * p1.1 - p0.1;
+    let error = 3f32.sqrt() / 36.0 * third_x.hypot(third_y);
+    let pieces = (error / tolerance).cbrt().ceil().clamp(1.0, 16.0) as usize;
+
+    (0..pieces)
+        .map(|i| {
+            let (t0, t1) = (i as f32 / pieces as f32, (i + 1) as f32 / pieces as f32);
+            let (q0, q3) = (point(t0), point(t1));
+            let (d0, d3) = (tangent(t0), tangent(t1));
+            let h = (t1 - t0) / 3.0;
+            let c1 = (q0.0 + h * d0.0, q0.1 + h * d0.1);
+            let c2 = (q3.0 - h * d3.0, q3.1 - h * d3.1);
+            (
+                (3.0 * (c1.0 + c2.0) - q0.0 - q3.0) / 4.0,
+                (3.0 * (c1.1 + c2.1) - q0.1 - q3.1) / 4.0,
+                q3.0,
+                q3.1,
+            )
+        })
+        .collect()
+}
+
+/// Big-endian table data
+#[derive(Default)]
+struct TableWriter(Vec<u8>);
+
+impl TableWriter {
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+    fn u16(&mut self, v: u16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+    fn i16(&mut self, v: i16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+    fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(v);
+        self
+    }
+}
+
+/// Build a TrueType font with the given glyphs and character map, and pack it as WOFF2.
+/// Glyph 0 is an empty `.notdef`; `glyphs[i]` becomes glyph `i + 1`.
+pub(crate) fn woff2_font(
+    family: &str,
+    units_per_em: f32,
+    glyphs: &[FontGlyph],
+    cmap: &BTreeMap<char, u16>,
+) -> Vec<u8> {
+    let scale = FONT_UNITS_PER_EM as f32 / units_per_em;
+    let mut tt_glyphs = vec![TrueTypeGlyph::default()];
+    tt_glyphs.extend(glyphs.iter().map(|glyph| TrueTypeGlyph {
+        contours: ContourBuilder::new(scale).build(&glyph.path),
+        advance: (glyph.advance * scale).round().clamp(0.0, u16::MAX as f32) as u16,
+    }));
+
+    let (glyf, loca) = glyf_table(&tt_glyphs);
+    let tables: Vec<([u8; 4], Vec<u8>)> = vec![
+        (*b"cmap", cmap_table(cmap)),
+        (*b"head", head_table(&tt_glyphs)),
+        (*b"hhea", hhea_table(&tt_glyphs)),
+        (*b"hmtx", hmtx_table(&tt_glyphs)),
+        (*b"maxp", maxp_table(&tt_glyphs)),
+        (*b"name", name_table(family)),
+        (*b"OS/2", os2_table(&tt_glyphs, cmap)),
+        (*b"post", post_table()),
+        // WOFF2 requires loca to follow glyf
+        (*b"glyf", glyf),
+        (*b"loca", loca),
+    ];
+    woff2(&tables)
+}
+
+fn ascender() -> i16 {
+    (FONT_UNITS_PER_EM as f32 * 0.8) as i16
+}
+
+fn descender() -> i16 {
+    -((FONT_UNITS_PER_EM as f32 * 0.2) as i16)
+}
+
+fn font_bounds(glyphs: &[TrueTypeGlyph]) -> [i16; 4] {
+    glyphs
+        .iter()
+        .filter_map(TrueTypeGlyph::bounds)
+        .reduce(|[a0, a1, a2, a3], [b0, b1, b2, b3]| {
+            [a0.min(b0), a1.min(b1), a2.max(b2), a3.max(b3)]
+        })
+        .unwrap_or_default()
+}
+
+fn head_table(glyphs: &[TrueTypeGlyph]) -> Vec<u8> {
+    let [x_min, y_min, x_max, y_max] = font_bounds(glyphs);
+    let mut t = TableWriter::default();
+    t.u32(0x0001_0000) // version
+        .u32(0x0001_0000) // fontRevision
+        .u32(0) // checksumAdjustment
+        .u32(0x5F0F_3CF5) // magicNumber
+        // Baseline at y = 0, transformed by the WOFF2 encoder
+        .u16(0x0001 | 0x0800)
+        .u16(FONT_UNITS_PER_EM)
+        .bytes(&[0; 16]) // created, modified
+        .i16(x_min)
+        .i16(y_min)
+        .i16(x_max)
+        .i16(y_max)
+        .u16(0) // macStyle
+        .u16(8) // lowestRecPPEM
+        .i16(2) // fontDirectionHint
+        .i16(1) // indexToLocFormat: long offsets
+        .i16(0); // glyphDataFormat
+    t.0
+}
+
+fn hhea_table(glyphs: &[TrueTypeGlyph]) -> Vec<u8> {
+    let advance_max = glyphs.iter().map(|g| g.advance).max().unwrap_or(0);
+    let extents = glyphs
+        .iter()
+        .filter_map(|g| g.bounds().map(|b| (g.advance, b)));
+    let min_lsb = extents.clone().map(|(_, b)| b[0]).min().unwrap_or(0);
+    let min_rsb = extents
+        .clone()
+        .map(|(advance, b)| (advance as i32 - b[2] as i32) as i16)
+        .min()
+        .unwrap_or(0);
+    let max_extent = extents.map(|(_, b)| b[2]).max().unwrap_or(0);
+    let mut t = TableWriter::default();
+    t.u32(0x0001_0000)
+        .i16(ascender())
+        .i16(descender())
+        .i16(0) // lineGap
+        .u16(advance_max)
+        .i16(min_lsb)
+        .i16(min_rsb)
+        .i16(max_extent)
+        .i16(1) // caretSlopeRise
+        .i16(0) // caretSlopeRun
+        .i16(0) // caretOffset
+        .bytes(&[0; 8])
+        .i16(0) // metricDataFormat
+        .u16(glyphs.len() as u16);
+    t.0
+}
+
+fn hmtx_table(glyphs: &[TrueTypeGlyph]) -> Vec<u8> {
+    let mut t = TableWriter::default();
+    for glyph in glyphs {
+        let lsb = glyph.bounds().map_or(0, |b| b[0]);
+        t.u16(glyph.advance).i16(lsb);
+    }
+    t.0
+}
+
+fn maxp_table(glyphs: &[TrueTypeGlyph]) -> Vec<u8> {
+    let max_points = glyphs.iter().map(|g| g.points().count()).max().unwrap_or(0);
+    let max_contours = glyphs.iter().map(|g| g.contours.len()).max().unwrap_or(0);
+    let mut t = TableWriter::default();
+    t.u32(0x0001_0000)
+        .u16(glyphs.len() as u16)
+        .u16(max_points as u16)
+        .u16(max_contours as u16)
+        .u16(0) // maxCompositePoints
+        .u16(0) // maxCompositeContours
+        .u16(2) // maxZones
+        .bytes(&[0; 16]); // no instructions
+    t.0
+}
+
+fn name_table(family: &str) -> Vec<u8> {
+    // Family, subfamily, full name and PostScript name
+    let names = [(1, family), (2, "Regular"), (4, family), (6, family)];
+    let strings: Vec<Vec<u8>> = names
+        .iter()
+        .map(|(_, s)| s.encode_utf16().flat_map(u16::to_be_bytes).collect())
+        .collect();
+    let mut t = TableWriter::default();
+    t.u16(0)
+        .u16(names.len() as u16)
+        .u16(6 + 12 * names.len() as u16);
+    let mut offset = 0;
+    for ((name_id, _), string) in names.iter().zip(strings.iter()) {
+        t.u16(3) // Windows
+            .u16(1) // Unicode BMP
+            .u16(0x0409)
+            .u16(*name_id)
+            .u16(string.len() as u16)
+            .u16(offset);
+        offset += string.len() as u16;
+    }
+    for string in strings {
+        t.bytes(&string);
+    }
+    t.0
+}
+
+fn os2_table(glyphs: &[TrueTypeGlyph], cmap: &BTreeMap<char, u16>) -> Vec<u8> {
+    let advances: Vec<u32> = glyphs
+        .iter()
+        .filter(|g| g.advance > 0)
+        .map(|g| g.advance as u32)
+        .collect();
+    let average = advances.iter().sum::<u32>() / (advances.len().max(1) as u32);
+    let first_char = cmap.keys().next().map_or(0, |&c| (c as u32).min(0xFFFF));
+    let last_char = cmap
+        .keys()
+        .next_back()
+        .map_or(0, |&c| (c as u32).min(0xFFFF));
+    let [_, y_min, _, y_max] = font_bounds(glyphs);
+    let mut t = TableWriter::default();
+    t.u16(4) // version
+        .i16(average as i16)
+        .u16(400) // usWeightClass
+        .u16(5) // usWidthClass
+        .u16(0) // fsType: installable
+        .bytes(&[0; 20]) // subscript, superscript and strikeout metrics
+        .i16(0) // sFamilyClass
+        .bytes(&[0; 10]) // panose
+        .bytes(&[0; 16]) // ulUnicodeRange
+        .bytes(b"    ") // achVendID
+        .u16(0x0040 | 0x0080) // REGULAR, USE_TYPO_METRICS
+        .u16(first_char as u16)
+        .u16(last_char as u16)
+        .i16(ascender())
+        .i16(descender())
+        .i16(0) // sTypoLineGap
+        .u16(y_max.max(ascender()) as u16)
+        .u16((-(y_min as i32)).max(-(descender() as i32)) as u16)
+        .u32(0)
+        .u32(0) // ulCodePageRange
+        .i16(0) // sxHeight
+        .i16(0) // sCapHeight
+        .u16(0) // usDefaultChar
+        .u16(0x20) // usBreakChar
+        .u16(0); // usMaxContext
+    t.0
+}
+
+fn post_table() -> Vec<u8> {
+    let mut t = TableWriter::default();
+    t.u32(0x0003_0000) // version 3, no glyph names
+        .u32(0) // italicAngle
+        .i16(-((FONT_UNITS_PER_EM / 10) as i16))
+        .i16((FONT_UNITS_PER_EM / 20) as i16)
+        .bytes(&[0; 20]);
+    t.0
+}
+
+/// `cmap` with a format 4 subtable for the BMP, and a format 12 one when there are other planes
+fn cmap_table(cmap: &BTreeMap<char, u16>) -> Vec<u8> {
+    // Runs of consecutive characters mapped to consecutive glyphs
+    let mut groups: Vec<(u32, u32, u16)> = Vec::new();
+    for (&ch, &glyph) in cmap.iter() {
+        let code = ch as u32;
+        match groups.last_mut() {
+            Some((start, end, first_glyph))
+                if *end + 1 == code && (*first_glyph as u32 + code - *start) == glyph as u32 =>
+            {
+                *end = code
+            }
+            _ => groups.push((code, code, glyph)),
+        }
+    }
+
+    let mut format4 = TableWriter::default();
+    let mut segments: Vec<(u16, u16, u16)> = groups
+        .iter()
+        .filter(|(start, _, _)| *start <= 0xFFFF)
+        .map(|&(start, end, glyph)| {
+            let end = end.min(0xFFFF);
+            (start as u16, end as u16, glyph.wrapping_sub(start as u16))
+        })
+        .filter(|&(_, end, _)| end != 0xFFFF)
+        .collect();
+    segments.push((0xFFFF, 0xFFFF, 1));
+    let seg_count = segments.len() as u16;
+    let entry_selector = 15 - seg_count.leading_zeros() as u16;
+    let search_range = 2 << entry_selector;
+    format4
+        .u16(4)
+        .u16(16 + 8 * seg_count)
+        .u16(0) // language
+        .u16(seg_count * 2)
+        .u16(search_range)
+        .u16(entry_selector)
+        .u16(seg_count * 2 - search_range);
+    for (_, end, _) in segments.iter() {
+        format4.u16(*end);
+    }
+    format4.u16(0);
+    for (start, _, _) in segments.iter() {
+        format4.u16(*start);
+    }
+    for (_, _, delta) in segments.iter() {
+        format4.u16(*delta);
+    }
+    for _ in segments.iter() {
+        format4.u16(0); // idRangeOffset
+    }
+
+    let mut subtables = vec![((3, 1), format4.0)];
+    if groups.iter().any(|(_, end, _)| *end > 0xFFFF) {
+        let mut format12 = TableWriter::default();
+        format12
+            .u16(12)
+            .u16(0)
+            .u32(16 + 12 * groups.len() as u32)
+            .u32(0) // language
+            .u32(groups.len() as u32);
+        for (start, end, glyph) in groups.iter() {
+            format12.u32(*start).u32(*end).u32(*glyph as u32);
+        }
+        subtables.push(((3, 10), format12.0));
+    }
+
+    let mut t = TableWriter::default();
+    t.u16(0).u16(subtables.len() as u16);
+    let mut offset = 4 + 8 * subtables.len() as u32;
+    for ((platform, encoding), data) in subtables.iter() {
+        t.u16(*platform).u16(*encoding).u32(offset);
+        offset += data.len() as u32;
+    }
+    for (_, data) in subtables {
+        t.bytes(&data);
+    }
+    t.0
+}
+
+/// `glyf` and long-format `loca`
+fn glyf_table(glyphs: &[TrueTypeGlyph]) -> (Vec<u8>, Vec<u8>) {
+    const ON_CURVE: u8 = 0x01;
+    const X_SHORT: u8 = 0x02;
+    const Y_SHORT: u8 = 0x04;
+    const X_SAME_OR_POSITIVE: u8 = 0x10;
+    const Y_SAME_OR_POSITIVE: u8 = 0x20;
+
+    let mut glyf = TableWriter::default();
+    let mut loca = TableWriter::default();
+    for glyph in glyphs {
+        loca.u32(glyf.0.len() as u32);
+        let Some([x_min, y_min, x_max, y_max]) = glyph.bounds() else {
+            continue;
+        };
+        glyf.i16(glyph.contours.len() as i16)
+            .i16(x_min)
+            .i16(y_min)
+            .i16(x_max)
+            .i16(y_max);
+        let mut end = 0;
+        for contour in glyph.contours.iter() {
+            end += contour.len();
+            glyf.u16((end - 1) as u16);
+        }
+        glyf.u16(0); // instructionLength
+
+        let mut flags = Vec::new();
+        let mut xs = TableWriter::default();
+        let mut ys = TableWriter::default();
+        let (mut last_x, mut last_y) = (0i32, 0i32);
+        for &(x, y, on_curve) in glyph.points() {
+            let mut flag = if on_curve { ON_CURVE } else { 0 };
+            let (dx, dy) = (x as i32 - last_x, y as i32 - last_y);
+            (last_x, last_y) = (x as i32, y as i32);
+            for (delta, coords, short, same_or_positive) in [
+                (dx, &mut xs, X_SHORT, X_SAME_OR_POSITIVE),
+                (dy, &mut ys, Y_SHORT, Y_SAME_OR_POSITIVE),
+            ] {
+                if delta == 0 {
+                    flag |= same_or_positive;
+                } else if delta.abs() < 256 {
+                    flag |= short;
+                    if delta > 0 {
+                        flag |= same_or_positive;
+                    }
+                    coords.u8(delta.unsigned_abs() as u8);
+                } else {
+                    coords.i16(delta as i16);
+                }
+            }
+            flags.push(flag);
+        }
+        glyf.bytes(&flags).bytes(&xs.0).bytes(&ys.0);
+        while glyf.0.len() % 4 != 0 {
+            glyf.u8(0);
+        }
+    }
+    loca.u32(glyf.0.len() as u32);
+    (glyf.0, loca.0)
+}
+
+/// Tags that a WOFF2 table directory refers to by index
+const WOFF2_KNOWN_TAGS: [&[u8; 4]; 12] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca",
+];
+
+/// Pack sfnt tables into a WOFF2 file, without transforming them
+fn woff2(tables: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut directory = TableWriter::default();
+    for (tag, data) in tables {
+        let known = WOFF2_KNOWN_TAGS.iter().position(|known| *known == tag);
+        // Transform version 3 is the null transform of glyf and loca, 0 that of other tables
+        let transform = match tag {
+            b"glyf" | b"loca" => 0xC0,
+            _ => 0x00,
+        };
+        match known {
+            Some(index) => directory.u8(index as u8 | transform),
+            None => directory.u8(0x3F | transform).bytes(tag),
+        };
+        write_base128(&mut directory, data.len() as u32);
+    }
+
+    let mut compressor = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+    for (_, data) in tables {
+        compressor.write_all(data).unwrap();
+    }
+    let compressed = compressor.into_inner();
+
+    let sfnt_size: usize = 12
+        + 16 * tables.len()
+        + tables
+            .iter()
+            .map(|(_, data)| (data.len() + 3) & !3)
+            .sum::<usize>();
+    let length = (48 + directory.0.len() + compressed.len() + 3) & !3;
+    let mut t = TableWriter::default();
+    t.bytes(b"wOF2")
+        .u32(0x0001_0000) // flavor: TrueType
+        .u32(length as u32)
+        .u16(tables.len() as u16)
+        .u16(0)
+        .u32(sfnt_size as u32)
+        .u32(compressed.len() as u32)
+        .u16(1) // majorVersion
+        .u16(0) // minorVersion
+        .bytes(&[0; 20]) // no metadata or private data
+        .bytes(&directory.0)
+        .bytes(&compressed);
+    t.0.resize(length, 0);
+    t.0
+}
+
+/// Variable-length encoding of WOFF2, 7 bits per byte with the most significant first
+fn write_base128(t: &mut TableWriter, value: u32) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    for byte in groups.into_iter().rev() {
+        t.u8(byte);
+    }
+}
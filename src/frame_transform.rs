@@ -0,0 +1,303 @@
+use tiny_skia::Transform;
+
+use crate::css_gradient::{parse_angle, split_top_level};
+use crate::document::{DocumentFrame, FrameTransform, OriginOffset, TransformOrigin};
+
+/// Parse a `transform` list into the matrix it multiplies into; `none` is the identity
+pub(crate) fn parse_transform(css: &str) -> Option<Transform> {
+    let mut transform = Transform::identity();
+    if css.trim() == "none" {
+        return Some(transform);
+    }
+    for function in split_top_level(css, char::is_whitespace) {
+        let (name, args) = function.strip_suffix(')')?.split_once('(')?;
+        let args = split_top_level(args, |ch| ch == ',' || ch.is_whitespace());
+        transform = transform.pre_concat(parse_function(name, &args)?);
+    }
+    Some(transform)
+}
+
+fn parse_function(name: &str, args: &[&str]) -> Option<Transform> {
+    let parse_all = |parse: fn(&str) -> Option<f32>| -> Option<Vec<f32>> {
+        args.iter().map(|arg| parse(arg)).collect()
+    };
+    let number = |arg: &str| arg.parse::<f32>().ok();
+    let skew = |x: f32, y: f32| Transform::from_skew(x.to_radians().tan(), y.to_radians().tan());
+    let transform = match (name, args.len()) {
+        ("matrix", 6) => {
+            let [a, b, c, d, e, f] = parse_all(number)?[..] else {
+                return None;
+            };
+            Transform::from_row(a, b, c, d, e, f)
+        }
+        ("translate", 1 | 2) => {
+            let lengths = parse_all(parse_length)?;
+            Transform::from_translate(lengths[0], lengths.get(1).copied().unwrap_or(0.0))
+        }
+        ("translateX", 1) => Transform::from_translate(parse_length(args[0])?, 0.0),
+        ("translateY", 1) => Transform::from_translate(0.0, parse_length(args[0])?),
+        ("scale", 1 | 2) => {
+            let factors = parse_all(number)?;
+            Transform::from_scale(factors[0], factors.get(1).copied().unwrap_or(factors[0]))
+        }
+        ("scaleX", 1) => Transform::from_scale(number(args[0])?, 1.0),
+        ("scaleY", 1) => Transform::from_scale(1.0, number(args[0])?),
+        ("rotate", 1) => Transform::from_rotate(parse_angle(args[0])?),
+        ("skew", 1 | 2) => {
+            let angles = parse_all(parse_angle)?;
+            skew(angles[0], angles.get(1).copied().unwrap_or(0.0))
+        }
+        ("skewX", 1) => skew(parse_angle(args[0])?, 0.0),
+        ("skewY", 1) => skew(0.0, parse_angle(args[0])?),
+        _ => return None,
+    };
+    Some(transform)
+}
+
+/// Parse a `transform-origin` of one or two keywords, lengths or percentages. One value leaves
+/// the other axis centred; keywords may name the vertical axis first.
+pub(crate) fn parse_transform_origin(css: &str) -> Option<TransformOrigin> {
+    let center = OriginOffset::Percent(50.0);
+    let tokens: Vec<&str> = css.split_whitespace().collect();
+    let (x, y) = match tokens[..] {
+        [token @ ("top" | "bottom")] => (center, parse_offset(token, false)?),
+        [token] => (parse_offset(token, true)?, center),
+        [first @ ("top" | "bottom"), second] | [first, second @ ("left" | "right")] => {
+            (parse_offset(second, true)?, parse_offset(first, false)?)
+        }
+        [first, second] => (parse_offset(first, true)?, parse_offset(second, false)?),
+        _ => return None,
+    };
+    Some(TransformOrigin { x, y })
+}
+
+/// An offset along the horizontal or vertical side of the frame box
+fn parse_offset(token: &str, horizontal: bool) -> Option<OriginOffset> {
+    match (token, horizontal) {
+        ("center", _) => Some(OriginOffset::Percent(50.0)),
+        ("left", true) | ("top", false) => Some(OriginOffset::Percent(0.0)),
+        ("right", true) | ("bottom", false) => Some(OriginOffset::Percent(100.0)),
+        ("left" | "right" | "top" | "bottom", _) => None,
+        _ => match token.strip_suffix('%') {
+            Some(percent) => percent.parse().ok().map(OriginOffset::Percent),
+            None => parse_length(token).map(OriginOffset::Px),
+        },
+    }
+}
+
+fn parse_length(css: &str) -> Option<f32> {
+    css.strip_suffix("px").unwrap_or(css).parse().ok()
+}
+
+/// Maps a frame laid out in its own box onto the canvas: its `transform` around its
+/// `transform-origin`
+pub(crate) fn frame_transform(
+    frame: &DocumentFrame,
+    canvas_width: f32,
+    canvas_height: f32,
+) -> Transform {
+    let Some(FrameTransform(transform)) = frame.transform else {
+        return Transform::identity();
+    };
+    let left = frame.left.unwrap_or(0.0);
+    let top = frame.top.unwrap_or(0.0);
+    let right = frame.right.unwrap_or(canvas_width);
+    let bottom = frame.bottom.unwrap_or(canvas_height);
+    let origin_x = left + frame.transform_origin.x.resolve(right - left);
+    let origin_y = top + frame.transform_origin.y.resolve(bottom - top);
+    Transform::from_translate(origin_x, origin_y)
+        .pre_concat(transform)
+        .pre_translate(-origin_x, -origin_y)
+}
+
+/// How much a transform scales lengths on average, the square root of how it scales areas
+pub(crate) fn transform_scale(transform: &Transform) -> f32 {
+    (transform.sx * transform.sy - transform.kx * transform.ky)
+        .abs()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_skia::Point;
+
+    use super::*;
+    use crate::glyph_run::GlyphFrame;
+
+    fn assert_transform(actual: Transform, expected: Transform, css: &str) {
+        let actual = [
+            actual.sx, actual.ky, actual.kx, actual.sy, actual.tx, actual.ty,
+        ];
+        let expected = [
+            expected.sx,
+            expected.ky,
+            expected.kx,
+            expected.sy,
+            expected.tx,
+            expected.ty,
+        ];
+        let near = actual
+            .iter()
+            .zip(expected)
+            .all(|(a, b)| (a - b).abs() < 1e-5);
+        assert!(near, "{css:?}: {actual:?} != {expected:?}");
+    }
+
+    fn map(transform: Transform, x: f32, y: f32) -> (f32, f32) {
+        let mut point = [Point::from_xy(x, y)];
+        transform.map_points(&mut point);
+        (
+            (point[0].x * 1e3).round() / 1e3,
+            (point[0].y * 1e3).round() / 1e3,
+        )
+    }
+
+    fn frame(json: &str) -> DocumentFrame {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn transform_functions_make_their_matrices() {
+        for (css, (sx, ky, kx, sy, tx, ty)) in [
+            ("none", (1.0, 0.0, 0.0, 1.0, 0.0, 0.0)),
+            ("rotate(90deg)", (0.0, 1.0, -1.0, 0.0, 0.0, 0.0)),
+            ("rotate(0.5turn)", (-1.0, 0.0, 0.0, -1.0, 0.0, 0.0)),
+            ("rotate(0)", (1.0, 0.0, 0.0, 1.0, 0.0, 0.0)),
+            ("scale(2)", (2.0, 0.0, 0.0, 2.0, 0.0, 0.0)),
+            ("scale(2, 3)", (2.0, 0.0, 0.0, 3.0, 0.0, 0.0)),
+            ("scaleX(2)", (2.0, 0.0, 0.0, 1.0, 0.0, 0.0)),
+            ("scaleY(-1)", (1.0, 0.0, 0.0, -1.0, 0.0, 0.0)),
+            ("skewX(45deg)", (1.0, 0.0, 1.0, 1.0, 0.0, 0.0)),
+            ("skewY(45deg)", (1.0, 1.0, 0.0, 1.0, 0.0, 0.0)),
+            ("skew(45deg, -45deg)", (1.0, -1.0, 1.0, 1.0, 0.0, 0.0)),
+            ("translate(10px)", (1.0, 0.0, 0.0, 1.0, 10.0, 0.0)),
+            ("translate(10px, -5px)", (1.0, 0.0, 0.0, 1.0, 10.0, -5.0)),
+            ("translateY(5px)", (1.0, 0.0, 0.0, 1.0, 0.0, 5.0)),
+            ("matrix(1, 2, 3, 4, 5, 6)", (1.0, 2.0, 3.0, 4.0, 5.0, 6.0)),
+            ("matrix(1 2 3 4 5 6)", (1.0, 2.0, 3.0, 4.0, 5.0, 6.0)),
+        ] {
+            let expected = Transform::from_row(sx, ky, kx, sy, tx, ty);
+            assert_transform(parse_transform(css).unwrap(), expected, css);
+        }
+    }
+
+    #[test]
+    fn malformed_transforms_are_rejected() {
+        for css in [
+            "rotate(90)",
+            "rotate(90deg",
+            "rotate(90deg, 10deg)",
+            "matrix(1, 2, 3)",
+            "scale()",
+            "scale(two)",
+            "translate(10px, 20px, 30px)",
+            "spin(10deg)",
+            "rotate(90deg) none",
+        ] {
+            assert_eq!(parse_transform(css), None, "{css:?}");
+            assert!(FrameTransform::try_from(String::from(css)).is_err());
+        }
+    }
+
+    #[test]
+    fn functions_apply_from_the_last_one() {
+        // The point is rotated and then moved
+        let transform = parse_transform("translate(10px, 0) rotate(90deg)").unwrap();
+        assert_eq!(map(transform, 1.0, 0.0), (10.0, 1.0));
+        // The point is moved and then rotated
+        let transform = parse_transform("rotate(90deg) translate(10px, 0)").unwrap();
+        assert_eq!(map(transform, 1.0, 0.0), (0.0, 11.0));
+        let transform = parse_transform("scale(2) translateX(5px)").unwrap();
+        assert_eq!(map(transform, 0.0, 1.0), (10.0, 2.0));
+    }
+
+    #[test]
+    fn origins_take_keywords_lengths_and_percentages() {
+        let percent = OriginOffset::Percent;
+        for (css, origin) in [
+            ("center", Some((percent(50.0), percent(50.0)))),
+            ("left", Some((percent(0.0), percent(50.0)))),
+            ("top", Some((percent(50.0), percent(0.0)))),
+            ("right bottom", Some((percent(100.0), percent(100.0)))),
+            ("bottom right", Some((percent(100.0), percent(100.0)))),
+            ("top center", Some((percent(50.0), percent(0.0)))),
+            ("center left", Some((percent(0.0), percent(50.0)))),
+            ("25% 10px", Some((percent(25.0), OriginOffset::Px(10.0)))),
+            ("10px", Some((OriginOffset::Px(10.0), percent(50.0)))),
+            (
+                "-5px bottom",
+                Some((OriginOffset::Px(-5.0), percent(100.0))),
+            ),
+            ("top top", None),
+            ("left right", None),
+            ("middle", None),
+            ("1px 2px 3px", None),
+            ("", None),
+        ] {
+            let origin = origin.map(|(x, y)| TransformOrigin { x, y });
+            assert_eq!(parse_transform_origin(css), origin, "{css:?}");
+        }
+    }
+
+    #[test]
+    fn transforms_keep_their_origin_in_place() {
+        let rotated = |origin: &str| {
+            let json = r#"{"left": 100, "top": 0, "right": 300, "bottom": 100,
+                "transform": "rotate(90deg)", "transform-origin": "ORIGIN", "contents": ""}"#;
+            frame(&json.replace("ORIGIN", origin))
+        };
+        for (origin, fixed, moved) in [
+            ("center", (200.0, 50.0), (200.0, 150.0)),
+            ("left top", (100.0, 0.0), (50.0, 200.0)),
+            ("50% 100%", (200.0, 100.0), (250.0, 200.0)),
+            ("20px 10px", (120.0, 10.0), (80.0, 190.0)),
+        ] {
+            let transform = frame_transform(&rotated(origin), 1000.0, 1000.0);
+            assert_eq!(map(transform, fixed.0, fixed.1), fixed, "{origin:?}");
+            assert_eq!(map(transform, 300.0, 50.0), moved, "{origin:?}");
+        }
+        // Sides that are not given are the sides of the canvas
+        let unbounded = frame(r#"{"transform": "scale(2)", "contents": ""}"#);
+        let transform = frame_transform(&unbounded, 400.0, 200.0);
+        assert_eq!(map(transform, 200.0, 100.0), (200.0, 100.0));
+        assert_eq!(map(transform, 0.0, 0.0), (-200.0, -100.0));
+        let untransformed = frame(r#"{"left": 10, "contents": ""}"#);
+        assert_eq!(
+            frame_transform(&untransformed, 400.0, 200.0),
+            Transform::identity()
+        );
+    }
+
+    #[test]
+    fn canvas_bounds_enclose_the_transformed_box() {
+        for (transform, bounds) in [
+            (Transform::identity(), [0.0, 0.0, 20.0, 10.0]),
+            (Transform::from_rotate(90.0), [-10.0, 0.0, 0.0, 20.0]),
+            (
+                Transform::from_translate(5.0, 5.0).pre_scale(2.0, 3.0),
+                [5.0, 5.0, 45.0, 35.0],
+            ),
+            (Transform::from_skew(1.0, 0.0), [0.0, 0.0, 30.0, 10.0]),
+        ] {
+            let frame = GlyphFrame {
+                bounds: [0.0, 0.0, 20.0, 10.0],
+                transform,
+                ..GlyphFrame::default()
+            };
+            let actual = frame.canvas_bounds();
+            let near = actual.iter().zip(bounds).all(|(a, b)| (a - b).abs() < 1e-4);
+            assert!(near, "{transform:?}: {actual:?} != {bounds:?}");
+        }
+        // An eighth of a turn about the center of a square reaches out to its diagonals
+        let transform = Transform::from_rotate_at(45.0, 10.0, 10.0);
+        let frame = GlyphFrame {
+            bounds: [0.0, 0.0, 20.0, 20.0],
+            transform,
+            ..GlyphFrame::default()
+        };
+        let half_diagonal = 10.0 * std::f32::consts::SQRT_2;
+        let [x0, y0, x1, y1] = frame.canvas_bounds();
+        assert!((x1 - x0 - 2.0 * half_diagonal).abs() < 1e-4);
+        assert!((y1 - y0 - 2.0 * half_diagonal).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashSet;
+
+use quick_xml::events::{BytesCData, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use tiny_skia::{Pixmap, PremultipliedColorU8};
+use ttf_parser::{Face, GlyphId, RasterGlyphImage, RasterImageFormat};
+
+use crate::glyph_outline::vertical_origin;
+
+/// An image that stands for a glyph, from the `SVG ` table or from a bitmap strike
+pub(crate) enum GlyphImageData {
+    /// A PNG image placed in design units, with the y axis pointing down
+    Png {
+        data: Vec<u8>,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+    /// An uncompressed `SVG ` table document, identified by the first glyph it covers. Documents
+    /// that cover several glyphs have an element with the id `glyph{id}` for each of them.
+    Svg { document: Vec<u8>, first_glyph: u16 },
+}
+
+pub(crate) struct GlyphImage {
+    pub(crate) data: GlyphImageData,
+    /// Vertical origin of a sideways glyph, in design units with the y axis pointing up. The image
+    /// is turned around it like `glyph_path` turns outlines.
+    pub(crate) vertical_origin: Option<(f32, f32)>,
+}
+
+/// Image of a glyph: its `SVG ` document, or else the bitmap of the strike that suits
+/// `pixels_per_em` best. Bitmaps of fonts that have outlines are only used when they are in
+/// color, so that the grayscale strikes of CJK fonts do not replace their outlines.
+pub(crate) fn glyph_image(
+    face: &Face,
+    glyph_id: u16,
+    is_sideways: bool,
+    pixels_per_em: u16,
+) -> Option<GlyphImage> {
+    let glyph = GlyphId(glyph_id);
+    let data = match face.glyph_svg_image(glyph) {
+        Some(svg) => GlyphImageData::Svg {
+            document: decompress_svg(svg.data)?,
+            first_glyph: svg.start_glyph_id.0,
+        },
+        None => {
+            let image = face.glyph_raster_image(glyph, pixels_per_em)?;
+            let is_color = matches!(
+                image.format,
+                RasterImageFormat::PNG | RasterImageFormat::BitmapPremulBgra32
+            );
+            if !is_color && face.glyph_bounding_box(glyph).is_some() {
+                return None;
+            }
+            // Strike pixels to design units; the image offset is its bottom left, y pointing up
+            let scale = face.units_per_em() as f32 / image.pixels_per_em as f32;
+            GlyphImageData::Png {
+                data: png_data(&image)?,
+                x: image.x as f32 * scale,
+                y: -(image.y as f32 + image.height as f32) * scale,
+                width: image.width as f32 * scale,
+                height: image.height as f32 * scale,
+            }
+        }
+    };
+    let vertical_origin = is_sideways.then(|| {
+        let (x, y) = vertical_origin(face, glyph_id);
+        (x as f32, y as f32)
+    });
+    Some(GlyphImage {
+        data,
+        vertical_origin,
+    })
+}
+
+/// SVG documents may be stored gzip compressed
+fn decompress_svg(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return Some(data.to_vec());
+    }
+    // Skip the gzip header and its optional fields: extra data, file name, comment and CRC
+    let flags = *data.get(3)?;
+    let mut offset = 10;
+    if flags & 0x04 != 0 {
+        let len = u16::from_le_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+        offset += 2 + len as usize;
+    }
+    for flag in [0x08, 0x10] {
+        if flags & flag != 0 {
+            offset += data.get(offset..)?.iter().position(|b| *b == 0)? + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        offset += 2;
+    }
+    miniz_oxide::inflate::decompress_to_vec(data.get(offset..)?).ok()
+}
+
+/// PNG data of a raster image, encoding uncompressed bitmaps. Monochrome and grayscale bitmaps
+/// are coverage, drawn in black.
+fn png_data(image: &RasterGlyphImage) -> Option<Vec<u8>> {
+    let (bits, padded) = match image.format {
+        RasterImageFormat::PNG => return Some(image.data.to_vec()),
+        RasterImageFormat::BitmapMono => (1, true),
+        RasterImageFormat::BitmapMonoPacked => (1, false),
+        RasterImageFormat::BitmapGray2 => (2, true),
+        RasterImageFormat::BitmapGray2Packed => (2, false),
+        RasterImageFormat::BitmapGray4 => (4, true),
+        RasterImageFormat::BitmapGray4Packed => (4, false),
+        RasterImageFormat::BitmapGray8 => (8, true),
+        RasterImageFormat::BitmapPremulBgra32 => (32, true),
+    };
+    let width = image.width as usize;
+    let mut pixmap = Pixmap::new(image.width as u32, image.height as u32)?;
+    let row_bits = match padded {
+        true => (width * bits).div_ceil(8) * 8,
+        false => width * bits,
+    };
+    for (index, pixel) in pixmap.pixels_mut().iter_mut().enumerate() {
+        let (row, column) = (index / width, index % width);
+        let start = row * row_bits + column * bits;
+        *pixel = match bits {
+            32 => {
+                let bgra = image.data.get(start / 8..start / 8 + 4)?;
+                PremultipliedColorU8::from_rgba(bgra[2], bgra[1], bgra[0], bgra[3])
+                    .unwrap_or(PremultipliedColorU8::TRANSPARENT)
+            }
+            _ => {
+                let byte = *image.data.get(start / 8)? as u32;
+                let max = (1 << bits) - 1;
+                let value = (byte >> (8 - bits - start % 8)) & max;
+                let alpha = (value * 255 / max) as u8;
+                PremultipliedColorU8::from_rgba(0, 0, 0, alpha).unwrap()
+            }
+        };
+    }
+    pixmap.encode_png().ok()
+}
+
+/// An `SVG ` table document rewritten to be inlined into another SVG
+pub(crate) struct InlineSvg {
+    /// The document as a group with the id `prefix`
+    pub(crate) markup: String,
+    /// Ids of its elements, with the prefix
+    pub(crate) ids: HashSet<String>,
+}
+
+/// Turn the root `<svg>` of a glyph document into a `<g>` with the id `prefix`, and prefix every
+/// id in the document, so that it does not clash with other documents or with the output.
+/// Namespaced attributes other than `xlink:href` and `xml:*` are dropped, as is anything outside
+/// the root element.
+pub(crate) fn inline_svg(document: &[u8], prefix: &str) -> Option<InlineSvg> {
+    let mut reader = Reader::from_reader(document);
+    let mut writer = Writer::new(Vec::new());
+    let mut ids = HashSet::new();
+    let mut depth = 0usize;
+    let mut buf = Vec::new();
+    loop {
+        let event = reader.read_event_into(&mut buf).ok()?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(start) => {
+                depth += 1;
+                Event::Start(prefix_element(&start, depth == 1, prefix, &mut ids)?)
+            }
+            Event::Empty(start) => {
+                Event::Empty(prefix_element(&start, depth == 0, prefix, &mut ids)?)
+            }
+            Event::End(end) => {
+                depth = depth.checked_sub(1)?;
+                match depth {
+                    0 => Event::End(BytesEnd::new("g")),
+                    _ => Event::End(end),
+                }
+            }
+            Event::Text(text) if depth > 0 => {
+                let text = text.unescape().ok()?;
+                Event::Text(BytesText::new(&prefix_urls(&text, prefix)).into_owned())
+            }
+            Event::CData(data) if depth > 0 => {
+                let text = String::from_utf8_lossy(&data).into_owned();
+                Event::CData(BytesCData::new(prefix_urls(&text, prefix)))
+            }
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+        writer.write_event(event).ok()?;
+        buf.clear();
+    }
+    let markup = String::from_utf8(writer.into_inner()).ok()?;
+    ids.insert(prefix.to_string());
+    Some(InlineSvg { markup, ids })
+}
+
+fn prefix_element(
+    start: &BytesStart,
+    is_root: bool,
+    prefix: &str,
+    ids: &mut HashSet<String>,
+) -> Option<BytesStart<'static>> {
+    let name = String::from_utf8(start.name().as_ref().to_vec()).ok()?;
+    let mut element = match is_root {
+        true => BytesStart::new("g").with_attributes([("id", prefix)]),
+        false => BytesStart::new(name),
+    };
+    for attribute in start.attributes() {
+        let attribute = attribute.ok()?;
+        let key = String::from_utf8(attribute.key.as_ref().to_vec()).ok()?;
+        let value = attribute.unescape_value().ok()?;
+        let dropped = match is_root {
+            true => matches!(
+                key.as_str(),
+                "id" | "viewBox" | "width" | "height" | "x" | "y" | "version" | "baseProfile"
+            ),
+            false => false,
+        };
+        let key = match key.as_str() {
+            "xlink:href" => String::from("href"),
+            _ if dropped || key == "xmlns" => continue,
+            _ if key.contains(':') && !key.starts_with("xml:") => continue,
+            _ => key,
+        };
+        let value = match key.as_str() {
+            "id" => {
+                let id = format!("{}-{}", prefix, value);
+                ids.insert(id.clone());
+                id
+            }
+            "href" => match value.strip_prefix('#') {
+                Some(id) => format!("#{}-{}", prefix, id),
+                None => value.into_owned(),
+            },
+            _ => prefix_urls(&value, prefix),
+        };
+        element.push_attribute((key.as_str(), value.as_str()));
+    }
+    Some(element)
+}
+
+/// Prefix the ids in `url(#id)` references, quoted or not
+fn prefix_urls(value: &str, prefix: &str) -> String {
+    ["", "'", "\""]
+        .iter()
+        .fold(value.to_string(), |value, quote| {
+            let url = format!("url({}#", quote);
+            value.replace(&url, &format!("{}{}-", url, prefix))
+        })
+}
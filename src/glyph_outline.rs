@@ -0,0 +1,185 @@
+use ttf_parser::{Face, GlyphId, OutlineBuilder};
+
+use crate::path::{PathBuilder, PathSegment};
+
+/// Feeds `ttf-parser` outlines into a `PathBuilder`, flipping them into the y-down SVG space.
+/// `glyf` quadratics stay quadratics and `CFF`/`CFF2` cubics stay cubics, as stored in the font.
+///
+/// Sideways glyphs are turned 90 degrees to the left around their vertical origin, like the
+/// outlines DirectWrite returns for `isSideways` runs.
+struct OutlineAdapter<'a> {
+    builder: &'a mut PathBuilder,
+    space: OutlineSpace,
+}
+
+enum OutlineSpace {
+    Upright,
+    /// Turned around the vertical origin
+    Sideways(f32, f32),
+    /// Design space with the y axis pointing up, where `COLR` paints
+    Design,
+}
+
+impl OutlineAdapter<'_> {
+    // Written as subtractions so that 0 does not become -0
+    fn map(&self, x: f32, y: f32) -> (f32, f32) {
+        match self.space {
+            OutlineSpace::Upright => (x, 0.0 - y),
+            OutlineSpace::Sideways(origin_x, origin_y) => (origin_y - y, origin_x - x),
+            OutlineSpace::Design => (x, y),
+        }
+    }
+}
+
+impl OutlineBuilder for OutlineAdapter<'_> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.map(x, y);
+        self.builder.move_to(x, y);
+    }
+    fn line_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.map(x, y);
+        self.builder.line_to(x, y);
+    }
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (x1, y1) = self.map(x1, y1);
+        let (x, y) = self.map(x, y);
+        self.builder.quad_to(x1, y1, x, y);
+    }
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (x1, y1) = self.map(x1, y1);
+        let (x2, y2) = self.map(x2, y2);
+        let (x, y) = self.map(x, y);
+        self.builder.exact_cubic_to(x1, y1, x2, y2, x, y);
+    }
+    fn close(&mut self) {
+        self.builder.close();
+    }
+}
+
+/// Outline of one glyph, in design units
+pub(crate) fn glyph_path(face: &Face, glyph_id: u16, is_sideways: bool) -> Vec<PathSegment> {
+    let space = match is_sideways {
+        true => {
+            let (x, y) = vertical_origin(face, glyph_id);
+            OutlineSpace::Sideways(x as f32, y as f32)
+        }
+        false => OutlineSpace::Upright,
+    };
+    outline_path(face, glyph_id, space)
+}
+
+/// Outline of one glyph, in design units with the y axis pointing up
+pub(crate) fn design_glyph_path(face: &Face, glyph_id: u16) -> Vec<PathSegment> {
+    outline_path(face, glyph_id, OutlineSpace::Design)
+}
+
+fn outline_path(face: &Face, glyph_id: u16, space: OutlineSpace) -> Vec<PathSegment> {
+    let mut builder = PathBuilder::new();
+    let mut adapter = OutlineAdapter {
+        builder: &mut builder,
+        space,
+    };
+    face.outline_glyph(GlyphId(glyph_id), &mut adapter);
+    builder.reset()
+}
+
+/// Vertical origin of a glyph in design units, y pointing up: from `VORG`, then from `vmtx` and
+/// the glyph bounds, then centring the glyph in the ascender-descender box. This is the origin
+/// rustybuzz removes from the offsets of vertical runs.
+pub(crate) fn vertical_origin(face: &Face, glyph_id: u16) -> (i32, i32) {
+    let glyph = GlyphId(glyph_id);
+    let x = face.glyph_hor_advance(glyph).unwrap_or(0) as i32 / 2;
+    if let Some(y) = face.glyph_y_origin(glyph) {
+        return (x, y as i32);
+    }
+    let y = match face.glyph_bounding_box(glyph) {
+        Some(bbox) if face.tables().vmtx.is_some() => {
+            bbox.y_max as i32 + face.glyph_ver_side_bearing(glyph).unwrap_or(0) as i32
+        }
+        Some(bbox) => {
+            let advance = face.ascender() as i32 - face.descender() as i32;
+            let height = bbox.y_max as i32 - bbox.y_min as i32;
+            bbox.y_max as i32 + ((advance - height) >> 1)
+        }
+        None => face.ascender() as i32,
+    };
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data");
+
+    fn font_data(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{TEST_DATA}/{name}")).unwrap()
+    }
+
+    /// Outlines of every glyph of a font, as drawn upright
+    fn all_segments(face: &Face) -> Vec<PathSegment> {
+        (0..face.number_of_glyphs())
+            .flat_map(|glyph_id| glyph_path(face, glyph_id, false))
+            .collect()
+    }
+
+    fn count(segments: &[PathSegment], quads: bool) -> usize {
+        segments
+            .iter()
+            .filter(|segment| match quads {
+                true => matches!(segment, PathSegment::QuadTo(..)),
+                false => matches!(segment, PathSegment::CubicTo(..)),
+            })
+            .count()
+    }
+
+    #[test]
+    fn glyf_outlines_stay_quadratic() {
+        let data = font_data("DejaVuSans.ttf");
+        let face = Face::parse(&data, 0).unwrap();
+        let o = face.glyph_index('o').unwrap().0;
+        let segments = glyph_path(&face, o, false);
+        assert!(count(&segments, true) > 0);
+        assert_eq!(count(&segments, false), 0);
+        assert_eq!(count(&all_segments(&face), false), 0);
+    }
+
+    #[test]
+    fn cff_outlines_stay_cubic() {
+        // A CFF font and a CFF2 variable font
+        for name in ["TestGPOSTwo.otf", "AdobeVFPrototype-Subset.otf"] {
+            let data = font_data(name);
+            let face = Face::parse(&data, 0).unwrap();
+            let segments = all_segments(&face);
+            assert!(count(&segments, false) > 0, "{name}");
+            assert_eq!(count(&segments, true), 0, "{name}");
+        }
+    }
+
+    #[test]
+    fn outlines_are_flipped_or_turned_for_their_space() {
+        let data = font_data("DejaVuSans.ttf");
+        let face = Face::parse(&data, 0).unwrap();
+        let glyph_id = face.glyph_index('L').unwrap().0;
+        let design = design_glyph_path(&face, glyph_id);
+        let upright = glyph_path(&face, glyph_id, false);
+        let sideways = glyph_path(&face, glyph_id, true);
+        let (origin_x, origin_y) = vertical_origin(&face, glyph_id);
+        let (origin_x, origin_y) = (origin_x as f32, origin_y as f32);
+        assert_eq!(design.len(), upright.len());
+        for ((design, upright), sideways) in design.iter().zip(upright.iter()).zip(sideways) {
+            match (design, upright, sideways) {
+                (
+                    PathSegment::MoveTo(x, y) | PathSegment::LineTo(x, y),
+                    PathSegment::MoveTo(ux, uy) | PathSegment::LineTo(ux, uy),
+                    PathSegment::MoveTo(sx, sy) | PathSegment::LineTo(sx, sy),
+                ) => {
+                    assert_eq!((*ux, *uy), (*x, 0.0 - y));
+                    assert_eq!((sx, sy), (origin_y - y, origin_x - x));
+                }
+                (PathSegment::Close, PathSegment::Close, PathSegment::Close) => {}
+                segments => panic!("{segments:?}"),
+            }
+        }
+    }
+}
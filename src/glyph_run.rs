@@ -0,0 +1,548 @@
+use std::ops::Range;
+use std::path::PathBuf;
+
+use tiny_skia::{Point, Transform};
+
+use crate::color_glyph::{Bounds, ColorGlyph};
+use crate::css_gradient::CssGradient;
+use crate::document::{
+    DocumentFrame, FontPalette, GradientBox, InlineObject, MixBlendMode, PaintOrder,
+    StrokeLineJoin, TextDecorationLine, TextDecorationStyle, TextStyle,
+};
+use crate::document_analyzer::LayoutBounds;
+use crate::error::Result;
+use crate::glyph_image::GlyphImage;
+use crate::path::{build_path, PathSegment};
+
+/// Identifies a font face within the `GlyphOutlineSource` of the backend that produced a run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct FontId(pub(crate) usize);
+
+/// Rotation of a glyph run, clockwise, like `DWRITE_GLYPH_ORIENTATION_ANGLE`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum GlyphOrientation {
+    #[default]
+    Angle0,
+    Angle90,
+    #[allow(unused)]
+    Angle180,
+    Angle270,
+}
+
+impl GlyphOrientation {
+    pub(crate) fn quarters(self) -> u32 {
+        match self {
+            Self::Angle0 => 0,
+            Self::Angle90 => 1,
+            Self::Angle180 => 2,
+            Self::Angle270 => 3,
+        }
+    }
+}
+
+/// Glyph offset in DIPs, like `DWRITE_GLYPH_OFFSET`: along the reading direction and upwards
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct GlyphOffset {
+    pub(crate) advance_offset: f32,
+    pub(crate) ascender_offset: f32,
+}
+
+/// Painting properties attached to a run by the style it comes from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct DrawingEffect {
+    /// Text color; the first stop of a gradient, for what is drawn in a single color
+    pub(crate) color: Option<csscolorparser::Color>,
+    pub(crate) gradient: Option<CssGradient>,
+    pub(crate) gradient_box: GradientBox,
+    pub(crate) palette: Option<FontPalette>,
+    pub(crate) decoration: Option<TextDecoration>,
+    pub(crate) stroke: Option<TextStroke>,
+    pub(crate) shadows: Vec<TextShadow>,
+    /// Opacity of the run as a whole, when it is not opaque
+    pub(crate) opacity: Option<f32>,
+    pub(crate) blend_mode: MixBlendMode,
+}
+
+impl DrawingEffect {
+    /// Effect of a style, `None` when it has nothing to paint differently
+    pub(crate) fn from_style(style: &TextStyle) -> Option<Self> {
+        let parse_color = |color: &String| csscolorparser::parse(color).unwrap_or_default();
+        let decoration = style
+            .text_decoration_line
+            .filter(|line| !line.is_none())
+            .map(|line| TextDecoration {
+                line,
+                color: style.text_decoration_color.as_ref().map(parse_color),
+                style: style.text_decoration_style.unwrap_or_default(),
+                thickness: style.text_decoration_thickness,
+                underline_offset: style.text_underline_offset,
+            });
+        let stroke =
+            (style.stroke_color.is_some() || style.stroke_width.is_some()).then(|| TextStroke {
+                color: style.stroke_color.as_ref().map(parse_color),
+                width: style.stroke_width.unwrap_or(1.0),
+                line_join: style.stroke_linejoin.unwrap_or_default(),
+                paint_order: style.paint_order.unwrap_or_default(),
+            });
+        let shadows = style
+            .text_shadow
+            .as_ref()
+            .map(|list| list.0.clone())
+            .unwrap_or_default();
+        if style.color.is_none()
+            && style.font_palette.is_none()
+            && decoration.is_none()
+            && stroke.is_none()
+            && shadows.is_empty()
+            && style.opacity.is_none()
+            && style.mix_blend_mode.is_none()
+        {
+            return None;
+        }
+        Some(Self {
+            color: style.color.as_ref().map(|color| color.color.clone()),
+            gradient: style
+                .color
+                .as_ref()
+                .and_then(|color| color.gradient.clone()),
+            gradient_box: style.gradient_box.unwrap_or_default(),
+            palette: style.font_palette.clone(),
+            decoration,
+            stroke,
+            shadows,
+            opacity: style.opacity.map(|opacity| opacity.clamp(0.0, 1.0)),
+            blend_mode: style.mix_blend_mode.unwrap_or_default(),
+        })
+    }
+
+    pub(crate) fn opacity(&self) -> f32 {
+        self.opacity.unwrap_or(1.0)
+    }
+
+    /// Whether the run is drawn in a layer of its own, to fade or blend it as a whole
+    pub(crate) fn is_composited(&self) -> bool {
+        self.opacity() < 1.0 || self.blend_mode != MixBlendMode::Normal
+    }
+
+    /// Color of decoration lines, falling back to the text color, faded by the opacity of the
+    /// run since decorations are drawn apart from it
+    pub(crate) fn decoration_color(&self) -> Option<csscolorparser::Color> {
+        let decoration_color = self.decoration.as_ref().and_then(|d| d.color.clone());
+        let color = decoration_color.or(self.color.clone());
+        match self.opacity {
+            Some(opacity) if opacity < 1.0 => {
+                let mut color = color.unwrap_or_default();
+                color.a *= opacity as f64;
+                Some(color)
+            }
+            _ => color,
+        }
+    }
+
+    /// Color of the stroke, falling back to the text color
+    pub(crate) fn stroke_color(&self) -> Option<csscolorparser::Color> {
+        let stroke_color = self.stroke.as_ref().and_then(|s| s.color.clone());
+        stroke_color.or(self.color.clone())
+    }
+
+    /// Color of a shadow, falling back to the text color
+    pub(crate) fn shadow_color(&self, shadow: &TextShadow) -> Option<csscolorparser::Color> {
+        shadow.color.clone().or(self.color.clone())
+    }
+}
+
+/// One shadow of a `text-shadow` list, with lengths in DIPs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct TextShadow {
+    pub(crate) offset_x: f32,
+    pub(crate) offset_y: f32,
+    pub(crate) blur: f32,
+    pub(crate) color: Option<csscolorparser::Color>,
+}
+
+/// The `stroke-*` and `paint-order` properties of a style, with the width in DIPs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct TextStroke {
+    pub(crate) color: Option<csscolorparser::Color>,
+    pub(crate) width: f32,
+    pub(crate) line_join: StrokeLineJoin,
+    pub(crate) paint_order: PaintOrder,
+}
+
+/// The `text-decoration-*` properties of a style, with lengths in DIPs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct TextDecoration {
+    pub(crate) line: TextDecorationLine,
+    pub(crate) color: Option<csscolorparser::Color>,
+    pub(crate) style: TextDecorationStyle,
+    pub(crate) thickness: Option<f32>,
+    pub(crate) underline_offset: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DecorationKind {
+    Underline,
+    Overline,
+    LineThrough,
+}
+
+/// One decoration line, like the `DWRITE_UNDERLINE` and `DWRITE_STRIKETHROUGH` a layout hands to
+/// its renderer. It starts at the origin on the baseline and goes `width` DIPs along the line,
+/// turned by the orientation like a run. `offset` is the distance from the baseline down to the
+/// top edge of the line.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Decoration {
+    pub(crate) kind: DecorationKind,
+    pub(crate) origin_x: f32,
+    pub(crate) origin_y: f32,
+    pub(crate) orientation: GlyphOrientation,
+    pub(crate) width: f32,
+    pub(crate) offset: f32,
+    pub(crate) thickness: f32,
+    pub(crate) style: TextDecorationStyle,
+    /// Decoration color, falling back to the text color
+    pub(crate) color: Option<csscolorparser::Color>,
+}
+
+impl Decoration {
+    /// Clockwise rotation of the line in degrees
+    pub(crate) fn rotate_angle(&self) -> f32 {
+        90.0 * (self.orientation.quarters() as f32)
+    }
+}
+
+/// The `background-*` properties of a style, with lengths in DIPs
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TextBackground {
+    pub(crate) color: csscolorparser::Color,
+    pub(crate) padding: f32,
+    pub(crate) radius: f32,
+}
+
+impl TextBackground {
+    /// Background of a style, `None` when it sets no background color
+    pub(crate) fn from_style(style: &TextStyle) -> Option<Self> {
+        let mut color = csscolorparser::parse(style.background_color.as_ref()?).unwrap_or_default();
+        color.a *= style.opacity.map_or(1.0, |opacity| opacity.clamp(0.0, 1.0)) as f64;
+        Some(Self {
+            color,
+            padding: style.background_padding.unwrap_or(0.0),
+            radius: style.background_radius.unwrap_or(0.0),
+        })
+    }
+
+    /// The background of a piece of text on one line, given the box it takes on that line
+    pub(crate) fn around(&self, x: f32, y: f32, width: f32, height: f32) -> Background {
+        Background {
+            x: x - self.padding,
+            y: y - self.padding,
+            width: width + 2.0 * self.padding,
+            height: height + 2.0 * self.padding,
+            radius: self.radius,
+            color: self.color.clone(),
+        }
+    }
+}
+
+/// A rectangle filled behind the text of one style on one line, in the layout box. A style whose
+/// text is split by a line break or by bidi reordering gets a rectangle for each piece.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Background {
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+    pub(crate) radius: f32,
+    pub(crate) color: csscolorparser::Color,
+}
+
+/// A positioned glyph run, as a layout backend hands it to an output format.
+///
+/// Positions and sizes are in DIPs. Glyphs are stored in logical order; when `bidi_level` is
+/// odd they advance right-to-left from the baseline origin, as with `DWRITE_GLYPH_RUN`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GlyphRun {
+    pub(crate) font: FontId,
+    pub(crate) font_size: f32,
+    pub(crate) baseline_origin_x: f32,
+    pub(crate) baseline_origin_y: f32,
+    pub(crate) glyph_ids: Vec<u16>,
+    pub(crate) advances: Vec<f32>,
+    pub(crate) offsets: Vec<GlyphOffset>,
+    pub(crate) orientation: GlyphOrientation,
+    pub(crate) is_sideways: bool,
+    /// Clockwise rotation in degrees on top of the orientation, of a cluster set on a path
+    pub(crate) path_angle: f32,
+    pub(crate) bidi_level: u8,
+    /// UTF-16 position of `source_text` in the frame's text
+    pub(crate) text_position: usize,
+    pub(crate) source_text: String,
+    /// For every UTF-16 code unit of `source_text`, the index of the first glyph of its cluster
+    pub(crate) cluster_map: Vec<u16>,
+    pub(crate) drawing_effect: Option<DrawingEffect>,
+}
+
+impl GlyphRun {
+    pub(crate) fn is_right_to_left(&self) -> bool {
+        self.bidi_level % 2 == 1
+    }
+
+    /// Clockwise rotation of the run in degrees; sideways glyphs add a quarter turn
+    pub(crate) fn rotate_angle(&self) -> f32 {
+        let mut quarters = self.orientation.quarters();
+        if self.is_sideways {
+            quarters = (1 + quarters) % 4
+        }
+        90.0 * (quarters as f32) + self.path_angle
+    }
+
+    /// Position of each glyph's origin relative to the baseline origin, along the run
+    pub(crate) fn glyph_positions(&self) -> Vec<(f32, f32)> {
+        let mut positions = Vec::with_capacity(self.glyph_ids.len());
+        let mut pen = 0.0;
+        for (i, advance) in self.advances.iter().enumerate() {
+            let offset = self.offsets.get(i).copied().unwrap_or_default();
+            if self.is_right_to_left() {
+                pen -= advance;
+                positions.push((pen - offset.advance_offset, -offset.ascender_offset));
+            } else {
+                positions.push((pen + offset.advance_offset, -offset.ascender_offset));
+                pen += advance;
+            }
+        }
+        positions
+    }
+
+    /// Glyphs and source text of every cluster, in logical order
+    pub(crate) fn clusters(&self) -> Vec<(Range<usize>, String)> {
+        let units: Vec<u16> = self.source_text.encode_utf16().collect();
+        let mut clusters: Vec<(usize, Vec<u16>)> = Vec::new();
+        for (unit, glyph) in units.iter().zip(self.cluster_map.iter()) {
+            match clusters.last_mut() {
+                Some((first, text)) if *first == *glyph as usize => text.push(*unit),
+                _ => clusters.push((*glyph as usize, vec![*unit])),
+            }
+        }
+        let ends: Vec<usize> = clusters
+            .iter()
+            .skip(1)
+            .map(|(first, _)| *first)
+            .chain([self.glyph_ids.len()])
+            .collect();
+        clusters
+            .into_iter()
+            .zip(ends)
+            .map(|((first, text), end)| (first..end.max(first), String::from_utf16_lossy(&text)))
+            .collect()
+    }
+
+    pub(crate) fn total_advance(&self) -> f32 {
+        self.advances.iter().sum()
+    }
+
+    /// Bounds of the glyph outlines on the canvas, `None` when no glyph has an outline
+    pub(crate) fn ink_bounds(
+        &self,
+        frame: &GlyphFrame,
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Result<Option<Bounds>> {
+        let scalar = outlines.units_per_em(self.font) / self.font_size;
+        let run_transform = Transform::from_translate(
+            self.baseline_origin_x + frame.offset_x,
+            self.baseline_origin_y + frame.offset_y,
+        )
+        .pre_rotate(self.rotate_angle())
+        .pre_scale(1.0 / scalar, 1.0 / scalar);
+        let mut bounds = None;
+        for (glyph_id, (x, y)) in self.glyph_ids.iter().zip(self.glyph_positions()) {
+            let segments = outlines.glyph_path(self.font, *glyph_id, self.is_sideways)?;
+            let transform = run_transform.pre_translate(x * scalar, y * scalar);
+            let Some(rect) =
+                build_path(&segments).and_then(|path| path.bounds().transform(transform))
+            else {
+                continue;
+            };
+            let glyph = [rect.left(), rect.top(), rect.right(), rect.bottom()];
+            bounds = join_bounds(bounds, Some(glyph));
+        }
+        Ok(bounds)
+    }
+}
+
+pub(crate) fn join_bounds(a: Option<Bounds>, b: Option<Bounds>) -> Option<Bounds> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some([
+            a[0].min(b[0]),
+            a[1].min(b[1]),
+            a[2].max(b[2]),
+            a[3].max(b[3]),
+        ]),
+        (a, b) => a.or(b),
+    }
+}
+
+/// An inline object placed by a layout backend, upright with its top left corner at `x`, `y`
+#[derive(Clone, Debug)]
+pub(crate) struct PlacedObject {
+    pub(crate) object: InlineObject,
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+}
+
+/// All runs drawn into one `DocumentFrame`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GlyphFrame {
+    pub(crate) offset_x: f32,
+    pub(crate) offset_y: f32,
+    /// Box of the laid out text on the canvas, before `transform`
+    pub(crate) bounds: Bounds,
+    /// Maps everything in the frame onto the canvas
+    pub(crate) transform: Transform,
+    pub(crate) title: Option<String>,
+    pub(crate) desc: Option<String>,
+    pub(crate) copyable: bool,
+    pub(crate) runs: Vec<GlyphRun>,
+    pub(crate) backgrounds: Vec<Background>,
+    pub(crate) decorations: Vec<Decoration>,
+    pub(crate) objects: Vec<PlacedObject>,
+}
+
+impl GlyphFrame {
+    pub(crate) fn new(frame: &DocumentFrame, offset_x: f32, offset_y: f32) -> Self {
+        Self {
+            offset_x,
+            offset_y,
+            bounds: [offset_x, offset_y, offset_x, offset_y],
+            transform: Transform::identity(),
+            title: frame.title.clone(),
+            desc: frame.desc.clone(),
+            copyable: frame.copyable.unwrap_or(false),
+            runs: Vec::new(),
+            backgrounds: Vec::new(),
+            decorations: Vec::new(),
+            objects: Vec::new(),
+        }
+    }
+
+    /// Set the box of the laid out text, which `LayoutBounds` gives relative to the offset
+    pub(crate) fn set_bounds(&mut self, bounds: &LayoutBounds) {
+        let (left, top) = (self.offset_x + bounds.left, self.offset_y + bounds.top);
+        self.bounds = [left, top, left + bounds.width, top + bounds.height];
+    }
+
+    /// Box of the laid out text on the canvas, enclosing it once transformed
+    pub(crate) fn canvas_bounds(&self) -> Bounds {
+        let [x0, y0, x1, y1] = self.bounds;
+        let mut corners =
+            [(x0, y0), (x1, y0), (x1, y1), (x0, y1)].map(|(x, y)| Point::from_xy(x, y));
+        self.transform.map_points(&mut corners);
+        corners.iter().fold(
+            [
+                f32::INFINITY,
+                f32::INFINITY,
+                f32::NEG_INFINITY,
+                f32::NEG_INFINITY,
+            ],
+            |[x0, y0, x1, y1], p| [x0.min(p.x), y0.min(p.y), x1.max(p.x), y1.max(p.y)],
+        )
+    }
+}
+
+/// Resolves the `FontId`s found in glyph runs to glyph outlines
+pub(crate) trait GlyphOutlineSource {
+    fn units_per_em(&self, font: FontId) -> f32;
+
+    /// Outline of a glyph, in design units with the y axis pointing down
+    fn glyph_path(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+    ) -> Result<Vec<PathSegment>>;
+
+    /// Path and face index of the font file behind a face, when it is known
+    fn font_file(&self, font: FontId) -> Option<(PathBuf, u32)>;
+
+    /// Paint graph of a `COLR` glyph with the colors of a `CPAL` palette, `None` when the glyph
+    /// has no color layers. `foreground` replaces the text color entries of the palette.
+    fn color_glyph(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+        palette: Option<&FontPalette>,
+        foreground: &csscolorparser::Color,
+    ) -> Option<ColorGlyph>;
+
+    /// `SVG ` document or bitmap of a glyph, taking the bitmap strike that suits `pixels_per_em`
+    fn glyph_image(
+        &self,
+        font: FontId,
+        glyph_id: u16,
+        is_sideways: bool,
+        pixels_per_em: u16,
+    ) -> Option<GlyphImage>;
+}
+
+/// An output format that glyph frames are drawn into
+pub(crate) trait DocumentRenderer {
+    fn add_frame(&mut self, frame: &GlyphFrame, outlines: &dyn GlyphOutlineSource) -> Result<()>;
+
+    /// Write the finished document
+    fn write(self: Box<Self>, out: &mut dyn std::io::Write) -> Result<()>;
+}
+
+#[cfg(test)]
+pub(crate) mod test_outlines {
+    use super::*;
+
+    /// Glyph `n` is a square of `n` units, a DIP at a font size of one
+    pub(crate) struct Squares;
+
+    impl GlyphOutlineSource for Squares {
+        fn units_per_em(&self, _font: FontId) -> f32 {
+            1.0
+        }
+
+        fn glyph_path(
+            &self,
+            _font: FontId,
+            glyph_id: u16,
+            _is_sideways: bool,
+        ) -> Result<Vec<PathSegment>> {
+            let size = glyph_id as f32;
+            Ok(vec![
+                PathSegment::MoveTo(0.0, 0.0),
+                PathSegment::LineTo(size, 0.0),
+                PathSegment::LineTo(size, -size),
+                PathSegment::LineTo(0.0, -size),
+                PathSegment::Close,
+            ])
+        }
+
+        fn font_file(&self, _font: FontId) -> Option<(PathBuf, u32)> {
+            None
+        }
+
+        fn color_glyph(
+            &self,
+            _font: FontId,
+            _glyph_id: u16,
+            _is_sideways: bool,
+            _palette: Option<&FontPalette>,
+            _foreground: &csscolorparser::Color,
+        ) -> Option<ColorGlyph> {
+            None
+        }
+
+        fn glyph_image(
+            &self,
+            _font: FontId,
+            _glyph_id: u16,
+            _is_sideways: bool,
+            _pixels_per_em: u16,
+        ) -> Option<GlyphImage> {
+            None
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs;
+
+#[cfg(windows)]
+use windows::{
+    core::{implement, IUnknown, Result as WinResult},
+    Win32::{Foundation::BOOL, Graphics::DirectWrite::*},
+};
+
+use crate::document::{InlineObject, InlineObjectSource};
+use crate::error::{AppError, Result};
+
+/// What an inline object draws
+pub(crate) enum ObjectContent {
+    /// A PNG, JPEG or SVG file with its media type, scaled to fit the object
+    Image { mime: &'static str, data: Vec<u8> },
+    /// SVG elements in the coordinates of the object
+    Svg(String),
+}
+
+/// Read the image file of an object, telling its format from its signature
+pub(crate) fn object_content(object: &InlineObject) -> Result<ObjectContent> {
+    match &object.source {
+        InlineObjectSource::Svg(markup) => Ok(ObjectContent::Svg(markup.clone())),
+        InlineObjectSource::Image(path) => {
+            let data = fs::read(path)?;
+            let mime = image_type(&data).ok_or_else(|| {
+                AppError::Render(format!("{} is not a PNG, JPEG or SVG image", path))
+            })?;
+            Ok(ObjectContent::Image { mime, data })
+        }
+    }
+}
+
+fn image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some("image/png");
+    }
+    if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        return Some("image/jpeg");
+    }
+    let head = String::from_utf8_lossy(&data[..data.len().min(4096)]);
+    head.contains("<svg").then_some("image/svg+xml")
+}
+
+/// An inline object as DirectWrite measures it. Objects are collected by the renderer's
+/// `DrawInlineObject`, so they draw nothing themselves.
+#[cfg(windows)]
+#[implement(IDWriteInlineObject)]
+pub(crate) struct InlineObjectImpl {
+    object: InlineObject,
+}
+
+#[cfg(windows)]
+impl InlineObjectImpl {
+    pub(crate) fn new(object: InlineObject) -> Self {
+        Self { object }
+    }
+
+    pub(crate) fn object(&self) -> &InlineObject {
+        &self.object
+    }
+}
+
+#[cfg(windows)]
+#[allow(non_snake_case)]
+impl IDWriteInlineObject_Impl for InlineObjectImpl_Impl {
+    fn Draw(
+        &self,
+        _client_drawing_context: *const core::ffi::c_void,
+        _renderer: Option<&IDWriteTextRenderer>,
+        _origin_x: f32,
+        _origin_y: f32,
+        _is_sideways: BOOL,
+        _is_right_to_left: BOOL,
+        _client_drawing_effect: Option<&IUnknown>,
+    ) -> WinResult<()> {
+        Ok(())
+    }
+
+    fn GetMetrics(&self) -> WinResult<DWRITE_INLINE_OBJECT_METRICS> {
+        Ok(DWRITE_INLINE_OBJECT_METRICS {
+            width: self.object.width,
+            height: self.object.height,
+            baseline: self.object.baseline(),
+            supportsSideways: BOOL::from(false),
+        })
+    }
+
+    fn GetOverhangMetrics(&self) -> WinResult<DWRITE_OVERHANG_METRICS> {
+        Ok(DWRITE_OVERHANG_METRICS::default())
+    }
+
+    fn GetBreakConditions(
+        &self,
+        break_condition_before: *mut DWRITE_BREAK_CONDITION,
+        break_condition_after: *mut DWRITE_BREAK_CONDITION,
+    ) -> WinResult<()> {
+        unsafe {
+            *break_condition_before = DWRITE_BREAK_CONDITION_NEUTRAL;
+            *break_condition_after = DWRITE_BREAK_CONDITION_NEUTRAL;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+use serde::Serialize;
+
+use crate::{
+    color_glyph::Bounds,
+    document::{InlineObjectSource, TextDecorationStyle},
+    error::Result,
+    glyph_run::{
+        Background, Decoration, DecorationKind, DocumentRenderer, GlyphFrame, GlyphOutlineSource,
+        GlyphRun, PlacedObject,
+    },
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutDocument {
+    frames: Vec<LayoutFrame>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutFrame {
+    offset_x: f32,
+    offset_y: f32,
+    /// Box of the laid out text on the canvas, enclosing it once transformed
+    bounds: LayoutRect,
+    /// `matrix()` values of the frame transform, which places everything else on the canvas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transform: Option<[f32; 6]>,
+    runs: Vec<LayoutRun>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    backgrounds: Vec<LayoutBackground>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    decorations: Vec<LayoutDecoration>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    objects: Vec<LayoutObject>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutRect {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+/// A glyph run as the layout backend produced it, before any outline is looked up
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutRun {
+    font_file: Option<String>,
+    face_index: Option<u32>,
+    units_per_em: f32,
+    font_size: f32,
+    baseline_origin_x: f32,
+    baseline_origin_y: f32,
+    /// Clockwise rotation in degrees, without the quarter turn of sideways glyphs
+    orientation: u32,
+    is_sideways: bool,
+    /// Rotation in degrees on top of the orientation, of a cluster set on a path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path_angle: Option<f32>,
+    bidi_level: u8,
+    glyph_ids: Vec<u16>,
+    advances: Vec<f32>,
+    /// Advance and ascender offsets of every glyph
+    offsets: Vec<[f32; 2]>,
+    /// UTF-16 offset of the run in the frame's text
+    text_position: usize,
+    source_text: String,
+    cluster_map: Vec<u16>,
+    /// For every glyph, the UTF-16 offset in the frame's text where its cluster starts
+    glyph_clusters: Vec<usize>,
+    color: Option<String>,
+}
+
+/// A background rectangle, padding included
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutBackground {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    radius: f32,
+    color: String,
+}
+
+/// A decoration line, with its top edge `offset` below the baseline at its origin
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutDecoration {
+    kind: &'static str,
+    origin_x: f32,
+    origin_y: f32,
+    /// Clockwise rotation in degrees
+    orientation: u32,
+    width: f32,
+    offset: f32,
+    thickness: f32,
+    style: TextDecorationStyle,
+    color: Option<String>,
+}
+
+/// An inline object's box; `image` is the path of its file, absent for SVG elements
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutObject {
+    image: Option<String>,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    alt: Option<String>,
+}
+
+/// Writes the glyph runs of every frame as JSON, so that layout results can be compared
+/// without comparing outlines
+pub(crate) struct LayoutJsonRenderer {
+    frames: Vec<LayoutFrame>,
+}
+
+impl LayoutJsonRenderer {
+    pub(crate) fn new() -> Self {
+        Self { frames: Vec::new() }
+    }
+}
+
+impl DocumentRenderer for LayoutJsonRenderer {
+    fn add_frame(&mut self, frame: &GlyphFrame, outlines: &dyn GlyphOutlineSource) -> Result<()> {
+        let runs = frame
+            .runs
+            .iter()
+            .map(|run| layout_run(run, outlines))
+            .collect();
+        self.frames.push(LayoutFrame {
+            offset_x: frame.offset_x,
+            offset_y: frame.offset_y,
+            bounds: layout_rect(frame.canvas_bounds()),
+            transform: (!frame.transform.is_identity()).then(|| {
+                let m = frame.transform;
+                [m.sx, m.ky, m.kx, m.sy, m.tx, m.ty]
+            }),
+            runs,
+            backgrounds: frame.backgrounds.iter().map(layout_background).collect(),
+            decorations: frame.decorations.iter().map(layout_decoration).collect(),
+            objects: frame.objects.iter().map(layout_object).collect(),
+        });
+        Ok(())
+    }
+
+    fn write(self: Box<Self>, out: &mut dyn std::io::Write) -> Result<()> {
+        let document = LayoutDocument {
+            frames: self.frames,
+        };
+        serde_json::to_writer_pretty(&mut *out, &document)?;
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+fn layout_run(run: &GlyphRun, outlines: &dyn GlyphOutlineSource) -> LayoutRun {
+    let font_file = outlines.font_file(run.font);
+    LayoutRun {
+        font_file: font_file
+            .as_ref()
+            .map(|(path, _)| path.display().to_string()),
+        face_index: font_file.map(|(_, index)| index),
+        units_per_em: outlines.units_per_em(run.font),
+        font_size: run.font_size,
+        baseline_origin_x: run.baseline_origin_x,
+        baseline_origin_y: run.baseline_origin_y,
+        orientation: 90 * run.orientation.quarters(),
+        is_sideways: run.is_sideways,
+        path_angle: (run.path_angle != 0.0).then_some(run.path_angle),
+        bidi_level: run.bidi_level,
+        glyph_ids: run.glyph_ids.clone(),
+        advances: run.advances.clone(),
+        offsets: run
+            .offsets
+            .iter()
+            .map(|offset| [offset.advance_offset, offset.ascender_offset])
+            .collect(),
+        text_position: run.text_position,
+        source_text: run.source_text.clone(),
+        cluster_map: run.cluster_map.clone(),
+        glyph_clusters: glyph_clusters(run),
+        color: run
+            .drawing_effect
+            .as_ref()
+            .and_then(|effect| effect.color.as_ref())
+            .map(|color| color.to_hex_string()),
+    }
+}
+
+fn layout_rect([x0, y0, x1, y1]: Bounds) -> LayoutRect {
+    LayoutRect {
+        x: x0,
+        y: y0,
+        width: x1 - x0,
+        height: y1 - y0,
+    }
+}
+
+fn layout_background(background: &Background) -> LayoutBackground {
+    LayoutBackground {
+        x: background.x,
+        y: background.y,
+        width: background.width,
+        height: background.height,
+        radius: background.radius,
+        color: background.color.to_hex_string(),
+    }
+}
+
+fn layout_decoration(decoration: &Decoration) -> LayoutDecoration {
+    LayoutDecoration {
+        kind: match decoration.kind {
+            DecorationKind::Underline => "underline",
+            DecorationKind::Overline => "overline",
+            DecorationKind::LineThrough => "line-through",
+        },
+        origin_x: decoration.origin_x,
+        origin_y: decoration.origin_y,
+        orientation: 90 * decoration.orientation.quarters(),
+        width: decoration.width,
+        offset: decoration.offset,
+        thickness: decoration.thickness,
+        style: decoration.style,
+        color: decoration.color.as_ref().map(|color| color.to_hex_string()),
+    }
+}
+
+fn layout_object(placed: &PlacedObject) -> LayoutObject {
+    let object = &placed.object;
+    LayoutObject {
+        image: match &object.source {
+            InlineObjectSource::Image(path) => Some(path.clone()),
+            InlineObjectSource::Svg(_) => None,
+        },
+        x: placed.x,
+        y: placed.y,
+        width: object.width,
+        height: object.height,
+        alt: object.alt.clone(),
+    }
+}
+
+/// Invert the cluster map: the first code unit mapped to each glyph's cluster
+fn glyph_clusters(run: &GlyphRun) -> Vec<usize> {
+    // First glyph and first code unit of every cluster, in order
+    let mut clusters: Vec<(usize, usize)> = Vec::new();
+    for (unit, &glyph) in run.cluster_map.iter().enumerate() {
+        if clusters.last().map(|&(first, _)| first) != Some(glyph as usize) {
+            clusters.push((glyph as usize, unit));
+        }
+    }
+    (0..run.glyph_ids.len())
+        .map(|glyph| {
+            let unit = clusters
+                .iter()
+                .rev()
+                .find(|&&(first, _)| first <= glyph)
+                .map_or(0, |&(_, unit)| unit);
+            run.text_position + unit
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        document::Document, document_analyzer::DocumentAnalyzer, font_collection::FontCollection,
+        text_layout::TextLayout,
+    };
+
+    /// Runs of a frame laid out by the portable backend with the test font
+    fn layout_runs(writing_mode: &str, text: &str) -> Vec<LayoutRun> {
+        let document: Document = serde_json::from_value(serde_json::json!({
+            "fontFiles": [concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/DejaVuSans.ttf")],
+            "frames": [{
+                "writing-mode": writing_mode,
+                "contents": [{ "font-family": "DejaVu Sans" }, text],
+            }],
+        }))
+        .unwrap();
+        let fonts = FontCollection::load(&document).unwrap();
+        let frame = &document.frames[0];
+        let mut analyzer = DocumentAnalyzer::new();
+        analyzer.analyze(&frame.contents);
+        let text_layout =
+            TextLayout::new(&fonts, &analyzer, document.width, document.height, frame);
+        let mut glyph_frame = GlyphFrame::new(frame, 0.0, 0.0);
+        glyph_frame.runs = text_layout.glyph_runs();
+
+        let mut renderer = LayoutJsonRenderer::new();
+        renderer.add_frame(&glyph_frame, &fonts).unwrap();
+        renderer.frames.remove(0).runs
+    }
+
+    /// Every code unit of a run maps to a glyph whose cluster starts at or before it
+    fn assert_clusters_cover_text(run: &LayoutRun) {
+        assert_eq!(
+            run.cluster_map.len(),
+            run.source_text.encode_utf16().count()
+        );
+        assert_eq!(run.glyph_clusters.len(), run.glyph_ids.len());
+        for (unit, &glyph) in run.cluster_map.iter().enumerate() {
+            let glyph = glyph as usize;
+            assert!(run.glyph_clusters[glyph] <= run.text_position + unit);
+            assert_eq!(
+                run.cluster_map[run.glyph_clusters[glyph] - run.text_position],
+                glyph as u16
+            );
+        }
+    }
+
+    fn clusters(run: &LayoutRun) -> (&str, usize, &[u16], &[usize]) {
+        (
+            &run.source_text,
+            run.text_position,
+            &run.cluster_map,
+            &run.glyph_clusters,
+        )
+    }
+
+    #[test]
+    fn clusters_map_to_utf16_offsets() {
+        // The ffi ligature, a Hebrew letter with its point, and a character outside the BMP
+        let runs = layout_runs("lr-tb", "office \u{5d0}\u{5b8}\u{5d1} \u{1d400}!");
+        runs.iter().for_each(assert_clusters_cover_text);
+        let runs: Vec<_> = runs.iter().map(clusters).collect();
+        assert_eq!(
+            runs,
+            [
+                (
+                    "office ",
+                    0,
+                    &[0, 1, 1, 1, 2, 3, 4][..],
+                    &[0, 1, 4, 5, 6][..]
+                ),
+                ("\u{5d0}\u{5b8}\u{5d1}", 7, &[0, 0, 2], &[7, 7, 9]),
+                (" \u{1d400}!", 10, &[0, 1, 1, 2], &[10, 11, 13]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reordered_runs_keep_their_logical_offsets() {
+        // In a right-to-left paragraph the embedded left-to-right run comes first, its ligature
+        // still mapped to the offsets of its text
+        let runs = layout_runs("rl-tb", "\u{5d0}\u{5b8}\u{5d1} office \u{1d400}!");
+        runs.iter().for_each(assert_clusters_cover_text);
+        let levels: Vec<u8> = runs.iter().map(|run| run.bidi_level).collect();
+        assert_eq!(levels, [1, 2, 1]);
+        let runs: Vec<_> = runs.iter().map(clusters).collect();
+        assert_eq!(
+            runs,
+            [
+                ("!", 13, &[0][..], &[13][..]),
+                (
+                    "office \u{1d400}",
+                    4,
+                    &[0, 1, 1, 1, 2, 3, 4, 5, 5],
+                    &[4, 5, 8, 9, 10, 11]
+                ),
+                ("\u{5d0}\u{5b8}\u{5d1} ", 0, &[0, 0, 2, 3], &[0, 0, 2, 3]),
+            ]
+        );
+    }
+}
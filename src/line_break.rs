@@ -0,0 +1,207 @@
+use unicode_linebreak::{break_property, linebreaks, BreakClass, BreakOpportunity};
+
+use crate::document::{LineBreak, WordBreak};
+
+/// Line break opportunities of a text: UAX #14, tailored by the CSS `line-break` and
+/// `word-break` properties. Positions are UTF-8 byte offsets of the text following the break.
+pub(crate) fn line_breaks(
+    text: &str,
+    line_break: &LineBreak,
+    word_break: &WordBreak,
+) -> Vec<(usize, BreakOpportunity)> {
+    let mut breaks: Vec<Option<BreakOpportunity>> = vec![None; text.len() + 1];
+    for (pos, opportunity) in linebreaks(text) {
+        breaks[pos] = Some(opportunity);
+    }
+
+    // Class of the preceding character, with combining marks attached to their base (LB9)
+    let mut before: Option<BreakClass> = None;
+    for (pos, ch) in text.char_indices() {
+        let class = break_property(ch as u32);
+        if let Some(prev) = before {
+            if breaks[pos] != Some(BreakOpportunity::Mandatory) {
+                let allowed = match line_break {
+                    LineBreak::Anywhere => !is_attached(class),
+                    _ => {
+                        let allowed = breaks[pos].is_some()
+                            || tailored_break(line_break, prev, class)
+                            || word_break_allows(word_break, prev, class);
+                        allowed && !word_break_forbids(word_break, prev, class)
+                    }
+                };
+                breaks[pos] = allowed.then_some(BreakOpportunity::Allowed);
+            }
+        }
+        if !is_attached(class) || before.is_none() {
+            before = Some(class);
+        }
+    }
+
+    breaks
+        .into_iter()
+        .enumerate()
+        .filter_map(|(pos, opportunity)| opportunity.map(|o| (pos, o)))
+        .collect()
+}
+
+/// Characters that stay with the one before them, even with `line-break: anywhere`
+fn is_attached(class: BreakClass) -> bool {
+    matches!(
+        class,
+        BreakClass::CombiningMark | BreakClass::ZeroWidthJoiner | BreakClass::EmojiModifier
+    )
+}
+
+/// Ideographs, kana and hangul
+fn is_cjk(class: BreakClass) -> bool {
+    matches!(
+        class,
+        BreakClass::Ideographic
+            | BreakClass::ConditionalJapaneseStarter
+            | BreakClass::HangulLvSyllable
+            | BreakClass::HangulLvtSyllable
+            | BreakClass::HangulLJamo
+            | BreakClass::HangulVJamo
+            | BreakClass::HangulTJamo
+    )
+}
+
+/// Typographic letter units, which `word-break` applies to
+fn is_letter(class: BreakClass) -> bool {
+    is_cjk(class)
+        || matches!(
+            class,
+            BreakClass::Alphabetic
+                | BreakClass::HebrewLetter
+                | BreakClass::Ambiguous
+                | BreakClass::Numeric
+                | BreakClass::ComplexContext
+        )
+}
+
+/// Breaks that `line-break` adds on top of UAX #14, which resolves small kana to NS (strict)
+fn tailored_break(line_break: &LineBreak, prev: BreakClass, class: BreakClass) -> bool {
+    let normal =
+        class == BreakClass::ConditionalJapaneseStarter && (is_cjk(prev) || is_closing(prev));
+    let loose = is_cjk(prev)
+        && matches!(
+            class,
+            BreakClass::NonStarter | BreakClass::Inseparable | BreakClass::Postfix
+        )
+        || prev == BreakClass::Prefix && is_cjk(class);
+    match line_break {
+        LineBreak::Strict | LineBreak::Anywhere => false,
+        LineBreak::Auto | LineBreak::Normal => normal,
+        LineBreak::Loose => normal || loose,
+    }
+}
+
+fn is_closing(class: BreakClass) -> bool {
+    matches!(
+        class,
+        BreakClass::ClosePunctuation | BreakClass::CloseParenthesis
+    )
+}
+
+fn word_break_allows(word_break: &WordBreak, prev: BreakClass, class: BreakClass) -> bool {
+    matches!(word_break, WordBreak::BreakAll) && is_letter(prev) && is_letter(class)
+}
+
+fn word_break_forbids(word_break: &WordBreak, prev: BreakClass, class: BreakClass) -> bool {
+    matches!(word_break, WordBreak::KeepAll) && is_letter(prev) && is_letter(class)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Character positions of the allowed and mandatory breaks, end of text included
+    fn breaks(text: &str, line_break: LineBreak, word_break: WordBreak) -> Vec<usize> {
+        line_breaks(text, &line_break, &word_break)
+            .into_iter()
+            .map(|(pos, _)| text[..pos].chars().count())
+            .collect()
+    }
+
+    #[test]
+    fn small_kana_start_lines_unless_strict() {
+        // ID, small hiragana a (CJ), ID
+        let text = "\u{6f22}\u{3041}\u{6f22}";
+        assert_eq!(breaks(text, LineBreak::Strict, WordBreak::Normal), [2, 3]);
+        assert_eq!(
+            breaks(text, LineBreak::Normal, WordBreak::Normal),
+            [1, 2, 3]
+        );
+        assert_eq!(breaks(text, LineBreak::Auto, WordBreak::Normal), [1, 2, 3]);
+        assert_eq!(breaks(text, LineBreak::Loose, WordBreak::Normal), [1, 2, 3]);
+    }
+
+    #[test]
+    fn loose_breaks_before_non_starters_after_ideographs() {
+        // Iteration mark (NS), two dot leader (IN) and per mille sign (PO) after an ideograph
+        for ch in ['\u{3005}', '\u{2025}', '\u{2030}'] {
+            let text = format!("\u{6f22}{ch}");
+            assert_eq!(
+                breaks(&text, LineBreak::Normal, WordBreak::Normal),
+                [2],
+                "{ch:?}"
+            );
+            assert_eq!(
+                breaks(&text, LineBreak::Loose, WordBreak::Normal),
+                [1, 2],
+                "{ch:?}"
+            );
+        }
+        // Not after letters of other scripts
+        assert_eq!(
+            breaks("a\u{3005}", LineBreak::Loose, WordBreak::Normal),
+            [2]
+        );
+        // Prefixes (PR) before ideographs
+        assert_eq!(
+            breaks("$\u{6f22}", LineBreak::Normal, WordBreak::Normal),
+            [2]
+        );
+        assert_eq!(
+            breaks("$\u{6f22}", LineBreak::Loose, WordBreak::Normal),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn keep_all_keeps_hangul_words_together() {
+        let text = "\u{d55c}\u{ad6d}\u{c5b4} \u{bb38}\u{c7a5}";
+        assert_eq!(
+            breaks(text, LineBreak::Auto, WordBreak::Normal),
+            [1, 2, 4, 5, 6]
+        );
+        assert_eq!(breaks(text, LineBreak::Auto, WordBreak::KeepAll), [4, 6]);
+    }
+
+    #[test]
+    fn break_all_breaks_latin_words_between_letters() {
+        let text = "word wrap";
+        assert_eq!(breaks(text, LineBreak::Auto, WordBreak::Normal), [5, 9]);
+        assert_eq!(
+            breaks(text, LineBreak::Auto, WordBreak::BreakAll),
+            [1, 2, 3, 5, 6, 7, 8, 9]
+        );
+    }
+
+    #[test]
+    fn anywhere_keeps_combining_marks_with_their_base() {
+        assert_eq!(
+            breaks("e\u{301}a b", LineBreak::Anywhere, WordBreak::Normal),
+            [2, 3, 4, 5]
+        );
+        // Marks after a space stay with the space
+        assert_eq!(
+            breaks("a \u{301}b", LineBreak::Anywhere, WordBreak::Normal),
+            [1, 3, 4]
+        );
+        assert_eq!(
+            breaks("a\nb", LineBreak::Anywhere, WordBreak::Normal),
+            [1, 2, 3]
+        );
+    }
+}
@@ -1,25 +1,122 @@
 use clap::Parser;
 use document::Document;
+use layout_json::LayoutJsonRenderer;
+use pdf_render::PdfDocumentRenderer;
+use png_render::PngDocumentRenderer;
 use std::fs::File;
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+#[cfg(windows)]
+use std::{cell::RefCell, rc::Rc};
 use svg_text_render::SvgDocumentRenderer;
+#[cfg(windows)]
 use windows::{
     core::{w, Interface},
     Win32::Graphics::DirectWrite::*,
 };
 
 use crate::{
-    document_analyzer::DocumentAnalyzer, error::Result, font_loader::load_font_collection,
+    document_analyzer::DocumentAnalyzer,
+    error::Result,
+    font_collection::FontCollection,
+    frame_transform::frame_transform,
+    glyph_run::{DocumentRenderer, GlyphFrame},
+    text_layout::TextLayout,
+    text_path::place_on_path,
+};
+#[cfg(windows)]
+use crate::{
+    document_analyzer::LayoutBounds,
+    dwrite_text_render::{DWriteFontTable, GlyphRunCollector},
+    font_loader::load_font_collection,
 };
 
+mod color_glyph;
+mod css_gradient;
 mod document;
 mod document_analyzer;
+#[cfg(windows)]
+mod dwrite_text_render;
 mod error;
 mod escape;
+mod font_collection;
+mod font_fallback;
+#[cfg(windows)]
 mod font_loader;
+mod font_writer;
+mod frame_transform;
+mod glyph_image;
+mod glyph_outline;
+mod glyph_run;
+mod inline_object;
+mod layout_json;
+mod line_break;
+mod path;
+mod pdf_render;
+mod png_render;
+#[cfg(any(windows, test))]
+mod script_table;
+#[cfg(windows)]
 mod svg_color;
+mod svg_font;
 mod svg_text_render;
+mod text_background;
+mod text_decoration;
+mod text_layout;
+mod text_path;
+mod text_shadow;
+mod text_spacing;
+mod text_stroke;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Backend {
+    /// DirectWrite text layout (Windows only)
+    #[cfg(windows)]
+    Dwrite,
+    /// Portable shaping and layout
+    Portable,
+}
+
+impl Default for Backend {
+    #[cfg(windows)]
+    fn default() -> Self {
+        Self::Dwrite
+    }
+    #[cfg(not(windows))]
+    fn default() -> Self {
+        Self::Portable
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum OutputFormat {
+    /// SVG with glyph outlines as shared paths
+    Svg,
+    /// SVG with positioned text in embedded WOFF2 fonts
+    SvgText,
+    /// One-page vector PDF
+    Pdf,
+    /// Anti-aliased raster image
+    Png,
+    /// Glyph runs as JSON, for comparing layout results
+    LayoutJson,
+}
+
+impl OutputFormat {
+    /// Format implied by the extension of the output file, SVG when there is none
+    fn from_path(path: Option<&Path>) -> Self {
+        let extension = path
+            .and_then(|path| path.extension())
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("pdf") => Self::Pdf,
+            Some("png") => Self::Png,
+            Some("json") => Self::LayoutJson,
+            _ => Self::Svg,
+        }
+    }
+}
 
 #[derive(Debug, clap::Parser)]
 #[command(name = "dwtr", about = "Text rendering utility (DWrite)")]
@@ -31,6 +128,27 @@ struct Opt {
     /// Output file, stdout if not present
     #[arg(short, long)]
     output: Option<PathBuf>,
+
+    /// Layout backend
+    #[arg(short, long, value_enum, default_value_t)]
+    backend: Backend,
+
+    /// Output format, from the output file extension if not present
+    #[arg(short, long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Pixels per DIP of PNG output
+    #[arg(long, default_value_t = 1.0, conflicts_with = "dpi")]
+    scale: f32,
+
+    /// Resolution of PNG output, where 96 DPI is one pixel per DIP
+    #[arg(long)]
+    dpi: Option<f32>,
+
+    /// Fill the outlines of text strokes in SVG output with glyph outlines instead of stroking
+    /// glyphs, for consumers that cannot stroke. Other formats ignore it.
+    #[arg(long)]
+    bake_stroke: bool,
 }
 
 fn main() -> Result<()> {
@@ -39,8 +157,55 @@ fn main() -> Result<()> {
     let reader = BufReader::new(file);
     let document: Document = serde_json::from_reader(reader)?;
 
+    let format = opt
+        .format
+        .unwrap_or_else(|| OutputFormat::from_path(opt.output.as_deref()));
+    if opt.bake_stroke && !matches!(format, OutputFormat::Svg) {
+        eprintln!("warning: --bake-stroke only applies to SVG output with glyph outlines");
+    }
+    let mut document_renderer: Box<dyn DocumentRenderer> = match format {
+        OutputFormat::Svg => Box::new(
+            SvgDocumentRenderer::new(document.width, document.height)
+                .with_baked_strokes(opt.bake_stroke),
+        ),
+        OutputFormat::SvgText => Box::new(SvgDocumentRenderer::with_embedded_fonts(
+            document.width,
+            document.height,
+        )),
+        OutputFormat::Pdf => Box::new(PdfDocumentRenderer::new(document.width, document.height)),
+        OutputFormat::LayoutJson => Box::new(LayoutJsonRenderer::new()),
+        OutputFormat::Png => {
+            let scale = opt.dpi.map_or(opt.scale, |dpi| dpi / 96.0);
+            Box::new(PngDocumentRenderer::new(
+                document.width,
+                document.height,
+                scale,
+            )?)
+        }
+    };
+
+    match opt.backend {
+        #[cfg(windows)]
+        Backend::Dwrite => render_with_dwrite(&document, document_renderer.as_mut())?,
+        Backend::Portable => render_portable(&document, document_renderer.as_mut())?,
+    }
+
+    let mut out_stream: Box<dyn std::io::Write> = match opt.output {
+        Some(output) => Box::new(File::create(output.as_path())?),
+        None => Box::new(std::io::stdout()),
+    };
+    document_renderer.write(&mut out_stream)?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn render_with_dwrite(
+    document: &Document,
+    document_renderer: &mut dyn DocumentRenderer,
+) -> Result<()> {
     let factory = get_factory()?;
-    let font_collection = load_font_collection(factory.cast()?, &document)?;
+    let font_collection = load_font_collection(factory.cast()?, document)?;
 
     let format = unsafe {
         factory.CreateTextFormat(
@@ -54,7 +219,7 @@ fn main() -> Result<()> {
         )?
     };
 
-    let mut document_renderer = SvgDocumentRenderer::new(document.width, document.height);
+    let fonts = Rc::new(DWriteFontTable::new());
 
     for frame in document.frames.iter() {
         let mut analyzer = DocumentAnalyzer::new();
@@ -66,42 +231,85 @@ fn main() -> Result<()> {
             document.width,
             document.height,
             frame,
+            &document.fallback,
         )?;
 
         let mut metrics = DWRITE_TEXT_METRICS::default();
         unsafe { text_layout.GetMetrics(&mut metrics)? };
+        let bounds = LayoutBounds::from(&metrics);
         let (offset_x, offset_y) = DocumentAnalyzer::compute_layout_offset(
             document.width,
             document.height,
             frame,
-            &metrics,
+            &bounds,
         );
 
-        let frame_renderer = document_renderer.create_frame_renderer(offset_x, offset_y);
-        frame_renderer.set_title(frame.title.clone());
-        frame_renderer.set_desc(frame.desc.clone());
-        frame_renderer.set_copyable(frame.copyable.unwrap_or(false));
-
-        let fr1: IDWriteTextRenderer1 = frame_renderer.into();
+        let runs = Rc::new(RefCell::new(Vec::new()));
+        let decorations = Rc::new(RefCell::new(Vec::new()));
+        let objects = Rc::new(RefCell::new(Vec::new()));
+        let collector = GlyphRunCollector::new(
+            fonts.clone(),
+            runs.clone(),
+            decorations.clone(),
+            objects.clone(),
+        );
+        let fr1: IDWriteTextRenderer1 = collector.into();
         unsafe { text_layout.Draw(None, &fr1, 0.0, 0.0)? }
+
+        let mut glyph_frame = GlyphFrame::new(frame, offset_x, offset_y);
+        glyph_frame.set_bounds(&bounds);
+        glyph_frame.transform = frame_transform(frame, document.width, document.height);
+        glyph_frame.runs = runs.take();
+        glyph_frame.backgrounds = analyzer.text_backgrounds(&text_layout)?;
+        glyph_frame.decorations = decorations.take();
+        glyph_frame.objects = objects.take();
+        if let Some(text_path) = &frame.text_path {
+            place_on_path(&mut glyph_frame, frame, text_path, &bounds, fonts.as_ref())?;
+        }
+        document_renderer.add_frame(&glyph_frame, fonts.as_ref())?;
     }
 
-    let mut out_stream: Box<dyn std::io::Write> = match opt.output {
-        Some(output) => Box::new(std::fs::File::create(output.as_path()).unwrap()),
-        None => Box::new(std::io::stdout()),
-    };
+    Ok(())
+}
 
-    write!(
-        out_stream,
-        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n"
-    )?;
+fn render_portable(
+    document: &Document,
+    document_renderer: &mut dyn DocumentRenderer,
+) -> Result<()> {
+    let fonts = FontCollection::load(document)?;
 
-    let svg = document_renderer.into_xml();
-    svg::write(out_stream, &svg)?;
+    for frame in document.frames.iter() {
+        let mut analyzer = DocumentAnalyzer::new();
+        analyzer.analyze(&frame.contents);
+
+        let text_layout =
+            TextLayout::new(&fonts, &analyzer, document.width, document.height, frame);
+
+        let bounds = text_layout.bounds();
+        let (offset_x, offset_y) = DocumentAnalyzer::compute_layout_offset(
+            document.width,
+            document.height,
+            frame,
+            &bounds,
+        );
+
+        let mut glyph_frame = GlyphFrame::new(frame, offset_x, offset_y);
+        glyph_frame.set_bounds(&bounds);
+        glyph_frame.transform = frame_transform(frame, document.width, document.height);
+        glyph_frame.runs = text_layout.glyph_runs();
+        glyph_frame.backgrounds = text_layout.backgrounds();
+        glyph_frame.decorations = text_layout.decorations(&fonts);
+        glyph_frame.objects = text_layout.objects();
+        if let Some(text_path) = &frame.text_path {
+            place_on_path(&mut glyph_frame, frame, text_path, &bounds, &fonts)?;
+        }
+        document_renderer.add_frame(&glyph_frame, &fonts)?;
+    }
 
     Ok(())
 }
 
+#[cfg(windows)]
 fn get_factory() -> Result<IDWriteFactory> {
     unsafe {
         let factory_raw = DWriteCreateFactory::<IDWriteFactory7>(DWRITE_FACTORY_TYPE_SHARED)?;
@@ -0,0 +1,219 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use tiny_skia::Path;
+
+// Quadratic curves that come elevated to cubics are found back to this fraction of a unit
+const ELEVATION_RESOLUTION: f32 = 0x100 as f32;
+
+/// A segment of a path, with absolute coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PathSegment {
+    MoveTo(f32, f32),
+    LineTo(f32, f32),
+    QuadTo(f32, f32, f32, f32),
+    CubicTo(f32, f32, f32, f32, f32, f32),
+    Close,
+}
+
+/// Collects the segments of a path, like a glyph outline handed over by a font
+#[derive(Default)]
+pub(crate) struct PathBuilder {
+    segments: Vec<PathSegment>,
+    last_x: f32,
+    last_y: f32,
+}
+
+impl PathBuilder {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// The segments added so far, leaving the builder empty
+    pub(crate) fn reset(&mut self) -> Vec<PathSegment> {
+        std::mem::take(&mut self.segments)
+    }
+
+    fn set_last_point(&mut self, x: f32, y: f32) {
+        self.last_x = x;
+        self.last_y = y;
+    }
+
+    pub(crate) fn move_to(&mut self, x: f32, y: f32) {
+        self.segments.push(PathSegment::MoveTo(x, y));
+        self.set_last_point(x, y);
+    }
+
+    pub(crate) fn line_to(&mut self, x: f32, y: f32) {
+        self.segments.push(PathSegment::LineTo(x, y));
+        self.set_last_point(x, y);
+    }
+
+    pub(crate) fn quad_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        self.segments.push(PathSegment::QuadTo(x1, y1, x2, y2));
+        self.set_last_point(x2, y2);
+    }
+
+    /// Add a cubic segment as is, for outlines that are natively cubic
+    pub(crate) fn exact_cubic_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) {
+        self.segments
+            .push(PathSegment::CubicTo(x1, y1, x2, y2, x3, y3));
+        self.set_last_point(x3, y3);
+    }
+
+    /// Add a cubic segment. Curves that are degree-elevated quadratics are added as quadratics.
+    #[cfg_attr(not(windows), allow(unused))]
+    pub(crate) fn cubic_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) {
+        let (x0, y0) = (self.last_x, self.last_y);
+        let xm1 = x0 + (x1 - x0) * 1.5;
+        let ym1 = y0 + (y1 - y0) * 1.5;
+        let xm2 = x3 + (x2 - x3) * 1.5;
+        let ym2 = y3 + (y2 - y3) * 1.5;
+        if ELEVATION_RESOLUTION * (xm2 - xm1).abs() < 1.0
+            && ELEVATION_RESOLUTION * (ym2 - ym1).abs() < 1.0
+        {
+            self.quad_to((xm1 + xm2) / 2.0, (ym1 + ym2) / 2.0, x3, y3);
+        } else {
+            self.exact_cubic_to(x1, y1, x2, y2, x3, y3);
+        }
+    }
+
+    pub(crate) fn close(&mut self) {
+        self.segments.push(PathSegment::Close);
+    }
+}
+
+/// A tiny-skia path of the segments, `None` when it has no segments
+pub(crate) fn build_path(segments: &[PathSegment]) -> Option<Path> {
+    let mut builder = tiny_skia::PathBuilder::new();
+    for segment in segments {
+        match *segment {
+            PathSegment::MoveTo(x, y) => builder.move_to(x, y),
+            PathSegment::LineTo(x, y) => builder.line_to(x, y),
+            PathSegment::QuadTo(x1, y1, x, y) => builder.quad_to(x1, y1, x, y),
+            PathSegment::CubicTo(x1, y1, x2, y2, x, y) => builder.cubic_to(x1, y1, x2, y2, x, y),
+            PathSegment::Close => builder.close(),
+        }
+    }
+    builder.finish()
+}
+
+/// The segments of a tiny-skia path, like the outline of a stroke
+pub(crate) fn path_segments(path: &Path) -> Vec<PathSegment> {
+    let mut builder = PathBuilder::new();
+    for segment in path.segments() {
+        match segment {
+            tiny_skia::PathSegment::MoveTo(p) => builder.move_to(p.x, p.y),
+            tiny_skia::PathSegment::LineTo(p) => builder.line_to(p.x, p.y),
+            tiny_skia::PathSegment::QuadTo(p1, p) => builder.quad_to(p1.x, p1.y, p.x, p.y),
+            tiny_skia::PathSegment::CubicTo(p1, p2, p) => {
+                builder.exact_cubic_to(p1.x, p1.y, p2.x, p2.y, p.x, p.y)
+            }
+            tiny_skia::PathSegment::Close => builder.close(),
+        }
+    }
+    builder.reset()
+}
+
+/// Identifies a path by the bits of its coordinates, which floats cannot be hashed by
+fn path_key(segments: &[PathSegment]) -> Vec<u32> {
+    let mut key = Vec::new();
+    for segment in segments {
+        let (tag, coords): (u32, &[f32]) = match segment {
+            PathSegment::MoveTo(x, y) => (0, &[*x, *y]),
+            PathSegment::LineTo(x, y) => (1, &[*x, *y]),
+            PathSegment::QuadTo(x1, y1, x, y) => (2, &[*x1, *y1, *x, *y]),
+            PathSegment::CubicTo(x1, y1, x2, y2, x, y) => (3, &[*x1, *y1, *x2, *y2, *x, *y]),
+            PathSegment::Close => (4, &[]),
+        };
+        key.push(tag);
+        key.extend(coords.iter().map(|coord| coord.to_bits()));
+    }
+    key
+}
+
+/// Paths shared by every glyph drawn with them, each path given an id from 1
+pub(crate) struct SharedStore {
+    ids: HashMap<Vec<u32>, usize>,
+    path_defs: Vec<Vec<PathSegment>>,
+}
+
+impl SharedStore {
+    pub(crate) fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            path_defs: Vec::new(),
+        }
+    }
+
+    /// Id of a path, adding it once; 0 for an empty path
+    pub(crate) fn add_path_def(&mut self, segments: Vec<PathSegment>) -> usize {
+        if segments.is_empty() {
+            return 0;
+        }
+        match self.ids.entry(path_key(&segments)) {
+            Entry::Occupied(o) => *o.get(),
+            Entry::Vacant(v) => {
+                self.path_defs.push(segments);
+                *v.insert(self.path_defs.len())
+            }
+        }
+    }
+
+    /// Collected paths with their ids, in the order they were added
+    pub(crate) fn path_defs(&self) -> impl Iterator<Item = (&[PathSegment], usize)> {
+        self.path_defs
+            .iter()
+            .enumerate()
+            .map(|(i, segments)| (segments.as_slice(), i + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(size: f32) -> Vec<PathSegment> {
+        let mut builder = PathBuilder::new();
+        builder.move_to(0.0, 0.0);
+        builder.line_to(size, 0.0);
+        builder.line_to(size, -size);
+        builder.close();
+        builder.reset()
+    }
+
+    #[test]
+    fn shared_store_adds_each_path_once() {
+        let mut store = SharedStore::new();
+        assert_eq!(store.add_path_def(square(10.0)), 1);
+        assert_eq!(store.add_path_def(square(20.0)), 2);
+        assert_eq!(store.add_path_def(square(10.0)), 1);
+        assert_eq!(store.add_path_def(Vec::new()), 0);
+        let ids: Vec<usize> = store.path_defs().map(|(_, id)| id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(store.path_defs().next().unwrap().0, square(10.0));
+    }
+
+    #[test]
+    fn elevated_quadratics_are_found_back() {
+        let mut builder = PathBuilder::new();
+        builder.move_to(0.0, 0.0);
+        // The quadratic with control point (30, 60), elevated to a cubic
+        builder.cubic_to(20.0, 40.0, 40.0, 40.0, 60.0, 0.0);
+        builder.cubic_to(70.0, 10.0, 80.0, -10.0, 90.0, 0.0);
+        assert_eq!(
+            builder.reset(),
+            [
+                PathSegment::MoveTo(0.0, 0.0),
+                PathSegment::QuadTo(30.0, 60.0, 60.0, 0.0),
+                PathSegment::CubicTo(70.0, 10.0, 80.0, -10.0, 90.0, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn skia_paths_round_trip() {
+        let path = build_path(&square(10.0)).unwrap();
+        assert_eq!(path_segments(&path), square(10.0));
+        assert!(build_path(&[]).is_none());
+    }
+}
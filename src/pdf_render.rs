@@ -0,0 +1,765 @@
+use std::collections::HashMap;
+
+use indexmap::{IndexMap, IndexSet};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use pdf_writer::{
+    types::{BlendMode, LineCapStyle, LineJoinStyle, SystemInfo, UnicodeCmap},
+    Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr,
+};
+
+use tiny_skia::Pixmap;
+
+use crate::{
+    document::{MixBlendMode, PaintOrder, StrokeLineJoin},
+    error::{AppError, Result},
+    frame_transform::transform_scale,
+    glyph_run::{
+        Background, Decoration, DecorationKind, DocumentRenderer, DrawingEffect, FontId,
+        GlyphFrame, GlyphOutlineSource, GlyphRun, PlacedObject,
+    },
+    inline_object::{object_content, ObjectContent},
+    path::{PathSegment, SharedStore},
+    text_background::background_path,
+    text_decoration::decoration_stroke,
+    text_shadow::{shadow_mask, ShadowMask},
+};
+
+// Type 3 fonts address their glyphs with single-byte codes
+const MAX_TYPE3_GLYPHS: usize = 256;
+
+// Pixels per DIP of the images that shadows are drawn as
+const SHADOW_SCALE: f32 = 4.0;
+
+/// A glyph of a Type 3 font, drawn by the path of the same id
+struct PdfGlyph {
+    path_id: usize,
+    width: f32,
+    text: String,
+}
+
+/// Glyphs of one face shown as text, at most 256 of them per font
+struct Type3Subset {
+    font: FontId,
+    is_sideways: bool,
+    glyphs: Vec<PdfGlyph>,
+}
+
+/// A PNG inline object or a shadow as an image XObject, with its alpha channel as a soft mask
+struct PdfImage {
+    width: u32,
+    height: u32,
+    rgb: Vec<u8>,
+    alpha: Vec<u8>,
+}
+
+/// Draws glyph frames into a one-page PDF. Glyph outlines become form XObjects shared like
+/// the SVG `<defs>`; copyable frames show them through Type 3 fonts with a `ToUnicode` map, so
+/// that their text can be selected and copied.
+pub(crate) struct PdfDocumentRenderer {
+    canvas_width: f32,
+    canvas_height: f32,
+    shared_store: SharedStore,
+    content: Content,
+    subsets: Vec<Type3Subset>,
+    glyph_codes: HashMap<(FontId, bool, u16), (usize, u8)>,
+    alphas: IndexMap<u32, f32>,
+    blend_modes: IndexSet<MixBlendMode>,
+    images: Vec<PdfImage>,
+    warned_gradient: bool,
+    warned_image: bool,
+}
+
+impl PdfDocumentRenderer {
+    pub(crate) fn new(canvas_width: f32, canvas_height: f32) -> Self {
+        let mut content = Content::new();
+        // Work in the y-down space of the layout, like SVG
+        content.save_state();
+        content.transform([1.0, 0.0, 0.0, -1.0, 0.0, canvas_height]);
+        Self {
+            canvas_width,
+            canvas_height,
+            shared_store: SharedStore::new(),
+            content,
+            subsets: Vec::new(),
+            glyph_codes: HashMap::new(),
+            alphas: IndexMap::new(),
+            blend_modes: IndexSet::new(),
+            images: Vec::new(),
+            warned_gradient: false,
+            warned_image: false,
+        }
+    }
+
+    fn draw_run(
+        &mut self,
+        frame: &GlyphFrame,
+        glyph_run: &GlyphRun,
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Result<()> {
+        let upm = outlines.units_per_em(glyph_run.font);
+        let scalar = upm / glyph_run.font_size;
+        let texts = cluster_texts(glyph_run);
+
+        let effect = glyph_run.drawing_effect.as_ref();
+        if effect.is_some_and(|effect| effect.gradient.is_some()) && !self.warned_gradient {
+            eprintln!(
+                "warning: PDF output fills gradient text with the first color of the gradient"
+            );
+            self.warned_gradient = true;
+        }
+        // Without transparency groups, the fill and the stroke of a faded run are each faded
+        let opacity = effect.map_or(1.0, DrawingEffect::opacity);
+        let stroke =
+            effect.and_then(|effect| Some((effect.stroke.clone()?, effect.stroke_color())));
+        let mut stroked = Vec::new();
+
+        let pixels_per_em = glyph_run.font_size * transform_scale(&frame.transform);
+        let pixels_per_em = pixels_per_em.ceil().clamp(1.0, u16::MAX as f32) as u16;
+        let mut glyphs = Vec::new();
+        let positions = glyph_run.glyph_positions();
+        for (i, (glyph_id, (x, y))) in glyph_run.glyph_ids.iter().zip(positions).enumerate() {
+            if !self.warned_image
+                && outlines
+                    .glyph_image(
+                        glyph_run.font,
+                        *glyph_id,
+                        glyph_run.is_sideways,
+                        pixels_per_em,
+                    )
+                    .is_some()
+            {
+                eprintln!(
+                    "warning: PDF output draws the outlines of glyphs, without their bitmap or \
+                     SVG images"
+                );
+                self.warned_image = true;
+            }
+            let path = outlines.glyph_path(glyph_run.font, *glyph_id, glyph_run.is_sideways)?;
+            if stroke.is_some() {
+                stroked.push((path.clone(), x * scalar, y * scalar));
+            }
+            let path_id = self.shared_store.add_path_def(path);
+            let width = glyph_run.advances.get(i).copied().unwrap_or(0.0) * scalar;
+            glyphs.push((*glyph_id, path_id, width, x * scalar, y * scalar, &texts[i]));
+        }
+
+        self.content.save_state();
+        if let Some(effect) = effect {
+            self.set_blend_mode(effect.blend_mode);
+        }
+        let color = effect.and_then(|effect| effect.color.as_ref());
+        if let Some(color) = color {
+            self.content
+                .set_fill_rgb(color.r as f32, color.g as f32, color.b as f32);
+        }
+        let alpha = color.map_or(1.0, |color| color.a as f32) * opacity;
+        if alpha < 1.0 {
+            let index = self.alpha_state(alpha);
+            self.content
+                .set_parameters(Name(format!("A{}", index).as_bytes()));
+        }
+        let (sin, cos) = glyph_run.rotate_angle().to_radians().sin_cos();
+        let scale = 1.0 / scalar;
+        self.content.transform([
+            scale * cos,
+            scale * sin,
+            -scale * sin,
+            scale * cos,
+            glyph_run.baseline_origin_x + frame.offset_x,
+            glyph_run.baseline_origin_y + frame.offset_y,
+        ]);
+
+        let stroke_first = matches!(&stroke, Some((s, _)) if s.paint_order == PaintOrder::Stroke);
+        if let Some((stroke, color)) = stroke.as_ref().filter(|_| stroke_first) {
+            self.stroke_glyphs(
+                &stroked,
+                stroke.width * scalar,
+                stroke.line_join,
+                color.as_ref(),
+                opacity,
+            );
+        }
+        if frame.copyable {
+            self.content
+                .begin_marked_content_with_properties(Name(b"Span"))
+                .properties()
+                .actual_text(TextStr(&glyph_run.source_text));
+            self.content.begin_text();
+            let mut current_font = None;
+            for (glyph_id, path_id, width, x, y, text) in glyphs {
+                let key = (glyph_run.font, glyph_run.is_sideways, glyph_id);
+                let (subset, code) = self.glyph_code(key, path_id, width, text);
+                if current_font != Some(subset) {
+                    self.content
+                        .set_font(Name(format!("F{}", subset).as_bytes()), 1.0);
+                    current_font = Some(subset);
+                }
+                self.content.set_text_matrix([1.0, 0.0, 0.0, 1.0, x, y]);
+                self.content.show(Str(&[code]));
+            }
+            self.content.end_text();
+            self.content.end_marked_content();
+        } else {
+            for (_, path_id, _, x, y, _) in glyphs {
+                if path_id > 0 {
+                    self.content.save_state();
+                    self.content.transform([1.0, 0.0, 0.0, 1.0, x, y]);
+                    self.content
+                        .x_object(Name(format!("P{}", path_id).as_bytes()));
+                    self.content.restore_state();
+                }
+            }
+        }
+        if let Some((stroke, color)) = stroke.as_ref().filter(|_| !stroke_first) {
+            self.stroke_glyphs(
+                &stroked,
+                stroke.width * scalar,
+                stroke.line_join,
+                color.as_ref(),
+                opacity,
+            );
+        }
+        self.content.restore_state();
+        Ok(())
+    }
+
+    /// Stroke glyph outlines placed in the space of their run, where the width is in design units
+    fn stroke_glyphs(
+        &mut self,
+        glyphs: &[(Vec<PathSegment>, f32, f32)],
+        width: f32,
+        line_join: StrokeLineJoin,
+        color: Option<&csscolorparser::Color>,
+        opacity: f32,
+    ) {
+        self.content.save_state();
+        // The graphics state of a translucent fill also sets the stroke opacity, so always set it
+        let alpha = color.map_or(1.0, |color| color.a as f32) * opacity;
+        let index = self.alpha_state(alpha);
+        self.content
+            .set_parameters(Name(format!("A{}", index).as_bytes()));
+        if let Some(color) = color {
+            self.content
+                .set_stroke_rgb(color.r as f32, color.g as f32, color.b as f32);
+        }
+        self.content.set_line_width(width);
+        self.content.set_line_join(match line_join {
+            StrokeLineJoin::Miter => LineJoinStyle::MiterJoin,
+            StrokeLineJoin::Round => LineJoinStyle::RoundJoin,
+            StrokeLineJoin::Bevel => LineJoinStyle::BevelJoin,
+        });
+        // The miter limit of SVG and tiny-skia
+        self.content.set_miter_limit(4.0);
+        for (path, x, y) in glyphs {
+            self.content.save_state();
+            self.content.transform([1.0, 0.0, 0.0, 1.0, *x, *y]);
+            append_path(&mut self.content, path);
+            self.content.stroke();
+            self.content.restore_state();
+        }
+        self.content.restore_state();
+    }
+
+    /// Draw a PNG inline object, scaled to its box; other objects are left out
+    fn draw_object(&mut self, frame: &GlyphFrame, placed: &PlacedObject) -> Result<()> {
+        let object = &placed.object;
+        let image = match object_content(object)? {
+            ObjectContent::Image {
+                mime: "image/png",
+                data,
+            } => Pixmap::decode_png(&data).map_err(|e| AppError::Render(e.to_string()))?,
+            _ => {
+                eprintln!("warning: PDF output only draws inline objects that are PNG images");
+                return Ok(());
+            }
+        };
+        let mut rgb = Vec::with_capacity(image.pixels().len() * 3);
+        let mut alpha = Vec::with_capacity(image.pixels().len());
+        for pixel in image.pixels() {
+            let color = pixel.demultiply();
+            rgb.extend([color.red(), color.green(), color.blue()]);
+            alpha.push(color.alpha());
+        }
+        self.images.push(PdfImage {
+            width: image.width(),
+            height: image.height(),
+            rgb,
+            alpha,
+        });
+
+        // Images fill the unit square from their last row up, which the y-down space flips
+        self.content.save_state();
+        self.content.transform([
+            object.width,
+            0.0,
+            0.0,
+            -object.height,
+            placed.x + frame.offset_x,
+            placed.y + frame.offset_y + object.height,
+        ]);
+        self.content
+            .x_object(Name(format!("I{}", self.images.len()).as_bytes()));
+        self.content.restore_state();
+        Ok(())
+    }
+
+    fn draw_background(&mut self, frame: &GlyphFrame, background: &Background) {
+        let color = &background.color;
+        self.content.save_state();
+        self.content
+            .set_fill_rgb(color.r as f32, color.g as f32, color.b as f32);
+        if color.a < 1.0 {
+            let index = self.alpha_state(color.a as f32);
+            self.content
+                .set_parameters(Name(format!("A{}", index).as_bytes()));
+        }
+        self.content
+            .transform([1.0, 0.0, 0.0, 1.0, frame.offset_x, frame.offset_y]);
+        append_path(&mut self.content, &background_path(background));
+        self.content.fill_nonzero();
+        self.content.restore_state();
+    }
+
+    /// Draw a shadow as an image in its color, with its coverage as the soft mask, faded and
+    /// blended like its run
+    fn draw_shadow(
+        &mut self,
+        shadow: &ShadowMask,
+        color: Option<&csscolorparser::Color>,
+        effect: &DrawingEffect,
+    ) {
+        let [r, g, b, a] = color.map_or([0, 0, 0, 255], |color| color.to_rgba8());
+        let a = (a as f32 * effect.opacity()).round() as u8;
+        let (width, height) = (shadow.mask.width(), shadow.mask.height());
+        let alpha = shadow
+            .mask
+            .data()
+            .iter()
+            .map(|&coverage| ((coverage as u32 * a as u32 + 127) / 255) as u8)
+            .collect();
+        self.images.push(PdfImage {
+            width,
+            height,
+            rgb: [r, g, b].repeat((width * height) as usize),
+            alpha,
+        });
+
+        self.content.save_state();
+        self.set_blend_mode(effect.blend_mode);
+        self.content.transform([
+            width as f32 / SHADOW_SCALE,
+            0.0,
+            0.0,
+            -(height as f32) / SHADOW_SCALE,
+            shadow.left as f32 / SHADOW_SCALE,
+            (shadow.top as f32 + height as f32) / SHADOW_SCALE,
+        ]);
+        self.content
+            .x_object(Name(format!("I{}", self.images.len()).as_bytes()));
+        self.content.restore_state();
+    }
+
+    fn draw_decoration(&mut self, frame: &GlyphFrame, decoration: &Decoration) {
+        let stroke = decoration_stroke(decoration);
+        self.content.save_state();
+        if let Some(color) = decoration.color.as_ref() {
+            self.content
+                .set_stroke_rgb(color.r as f32, color.g as f32, color.b as f32);
+            if color.a < 1.0 {
+                let index = self.alpha_state(color.a as f32);
+                self.content
+                    .set_parameters(Name(format!("A{}", index).as_bytes()));
+            }
+        }
+        let (sin, cos) = decoration.rotate_angle().to_radians().sin_cos();
+        self.content.transform([
+            cos,
+            sin,
+            -sin,
+            cos,
+            decoration.origin_x + frame.offset_x,
+            decoration.origin_y + frame.offset_y,
+        ]);
+        self.content.set_line_width(stroke.width);
+        if stroke.round_caps {
+            self.content.set_line_cap(LineCapStyle::RoundCap);
+        }
+        if let Some((dash, gap)) = stroke.dash {
+            self.content.set_dash_pattern([dash, gap], 0.0);
+        }
+        append_path(&mut self.content, &stroke.path);
+        self.content.stroke();
+        self.content.restore_state();
+    }
+
+    /// Code of a glyph in the Type 3 fonts, adding it to the last subset of its face
+    fn glyph_code(
+        &mut self,
+        key: (FontId, bool, u16),
+        path_id: usize,
+        width: f32,
+        text: &str,
+    ) -> (usize, u8) {
+        if let Some(code) = self.glyph_codes.get(&key) {
+            return *code;
+        }
+        let (font, is_sideways, _) = key;
+        let subset = self
+            .subsets
+            .iter()
+            .rposition(|s| s.font == font && s.is_sideways == is_sideways)
+            .filter(|&i| self.subsets[i].glyphs.len() < MAX_TYPE3_GLYPHS)
+            .unwrap_or_else(|| {
+                self.subsets.push(Type3Subset {
+                    font,
+                    is_sideways,
+                    glyphs: Vec::new(),
+                });
+                self.subsets.len() - 1
+            });
+        let glyphs = &mut self.subsets[subset].glyphs;
+        let code = (subset, glyphs.len() as u8);
+        glyphs.push(PdfGlyph {
+            path_id,
+            width,
+            text: text.to_string(),
+        });
+        self.glyph_codes.insert(key, code);
+        code
+    }
+
+    /// Index of the graphics state that sets a fill and stroke opacity
+    fn alpha_state(&mut self, alpha: f32) -> usize {
+        let entry = self.alphas.entry(alpha.to_bits());
+        let index = entry.index();
+        entry.or_insert(alpha);
+        index
+    }
+
+    /// Blend what follows with `mode`, through a graphics state that only sets the blend mode
+    fn set_blend_mode(&mut self, mode: MixBlendMode) {
+        if mode == MixBlendMode::Normal {
+            return;
+        }
+        let (index, _) = self.blend_modes.insert_full(mode);
+        self.content
+            .set_parameters(Name(format!("B{}", index).as_bytes()));
+    }
+
+    fn into_pdf(mut self) -> Vec<u8> {
+        self.content.restore_state();
+
+        let mut next_id = 1;
+        let mut alloc = || {
+            let id = Ref::new(next_id);
+            next_id += 1;
+            id
+        };
+        let catalog_id = alloc();
+        let page_tree_id = alloc();
+        let page_id = alloc();
+        let content_id = alloc();
+
+        let mut pdf = Pdf::new();
+        pdf.catalog(catalog_id).pages(page_tree_id);
+        pdf.pages(page_tree_id).kids([page_id]).count(1);
+
+        let mut path_ids = Vec::new();
+        for (segments, id) in self.shared_store.path_defs() {
+            let path_ref = alloc();
+            let (data, bbox) = path_content(segments);
+            let data = compress_to_vec_zlib(&data, 6);
+            let mut form = pdf.form_xobject(path_ref, &data);
+            form.bbox(bbox);
+            form.filter(Filter::FlateDecode);
+            path_ids.push((id, path_ref));
+        }
+
+        let mut font_ids = Vec::new();
+        for subset in self.subsets.iter() {
+            let font_ref = alloc();
+            let cmap_ref = alloc();
+            let mut cmap = UnicodeCmap::<u8>::new(Name(b"Custom"), system_info());
+            let mut char_procs = Vec::new();
+            for (code, glyph) in subset.glyphs.iter().enumerate() {
+                if !glyph.text.is_empty() {
+                    cmap.pair_with_multiple(code as u8, glyph.text.chars());
+                }
+                let mut proc = Content::new();
+                proc.start_color_glyph(glyph.width);
+                if glyph.path_id > 0 {
+                    proc.x_object(Name(format!("P{}", glyph.path_id).as_bytes()));
+                }
+                let proc_ref = alloc();
+                pdf.stream(proc_ref, &proc.finish());
+                char_procs.push(proc_ref);
+            }
+            pdf.cmap(cmap_ref, &cmap.finish());
+
+            let mut font = pdf.type3_font(font_ref);
+            font.bbox(Rect::new(0.0, 0.0, 0.0, 0.0))
+                .matrix([1.0, 0.0, 0.0, 1.0, 0.0, 0.0])
+                .first_char(0)
+                .last_char((subset.glyphs.len() - 1) as u8)
+                .widths(subset.glyphs.iter().map(|glyph| glyph.width))
+                .to_unicode(cmap_ref);
+            let names: Vec<String> = (0..subset.glyphs.len())
+                .map(|i| format!("g{}", i))
+                .collect();
+            font.encoding_custom()
+                .differences()
+                .consecutive(0, names.iter().map(|name| Name(name.as_bytes())));
+            let mut procs = font.char_procs();
+            for (name, proc_ref) in names.iter().zip(char_procs) {
+                procs.pair(Name(name.as_bytes()), proc_ref);
+            }
+            procs.finish();
+            let mut resources = font.resources();
+            let mut x_objects = resources.x_objects();
+            for glyph in subset.glyphs.iter().filter(|glyph| glyph.path_id > 0) {
+                let path_ref = path_ids[glyph.path_id - 1].1;
+                x_objects.pair(Name(format!("P{}", glyph.path_id).as_bytes()), path_ref);
+            }
+            x_objects.finish();
+            resources.finish();
+            font.finish();
+            font_ids.push(font_ref);
+        }
+
+        let mut image_ids = Vec::new();
+        for image in self.images.iter() {
+            let image_ref = alloc();
+            let mask_ref = alloc();
+            let data = compress_to_vec_zlib(&image.rgb, 6);
+            let mut x_object = pdf.image_xobject(image_ref, &data);
+            x_object.filter(Filter::FlateDecode);
+            x_object.width(image.width as i32);
+            x_object.height(image.height as i32);
+            x_object.color_space().device_rgb();
+            x_object.bits_per_component(8);
+            x_object.s_mask(mask_ref);
+            x_object.finish();
+            let data = compress_to_vec_zlib(&image.alpha, 6);
+            let mut mask = pdf.image_xobject(mask_ref, &data);
+            mask.filter(Filter::FlateDecode);
+            mask.width(image.width as i32);
+            mask.height(image.height as i32);
+            mask.color_space().device_gray();
+            mask.bits_per_component(8);
+            mask.finish();
+            image_ids.push(image_ref);
+        }
+
+        let mut alpha_ids = Vec::new();
+        for (_, alpha) in self.alphas.iter() {
+            let state_ref = alloc();
+            pdf.ext_graphics(state_ref)
+                .non_stroking_alpha(*alpha)
+                .stroking_alpha(*alpha);
+            alpha_ids.push(state_ref);
+        }
+        let mut blend_ids = Vec::new();
+        for mode in self.blend_modes.iter() {
+            let state_ref = alloc();
+            pdf.ext_graphics(state_ref)
+                .blend_mode(pdf_blend_mode(*mode));
+            blend_ids.push(state_ref);
+        }
+
+        let content = compress_to_vec_zlib(&self.content.finish(), 6);
+        pdf.stream(content_id, &content).filter(Filter::FlateDecode);
+
+        let mut page = pdf.page(page_id);
+        page.parent(page_tree_id)
+            .media_box(Rect::new(0.0, 0.0, self.canvas_width, self.canvas_height))
+            .contents(content_id);
+        let mut resources = page.resources();
+        let mut x_objects = resources.x_objects();
+        for (id, path_ref) in path_ids.iter() {
+            x_objects.pair(Name(format!("P{}", id).as_bytes()), *path_ref);
+        }
+        for (i, image_ref) in image_ids.iter().enumerate() {
+            x_objects.pair(Name(format!("I{}", i + 1).as_bytes()), *image_ref);
+        }
+        x_objects.finish();
+        let mut fonts = resources.fonts();
+        for (i, font_ref) in font_ids.iter().enumerate() {
+            fonts.pair(Name(format!("F{}", i).as_bytes()), *font_ref);
+        }
+        fonts.finish();
+        let mut states = resources.ext_g_states();
+        for (i, state_ref) in alpha_ids.iter().enumerate() {
+            states.pair(Name(format!("A{}", i).as_bytes()), *state_ref);
+        }
+        for (i, state_ref) in blend_ids.iter().enumerate() {
+            states.pair(Name(format!("B{}", i).as_bytes()), *state_ref);
+        }
+        states.finish();
+        resources.finish();
+        page.finish();
+
+        pdf.finish()
+    }
+}
+
+impl DocumentRenderer for PdfDocumentRenderer {
+    fn add_frame(&mut self, frame: &GlyphFrame, outlines: &dyn GlyphOutlineSource) -> Result<()> {
+        let transform = frame.transform;
+        let transformed = !transform.is_identity();
+        if transformed {
+            self.content.save_state();
+            self.content.transform([
+                transform.sx,
+                transform.ky,
+                transform.kx,
+                transform.sy,
+                transform.tx,
+                transform.ty,
+            ]);
+        }
+        for background in frame.backgrounds.iter() {
+            self.draw_background(frame, background);
+        }
+        // Shadows go below all text of the frame, the first shadow of a run on top
+        for run in frame.runs.iter() {
+            let Some(effect) = run.drawing_effect.as_ref() else {
+                continue;
+            };
+            for shadow in effect.shadows.iter().rev() {
+                if let Some(mask) = shadow_mask(frame, run, outlines, shadow, SHADOW_SCALE)? {
+                    self.draw_shadow(&mask, effect.shadow_color(shadow).as_ref(), effect);
+                }
+            }
+        }
+        // Underlines and overlines go below the text, line-throughs above it
+        let (through, under): (Vec<_>, Vec<_>) = frame
+            .decorations
+            .iter()
+            .partition(|decoration| decoration.kind == DecorationKind::LineThrough);
+        for decoration in under {
+            self.draw_decoration(frame, decoration);
+        }
+        for run in frame.runs.iter() {
+            self.draw_run(frame, run, outlines)?;
+        }
+        for object in frame.objects.iter() {
+            self.draw_object(frame, object)?;
+        }
+        for decoration in through {
+            self.draw_decoration(frame, decoration);
+        }
+        if transformed {
+            self.content.restore_state();
+        }
+        Ok(())
+    }
+
+    fn write(self: Box<Self>, out: &mut dyn std::io::Write) -> Result<()> {
+        out.write_all(&self.into_pdf())?;
+        Ok(())
+    }
+}
+
+fn pdf_blend_mode(mode: MixBlendMode) -> BlendMode {
+    match mode {
+        MixBlendMode::Normal => BlendMode::Normal,
+        MixBlendMode::Multiply => BlendMode::Multiply,
+        MixBlendMode::Screen => BlendMode::Screen,
+        MixBlendMode::Overlay => BlendMode::Overlay,
+        MixBlendMode::Darken => BlendMode::Darken,
+        MixBlendMode::Lighten => BlendMode::Lighten,
+        MixBlendMode::ColorDodge => BlendMode::ColorDodge,
+        MixBlendMode::ColorBurn => BlendMode::ColorBurn,
+        MixBlendMode::HardLight => BlendMode::HardLight,
+        MixBlendMode::SoftLight => BlendMode::SoftLight,
+        MixBlendMode::Difference => BlendMode::Difference,
+        MixBlendMode::Exclusion => BlendMode::Exclusion,
+        MixBlendMode::Hue => BlendMode::Hue,
+        MixBlendMode::Saturation => BlendMode::Saturation,
+        MixBlendMode::Color => BlendMode::Color,
+        MixBlendMode::Luminosity => BlendMode::Luminosity,
+    }
+}
+
+fn system_info() -> SystemInfo<'static> {
+    SystemInfo {
+        registry: Str(b"Adobe"),
+        ordering: Str(b"Identity"),
+        supplement: 0,
+    }
+}
+
+/// Source text of every glyph: a cluster's text goes to its first glyph
+fn cluster_texts(glyph_run: &GlyphRun) -> Vec<String> {
+    let mut texts = vec![String::new(); glyph_run.glyph_ids.len()];
+    for (glyphs, text) in glyph_run.clusters() {
+        if let Some(slot) = texts.get_mut(glyphs.start) {
+            slot.push_str(&text);
+        }
+    }
+    texts
+}
+
+/// Content stream filling a glyph path, with the bounds of its points
+fn path_content(path: &[PathSegment]) -> (Vec<u8>, Rect) {
+    let mut content = Content::new();
+    let bbox = append_path(&mut content, path);
+    content.fill_nonzero();
+    (content.finish(), bbox)
+}
+
+/// Add the segments of a path to a content stream, returning the bounds of its points
+fn append_path(content: &mut Content, path: &[PathSegment]) -> Rect {
+    let mut bounds = [
+        f32::INFINITY,
+        f32::INFINITY,
+        f32::NEG_INFINITY,
+        f32::NEG_INFINITY,
+    ];
+    let mut include = |x: f32, y: f32| {
+        bounds[0] = bounds[0].min(x);
+        bounds[1] = bounds[1].min(y);
+        bounds[2] = bounds[2].max(x);
+        bounds[3] = bounds[3].max(y);
+    };
+    let (mut last_x, mut last_y) = (0.0, 0.0);
+    for segment in path {
+        match *segment {
+            PathSegment::MoveTo(x, y) => {
+                content.move_to(x, y);
+                (last_x, last_y) = (x, y);
+            }
+            PathSegment::LineTo(x, y) => {
+                content.line_to(x, y);
+                (last_x, last_y) = (x, y);
+            }
+            // PDF has no quadratic segments; elevate them to cubics
+            PathSegment::QuadTo(x1, y1, x, y) => {
+                content.cubic_to(
+                    last_x + (x1 - last_x) * 2.0 / 3.0,
+                    last_y + (y1 - last_y) * 2.0 / 3.0,
+                    x + (x1 - x) * 2.0 / 3.0,
+                    y + (y1 - y) * 2.0 / 3.0,
+                    x,
+                    y,
+                );
+                include(x1, y1);
+                (last_x, last_y) = (x, y);
+            }
+            PathSegment::CubicTo(x1, y1, x2, y2, x, y) => {
+                content.cubic_to(x1, y1, x2, y2, x, y);
+                include(x1, y1);
+                include(x2, y2);
+                (last_x, last_y) = (x, y);
+            }
+            PathSegment::Close => {
+                content.close_path();
+            }
+        }
+        include(last_x, last_y);
+    }
+    match bounds[0].is_finite() {
+        true => Rect::new(bounds[0], bounds[1], bounds[2], bounds[3]),
+        false => Rect::new(0.0, 0.0, 0.0, 0.0),
+    }
+}
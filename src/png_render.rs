@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+
+use tiny_skia::{
+    BlendMode, Color, FillRule, FilterQuality, GradientStop, LineCap, LinearGradient, Paint, Path,
+    Pixmap, PixmapPaint, Point, RadialGradient, Rect, Shader, SpreadMode, Stroke, StrokeDash,
+    Transform,
+};
+
+use crate::{
+    css_gradient::{GradientPlacer, GradientShape, PlacedGradient},
+    document::{MixBlendMode, PaintOrder},
+    error::{AppError, Result},
+    frame_transform::transform_scale,
+    glyph_run::{
+        Background, Decoration, DecorationKind, DocumentRenderer, DrawingEffect, GlyphFrame,
+        GlyphOutlineSource, GlyphRun, PlacedObject,
+    },
+    inline_object::{object_content, ObjectContent},
+    path::{build_path, SharedStore},
+    text_background::background_path,
+    text_decoration::decoration_stroke,
+    text_shadow::{shadow_mask, ShadowMask},
+    text_stroke::skia_line_join,
+};
+
+/// Rasterizes glyph frames into an anti-aliased PNG on the CPU, filling the glyph paths collected
+/// in a `SharedStore` like the SVG output references them
+pub(crate) struct PngDocumentRenderer {
+    scale: f32,
+    shared_store: SharedStore,
+    paths: HashMap<usize, Option<Path>>,
+    pixmap: Pixmap,
+    warned_image: bool,
+}
+
+impl PngDocumentRenderer {
+    /// `scale` is the number of pixels per DIP
+    pub(crate) fn new(canvas_width: f32, canvas_height: f32, scale: f32) -> Result<Self> {
+        let width = (canvas_width * scale).ceil() as u32;
+        let height = (canvas_height * scale).ceil() as u32;
+        let pixmap = Pixmap::new(width, height).ok_or_else(|| {
+            AppError::Render(format!("cannot create a {}x{} image", width, height))
+        })?;
+        Ok(Self {
+            scale,
+            shared_store: SharedStore::new(),
+            paths: HashMap::new(),
+            pixmap,
+            warned_image: false,
+        })
+    }
+
+    /// Maps the DIPs of a frame onto the pixels of the image
+    fn canvas_transform(&self, frame: &GlyphFrame) -> Transform {
+        Transform::from_scale(self.scale, self.scale).pre_concat(frame.transform)
+    }
+
+    fn draw_run(
+        &mut self,
+        frame: &GlyphFrame,
+        glyph_run: &GlyphRun,
+        gradient: Option<&PlacedGradient>,
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Result<()> {
+        let upm = outlines.units_per_em(glyph_run.font);
+        let scalar = upm / glyph_run.font_size;
+
+        let effect = glyph_run.drawing_effect.as_ref();
+        let mut paint = Paint::default();
+        paint.set_color(skia_color(effect.and_then(|effect| effect.color.as_ref())));
+        let stroke = effect.and_then(|effect| {
+            let stroke = effect.stroke.as_ref()?;
+            let mut paint = Paint::default();
+            paint.set_color(skia_color(effect.stroke_color().as_ref()));
+            let skia_stroke = Stroke {
+                width: stroke.width * scalar,
+                line_join: skia_line_join(stroke.line_join),
+                ..Stroke::default()
+            };
+            Some((paint, skia_stroke, stroke.paint_order))
+        });
+
+        let canvas_transform = self.canvas_transform(frame);
+        let run_transform = canvas_transform
+            .pre_translate(
+                glyph_run.baseline_origin_x + frame.offset_x,
+                glyph_run.baseline_origin_y + frame.offset_y,
+            )
+            .pre_rotate(glyph_run.rotate_angle())
+            .pre_scale(1.0 / scalar, 1.0 / scalar);
+
+        let pixels_per_em = glyph_run.font_size * self.scale * transform_scale(&frame.transform);
+        let pixels_per_em = pixels_per_em.ceil().clamp(1.0, u16::MAX as f32) as u16;
+        let positions = glyph_run.glyph_positions();
+        for (glyph_id, (x, y)) in glyph_run.glyph_ids.iter().zip(positions) {
+            if !self.warned_image
+                && outlines
+                    .glyph_image(
+                        glyph_run.font,
+                        *glyph_id,
+                        glyph_run.is_sideways,
+                        pixels_per_em,
+                    )
+                    .is_some()
+            {
+                eprintln!(
+                    "warning: PNG output draws the outlines of glyphs, without their bitmap or \
+                     SVG images"
+                );
+                self.warned_image = true;
+            }
+            let segments = outlines.glyph_path(glyph_run.font, *glyph_id, glyph_run.is_sideways)?;
+            let path_id = self.shared_store.add_path_def(segments.clone());
+            if path_id == 0 {
+                continue;
+            }
+            let path = self
+                .paths
+                .entry(path_id)
+                .or_insert_with(|| build_path(&segments));
+            let Some(path) = path else {
+                continue;
+            };
+            let transform = run_transform.pre_translate(x * scalar, y * scalar);
+            // Shaders are placed in the space of the path, while gradients are in the frame
+            if let Some(shader) = gradient.and_then(|gradient| {
+                let canvas = transform
+                    .invert()?
+                    .pre_concat(canvas_transform)
+                    .pre_concat(gradient.shape_transform());
+                skia_gradient(gradient, canvas)
+            }) {
+                paint.shader = shader;
+            }
+            let stroke_glyph = |pixmap: &mut Pixmap| {
+                if let Some((stroke_paint, skia_stroke, _)) = &stroke {
+                    pixmap.stroke_path(path, stroke_paint, skia_stroke, transform, None);
+                }
+            };
+            let stroke_first = matches!(stroke, Some((_, _, PaintOrder::Stroke)));
+            if stroke_first {
+                stroke_glyph(&mut self.pixmap);
+            }
+            self.pixmap
+                .fill_path(path, &paint, FillRule::Winding, transform, None);
+            if !stroke_first {
+                stroke_glyph(&mut self.pixmap);
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw a run into a layer of its own when it is faded or blended, so that its glyphs and
+    /// their strokes are composited as a whole
+    fn draw_composited(
+        &mut self,
+        effect: Option<&DrawingEffect>,
+        draw: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let Some(effect) = effect.filter(|effect| effect.is_composited()) else {
+            return draw(self);
+        };
+        let (width, height) = (self.pixmap.width(), self.pixmap.height());
+        let layer = Pixmap::new(width, height).ok_or_else(|| {
+            AppError::Render(format!("cannot create a {}x{} image", width, height))
+        })?;
+        let canvas = std::mem::replace(&mut self.pixmap, layer);
+        let result = draw(self);
+        let layer = std::mem::replace(&mut self.pixmap, canvas);
+        result?;
+        self.pixmap.draw_pixmap(
+            0,
+            0,
+            layer.as_ref(),
+            &layer_paint(effect),
+            Transform::identity(),
+            None,
+        );
+        Ok(())
+    }
+
+    /// Draw a PNG inline object, scaled to its box; other objects are left out
+    fn draw_object(&mut self, frame: &GlyphFrame, placed: &PlacedObject) -> Result<()> {
+        let object = &placed.object;
+        let image = match object_content(object)? {
+            ObjectContent::Image {
+                mime: "image/png",
+                data,
+            } => Pixmap::decode_png(&data).map_err(|e| AppError::Render(e.to_string()))?,
+            _ => {
+                eprintln!("warning: PNG output only draws inline objects that are PNG images");
+                return Ok(());
+            }
+        };
+        let paint = PixmapPaint {
+            quality: FilterQuality::Bicubic,
+            ..PixmapPaint::default()
+        };
+        let transform = self
+            .canvas_transform(frame)
+            .pre_translate(placed.x + frame.offset_x, placed.y + frame.offset_y)
+            .pre_scale(
+                object.width / image.width() as f32,
+                object.height / image.height() as f32,
+            );
+        self.pixmap
+            .draw_pixmap(0, 0, image.as_ref(), &paint, transform, None);
+        Ok(())
+    }
+
+    fn draw_background(&mut self, frame: &GlyphFrame, background: &Background) {
+        let Some(path) = build_path(&background_path(background)) else {
+            return;
+        };
+        let mut paint = Paint::default();
+        paint.set_color(skia_color(Some(&background.color)));
+        let transform = self
+            .canvas_transform(frame)
+            .pre_translate(frame.offset_x, frame.offset_y);
+        self.pixmap
+            .fill_path(&path, &paint, FillRule::Winding, transform, None);
+    }
+
+    /// Draw the mask of a shadow, whose pixels `transform` maps onto the image
+    fn draw_shadow(
+        &mut self,
+        shadow: &ShadowMask,
+        color: Option<&csscolorparser::Color>,
+        layer_paint: &PixmapPaint,
+        transform: Transform,
+    ) {
+        let (width, height) = (shadow.mask.width(), shadow.mask.height());
+        let (Some(mut layer), Some(rect)) = (
+            Pixmap::new(width, height),
+            Rect::from_xywh(0.0, 0.0, width as f32, height as f32),
+        ) else {
+            return;
+        };
+        let mut paint = Paint::default();
+        paint.set_color(skia_color(color));
+        layer.fill_rect(rect, &paint, Transform::identity(), Some(&shadow.mask));
+        let paint = match transform.is_identity() {
+            true => *layer_paint,
+            false => PixmapPaint {
+                quality: FilterQuality::Bilinear,
+                ..*layer_paint
+            },
+        };
+        self.pixmap.draw_pixmap(
+            shadow.left,
+            shadow.top,
+            layer.as_ref(),
+            &paint,
+            transform,
+            None,
+        );
+    }
+
+    fn draw_decoration(&mut self, frame: &GlyphFrame, decoration: &Decoration) {
+        let stroke = decoration_stroke(decoration);
+        let Some(path) = build_path(&stroke.path) else {
+            return;
+        };
+        let mut paint = Paint::default();
+        paint.set_color(skia_color(decoration.color.as_ref()));
+        let dash = stroke
+            .dash
+            .and_then(|(dash, gap)| StrokeDash::new(vec![dash, gap], 0.0));
+        let line_cap = match stroke.round_caps {
+            true => LineCap::Round,
+            false => LineCap::Butt,
+        };
+        let stroke = Stroke {
+            width: stroke.width,
+            line_cap,
+            dash,
+            ..Stroke::default()
+        };
+        let transform = self
+            .canvas_transform(frame)
+            .pre_translate(
+                decoration.origin_x + frame.offset_x,
+                decoration.origin_y + frame.offset_y,
+            )
+            .pre_rotate(decoration.rotate_angle());
+        self.pixmap
+            .stroke_path(&path, &paint, &stroke, transform, None);
+    }
+}
+
+impl DocumentRenderer for PngDocumentRenderer {
+    fn add_frame(&mut self, frame: &GlyphFrame, outlines: &dyn GlyphOutlineSource) -> Result<()> {
+        for background in frame.backgrounds.iter() {
+            self.draw_background(frame, background);
+        }
+        // Shadows go below all text of the frame, the first shadow of a run on top. They are
+        // blurred in the frame, at the resolution its transform scales them to.
+        let mask_scale = self.scale * transform_scale(&frame.transform);
+        let mask_transform = self
+            .canvas_transform(frame)
+            .pre_scale(1.0 / mask_scale, 1.0 / mask_scale);
+        for run in frame.runs.iter() {
+            let Some(effect) = run.drawing_effect.as_ref() else {
+                continue;
+            };
+            for shadow in effect.shadows.iter().rev() {
+                if let Some(mask) = shadow_mask(frame, run, outlines, shadow, mask_scale)? {
+                    let color = effect.shadow_color(shadow);
+                    let paint = layer_paint(effect);
+                    self.draw_shadow(&mask, color.as_ref(), &paint, mask_transform);
+                }
+            }
+        }
+        // Underlines and overlines go below the text, line-throughs above it
+        let (through, under): (Vec<_>, Vec<_>) = frame
+            .decorations
+            .iter()
+            .partition(|decoration| decoration.kind == DecorationKind::LineThrough);
+        for decoration in under {
+            self.draw_decoration(frame, decoration);
+        }
+        let mut gradients = GradientPlacer::new(frame, outlines);
+        for run in frame.runs.iter() {
+            let gradient = gradients.place(run)?;
+            self.draw_composited(run.drawing_effect.as_ref(), |renderer| {
+                renderer.draw_run(frame, run, gradient.as_ref(), outlines)
+            })?;
+        }
+        for object in frame.objects.iter() {
+            self.draw_object(frame, object)?;
+        }
+        for decoration in through {
+            self.draw_decoration(frame, decoration);
+        }
+        Ok(())
+    }
+
+    fn write(self: Box<Self>, out: &mut dyn std::io::Write) -> Result<()> {
+        let png = self
+            .pixmap
+            .encode_png()
+            .map_err(|e| AppError::Render(e.to_string()))?;
+        out.write_all(&png)?;
+        Ok(())
+    }
+}
+
+fn skia_color(color: Option<&csscolorparser::Color>) -> Color {
+    match color {
+        Some(color) => Color::from_rgba(
+            color.r as f32,
+            color.g as f32,
+            color.b as f32,
+            color.a as f32,
+        )
+        .unwrap_or(Color::BLACK),
+        None => Color::BLACK,
+    }
+}
+
+/// Paint of the layer of a run, with its opacity and blend mode
+fn layer_paint(effect: &DrawingEffect) -> PixmapPaint {
+    PixmapPaint {
+        opacity: effect.opacity(),
+        blend_mode: skia_blend_mode(effect.blend_mode),
+        quality: FilterQuality::Nearest,
+    }
+}
+
+fn skia_blend_mode(mode: MixBlendMode) -> BlendMode {
+    match mode {
+        MixBlendMode::Normal => BlendMode::SourceOver,
+        MixBlendMode::Multiply => BlendMode::Multiply,
+        MixBlendMode::Screen => BlendMode::Screen,
+        MixBlendMode::Overlay => BlendMode::Overlay,
+        MixBlendMode::Darken => BlendMode::Darken,
+        MixBlendMode::Lighten => BlendMode::Lighten,
+        MixBlendMode::ColorDodge => BlendMode::ColorDodge,
+        MixBlendMode::ColorBurn => BlendMode::ColorBurn,
+        MixBlendMode::HardLight => BlendMode::HardLight,
+        MixBlendMode::SoftLight => BlendMode::SoftLight,
+        MixBlendMode::Difference => BlendMode::Difference,
+        MixBlendMode::Exclusion => BlendMode::Exclusion,
+        MixBlendMode::Hue => BlendMode::Hue,
+        MixBlendMode::Saturation => BlendMode::Saturation,
+        MixBlendMode::Color => BlendMode::Color,
+        MixBlendMode::Luminosity => BlendMode::Luminosity,
+    }
+}
+
+/// Shader of a gradient whose shape `transform` places; a radial gradient is a circle of its x
+/// radius until then
+fn skia_gradient(gradient: &PlacedGradient, transform: Transform) -> Option<Shader<'static>> {
+    let stops = gradient
+        .stops
+        .iter()
+        .map(|(offset, color)| GradientStop::new(*offset, skia_color(Some(color))))
+        .collect();
+    match gradient.shape {
+        GradientShape::Linear { start, end } => LinearGradient::new(
+            Point::from_xy(start.0, start.1),
+            Point::from_xy(end.0, end.1),
+            stops,
+            SpreadMode::Pad,
+            transform,
+        ),
+        GradientShape::Radial { center, radius } => {
+            let center = Point::from_xy(center.0, center.1);
+            RadialGradient::new(center, center, radius.0, stops, SpreadMode::Pad, transform)
+        }
+    }
+}
@@ -1,7 +1,9 @@
 #![allow(non_snake_case)]
 
 use windows::core::{implement, interface, IUnknown, IUnknown_Vtbl, HRESULT};
-use windows::Win32::Foundation::S_OK;
+use windows::Win32::Foundation::{S_FALSE, S_OK};
+
+use crate::glyph_run::DrawingEffect;
 
 /// My interface
 #[interface("f2496799-9fb3-4933-96c4-46c7ab425974")]
@@ -15,18 +17,28 @@ pub(crate) unsafe trait ISvgColor: IUnknown {
     ) -> HRESULT;
 }
 
+/// Drawing effect set on text ranges: the color or gradient of a run, its `font-palette`, its
+/// decorations and its stroke
 #[implement(ISvgColor)]
 pub(crate) struct SvgColorImpl {
-    color: csscolorparser::Color,
+    effect: DrawingEffect,
 }
 impl SvgColorImpl {
-    pub(crate) fn new(color: csscolorparser::Color) -> Self {
-        Self { color }
+    pub(crate) fn new(effect: DrawingEffect) -> Self {
+        Self { effect }
+    }
+
+    pub(crate) fn drawing_effect(&self) -> DrawingEffect {
+        self.effect.clone()
     }
 }
 impl ISvgColor_Impl for SvgColorImpl_Impl {
+    /// `S_FALSE` when the effect sets no color
     unsafe fn GetColor(&self, pr: *mut f64, pg: *mut f64, pb: *mut f64, pa: *mut f64) -> HRESULT {
-        let [r, g, b, a] = self.color.to_array();
+        let Some(color) = &self.effect.color else {
+            return S_FALSE;
+        };
+        let [r, g, b, a] = color.to_array();
         *pr = r;
         *pg = g;
         *pb = b;
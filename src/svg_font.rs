@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use unicode_bidi::{bidi_class, BidiClass};
+
+use crate::{
+    font_writer::{woff2_font, FontGlyph},
+    glyph_run::FontId,
+};
+
+// Keeps the format 4 `cmap` of every font well below its size limit
+const MAX_SUBSET_CHARS: usize = 4096;
+
+/// A font embedded in the SVG: some glyphs of one face, each reached through a character
+struct FontSubset {
+    face: Option<(FontId, bool)>,
+    units_per_em: f32,
+    glyphs: Vec<FontGlyph>,
+    glyph_indices: HashMap<u16, u16>,
+    cmap: BTreeMap<char, u16>,
+}
+
+impl FontSubset {
+    /// Whether `ch` can show `glyph_id` in this font
+    fn accepts(&self, ch: char, glyph_id: u16) -> bool {
+        match self.cmap.get(&ch) {
+            Some(index) => self.glyph_indices.get(&glyph_id) == Some(index),
+            None => self.cmap.len() < MAX_SUBSET_CHARS,
+        }
+    }
+}
+
+/// Fonts of the glyphs shown as SVG text. A face is split into several fonts when its glyphs
+/// need the same character, like ligatures and contextual forms. Characters that have no glyph
+/// of their own, like the rest of a ligature's cluster, go into blank fonts.
+pub(crate) struct SvgFontStore {
+    subsets: Vec<FontSubset>,
+}
+
+impl SvgFontStore {
+    pub(crate) fn new() -> Self {
+        Self {
+            subsets: Vec::new(),
+        }
+    }
+
+    /// Index of a font that shows `glyph_id` of the face for `ch`, adding it when needed
+    pub(crate) fn add_glyph(
+        &mut self,
+        face: (FontId, bool),
+        units_per_em: f32,
+        glyph_id: u16,
+        ch: char,
+        glyph: impl FnOnce() -> FontGlyph,
+    ) -> usize {
+        let subset = self.subset_for(Some(face), units_per_em, ch, glyph_id);
+        let font = &mut self.subsets[subset];
+        let index = match font.glyph_indices.get(&glyph_id) {
+            Some(index) => *index,
+            None => {
+                font.glyphs.push(glyph());
+                let index = font.glyphs.len() as u16;
+                font.glyph_indices.insert(glyph_id, index);
+                index
+            }
+        };
+        font.cmap.insert(ch, index);
+        subset
+    }
+
+    /// Index of a font where `ch` is blank
+    pub(crate) fn add_blank(&mut self, ch: char) -> usize {
+        let subset = self.subset_for(None, 1.0, ch, 0);
+        let font = &mut self.subsets[subset];
+        if font.glyphs.is_empty() {
+            font.glyphs.push(FontGlyph {
+                path: Vec::new(),
+                advance: 0.0,
+            });
+            font.glyph_indices.insert(0, 1);
+        }
+        font.cmap.insert(ch, 1);
+        subset
+    }
+
+    fn subset_for(
+        &mut self,
+        face: Option<(FontId, bool)>,
+        units_per_em: f32,
+        ch: char,
+        glyph_id: u16,
+    ) -> usize {
+        let existing = self
+            .subsets
+            .iter()
+            .position(|s| s.face == face && s.accepts(ch, glyph_id));
+        existing.unwrap_or_else(|| {
+            self.subsets.push(FontSubset {
+                face,
+                units_per_em,
+                glyphs: Vec::new(),
+                glyph_indices: HashMap::new(),
+                cmap: BTreeMap::new(),
+            });
+            self.subsets.len() - 1
+        })
+    }
+
+    /// `@font-face` rules of all fonts, as WOFF2 data URLs
+    pub(crate) fn style_sheet(&self) -> String {
+        let mut css = String::new();
+        for (index, subset) in self.subsets.iter().enumerate() {
+            let family = font_family(index);
+            let data = woff2_font(&family, subset.units_per_em, &subset.glyphs, &subset.cmap);
+            css.push_str(&format!(
+                "@font-face{{font-family:\"{}\";src:url(data:font/woff2;base64,{}) format(\"woff2\")}}\n",
+                family,
+                STANDARD.encode(data)
+            ));
+        }
+        css
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.subsets.is_empty()
+    }
+}
+
+/// Family name of an embedded font
+pub(crate) fn font_family(index: usize) -> String {
+    format!("dwtr-{}", index)
+}
+
+/// Whether a character can stand for a glyph in SVG text: renderers move, merge or ignore marks,
+/// controls and invisible formatting characters, and may position astral characters differently
+pub(crate) fn is_plain_char(ch: char) -> bool {
+    (ch as u32) <= 0xFFFF
+        && !ch.is_control()
+        && !is_formatting_char(ch)
+        && !matches!(bidi_class(ch), BidiClass::NSM)
+}
+
+/// Characters that are dropped from SVG text: controls and explicit bidi formatting would break
+/// the text or its ordering
+pub(crate) fn is_dropped_char(ch: char) -> bool {
+    ch.is_control()
+        || matches!(
+            bidi_class(ch),
+            BidiClass::LRE
+                | BidiClass::LRO
+                | BidiClass::RLE
+                | BidiClass::RLO
+                | BidiClass::PDF
+                | BidiClass::LRI
+                | BidiClass::RLI
+                | BidiClass::FSI
+                | BidiClass::PDI
+        )
+}
+
+fn is_formatting_char(ch: char) -> bool {
+    matches!(bidi_class(ch), BidiClass::BN | BidiClass::B | BidiClass::S) || is_dropped_char(ch)
+}
@@ -1,31 +1,156 @@
-use core::ffi::c_void;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use core::fmt::Write;
-use indexmap::{map::Entry, IndexMap};
-use std::{cell::RefCell, rc::Rc};
+use std::collections::{HashMap, HashSet};
 use svg::{node::element, Document, Node};
-use windows::{
-    core::{AsImpl, IUnknown, Interface, Result},
-    Win32::Foundation::BOOL,
-    Win32::Graphics::{Direct2D::Common::*, DirectWrite::*},
+use tiny_skia::Transform;
+use ttf_parser::{
+    colr::{CompositeMode, GradientExtend},
+    RgbaColor,
 };
 
-use crate::{escape::escape_str, svg_color::ISvgColor};
+use crate::{
+    color_glyph::{color_at, Bounds, ColorNode, ColorPaint, GradientStop},
+    css_gradient::{GradientPlacer, GradientShape, PlacedGradient},
+    document::{FontPalette, MixBlendMode, PaintOrder, StrokeLineJoin},
+    error::{AppError, Result},
+    escape::escape_str,
+    font_writer::FontGlyph,
+    frame_transform::transform_scale,
+    glyph_image::{inline_svg, GlyphImageData},
+    glyph_run::{
+        Background, Decoration, DecorationKind, DocumentRenderer, FontId, GlyphFrame,
+        GlyphOutlineSource, GlyphRun, PlacedObject,
+    },
+    inline_object::{object_content, ObjectContent},
+    path::{PathSegment, SharedStore},
+    svg_font::{font_family, is_dropped_char, is_plain_char, SvgFontStore},
+    text_decoration::decoration_stroke,
+    text_stroke::stroke_outline,
+};
+
+/// A color written as an opaque color and an opacity, which more viewers understand than hex
+/// colors with alpha, or a paint server
+#[derive(Clone)]
+struct SvgPaint {
+    color: String,
+    opacity: Option<f32>,
+}
+
+impl SvgPaint {
+    fn new(color: &csscolorparser::Color) -> Self {
+        let [r, g, b, a] = color.to_rgba8();
+        Self {
+            color: csscolorparser::Color::from_rgba8(r, g, b, 255).to_hex_string(),
+            opacity: (a < 255).then(|| (color.a as f32 * 1000.0).round() / 1000.0),
+        }
+    }
+
+    fn server(url: String) -> Self {
+        Self {
+            color: url,
+            opacity: None,
+        }
+    }
+
+    fn assign_fill<T: Node>(&self, node: &mut T) {
+        node.assign("fill", self.color.as_str());
+        if let Some(opacity) = self.opacity {
+            node.assign("fill-opacity", opacity);
+        }
+    }
+
+    fn assign_stroke<T: Node>(&self, node: &mut T) {
+        node.assign("stroke", self.color.as_str());
+        if let Some(opacity) = self.opacity {
+            node.assign("stroke-opacity", opacity);
+        }
+    }
+}
+
+impl Default for SvgPaint {
+    fn default() -> Self {
+        Self {
+            color: String::from("black"),
+            opacity: None,
+        }
+    }
+}
 
 struct SvgGlyph {
-    path_id: usize,
+    /// Id of the `<path>`, color glyph or glyph image in the definitions
+    def_id: String,
     offset_x: f32,
     offset_y: f32,
+    /// Fill other than the run's, for gradients and baked stroke outlines
+    fill: Option<SvgPaint>,
 }
 
 impl SvgGlyph {
-    fn as_element(&self) -> element::Use {
+    /// The glyph in the fill of its parent
+    fn as_unfilled_element(&self) -> element::Use {
         element::Use::new()
-            .set("href", format!("#path{}", self.path_id))
+            .set("href", format!("#{}", self.def_id))
             .set(
                 "transform",
                 format!("translate({} {})", self.offset_x, self.offset_y,),
             )
     }
+
+    fn as_element(&self) -> element::Use {
+        let mut element = self.as_unfilled_element();
+        if let Some(fill) = &self.fill {
+            fill.assign_fill(&mut element);
+        }
+        element
+    }
+}
+
+/// Characters of a `<text>` element set in one embedded font. Every character has a position,
+/// so that each starts a text chunk of its own and renderers shape it alone.
+struct SvgTextSpan {
+    font: usize,
+    text: String,
+    x: Vec<f32>,
+    y: Vec<f32>,
+}
+
+impl SvgTextSpan {
+    fn markup(&self) -> String {
+        let list = |values: &[f32]| {
+            // Adding zero turns -0 into 0
+            let strings: Vec<String> = values.iter().map(|v| (v + 0.0).to_string()).collect();
+            strings.join(" ")
+        };
+        // Without more values, the remaining characters keep the last y
+        let y = match self.y.iter().all(|y| *y == self.y[0]) {
+            true => &self.y[..1],
+            false => &self.y[..],
+        };
+        let mut markup = format!(
+            "<tspan font-family=\"{}\" x=\"{}\" y=\"{}\"",
+            font_family(self.font),
+            list(&self.x),
+            list(y)
+        );
+        write!(markup, ">{}</tspan>", escape_str(&self.text)).unwrap();
+        markup
+    }
+}
+
+/// Stroke of a run, with the width in design units
+struct SvgStroke {
+    color: SvgPaint,
+    width: f32,
+    line_join: StrokeLineJoin,
+    paint_order: PaintOrder,
+}
+
+/// A shadow of a run, with the id of the filter that blurs it
+struct SvgShadow {
+    offset_x: f32,
+    offset_y: f32,
+    color: SvgPaint,
+    filter: Option<String>,
 }
 
 struct SvgRun {
@@ -34,12 +159,28 @@ struct SvgRun {
     rotate_angle: f32,
     upm: f32,
     scalar: f32,
-    color: Option<String>,
+    color: Option<SvgPaint>,
+    /// Index of the gradient in a `SvgGradientStore`, which each glyph gets in its own space
+    gradient: Option<usize>,
+    /// Stroke drawn by the viewer; baked strokes are glyphs of their own
+    stroke: Option<SvgStroke>,
+    shadows: Vec<SvgShadow>,
+    /// Opacity of the run group and its shadows, when it is not opaque
+    opacity: Option<f32>,
+    blend_mode: MixBlendMode,
     source_text: String,
     glyphs: Vec<SvgGlyph>,
+    text_spans: Vec<SvgTextSpan>,
     copyable: bool,
 }
 impl SvgRun {
+    /// Maps the design units of the run onto the canvas, like its `transform` attribute
+    fn transform(&self) -> Transform {
+        Transform::from_translate(self.offset_x, self.offset_y)
+            .pre_rotate(self.rotate_angle)
+            .pre_scale(1.0 / self.scalar, 1.0 / self.scalar)
+    }
+
     fn as_element(&self) -> element::Group {
         let mut g = element::Group::new()
             .set(
@@ -52,15 +193,39 @@ impl SvgRun {
                     1.0 / self.scalar
                 ),
             )
-            .set("fill", self.color.clone().unwrap_or(String::from("black")))
             .set("data-source-text", escape_str(&self.source_text));
+        self.color.clone().unwrap_or_default().assign_fill(&mut g);
+        self.assign_compositing(&mut g);
+        if let Some(stroke) = &self.stroke {
+            stroke.color.assign_stroke(&mut g);
+            g.assign("stroke-width", round_coord(stroke.width));
+            match stroke.line_join {
+                StrokeLineJoin::Miter => {}
+                StrokeLineJoin::Round => g.assign("stroke-linejoin", "round"),
+                StrokeLineJoin::Bevel => g.assign("stroke-linejoin", "bevel"),
+            }
+            if stroke.paint_order == PaintOrder::Stroke {
+                g.assign("paint-order", "stroke");
+            }
+        }
 
-        if self.copyable {
+        if !self.text_spans.is_empty() {
+            // Written as one node, so that no whitespace gets between the spans
+            let spans: String = self.text_spans.iter().map(SvgTextSpan::markup).collect();
+            g.append(svg::node::Text::new(format!(
+                "<text font-size=\"{}\" direction=\"ltr\" unicode-bidi=\"bidi-override\" \
+                 xml:space=\"preserve\">{}</text>",
+                self.upm, spans
+            )));
+        } else if self.copyable {
             let mut text_element = element::Text::new()
                 .set("x", self.offset_x)
                 .set("y", self.offset_y)
                 .set("font-size", self.upm)
                 .set("fill", "transparent");
+            if self.stroke.is_some() {
+                text_element.assign("stroke", "none");
+            }
             text_element.append(svg::node::Text::new(escape_str(&self.source_text)));
             g.append(text_element)
         }
@@ -69,10 +234,166 @@ impl SvgRun {
         }
         g
     }
+
+    /// The glyphs and their stroke all in the color of a shadow, moved by its offset. Text of
+    /// embedded fonts is hidden from selection, so that it is not copied twice.
+    fn as_shadow_element(&self, shadow: &SvgShadow) -> element::Group {
+        let mut g = element::Group::new().set(
+            "transform",
+            format!(
+                "translate({} {}) rotate({}) scale({})",
+                self.offset_x + shadow.offset_x,
+                self.offset_y + shadow.offset_y,
+                self.rotate_angle,
+                1.0 / self.scalar
+            ),
+        );
+        shadow.color.assign_fill(&mut g);
+        if let Some(stroke) = &self.stroke {
+            shadow.color.assign_stroke(&mut g);
+            g.assign("stroke-width", round_coord(stroke.width));
+            match stroke.line_join {
+                StrokeLineJoin::Miter => {}
+                StrokeLineJoin::Round => g.assign("stroke-linejoin", "round"),
+                StrokeLineJoin::Bevel => g.assign("stroke-linejoin", "bevel"),
+            }
+        }
+        if !self.text_spans.is_empty() {
+            let spans: String = self.text_spans.iter().map(SvgTextSpan::markup).collect();
+            g.append(svg::node::Text::new(format!(
+                "<text font-size=\"{}\" direction=\"ltr\" unicode-bidi=\"bidi-override\" \
+                 xml:space=\"preserve\" aria-hidden=\"true\" style=\"user-select:none\">{}</text>",
+                self.upm, spans
+            )));
+        }
+        for glyph in &self.glyphs {
+            g.append(glyph.as_unfilled_element());
+        }
+        let mut g = match &shadow.filter {
+            Some(filter) => element::Group::new()
+                .set("filter", format!("url(#{})", filter))
+                .add(g),
+            None => g,
+        };
+        self.assign_compositing(&mut g);
+        g
+    }
+
+    /// Fade and blend the run as a whole, rather than each glyph over the ones before it
+    fn assign_compositing(&self, g: &mut element::Group) {
+        if let Some(opacity) = self.opacity {
+            g.assign("opacity", opacity);
+        }
+        if self.blend_mode != MixBlendMode::Normal {
+            g.assign(
+                "style",
+                format!("mix-blend-mode:{}", self.blend_mode.css_name()),
+            );
+        }
+    }
+}
+
+/// A decoration line, stroked along its center
+struct SvgDecoration {
+    kind: DecorationKind,
+    offset_x: f32,
+    offset_y: f32,
+    rotate_angle: f32,
+    path: String,
+    width: f32,
+    dash: Option<(f32, f32)>,
+    round_caps: bool,
+    color: SvgPaint,
+}
+
+impl SvgDecoration {
+    fn new(frame: &GlyphFrame, decoration: &Decoration) -> Self {
+        let stroke = decoration_stroke(decoration);
+        Self {
+            kind: decoration.kind,
+            offset_x: decoration.origin_x + frame.offset_x,
+            offset_y: decoration.origin_y + frame.offset_y,
+            rotate_angle: decoration.rotate_angle(),
+            path: path_data(&stroke.path),
+            width: stroke.width,
+            dash: stroke.dash,
+            round_caps: stroke.round_caps,
+            color: decoration
+                .color
+                .as_ref()
+                .map(SvgPaint::new)
+                .unwrap_or_default(),
+        }
+    }
+
+    fn as_element(&self) -> element::Path {
+        let mut path = element::Path::new()
+            .set("d", self.path.as_str())
+            .set(
+                "transform",
+                format!(
+                    "translate({} {}) rotate({})",
+                    self.offset_x, self.offset_y, self.rotate_angle
+                ),
+            )
+            .set("fill", "none")
+            .set("stroke-width", round_coord(self.width));
+        self.color.assign_stroke(&mut path);
+        if let Some((dash, gap)) = self.dash {
+            path.assign(
+                "stroke-dasharray",
+                format!("{} {}", round_coord(dash), round_coord(gap)),
+            );
+        }
+        if self.round_caps {
+            path.assign("stroke-linecap", "round");
+        }
+        path
+    }
+}
+
+/// A background rectangle, with its corners rounded by `rx`
+struct SvgBackground {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    radius: f32,
+    color: SvgPaint,
+}
+
+impl SvgBackground {
+    fn new(frame: &GlyphFrame, background: &Background) -> Self {
+        Self {
+            x: background.x + frame.offset_x,
+            y: background.y + frame.offset_y,
+            width: background.width,
+            height: background.height,
+            radius: background.radius,
+            color: SvgPaint::new(&background.color),
+        }
+    }
+
+    fn as_element(&self) -> element::Rectangle {
+        let mut rect = element::Rectangle::new()
+            .set("x", round_coord(self.x))
+            .set("y", round_coord(self.y))
+            .set("width", round_coord(self.width))
+            .set("height", round_coord(self.height));
+        self.color.assign_fill(&mut rect);
+        if self.radius > 0.0 {
+            rect.assign("rx", round_coord(self.radius));
+        }
+        rect
+    }
 }
 
 pub(crate) struct SvgFrame {
     runs: Vec<SvgRun>,
+    backgrounds: Vec<SvgBackground>,
+    decorations: Vec<SvgDecoration>,
+    objects: Vec<element::Group>,
+    transform: Transform,
     copyable: bool,
     frame_title: Option<String>,
     frame_desc: Option<String>,
@@ -82,6 +403,10 @@ impl SvgFrame {
     pub(crate) fn new() -> Self {
         Self {
             runs: Vec::new(),
+            backgrounds: Vec::new(),
+            decorations: Vec::new(),
+            objects: Vec::new(),
+            transform: Transform::identity(),
             copyable: false,
             frame_desc: None,
             frame_title: None,
@@ -90,513 +415,1223 @@ impl SvgFrame {
 
     fn as_element(&self) -> element::Group {
         let mut g = element::Group::new();
+        if !self.transform.is_identity() {
+            let m = self.transform;
+            g.assign(
+                "transform",
+                format!(
+                    "matrix({} {} {} {} {} {})",
+                    m.sx, m.ky, m.kx, m.sy, m.tx, m.ty
+                ),
+            );
+        }
         if let Some(title) = &self.frame_title {
             g.append(element::Title::new().add(svg::node::Text::new(escape_str(title))));
         }
         if let Some(desc) = &self.frame_desc {
             g.append(element::Description::new().add(svg::node::Text::new(escape_str(desc))));
         }
+        for background in &self.backgrounds {
+            g.append(background.as_element());
+        }
+        // Shadows go below all text of the frame, the first shadow of a run on top
+        for run in &self.runs {
+            for shadow in run.shadows.iter().rev() {
+                g.append(run.as_shadow_element(shadow));
+            }
+        }
+        // Underlines and overlines go below the text, line-throughs above it
+        let (through, under): (Vec<_>, Vec<_>) = self
+            .decorations
+            .iter()
+            .partition(|decoration| decoration.kind == DecorationKind::LineThrough);
+        for decoration in under {
+            g.append(decoration.as_element());
+        }
         for run in &self.runs {
             g.append(run.as_element());
         }
+        for object in &self.objects {
+            g.append(object.clone());
+        }
+        for decoration in through {
+            g.append(decoration.as_element());
+        }
         g
     }
 }
 
-pub(crate) struct SharedStore {
-    last_path_id: usize,
-    path_defs: IndexMap<String, usize>,
+/// Text gradients in canvas coordinates, and the gradients that reference them to draw them in
+/// the space of an element. Paint servers in user space take the space of the element they fill,
+/// so that glyphs placed by a `<use>` each need one of their own.
+struct SvgGradientStore {
+    gradients: Vec<PlacedGradient>,
+    defs: Vec<Box<dyn Node>>,
+    fills: usize,
 }
 
-impl SharedStore {
-    pub(crate) fn new() -> Self {
+impl SvgGradientStore {
+    fn new() -> Self {
         Self {
-            last_path_id: 0,
-            path_defs: IndexMap::new(),
+            gradients: Vec::new(),
+            defs: Vec::new(),
+            fills: 0,
         }
     }
 
-    pub(crate) fn add_path_def(&mut self, str: String) -> usize {
-        if str.is_empty() {
-            return 0;
+    /// Index of a gradient, adding it once
+    fn add(&mut self, gradient: &PlacedGradient) -> usize {
+        if let Some(index) = self.gradients.iter().position(|g| g == gradient) {
+            return index;
         }
-        match self.path_defs.entry(str) {
-            Entry::Occupied(o) => *o.get(),
-            Entry::Vacant(v) => {
-                self.last_path_id += 1;
-                v.insert(self.last_path_id);
-                self.last_path_id
-            }
+        let id = format!("gradient{}", self.gradients.len() + 1);
+        let element: Box<dyn Node> = match gradient.shape {
+            GradientShape::Linear { start, end } => Box::new(append_css_stops(
+                element::LinearGradient::new()
+                    .set("id", id)
+                    .set("gradientUnits", "userSpaceOnUse")
+                    .set("x1", round_coord(start.0))
+                    .set("y1", round_coord(start.1))
+                    .set("x2", round_coord(end.0))
+                    .set("y2", round_coord(end.1)),
+                &gradient.stops,
+            )),
+            GradientShape::Radial { center, radius } => Box::new(append_css_stops(
+                element::RadialGradient::new()
+                    .set("id", id)
+                    .set("gradientUnits", "userSpaceOnUse")
+                    .set("cx", round_coord(center.0))
+                    .set("cy", round_coord(center.1))
+                    .set("r", round_coord(radius.0)),
+                &gradient.stops,
+            )),
+        };
+        self.defs.push(element);
+        self.gradients.push(gradient.clone());
+        self.gradients.len() - 1
+    }
+
+    /// Fill that draws a gradient in the space `transform` maps onto the canvas
+    fn fill(&mut self, index: usize, transform: Transform) -> String {
+        let gradient = &self.gradients[index];
+        let m = transform
+            .invert()
+            .unwrap_or_default()
+            .pre_concat(gradient.shape_transform());
+        self.fills += 1;
+        let id = format!("gradient{}-{}", index + 1, self.fills);
+        let href = format!("#gradient{}", index + 1);
+        let matrix = format!(
+            "matrix({} {} {} {} {} {})",
+            m.sx, m.ky, m.kx, m.sy, m.tx, m.ty
+        );
+        let element: Box<dyn Node> = match gradient.shape {
+            GradientShape::Linear { .. } => Box::new(
+                element::LinearGradient::new()
+                    .set("id", id.clone())
+                    .set("href", href)
+                    .set("gradientTransform", matrix),
+            ),
+            GradientShape::Radial { .. } => Box::new(
+                element::RadialGradient::new()
+                    .set("id", id.clone())
+                    .set("href", href)
+                    .set("gradientTransform", matrix),
+            ),
+        };
+        self.defs.push(element);
+        format!("url(#{})", id)
+    }
+}
+
+fn append_css_stops<T: Node>(mut gradient: T, stops: &[(f32, csscolorparser::Color)]) -> T {
+    for (offset, color) in stops {
+        let paint = SvgPaint::new(color);
+        let mut stop = element::Stop::new()
+            .set("offset", *offset)
+            .set("stop-color", paint.color);
+        if let Some(opacity) = paint.opacity {
+            stop.assign("stop-opacity", opacity);
         }
+        gradient.append(stop);
     }
+    gradient
 }
 
-pub(crate) struct SvgDocumentRenderer {
-    canvas_width: f32,
-    canvas_height: f32,
-    shared_store: Rc<RefCell<SharedStore>>,
-    frames: Vec<Rc<RefCell<SvgFrame>>>,
+/// A color glyph as drawn by one run: face, glyph, sideways, palette and foreground color
+type ColorGlyphKey = (FontId, u16, bool, Option<FontPalette>, [u8; 4]);
+
+/// A glyph image as drawn by one run: face, glyph, sideways and pixels per em
+type GlyphImageKey = (FontId, u16, bool, u16);
+
+/// Color glyphs converted to SVG groups, and glyph images as `<image>` elements or inlined
+/// `SVG ` documents, each defined once and referenced by `<use>`
+struct ColorGlyphStore {
+    ids: HashMap<ColorGlyphKey, Option<usize>>,
+    image_ids: HashMap<GlyphImageKey, Option<String>>,
+    /// Prefixes and element ids of the inlined documents, by face and first glyph
+    documents: HashMap<(FontId, u16), Option<InlineSvgDocument>>,
+    defs: Vec<Box<dyn Node>>,
+    /// Scale of the frame transform the glyphs are drawn with, which bitmap strikes suit
+    scale: f32,
 }
 
-impl SvgDocumentRenderer {
-    pub(crate) fn new(canvas_width: f32, canvas_height: f32) -> Self {
+struct InlineSvgDocument {
+    prefix: String,
+    ids: HashSet<String>,
+}
+
+impl ColorGlyphStore {
+    fn new() -> Self {
         Self {
-            canvas_width,
-            canvas_height,
-            shared_store: Rc::new(RefCell::new(SharedStore::new())),
-            frames: Vec::new(),
+            ids: HashMap::new(),
+            image_ids: HashMap::new(),
+            documents: HashMap::new(),
+            defs: Vec::new(),
+            scale: 1.0,
         }
     }
 
-    pub(crate) fn create_frame_renderer(
+    /// Id of the definition of a color glyph: its `COLR` layers, or else its image. `None` when
+    /// the glyph has neither.
+    fn add(
         &mut self,
-        offset_x: f32,
-        offset_y: f32,
-    ) -> SvgFrameRenderer {
-        let frame_store = Rc::new(RefCell::new(SvgFrame::new()));
-        let frame_renderer = SvgFrameRenderer::new(
-            self.shared_store.clone(),
-            frame_store.clone(),
-            offset_x,
-            offset_y,
-        );
-        self.frames.push(frame_store);
-        frame_renderer
+        glyph_run: &GlyphRun,
+        glyph_id: u16,
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Option<String> {
+        self.add_layers(glyph_run, glyph_id, outlines)
+            .or_else(|| self.add_image(glyph_run, glyph_id, outlines))
     }
 
-    pub(crate) fn into_xml(&self) -> Document {
-        let store = self.shared_store.borrow();
-
-        let mut defs = element::Definitions::new();
-        for (path_d, id) in &store.path_defs {
-            let path = element::Path::new()
-                .set("id", format!("path{}", id))
-                .set("d", path_d.clone());
-            defs.append(path);
+    fn add_layers(
+        &mut self,
+        glyph_run: &GlyphRun,
+        glyph_id: u16,
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Option<String> {
+        let effect = glyph_run.drawing_effect.as_ref();
+        let palette = effect.and_then(|effect| effect.palette.clone());
+        let foreground = effect
+            .and_then(|effect| effect.color.clone())
+            .unwrap_or(csscolorparser::Color::new(0.0, 0.0, 0.0, 1.0));
+        let key = (
+            glyph_run.font,
+            glyph_id,
+            glyph_run.is_sideways,
+            palette,
+            foreground.to_rgba8(),
+        );
+        if let Some(index) = self.ids.get(&key) {
+            return index.map(color_glyph_id);
         }
+        let glyph = outlines.color_glyph(
+            glyph_run.font,
+            glyph_id,
+            glyph_run.is_sideways,
+            key.3.as_ref(),
+            &foreground,
+        );
+        let index = glyph.map(|glyph| {
+            let id = color_glyph_id(self.defs.len());
+            let mut writer = ColorGlyphWriter {
+                id: id.clone(),
+                next_def: 0,
+            };
+            let mut g = element::Group::new().set("id", id);
+            writer.append_nodes(&mut g, &glyph.nodes);
+            self.defs.push(Box::new(g));
+            self.defs.len() - 1
+        });
+        self.ids.insert(key, index);
+        index.map(color_glyph_id)
+    }
 
-        let mut svg = Document::new()
-            .set(
-                "viewBox",
-                format!("0 0 {} {}", self.canvas_width, self.canvas_height),
-            )
-            .set("width", self.canvas_width)
-            .set("height", self.canvas_height)
-            .add(defs);
-
-        for frame in &self.frames {
-            svg.append(frame.borrow().as_element());
+    /// Id of the definition of a glyph image, taking the bitmap strike closest to the font size
+    /// of the run in pixels once the frame is transformed
+    fn add_image(
+        &mut self,
+        glyph_run: &GlyphRun,
+        glyph_id: u16,
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Option<String> {
+        let pixels_per_em = (glyph_run.font_size * self.scale)
+            .ceil()
+            .clamp(1.0, u16::MAX as f32) as u16;
+        let key = (
+            glyph_run.font,
+            glyph_id,
+            glyph_run.is_sideways,
+            pixels_per_em,
+        );
+        if let Some(id) = self.image_ids.get(&key) {
+            return id.clone();
         }
+        let image = outlines.glyph_image(
+            glyph_run.font,
+            glyph_id,
+            glyph_run.is_sideways,
+            pixels_per_em,
+        );
+        let id = image.and_then(|image| {
+            // Turn sideways images around the vertical origin, like `glyph_path` does outlines
+            let transform = image
+                .vertical_origin
+                .map(|(x, y)| format!("matrix(0 -1 1 0 {} {})", y, x));
+            match image.data {
+                GlyphImageData::Png {
+                    data,
+                    x,
+                    y,
+                    width,
+                    height,
+                } => {
+                    let id = color_glyph_id(self.defs.len());
+                    let mut element = element::Image::new()
+                        .set("id", id.clone())
+                        .set("x", x)
+                        .set("y", y)
+                        .set("width", width)
+                        .set("height", height)
+                        .set(
+                            "href",
+                            format!("data:image/png;base64,{}", STANDARD.encode(data)),
+                        );
+                    if let Some(transform) = transform {
+                        element.assign("transform", transform);
+                    }
+                    self.defs.push(Box::new(element));
+                    Some(id)
+                }
+                GlyphImageData::Svg {
+                    document,
+                    first_glyph,
+                } => {
+                    let target = self.add_document(glyph_run.font, first_glyph, &document)?;
+                    let glyph_element = format!("{}-glyph{}", target.prefix, glyph_id);
+                    let href = match target.ids.contains(&glyph_element) {
+                        true => glyph_element,
+                        false => target.prefix.clone(),
+                    };
+                    let Some(transform) = transform else {
+                        return Some(href);
+                    };
+                    let id = color_glyph_id(self.defs.len());
+                    let element = element::Use::new()
+                        .set("id", id.clone())
+                        .set("href", format!("#{}", href))
+                        .set("transform", transform);
+                    self.defs.push(Box::new(element));
+                    Some(id)
+                }
+            }
+        });
+        self.image_ids.insert(key, id.clone());
+        id
+    }
 
-        svg
+    /// Inline an `SVG ` table document once for all of its glyphs
+    fn add_document(
+        &mut self,
+        font: FontId,
+        first_glyph: u16,
+        document: &[u8],
+    ) -> Option<&InlineSvgDocument> {
+        if !self.documents.contains_key(&(font, first_glyph)) {
+            let prefix = color_glyph_id(self.defs.len());
+            let inline = inline_svg(document, &prefix).map(|inline| {
+                self.defs
+                    .push(Box::new(svg::node::Text::new(inline.markup)));
+                InlineSvgDocument {
+                    prefix,
+                    ids: inline.ids,
+                }
+            });
+            self.documents.insert((font, first_glyph), inline);
+        }
+        self.documents[&(font, first_glyph)].as_ref()
     }
 }
 
-#[windows::core::implement(IDWriteTextRenderer1)]
-pub(crate) struct SvgFrameRenderer {
-    shared_store: Rc<RefCell<SharedStore>>,
-    frame_store: Rc<RefCell<SvgFrame>>,
-    // frame properties
-    offset_x: f32,
-    offset_y: f32,
+fn color_glyph_id(index: usize) -> String {
+    format!("color{}", index + 1)
 }
 
-impl SvgFrameRenderer {
-    pub(crate) fn new(
-        shared_store: Rc<RefCell<SharedStore>>,
-        frame_store: Rc<RefCell<SvgFrame>>,
-        offset_x: f32,
-        offset_y: f32,
-    ) -> Self {
-        Self {
-            shared_store,
-            frame_store,
-            offset_x,
-            offset_y,
-        }
+/// Id of the definition a glyph is drawn with: its color layers or image, or else its outline in
+/// the shared store. `None` for empty glyphs.
+fn glyph_def(
+    shared_store: &mut SharedStore,
+    color_glyphs: &mut ColorGlyphStore,
+    glyph_run: &GlyphRun,
+    glyph_id: u16,
+    outlines: &dyn GlyphOutlineSource,
+) -> Result<Option<String>> {
+    if let Some(id) = color_glyphs.add(glyph_run, glyph_id, outlines) {
+        return Ok(Some(id));
     }
+    let path = outlines.glyph_path(glyph_run.font, glyph_id, glyph_run.is_sideways)?;
+    let path_id = shared_store.add_path_def(path);
+    Ok((path_id > 0).then(|| format!("path{}", path_id)))
+}
 
-    pub(crate) fn set_title(&self, title: Option<String>) {
-        self.frame_store.borrow_mut().frame_title = title;
-    }
-    pub(crate) fn set_desc(&self, desc: Option<String>) {
-        self.frame_store.borrow_mut().frame_desc = desc;
-    }
-    pub(crate) fn set_copyable(&self, copyable: bool) {
-        self.frame_store.borrow_mut().copyable = copyable;
+/// Number of wedges that approximate a sweep gradient, which SVG does not have
+const SWEEP_WEDGES: usize = 180;
+
+/// Writes the paint graph of a color glyph as SVG, with clip paths and gradients next to the
+/// shapes that use them
+struct ColorGlyphWriter {
+    id: String,
+    next_def: usize,
+}
+
+impl ColorGlyphWriter {
+    fn def_id(&mut self) -> String {
+        self.next_def += 1;
+        format!("{}-{}", self.id, self.next_def)
     }
 
-    fn get_color_from_brush(&self, brush: Option<&IUnknown>) -> Option<String> {
-        match brush {
-            Some(brush) => match brush.cast::<ISvgColor>() {
-                Ok(color) => {
-                    let mut sink = csscolorparser::Color::default();
-                    unsafe {
-                        color
-                            .GetColor(&mut sink.r, &mut sink.g, &mut sink.b, &mut sink.a)
-                            .unwrap()
+    fn append_nodes(&mut self, parent: &mut element::Group, nodes: &[ColorNode]) {
+        for node in nodes {
+            match node {
+                ColorNode::Fill {
+                    path,
+                    bounds,
+                    paint,
+                } => self.append_fill(parent, path, *bounds, paint),
+                ColorNode::Clip { path, children } => {
+                    let mut g = self.clip_group(parent, path);
+                    self.append_nodes(&mut g, children);
+                    parent.append(g);
+                }
+                ColorNode::Transform {
+                    transform: t,
+                    children,
+                } => {
+                    let mut g = element::Group::new().set(
+                        "transform",
+                        format!("matrix({} {} {} {} {} {})", t.a, t.b, t.c, t.d, t.e, t.f),
+                    );
+                    self.append_nodes(&mut g, children);
+                    parent.append(g);
+                }
+                ColorNode::Layer { mode, children } => {
+                    let Some(style) = composite_style(*mode) else {
+                        continue;
                     };
-                    Some(sink.to_hex_string())
+                    let mut g = element::Group::new().set("style", style);
+                    self.append_nodes(&mut g, children);
+                    parent.append(g);
                 }
-                _ => None,
-            },
-            _ => None,
+            }
         }
     }
-    fn add_path_def(&self, str: String) -> usize {
-        self.shared_store.borrow_mut().add_path_def(str)
+
+    /// A `<clipPath>` added to `parent`, and a group clipped by it
+    fn clip_group(&mut self, parent: &mut element::Group, path: &[PathSegment]) -> element::Group {
+        let id = self.def_id();
+        parent.append(
+            element::ClipPath::new()
+                .set("id", id.clone())
+                .add(element::Path::new().set("d", path_data(path))),
+        );
+        element::Group::new().set("clip-path", format!("url(#{})", id))
     }
-    fn push_run(&self, run: SvgRun) {
-        self.frame_store.borrow_mut().runs.push(run);
+
+    fn append_fill(
+        &mut self,
+        parent: &mut element::Group,
+        path: &[PathSegment],
+        bounds: Bounds,
+        paint: &ColorPaint,
+    ) {
+        let fill = match paint {
+            ColorPaint::Solid(color) => svg_color(*color),
+            ColorPaint::LinearGradient {
+                start,
+                end,
+                stops,
+                extend,
+            } => {
+                let id = self.def_id();
+                let gradient = element::LinearGradient::new()
+                    .set("id", id.clone())
+                    .set("gradientUnits", "userSpaceOnUse")
+                    .set("x1", start.0)
+                    .set("y1", start.1)
+                    .set("x2", end.0)
+                    .set("y2", end.1)
+                    .set("spreadMethod", spread_method(*extend));
+                parent.append(append_stops(gradient, stops));
+                format!("url(#{})", id)
+            }
+            ColorPaint::RadialGradient {
+                start,
+                start_radius,
+                end,
+                end_radius,
+                stops,
+                extend,
+            } => {
+                let id = self.def_id();
+                let gradient = element::RadialGradient::new()
+                    .set("id", id.clone())
+                    .set("gradientUnits", "userSpaceOnUse")
+                    .set("fx", start.0)
+                    .set("fy", start.1)
+                    .set("fr", *start_radius)
+                    .set("cx", end.0)
+                    .set("cy", end.1)
+                    .set("r", *end_radius)
+                    .set("spreadMethod", spread_method(*extend));
+                parent.append(append_stops(gradient, stops));
+                format!("url(#{})", id)
+            }
+            ColorPaint::SweepGradient {
+                center,
+                start_angle,
+                end_angle,
+                stops,
+                extend,
+            } => {
+                let mut g = self.clip_group(parent, path);
+                let angles = (*start_angle, *end_angle);
+                append_sweep_wedges(&mut g, *center, bounds, angles, stops, *extend);
+                parent.append(g);
+                return;
+            }
+        };
+        parent.append(
+            element::Path::new()
+                .set("fill", fill)
+                .set("d", path_data(path)),
+        );
     }
 }
 
-#[allow(non_snake_case)]
-impl IDWritePixelSnapping_Impl for SvgFrameRenderer_Impl {
-    fn IsPixelSnappingDisabled(&self, _client_drawing_context: *const c_void) -> Result<BOOL> {
-        Ok(false.into())
-    }
-    fn GetCurrentTransform(
-        &self,
-        _client_drawing_context: *const core::ffi::c_void,
-        transform: *mut DWRITE_MATRIX,
-    ) -> windows::core::Result<()> {
-        unsafe {
-            *transform = DWRITE_MATRIX {
-                m11: 1.0,
-                m12: 1.0,
-                m21: 1.0,
-                m22: 1.0,
-                dx: 0.0,
-                dy: 0.0,
-            };
-        }
-        Ok(())
-    }
-    fn GetPixelsPerDip(&self, _client_drawing_context: *const c_void) -> Result<f32> {
-        Ok(1.0)
+/// Fill a sweep gradient with narrow wedges around its center, each in the color at its middle,
+/// reaching out of `bounds`. Wedges overlap the next one a little so that no seams show between
+/// them.
+fn append_sweep_wedges(
+    g: &mut element::Group,
+    (cx, cy): (f32, f32),
+    [x0, y0, x1, y1]: Bounds,
+    (start_angle, end_angle): (f32, f32),
+    stops: &[GradientStop],
+    extend: GradientExtend,
+) {
+    let step = 360.0 / SWEEP_WEDGES as f32;
+    // Wedges are triangles, so their far side must clear the corners by the angle they span
+    let radius = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)]
+        .iter()
+        .map(|(x, y)| (x - cx).hypot(y - cy))
+        .fold(0.0, f32::max)
+        / (step * 1.5).to_radians().cos()
+        + 1.0;
+    let point = |angle: f32| {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        (
+            round_coord(cx + radius * cos),
+            round_coord(cy + radius * sin),
+        )
+    };
+    for i in 0..SWEEP_WEDGES {
+        let from = i as f32 * step;
+        let t = (from + step / 2.0 - start_angle) / (end_angle - start_angle);
+        let color = color_at(stops, extend, t);
+        let (x0, y0) = point(from);
+        let (x1, y1) = point(from + step * 1.5);
+        g.append(element::Path::new().set("fill", svg_color(color)).set(
+            "d",
+            format!("M {} {} L {} {} L {} {} Z", cx, cy, x0, y0, x1, y1),
+        ));
     }
 }
 
-#[allow(non_snake_case)]
-impl IDWriteTextRenderer_Impl for SvgFrameRenderer_Impl {
-    fn DrawGlyphRun(
-        &self,
-        client_drawing_context: *const c_void,
-        baseline_origin_x: f32,
-        baseline_origin_y: f32,
-        measuring_mode: DWRITE_MEASURING_MODE,
-        glyph_run: *const DWRITE_GLYPH_RUN,
-        glyph_run_description: *const DWRITE_GLYPH_RUN_DESCRIPTION,
-        client_drawing_effect: Option<&IUnknown>,
-    ) -> Result<()> {
-        IDWriteTextRenderer1_Impl::DrawGlyphRun(
-            self,
-            client_drawing_context,
-            baseline_origin_x,
-            baseline_origin_y,
-            DWRITE_GLYPH_ORIENTATION_ANGLE_0_DEGREES,
-            measuring_mode,
-            glyph_run,
-            glyph_run_description,
-            client_drawing_effect,
-        )
+fn append_stops<T: Node>(mut gradient: T, stops: &[GradientStop]) -> T {
+    for stop in stops {
+        gradient.append(
+            element::Stop::new()
+                .set("offset", stop.offset)
+                .set("stop-color", svg_color(stop.color)),
+        );
     }
+    gradient
+}
 
-    fn DrawInlineObject(
-        &self,
-        _client_drawing_context: *const c_void,
-        _origin_x: f32,
-        _origin_y: f32,
-        _inline_object: Option<&IDWriteInlineObject>,
-        _is_sideways: BOOL,
-        _is_right_to_left: BOOL,
-        _client_drawing_effect: Option<&IUnknown>,
-    ) -> Result<()> {
-        Ok(())
+fn svg_color(color: RgbaColor) -> String {
+    csscolorparser::Color::from_rgba8(color.red, color.green, color.blue, color.alpha)
+        .to_hex_string()
+}
+
+fn spread_method(extend: GradientExtend) -> &'static str {
+    match extend {
+        GradientExtend::Pad => "pad",
+        GradientExtend::Repeat => "repeat",
+        GradientExtend::Reflect => "reflect",
     }
+}
 
-    fn DrawUnderline(
-        &self,
-        _client_drawing_context: *const c_void,
-        _baseline_origin_x: f32,
-        _baseline_origin_y: f32,
-        _underline: *const DWRITE_UNDERLINE,
-        _client_drawing_effect: Option<&IUnknown>,
-    ) -> Result<()> {
-        Ok(())
+/// Style of a layer composited with `mode`; `None` when the layer is not drawn at all. Source-over
+/// layers isolate the blending inside them; Porter-Duff modes other than `dest` and `clear`,
+/// which SVG cannot express, are drawn as source-over.
+fn composite_style(mode: CompositeMode) -> Option<String> {
+    let blend_mode = match mode {
+        CompositeMode::Clear | CompositeMode::Destination => return None,
+        CompositeMode::Screen => MixBlendMode::Screen,
+        CompositeMode::Overlay => MixBlendMode::Overlay,
+        CompositeMode::Darken => MixBlendMode::Darken,
+        CompositeMode::Lighten => MixBlendMode::Lighten,
+        CompositeMode::ColorDodge => MixBlendMode::ColorDodge,
+        CompositeMode::ColorBurn => MixBlendMode::ColorBurn,
+        CompositeMode::HardLight => MixBlendMode::HardLight,
+        CompositeMode::SoftLight => MixBlendMode::SoftLight,
+        CompositeMode::Difference => MixBlendMode::Difference,
+        CompositeMode::Exclusion => MixBlendMode::Exclusion,
+        CompositeMode::Multiply => MixBlendMode::Multiply,
+        CompositeMode::Hue => MixBlendMode::Hue,
+        CompositeMode::Saturation => MixBlendMode::Saturation,
+        CompositeMode::Color => MixBlendMode::Color,
+        CompositeMode::Luminosity => MixBlendMode::Luminosity,
+        _ => return Some(String::from("isolation:isolate")),
+    };
+    Some(format!("mix-blend-mode:{}", blend_mode.css_name()))
+}
+
+pub(crate) struct SvgDocumentRenderer {
+    canvas_width: f32,
+    canvas_height: f32,
+    shared_store: SharedStore,
+    color_glyphs: ColorGlyphStore,
+    gradients: SvgGradientStore,
+    filters: Vec<element::Filter>,
+    fonts: Option<SvgFontStore>,
+    frames: Vec<SvgFrame>,
+    object_count: usize,
+    bake_strokes: bool,
+    /// Ids of the baked stroke outlines of glyphs, by face, glyph, width and line join
+    stroke_outlines: HashMap<(FontId, bool, u16, u32, StrokeLineJoin), Option<String>>,
+}
+
+impl SvgDocumentRenderer {
+    pub(crate) fn new(canvas_width: f32, canvas_height: f32) -> Self {
+        Self {
+            canvas_width,
+            canvas_height,
+            shared_store: SharedStore::new(),
+            color_glyphs: ColorGlyphStore::new(),
+            gradients: SvgGradientStore::new(),
+            filters: Vec::new(),
+            fonts: None,
+            frames: Vec::new(),
+            object_count: 0,
+            bake_strokes: false,
+            stroke_outlines: HashMap::new(),
+        }
     }
 
-    fn DrawStrikethrough(
-        &self,
-        _client_drawing_context: *const c_void,
-        _baseline_origin_x: f32,
-        _baseline_origin_y: f32,
-        _strike_through: *const DWRITE_STRIKETHROUGH,
-        _client_drawing_effect: Option<&IUnknown>,
-    ) -> Result<()> {
-        Ok(())
+    /// Fill the area that strokes cover instead of stroking glyphs, for consumers that cannot
+    /// stroke. Text in embedded fonts keeps its stroke.
+    pub(crate) fn with_baked_strokes(self, bake_strokes: bool) -> Self {
+        Self {
+            bake_strokes,
+            ..self
+        }
     }
-}
 
-#[allow(non_snake_case)]
-impl IDWriteTextRenderer1_Impl for SvgFrameRenderer_Impl {
-    fn DrawGlyphRun(
-        &self,
-        _client_drawing_context: *const c_void,
-        baseline_origin_x: f32,
-        baseline_origin_y: f32,
-        orientation_angle: DWRITE_GLYPH_ORIENTATION_ANGLE,
-        _measuring_mode: DWRITE_MEASURING_MODE,
-        glyph_run: *const DWRITE_GLYPH_RUN,
-        glyph_run_description: *const DWRITE_GLYPH_RUN_DESCRIPTION,
-        client_drawing_effect: Option<&IUnknown>,
-    ) -> Result<()> {
-        if let Some(font_face) = unsafe { (*glyph_run).fontFace.as_ref() } {
-            let mut metrics = DWRITE_FONT_METRICS::default();
-            unsafe { font_face.GetMetrics(&mut metrics) }
+    /// Write glyphs as `<text>` in embedded WOFF2 fonts made of their outlines, instead of
+    /// referencing shared paths
+    pub(crate) fn with_embedded_fonts(canvas_width: f32, canvas_height: f32) -> Self {
+        Self {
+            fonts: Some(SvgFontStore::new()),
+            ..Self::new(canvas_width, canvas_height)
+        }
+    }
 
-            let glyph_count = unsafe { (*glyph_run).glyphCount };
-            let color = self.get_color_from_brush(client_drawing_effect);
+    fn create_run(
+        &mut self,
+        frame: &GlyphFrame,
+        glyph_run: &GlyphRun,
+        gradient: Option<&PlacedGradient>,
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Result<SvgRun> {
+        let upm = outlines.units_per_em(glyph_run.font);
+        let scalar = upm / glyph_run.font_size;
 
-            let scalar = (metrics.designUnitsPerEm as f32) / unsafe { (*glyph_run).fontEmSize };
+        let effect = glyph_run.drawing_effect.as_ref();
+        let stroke = effect.and_then(|effect| {
+            let stroke = effect.stroke.as_ref()?;
+            Some(SvgStroke {
+                color: effect
+                    .stroke_color()
+                    .as_ref()
+                    .map(SvgPaint::new)
+                    .unwrap_or_default(),
+                width: stroke.width * scalar,
+                line_join: stroke.line_join,
+                paint_order: stroke.paint_order,
+            })
+        });
+        let (stroke, baked_stroke) = match self.bake_strokes && self.fonts.is_none() {
+            true => (None, stroke),
+            false => (stroke, None),
+        };
+        let mut run = SvgRun {
+            offset_x: glyph_run.baseline_origin_x + frame.offset_x,
+            offset_y: glyph_run.baseline_origin_y + frame.offset_y,
+            rotate_angle: glyph_run.rotate_angle(),
+            upm,
+            scalar,
+            color: effect
+                .and_then(|effect| effect.color.as_ref())
+                .map(SvgPaint::new),
+            gradient: gradient.map(|gradient| self.gradients.add(gradient)),
+            stroke,
+            shadows: self.create_shadows(frame, glyph_run, outlines)?,
+            opacity: effect
+                .and_then(|effect| effect.opacity)
+                .filter(|o| *o < 1.0),
+            blend_mode: effect.map(|effect| effect.blend_mode).unwrap_or_default(),
+            source_text: glyph_run.source_text.clone(),
+            glyphs: Vec::new(),
+            text_spans: Vec::new(),
+            copyable: frame.copyable,
+        };
 
-            let mut run = SvgRun {
-                offset_x: baseline_origin_x + self.offset_x,
-                offset_y: baseline_origin_y + self.offset_y,
-                rotate_angle: dw_angle_to_angle(&orientation_angle, unsafe {
-                    (*glyph_run).isSideways.as_bool()
+        let positions = glyph_run.glyph_positions();
+        if self.fonts.is_some() {
+            // The text is in the space of the run, unlike glyphs placed by a `<use>`
+            if let Some(index) = run.gradient {
+                run.color = Some(SvgPaint::server(
+                    self.gradients.fill(index, run.transform()),
+                ));
+            }
+            self.add_text(&mut run, glyph_run, &positions, outlines)?;
+            return Ok(run);
+        }
+        for (glyph_id, (x, y)) in glyph_run.glyph_ids.iter().zip(positions) {
+            let (offset_x, offset_y) = (round_coord(x * scalar), round_coord(y * scalar));
+            let def = glyph_def(
+                &mut self.shared_store,
+                &mut self.color_glyphs,
+                glyph_run,
+                *glyph_id,
+                outlines,
+            )?;
+            let fill = def.map(|def_id| SvgGlyph {
+                def_id,
+                offset_x,
+                offset_y,
+                fill: run.gradient.map(|index| {
+                    let transform = run.transform().pre_translate(offset_x, offset_y);
+                    SvgPaint::server(self.gradients.fill(index, transform))
                 }),
-                upm: metrics.designUnitsPerEm as f32,
-                scalar,
-                color,
-                source_text: unsafe {
-                    String::from_utf16_lossy(std::slice::from_raw_parts(
-                        (*glyph_run_description).string.0,
-                        (*glyph_run_description).stringLength as usize,
-                    ))
-                },
-                glyphs: Vec::new(),
-                copyable: self.frame_store.borrow().copyable,
+            });
+            let outline = match &baked_stroke {
+                Some(stroke) => self
+                    .stroke_outline_def(glyph_run, *glyph_id, stroke, outlines)?
+                    .map(|def_id| SvgGlyph {
+                        def_id,
+                        offset_x,
+                        offset_y,
+                        fill: Some(stroke.color.clone()),
+                    }),
+                None => None,
+            };
+            // Each glyph is filled and stroked before the next, as a viewer strokes them
+            let glyphs = match baked_stroke.as_ref().map(|stroke| stroke.paint_order) {
+                Some(PaintOrder::Stroke) => [outline, fill],
+                _ => [fill, outline],
             };
+            run.glyphs.extend(glyphs.into_iter().flatten());
+        }
+        Ok(run)
+    }
 
-            let geometry_sink: ID2D1SimplifiedGeometrySink = SvgGeometrySink::new(scalar).into();
-            let geometry_sink_impl = unsafe { geometry_sink.as_impl() };
+    fn create_shadows(
+        &mut self,
+        frame: &GlyphFrame,
+        glyph_run: &GlyphRun,
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Result<Vec<SvgShadow>> {
+        let Some(effect) = glyph_run.drawing_effect.as_ref() else {
+            return Ok(Vec::new());
+        };
+        if effect.shadows.is_empty() {
+            return Ok(Vec::new());
+        }
+        let Some(bounds) = glyph_run.ink_bounds(frame, outlines)? else {
+            return Ok(Vec::new());
+        };
+        let stroke_width = effect.stroke.as_ref().map_or(0.0, |stroke| stroke.width);
+        let shadows = effect.shadows.iter().map(|shadow| {
+            let filter = (shadow.blur > 0.0).then(|| {
+                let [x0, y0, x1, y1] = bounds;
+                // A blur spreads about three standard deviations, and miter joins reach past
+                // the stroke
+                let margin = 1.5 * shadow.blur + stroke_width * 2.0 + 1.0;
+                let id = format!("shadow{}", self.filters.len() + 1);
+                let mut blur = element::Element::new("feGaussianBlur");
+                blur.assign("stdDeviation", shadow.blur / 2.0);
+                self.filters.push(
+                    element::Filter::new()
+                        .set("id", id.clone())
+                        .set("filterUnits", "userSpaceOnUse")
+                        .set("x", round_coord(x0 + shadow.offset_x - margin))
+                        .set("y", round_coord(y0 + shadow.offset_y - margin))
+                        .set("width", round_coord(x1 - x0 + 2.0 * margin))
+                        .set("height", round_coord(y1 - y0 + 2.0 * margin))
+                        .add(blur),
+                );
+                id
+            });
+            SvgShadow {
+                offset_x: shadow.offset_x,
+                offset_y: shadow.offset_y,
+                color: effect
+                    .shadow_color(shadow)
+                    .as_ref()
+                    .map(SvgPaint::new)
+                    .unwrap_or_default(),
+                filter,
+            }
+        });
+        Ok(shadows.collect())
+    }
 
-            let mut offset_x = 0.0;
-            let offset_y = 0.0;
+    /// Id of the path that fills the area a stroke along a glyph's outline covers
+    fn stroke_outline_def(
+        &mut self,
+        glyph_run: &GlyphRun,
+        glyph_id: u16,
+        stroke: &SvgStroke,
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Result<Option<String>> {
+        let key = (
+            glyph_run.font,
+            glyph_run.is_sideways,
+            glyph_id,
+            stroke.width.to_bits(),
+            stroke.line_join,
+        );
+        if let Some(def_id) = self.stroke_outlines.get(&key) {
+            return Ok(def_id.clone());
+        }
+        let path = outlines.glyph_path(glyph_run.font, glyph_id, glyph_run.is_sideways)?;
+        let path_id =
+            self.shared_store
+                .add_path_def(stroke_outline(&path, stroke.width, stroke.line_join));
+        let def_id = (path_id > 0).then(|| format!("path{}", path_id));
+        self.stroke_outlines.insert(key, def_id.clone());
+        Ok(def_id)
+    }
 
-            for i in 0..glyph_count {
-                unsafe {
-                    let p_glyph_offset = (*glyph_run).glyphOffsets;
-                    let p_glyph_offset = if p_glyph_offset.is_null() {
-                        p_glyph_offset
-                    } else {
-                        p_glyph_offset.offset(i as isize)
-                    };
+    /// An inline object at its place: image files as `<image>`, and SVG elements inlined with
+    /// their ids prefixed
+    fn create_object(
+        &mut self,
+        frame: &GlyphFrame,
+        placed: &PlacedObject,
+    ) -> Result<element::Group> {
+        let object = &placed.object;
+        let mut g = element::Group::new().set(
+            "transform",
+            format!(
+                "translate({} {})",
+                placed.x + frame.offset_x,
+                placed.y + frame.offset_y
+            ),
+        );
+        if let Some(alt) = &object.alt {
+            g.append(element::Title::new().add(svg::node::Text::new(escape_str(alt))));
+        }
+        match object_content(object)? {
+            ObjectContent::Image { mime, data } => g.append(
+                element::Image::new()
+                    .set("width", object.width)
+                    .set("height", object.height)
+                    .set(
+                        "href",
+                        format!("data:{};base64,{}", mime, STANDARD.encode(data)),
+                    ),
+            ),
+            ObjectContent::Svg(markup) => {
+                self.object_count += 1;
+                let prefix = format!("object{}", self.object_count);
+                let document = format!("<svg>{}</svg>", markup);
+                let inline = inline_svg(document.as_bytes(), &prefix).ok_or_else(|| {
+                    AppError::Render(String::from("cannot parse the SVG of an inline object"))
+                })?;
+                g.append(svg::node::Text::new(inline.markup));
+            }
+        }
+        Ok(g)
+    }
 
-                    font_face.GetGlyphRunOutline(
-                        (*glyph_run).fontEmSize,
-                        (*glyph_run).glyphIndices.offset(i as isize),
-                        Some((*glyph_run).glyphAdvances.offset(i as isize)),
-                        Some(p_glyph_offset),
-                        1,
-                        (*glyph_run).isSideways,
-                        (*glyph_run).bidiLevel % 2 == 1,
-                        &geometry_sink,
-                    )?;
-                }
+    /// Show the glyphs of a run as text. A cluster of one glyph is set with its first character,
+    /// and its other characters are blank; other clusters and color glyphs fall back to shared
+    /// definitions, keeping their characters as blank text.
+    fn add_text(
+        &mut self,
+        run: &mut SvgRun,
+        glyph_run: &GlyphRun,
+        positions: &[(f32, f32)],
+        outlines: &dyn GlyphOutlineSource,
+    ) -> Result<()> {
+        let Some(fonts) = self.fonts.as_mut() else {
+            return Ok(());
+        };
+        let face = (glyph_run.font, glyph_run.is_sideways);
+        let scalar = run.scalar;
 
-                let path_id = self.add_path_def(geometry_sink_impl.reset());
-                if path_id > 0 {
-                    run.glyphs.push(SvgGlyph {
-                        path_id,
-                        offset_x: geometry_sink_impl.process_coord(offset_x),
-                        offset_y: geometry_sink_impl.process_coord(offset_y),
-                    });
+        for (glyphs, text) in glyph_run.clusters() {
+            let chars: Vec<char> = text.chars().filter(|ch| !is_dropped_char(*ch)).collect();
+            let (x, y) = positions
+                .get(glyphs.start)
+                .or(positions.last())
+                .copied()
+                .unwrap_or_default();
+            let position = (round_coord(x * scalar), round_coord(y * scalar));
+            let is_color = glyphs.len() == 1
+                && self
+                    .color_glyphs
+                    .add(glyph_run, glyph_run.glyph_ids[glyphs.start], outlines)
+                    .is_some();
+            let blank_from = match chars.first() {
+                Some(&ch) if glyphs.len() == 1 && is_plain_char(ch) && !is_color => {
+                    let glyph_id = glyph_run.glyph_ids[glyphs.start];
+                    let path =
+                        outlines.glyph_path(glyph_run.font, glyph_id, glyph_run.is_sideways)?;
+                    let advance = glyph_run.advances[glyphs.start] * scalar;
+                    let font = fonts
+                        .add_glyph(face, run.upm, glyph_id, ch, || FontGlyph { path, advance });
+                    push_text(&mut run.text_spans, font, ch, position);
+                    1
                 }
-
-                unsafe {
-                    let direction = if (*glyph_run).bidiLevel % 2 == 1 {
-                        -1.0
-                    } else {
-                        1.0
-                    };
-                    offset_x += direction * *((*glyph_run).glyphAdvances.offset(i as isize));
+                _ => {
+                    for i in glyphs {
+                        let def = glyph_def(
+                            &mut self.shared_store,
+                            &mut self.color_glyphs,
+                            glyph_run,
+                            glyph_run.glyph_ids[i],
+                            outlines,
+                        )?;
+                        if let Some(def_id) = def {
+                            let (x, y) = positions[i];
+                            let (offset_x, offset_y) =
+                                (round_coord(x * scalar), round_coord(y * scalar));
+                            run.glyphs.push(SvgGlyph {
+                                def_id,
+                                offset_x,
+                                offset_y,
+                                fill: run.gradient.map(|index| {
+                                    let transform =
+                                        run.transform().pre_translate(offset_x, offset_y);
+                                    SvgPaint::server(self.gradients.fill(index, transform))
+                                }),
+                            });
+                        }
+                    }
+                    0
                 }
+            };
+            for &ch in &chars[blank_from..] {
+                push_text(&mut run.text_spans, fonts.add_blank(ch), ch, position);
             }
-
-            self.push_run(run);
         }
-
         Ok(())
     }
 
-    fn DrawInlineObject(
-        &self,
-        _client_drawing_context: *const c_void,
-        _origin_x: f32,
-        _origin_y: f32,
-        _orientation_angle: DWRITE_GLYPH_ORIENTATION_ANGLE,
-        _inline_object: Option<&IDWriteInlineObject>,
-        _is_sideways: BOOL,
-        _is_right_to_left: BOOL,
-        _client_drawing_effect: Option<&IUnknown>,
-    ) -> Result<()> {
-        Ok(())
+    pub(crate) fn into_xml(self) -> Document {
+        let mut defs = element::Definitions::new();
+        for (segments, id) in self.shared_store.path_defs() {
+            let path = element::Path::new()
+                .set("id", format!("path{}", id))
+                .set("d", path_data(segments));
+            defs.append(path);
+        }
+        for def in self.color_glyphs.defs {
+            defs.append(def);
+        }
+        for def in self.gradients.defs {
+            defs.append(def);
+        }
+        for filter in self.filters {
+            defs.append(filter);
+        }
+
+        let mut svg = Document::new()
+            .set(
+                "viewBox",
+                format!("0 0 {} {}", self.canvas_width, self.canvas_height),
+            )
+            .set("width", self.canvas_width)
+            .set("height", self.canvas_height)
+            .add(defs);
+        if let Some(fonts) = self.fonts.as_ref().filter(|fonts| !fonts.is_empty()) {
+            svg.append(element::Style::new(fonts.style_sheet()));
+        }
+
+        for frame in &self.frames {
+            svg.append(frame.as_element());
+        }
+
+        svg
     }
+}
 
-    fn DrawUnderline(
-        &self,
-        _client_drawing_context: *const c_void,
-        _baseline_origin_x: f32,
-        _baseline_origin_y: f32,
-        _orientation_angle: DWRITE_GLYPH_ORIENTATION_ANGLE,
-        _underline: *const DWRITE_UNDERLINE,
-        _client_drawing_effect: Option<&IUnknown>,
-    ) -> Result<()> {
+impl DocumentRenderer for SvgDocumentRenderer {
+    fn add_frame(&mut self, frame: &GlyphFrame, outlines: &dyn GlyphOutlineSource) -> Result<()> {
+        let mut svg_frame = SvgFrame::new();
+        svg_frame.frame_title = frame.title.clone();
+        svg_frame.frame_desc = frame.desc.clone();
+        svg_frame.copyable = frame.copyable;
+        svg_frame.transform = frame.transform;
+        self.color_glyphs.scale = transform_scale(&frame.transform);
+        let mut gradients = GradientPlacer::new(frame, outlines);
+        for run in frame.runs.iter() {
+            let gradient = gradients.place(run)?;
+            let svg_run = self.create_run(frame, run, gradient.as_ref(), outlines)?;
+            svg_frame.runs.push(svg_run);
+        }
+        svg_frame.backgrounds = frame
+            .backgrounds
+            .iter()
+            .map(|background| SvgBackground::new(frame, background))
+            .collect();
+        svg_frame.decorations = frame
+            .decorations
+            .iter()
+            .map(|decoration| SvgDecoration::new(frame, decoration))
+            .collect();
+        for object in frame.objects.iter() {
+            let element = self.create_object(frame, object)?;
+            svg_frame.objects.push(element);
+        }
+        self.frames.push(svg_frame);
         Ok(())
     }
 
-    fn DrawStrikethrough(
-        &self,
-        _client_drawing_context: *const c_void,
-        _baseline_origin_x: f32,
-        _baseline_origin_y: f32,
-        _orientation_angle: DWRITE_GLYPH_ORIENTATION_ANGLE,
-        _strike_through: *const DWRITE_STRIKETHROUGH,
-        _client_drawing_effect: Option<&IUnknown>,
-    ) -> Result<()> {
+    fn write(self: Box<Self>, mut out: &mut dyn std::io::Write) -> Result<()> {
+        writeln!(
+            out,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>"
+        )?;
+        svg::write(&mut out, &self.into_xml())?;
         Ok(())
     }
 }
 
-/// Geometry sink that constructs a <path>
-#[windows::core::implement(ID2D1SimplifiedGeometrySink)]
-pub(crate) struct SvgGeometrySink {
-    scalar: f32,
-    body: RefCell<String>,
-    last_x: RefCell<f32>,
-    last_y: RefCell<f32>,
+/// Add a character to the last span of a `<text>`, or to a new span when its font differs.
+/// Characters outside the BMP get a span of their own, as renderers disagree on whether they take
+/// one position or two.
+fn push_text(spans: &mut Vec<SvgTextSpan>, font: usize, ch: char, (x, y): (f32, f32)) {
+    let is_astral = |ch: char| ch as u32 > 0xFFFF;
+    let continues = spans.last().is_some_and(|span| {
+        span.font == font && !is_astral(ch) && !span.text.chars().any(is_astral)
+    });
+    if !continues {
+        spans.push(SvgTextSpan {
+            font,
+            text: String::new(),
+            x: Vec::new(),
+            y: Vec::new(),
+        });
+    }
+    let span = spans.last_mut().unwrap();
+    span.text.push(ch);
+    span.x.push(x);
+    span.y.push(y);
 }
 
 const COORD_RESOLUTION: f32 = 0x100 as f32;
 
-impl SvgGeometrySink {
-    fn new(scalar: f32) -> Self {
-        Self {
-            scalar,
-            body: RefCell::new(String::new()),
-            last_x: RefCell::new(0.0),
-            last_y: RefCell::new(0.0),
+fn round_coord(f: f32) -> f32 {
+    (f * COORD_RESOLUTION).round() / COORD_RESOLUTION
+}
+
+/// The `d` attribute of a path, with its coordinates rounded
+fn path_data(segments: &[PathSegment]) -> String {
+    let mut data = String::new();
+    for segment in segments {
+        match *segment {
+            PathSegment::MoveTo(x, y) => {
+                write!(data, "M {} {} ", round_coord(x), round_coord(y)).unwrap()
+            }
+            PathSegment::LineTo(x, y) => {
+                write!(data, "L {} {} ", round_coord(x), round_coord(y)).unwrap()
+            }
+            PathSegment::QuadTo(x1, y1, x, y) => write!(
+                data,
+                "Q {} {} {} {} ",
+                round_coord(x1),
+                round_coord(y1),
+                round_coord(x),
+                round_coord(y)
+            )
+            .unwrap(),
+            PathSegment::CubicTo(x1, y1, x2, y2, x, y) => write!(
+                data,
+                "C {} {} {} {} {} {} ",
+                round_coord(x1),
+                round_coord(y1),
+                round_coord(x2),
+                round_coord(y2),
+                round_coord(x),
+                round_coord(y)
+            )
+            .unwrap(),
+            PathSegment::Close => data.push_str("Z "),
         }
     }
+    data
+}
 
-    fn reset(&self) -> String {
-        self.body.replace(String::new())
-    }
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, path::PathBuf};
 
-    fn process_coord(&self, f: f32) -> f32 {
-        (f * self.scalar * COORD_RESOLUTION).round() / COORD_RESOLUTION
-    }
+    use super::*;
+    use crate::glyph_image::GlyphImage;
+    use crate::glyph_run::DrawingEffect;
 
-    fn set_last_point(&self, x: f32, y: f32) {
-        self.last_x.replace(x);
-        self.last_y.replace(y);
+    /// Glyph `n` is a square of `n` hundred units, glyph 0 is empty. Records the strike sizes that
+    /// images are looked up with.
+    #[derive(Default)]
+    struct SquareOutlines {
+        image_strikes: RefCell<Vec<u16>>,
     }
-}
 
-#[allow(non_snake_case)]
-impl ID2D1SimplifiedGeometrySink_Impl for SvgGeometrySink_Impl {
-    fn SetFillMode(&self, _fill_mode: D2D1_FILL_MODE) {}
-    fn SetSegmentFlags(&self, _flags: D2D1_PATH_SEGMENT) {}
-    fn BeginFigure(&self, start_point: &D2D_POINT_2F, _figure_begin: D2D1_FIGURE_BEGIN) {
-        let cx_orig = start_point.x;
-        let cy_orig = start_point.y;
-        {
-            let cx = self.process_coord(cx_orig);
-            let cy = self.process_coord(cy_orig);
-            write!(self.body.borrow_mut(), "M {} {} ", cx, cy).unwrap();
+    impl GlyphOutlineSource for SquareOutlines {
+        fn units_per_em(&self, _font: FontId) -> f32 {
+            1000.0
         }
-        self.set_last_point(cx_orig, cy_orig);
-    }
-    fn AddLines(&self, points: *const D2D_POINT_2F, points_count: u32) {
-        let mut sink = self.body.borrow_mut();
-        for i in 0..points_count {
-            unsafe {
-                let point = points.offset(i as isize);
-                let cx_orig = (*point).x;
-                let cy_orig = (*point).y;
-                {
-                    let cx = self.process_coord(cx_orig);
-                    let cy = self.process_coord(cy_orig);
-                    write!(sink, "L {} {} ", cx, cy).unwrap();
-                }
-                self.set_last_point(cx_orig, cy_orig);
+
+        fn glyph_path(
+            &self,
+            _font: FontId,
+            glyph_id: u16,
+            _is_sideways: bool,
+        ) -> Result<Vec<PathSegment>> {
+            if glyph_id == 0 {
+                return Ok(Vec::new());
             }
+            let size = 100.0 * glyph_id as f32;
+            Ok(vec![
+                PathSegment::MoveTo(0.0, 0.0),
+                PathSegment::LineTo(size, 0.0),
+                PathSegment::LineTo(size, -size),
+                PathSegment::LineTo(0.0, -size),
+                PathSegment::Close,
+            ])
         }
-    }
-    fn AddBeziers(&self, beziers: *const D2D1_BEZIER_SEGMENT, beziers_count: u32) {
-        let mut sink = self.body.borrow_mut();
-        for i in 0..beziers_count {
-            unsafe {
-                let curve = beziers.offset(i as isize);
-
-                let x0_orig = self.last_x.borrow().clone();
-                let y0_orig = self.last_y.borrow().clone();
-                let x1_orig = (*curve).point1.x;
-                let y1_orig = (*curve).point1.y;
-                let x2_orig = (*curve).point2.x;
-                let y2_orig = (*curve).point2.y;
-                let x3_orig = (*curve).point3.x;
-                let y3_orig = (*curve).point3.y;
-
-                let xm1 = x0_orig + (x1_orig - x0_orig) * 1.5;
-                let ym1 = y0_orig + (y1_orig - y0_orig) * 1.5;
-                let xm2 = x3_orig + (x2_orig - x3_orig) * 1.5;
-                let ym2 = y3_orig + (y2_orig - y3_orig) * 1.5;
-                {
-                    let x1 = self.process_coord(x1_orig);
-                    let y1 = self.process_coord(y1_orig);
-                    let x2 = self.process_coord(x2_orig);
-                    let y2 = self.process_coord(y2_orig);
-                    let x3 = self.process_coord(x3_orig);
-                    let y3 = self.process_coord(y3_orig);
-
-                    if COORD_RESOLUTION * (xm2 - xm1).abs() < 1.0
-                        && COORD_RESOLUTION * (ym2 - ym1).abs() < 1.0
-                    {
-                        let xm = self.process_coord((xm1 + xm2) / 2.0);
-                        let ym = self.process_coord((ym1 + ym2) / 2.0);
-                        write!(sink, "Q {} {} {} {} ", xm, ym, x3, y3).unwrap();
-                    } else {
-                        write!(sink, "C {} {} {} {} {} {} ", x1, y1, x2, y2, x3, y3).unwrap();
-                    }
-                }
-                self.set_last_point(x3_orig, y3_orig);
-            }
+
+        fn font_file(&self, _font: FontId) -> Option<(PathBuf, u32)> {
+            None
+        }
+
+        fn color_glyph(
+            &self,
+            _font: FontId,
+            _glyph_id: u16,
+            _is_sideways: bool,
+            _palette: Option<&FontPalette>,
+            _foreground: &csscolorparser::Color,
+        ) -> Option<crate::color_glyph::ColorGlyph> {
+            None
+        }
+
+        fn glyph_image(
+            &self,
+            _font: FontId,
+            _glyph_id: u16,
+            _is_sideways: bool,
+            pixels_per_em: u16,
+        ) -> Option<GlyphImage> {
+            self.image_strikes.borrow_mut().push(pixels_per_em);
+            None
         }
     }
-    fn EndFigure(&self, figure_end: D2D1_FIGURE_END) {
-        if figure_end == D2D1_FIGURE_END_CLOSED {
-            write!(self.body.borrow_mut(), "Z ").unwrap();
+
+    /// A run at 10 DIPs per em, so that one DIP is a hundred design units
+    fn run(glyph_ids: &[u16], advances: &[f32], bidi_level: u8) -> GlyphRun {
+        GlyphRun {
+            font_size: 10.0,
+            baseline_origin_x: 50.0,
+            baseline_origin_y: 20.0,
+            glyph_ids: glyph_ids.to_vec(),
+            advances: advances.to_vec(),
+            bidi_level,
+            ..GlyphRun::default()
         }
     }
-    fn Close(&self) -> Result<()> {
-        Ok(())
+
+    fn render(runs: Vec<GlyphRun>) -> String {
+        let frame = GlyphFrame {
+            runs,
+            ..GlyphFrame::default()
+        };
+        let mut renderer = SvgDocumentRenderer::new(100.0, 100.0);
+        renderer
+            .add_frame(&frame, &SquareOutlines::default())
+            .unwrap();
+        renderer.into_xml().to_string()
     }
-}
 
-fn dw_angle_to_angle(angle: &DWRITE_GLYPH_ORIENTATION_ANGLE, is_sideways: bool) -> f32 {
-    let mut quarters = match angle {
-        &DWRITE_GLYPH_ORIENTATION_ANGLE_0_DEGREES => 0,
-        &DWRITE_GLYPH_ORIENTATION_ANGLE_90_DEGREES => 1,
-        &DWRITE_GLYPH_ORIENTATION_ANGLE_180_DEGREES => 2,
-        &DWRITE_GLYPH_ORIENTATION_ANGLE_270_DEGREES => 3,
-        _ => unreachable!(),
-    };
-    if is_sideways {
-        quarters = (1 + quarters) % 4
+    #[test]
+    fn glyphs_share_one_path() {
+        let svg = render(vec![
+            run(&[1, 2, 1], &[5.0, 5.0, 5.0], 0),
+            run(&[1], &[5.0], 0),
+        ]);
+        assert_eq!(svg.matches("<path ").count(), 2);
+        assert_eq!(svg.matches("href=\"#path1\"").count(), 3);
+        assert_eq!(svg.matches("href=\"#path2\"").count(), 1);
+        assert!(svg.contains("d=\"M 0 0 L 100 0 L 100 -100 L 0 -100 Z \""));
+    }
+
+    #[test]
+    fn empty_glyphs_are_left_out() {
+        let svg = render(vec![run(&[0, 1], &[5.0, 5.0], 0)]);
+        assert_eq!(svg.matches("<use ").count(), 1);
+        assert!(svg.contains("translate(500 "));
+    }
+
+    #[test]
+    fn right_to_left_runs_advance_leftwards() {
+        let svg = render(vec![run(&[1, 2], &[5.0, 3.0], 1)]);
+        let first = svg
+            .find("href=\"#path1\" transform=\"translate(-500 ")
+            .unwrap();
+        let second = svg
+            .find("href=\"#path2\" transform=\"translate(-800 ")
+            .unwrap();
+        assert!(first < second);
+        assert!(svg.contains("translate(50 20) rotate(0) scale(0.01)"));
+    }
+
+    #[test]
+    fn runs_are_filled_with_their_color() {
+        let mut translucent = run(&[1], &[5.0], 0);
+        translucent.drawing_effect = Some(DrawingEffect {
+            color: Some(csscolorparser::parse("rgba(255, 0, 0, 0.5)").unwrap()),
+            ..DrawingEffect::default()
+        });
+        let svg = render(vec![translucent, run(&[1], &[5.0], 0)]);
+        assert!(svg.contains("fill=\"#ff0000\" fill-opacity=\"0.5\""));
+        assert!(svg.contains("fill=\"black\""));
+    }
+
+    #[test]
+    fn image_strikes_suit_the_transformed_font_size() {
+        let frame = GlyphFrame {
+            runs: vec![run(&[1], &[5.0], 0)],
+            transform: Transform::from_scale(3.0, 3.0),
+            ..GlyphFrame::default()
+        };
+        let outlines = SquareOutlines::default();
+        let mut renderer = SvgDocumentRenderer::new(100.0, 100.0);
+        renderer.add_frame(&frame, &outlines).unwrap();
+        assert_eq!(outlines.image_strikes.take(), [30]);
     }
-    90.0 * (quarters as f32)
 }
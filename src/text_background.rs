@@ -0,0 +1,59 @@
+use crate::glyph_run::Background;
+use crate::path::{PathBuilder, PathSegment};
+
+// Control point distance of a cubic quarter circle, as a fraction of its radius
+const KAPPA: f32 = 0.552_284_8;
+
+/// Outline of a background rectangle with its corners rounded. The radius is limited to half the
+/// shorter side.
+pub(crate) fn background_path(background: &Background) -> Vec<PathSegment> {
+    let (left, top) = (background.x, background.y);
+    let (right, bottom) = (left + background.width, top + background.height);
+    let radius = background
+        .radius
+        .min(background.width / 2.0)
+        .min(background.height / 2.0)
+        .max(0.0);
+    let control = radius * (1.0 - KAPPA);
+    let mut builder = PathBuilder::new();
+    builder.move_to(left + radius, top);
+    builder.line_to(right - radius, top);
+    if radius > 0.0 {
+        builder.exact_cubic_to(
+            right - control,
+            top,
+            right,
+            top + control,
+            right,
+            top + radius,
+        );
+    }
+    builder.line_to(right, bottom - radius);
+    if radius > 0.0 {
+        builder.exact_cubic_to(
+            right,
+            bottom - control,
+            right - control,
+            bottom,
+            right - radius,
+            bottom,
+        );
+    }
+    builder.line_to(left + radius, bottom);
+    if radius > 0.0 {
+        builder.exact_cubic_to(
+            left + control,
+            bottom,
+            left,
+            bottom - control,
+            left,
+            bottom - radius,
+        );
+    }
+    builder.line_to(left, top + radius);
+    if radius > 0.0 {
+        builder.exact_cubic_to(left, top + control, left + control, top, left + radius, top);
+    }
+    builder.close();
+    builder.reset()
+}
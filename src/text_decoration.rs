@@ -0,0 +1,106 @@
+use ttf_parser::Face;
+
+use crate::document::TextDecorationStyle;
+use crate::glyph_run::{Decoration, DecorationKind};
+use crate::path::{PathBuilder, PathSegment};
+
+/// Offset of a decoration line's top edge below the baseline and its thickness, in design units:
+/// underlines from `post`, line-throughs from `OS/2`, and overlines at the ascender with the
+/// underline thickness
+pub(crate) fn font_decoration_metrics(face: &Face, kind: DecorationKind) -> (f32, f32) {
+    let upm = face.units_per_em() as f32;
+    let underline = face
+        .underline_metrics()
+        .filter(|metrics| metrics.thickness > 0);
+    let thickness = underline.map_or(upm / 20.0, |metrics| metrics.thickness as f32);
+    match kind {
+        DecorationKind::Underline => {
+            let position = underline.map_or(-upm / 10.0, |metrics| metrics.position as f32);
+            (-position, thickness)
+        }
+        DecorationKind::Overline => (-(face.ascender() as f32), thickness),
+        DecorationKind::LineThrough => match face
+            .strikeout_metrics()
+            .filter(|metrics| metrics.thickness > 0)
+        {
+            Some(metrics) => (-(metrics.position as f32), metrics.thickness as f32),
+            None => {
+                let x_height = face.x_height().map_or(upm / 2.0, |height| height as f32);
+                (-(x_height + thickness) / 2.0, thickness)
+            }
+        },
+    }
+}
+
+/// How a decoration line is stroked, in DIPs from its origin with its x axis along the line
+pub(crate) struct DecorationStroke {
+    /// Center lines
+    pub(crate) path: Vec<PathSegment>,
+    pub(crate) width: f32,
+    /// Dash and gap lengths
+    pub(crate) dash: Option<(f32, f32)>,
+    /// Round caps, which turn dashes of no length into dots
+    pub(crate) round_caps: bool,
+}
+
+/// Center lines of a decoration and how they are stroked
+pub(crate) fn decoration_stroke(decoration: &Decoration) -> DecorationStroke {
+    let thickness = decoration.thickness;
+    let width = decoration.width;
+    let center = decoration.offset + thickness / 2.0;
+    let mut builder = PathBuilder::new();
+    let mut line = |y: f32| {
+        builder.move_to(0.0, y);
+        builder.line_to(width, y);
+    };
+    let mut stroke = DecorationStroke {
+        path: Vec::new(),
+        width: thickness,
+        dash: None,
+        round_caps: false,
+    };
+    match decoration.style {
+        TextDecorationStyle::Double => {
+            // The second line goes away from the text; a line-through grows both ways
+            let (first, second) = match decoration.kind {
+                DecorationKind::Underline => (center, center + 2.0 * thickness),
+                DecorationKind::Overline => (center, center - 2.0 * thickness),
+                DecorationKind::LineThrough => (center - thickness, center + thickness),
+            };
+            line(first);
+            line(second);
+        }
+        TextDecorationStyle::Wavy if thickness > 0.0 => {
+            // Half waves of twice the thickness, reaching one thickness above and below
+            let half_wave = 2.0 * thickness;
+            builder.move_to(0.0, center);
+            let mut x = 0.0;
+            let mut amplitude = -thickness;
+            while x < width {
+                builder.quad_to(
+                    x + half_wave / 2.0,
+                    center + amplitude,
+                    x + half_wave,
+                    center,
+                );
+                x += half_wave;
+                amplitude = -amplitude;
+            }
+        }
+        style => {
+            line(center);
+            match style {
+                TextDecorationStyle::Dotted => {
+                    stroke.dash = Some((0.0, 2.0 * thickness));
+                    stroke.round_caps = true;
+                }
+                TextDecorationStyle::Dashed => {
+                    stroke.dash = Some((3.0 * thickness, 2.0 * thickness))
+                }
+                _ => {}
+            }
+        }
+    }
+    stroke.path = builder.reset();
+    stroke
+}
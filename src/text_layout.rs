@@ -0,0 +1,1482 @@
+use std::{collections::HashMap, ops::Range};
+
+use rustybuzz::{Direction, Feature, Language, UnicodeBuffer, Variation};
+use ttf_parser::Tag;
+use unicode_bidi::{BidiInfo, Level};
+use unicode_linebreak::BreakOpportunity;
+use unicode_vo::{char_orientation, Orientation};
+
+use crate::{
+    document::{DocumentFrame, InlineObject, TextAlign, TextOrientation, TextStyle, WritingMode},
+    document_analyzer::{
+        DocumentAnalyzer, LayoutBounds, ObjectRun, StyleRun, OBJECT_REPLACEMENT_CHARACTER,
+    },
+    font_collection::{FontCollection, FontQuery},
+    font_fallback::{clusters, itemize_fonts},
+    glyph_outline::vertical_origin,
+    glyph_run::{
+        Background, Decoration, DecorationKind, DrawingEffect, FontId, GlyphOffset,
+        GlyphOrientation, GlyphRun, PlacedObject, TextBackground,
+    },
+    line_break::line_breaks,
+    text_decoration::font_decoration_metrics,
+    text_spacing::TextSpacing,
+};
+
+// Defaults of the text format, matching the one created for DirectWrite
+const DEFAULT_FONT_FAMILY: &str = "Calibri";
+const DEFAULT_FONT_SIZE: f32 = 24.0;
+const DEFAULT_LOCALE: &str = "en-us";
+
+/// A style run with its face resolved. Ranges are UTF-8 byte offsets into the layout text.
+struct TextSegment {
+    range: Range<usize>,
+    face: FontId,
+    font_size: f32,
+    style: TextStyle,
+    // Resolved embedding level, before the per-line rules
+    level: Level,
+    placement: GlyphPlacement,
+    // Set on the object replacement character of an inline object
+    object: Option<ObjectMetrics>,
+}
+
+/// Extent of an inline object along its line, and above and below the baseline
+#[derive(Clone, Copy)]
+struct ObjectMetrics {
+    index: usize,
+    advance: f32,
+    ascent: f32,
+    descent: f32,
+}
+
+/// How the glyphs of a segment sit on their line
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum GlyphPlacement {
+    Horizontal,
+    /// In a vertical line, turned with it and centred on the central baseline
+    Sideways,
+    /// In a vertical line, standing upright and stacked with vertical metrics
+    Upright,
+}
+
+/// A shaped glyph, with advance and offsets in DIPs
+struct ShapedGlyph {
+    glyph_id: u16,
+    cluster: usize,
+    advance: f32,
+    offset_x: f32,
+    offset_y: f32,
+}
+
+/// A piece of a segment on one line. Glyphs are in logical order; lines keep runs in visual order.
+struct LineRun {
+    segment: usize,
+    range: Range<usize>,
+    level: Level,
+    glyphs: Vec<ShapedGlyph>,
+}
+
+struct LineSpan {
+    range: Range<usize>,
+    hard_break: bool,
+}
+
+struct LayoutLine {
+    runs: Vec<LineRun>,
+    // End of the line content, excluding trailing whitespace
+    visible_end: usize,
+    // Inline size, excluding trailing whitespace
+    width: f32,
+    // Inline position of the line start, after alignment
+    start: f32,
+    // Block position of the line box
+    top: f32,
+    height: f32,
+    // Distance from the line-over edge to the baseline
+    baseline: f32,
+}
+
+/// Portable counterpart of `IDWriteTextLayout`: shapes, breaks and positions one frame
+pub(crate) struct TextLayout {
+    text: String,
+    segments: Vec<TextSegment>,
+    objects: Vec<InlineObject>,
+    lines: Vec<LayoutLine>,
+    writing_mode: WritingMode,
+    box_width: f32,
+    box_height: f32,
+}
+
+impl TextLayout {
+    pub(crate) fn new(
+        fonts: &FontCollection,
+        analyzer: &DocumentAnalyzer,
+        canvas_width: f32,
+        canvas_height: f32,
+        frame: &DocumentFrame,
+    ) -> Self {
+        let box_width = frame.right.unwrap_or(canvas_width) - frame.left.unwrap_or(0.0);
+        let box_height = frame.bottom.unwrap_or(canvas_height) - frame.top.unwrap_or(0.0);
+        let writing_mode = frame.writing_mode.clone();
+        let inline_extent = if is_vertical(&writing_mode) {
+            box_height
+        } else {
+            box_width
+        };
+
+        let (text, utf8_offsets) = decode_utf16(analyzer.text());
+        let bidi = BidiInfo::new(&text, Some(paragraph_level(&writing_mode)));
+        let segments = build_segments(fonts, &text, &utf8_offsets, analyzer.style_runs());
+        let segments = split_at_levels(segments, &bidi.levels);
+        let segments = match is_vertical(&writing_mode) {
+            true => split_at_orientation(segments, &text, &frame.text_orientation),
+            false => segments,
+        };
+        let segments = split_at_objects(
+            segments,
+            &utf8_offsets,
+            analyzer.object_runs(),
+            is_vertical(&writing_mode),
+        );
+
+        // Shape the whole paragraph once to find where lines should break
+        let mut faces = ShapingFaces::new(fonts);
+        let mut advances = vec![0.0; text.len()];
+        let mut cluster_starts = vec![false; text.len()];
+        for segment in segments.iter() {
+            let range = segment.range.clone();
+            for glyph in shape(&mut faces, &text, range, segment, segment.level) {
+                advances[glyph.cluster] += glyph.advance;
+                cluster_starts[glyph.cluster] = true;
+            }
+        }
+        let opportunities = line_breaks(&text, &frame.line_break, &frame.word_break);
+        // Text on a path only breaks at hard line breaks
+        let max_inline = match frame.text_path {
+            Some(_) => f32::INFINITY,
+            None => inline_extent,
+        };
+        let spans = break_lines(
+            &text,
+            &opportunities,
+            &advances,
+            &cluster_starts,
+            max_inline,
+        );
+
+        let mut lines = Vec::new();
+        let mut top = 0.0;
+        for span in spans.iter() {
+            let mut line = layout_line(fonts, &mut faces, &text, &segments, &bidi, span, frame);
+            if matches!(frame.text_align, TextAlign::Justify) && !span.hard_break {
+                justify_line(&text, &mut line, inline_extent);
+            }
+            line.start = (inline_extent - line.width) * alignment_factor(frame);
+            line.top = top;
+            top += line.height;
+            lines.push(line);
+        }
+
+        Self {
+            text,
+            segments,
+            objects: analyzer
+                .object_runs()
+                .iter()
+                .map(|object_run| object_run.object.clone())
+                .collect(),
+            lines,
+            writing_mode,
+            box_width,
+            box_height,
+        }
+    }
+
+    fn block_extent(&self) -> f32 {
+        self.lines.iter().map(|line| line.height).sum()
+    }
+
+    /// Bounds of the formatted text relative to the layout box, like `DWRITE_TEXT_METRICS`
+    pub(crate) fn bounds(&self) -> LayoutBounds {
+        let block_extent = self.block_extent();
+        let inline_start = self
+            .lines
+            .iter()
+            .map(|line| line.start)
+            .fold(f32::INFINITY, f32::min);
+        let inline_end = self
+            .lines
+            .iter()
+            .map(|line| line.start + line.width)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let (inline_start, inline_end) = if self.lines.is_empty() {
+            (0.0, 0.0)
+        } else {
+            (inline_start, inline_end)
+        };
+
+        match self.writing_mode {
+            WritingMode::LrTb | WritingMode::RlTb => LayoutBounds {
+                left: inline_start,
+                top: 0.0,
+                width: inline_end - inline_start,
+                height: block_extent,
+            },
+            WritingMode::LrBt | WritingMode::RlBt => LayoutBounds {
+                left: inline_start,
+                top: self.box_height - block_extent,
+                width: inline_end - inline_start,
+                height: block_extent,
+            },
+            WritingMode::TbLr | WritingMode::BtLr => LayoutBounds {
+                left: 0.0,
+                top: self.physical_inline_start(inline_start, inline_end),
+                width: block_extent,
+                height: inline_end - inline_start,
+            },
+            WritingMode::TbRl | WritingMode::BtRl => LayoutBounds {
+                left: self.box_width - block_extent,
+                top: self.physical_inline_start(inline_start, inline_end),
+                width: block_extent,
+                height: inline_end - inline_start,
+            },
+        }
+    }
+
+    fn physical_inline_start(&self, inline_start: f32, inline_end: f32) -> f32 {
+        match self.writing_mode {
+            WritingMode::BtLr | WritingMode::BtRl => self.box_height - inline_end,
+            _ => inline_start,
+        }
+    }
+
+    /// Baseline origin of a line's start, the pen direction and the run rotation
+    fn line_origin(&self, line: &LayoutLine) -> ((f32, f32), (f32, f32), GlyphOrientation) {
+        match self.writing_mode {
+            WritingMode::LrTb | WritingMode::RlTb => (
+                (line.start, line.top + line.baseline),
+                (1.0, 0.0),
+                GlyphOrientation::Angle0,
+            ),
+            WritingMode::LrBt | WritingMode::RlBt => {
+                let over = self.box_height - line.top - line.height;
+                (
+                    (line.start, over + line.baseline),
+                    (1.0, 0.0),
+                    GlyphOrientation::Angle0,
+                )
+            }
+            WritingMode::TbRl => {
+                let right = self.box_width - line.top;
+                (
+                    (right - line.baseline, line.start),
+                    (0.0, 1.0),
+                    GlyphOrientation::Angle90,
+                )
+            }
+            WritingMode::TbLr => {
+                let right = line.top + line.height;
+                (
+                    (right - line.baseline, line.start),
+                    (0.0, 1.0),
+                    GlyphOrientation::Angle90,
+                )
+            }
+            WritingMode::BtLr => {
+                let left = line.top;
+                (
+                    (left + line.baseline, self.box_height - line.start),
+                    (0.0, -1.0),
+                    GlyphOrientation::Angle270,
+                )
+            }
+            WritingMode::BtRl => {
+                let left = self.box_width - line.top - line.height;
+                (
+                    (left + line.baseline, self.box_height - line.start),
+                    (0.0, -1.0),
+                    GlyphOrientation::Angle270,
+                )
+            }
+        }
+    }
+
+    pub(crate) fn glyph_runs(&self) -> Vec<GlyphRun> {
+        let mut runs = Vec::new();
+        for line in self.lines.iter() {
+            let ((origin_x, origin_y), (dir_x, dir_y), line_orientation) = self.line_origin(line);
+            let bottom_to_top = dir_y < 0.0;
+            let mut pen = 0.0;
+            for line_run in line.runs.iter() {
+                let segment = &self.segments[line_run.segment];
+                if segment.object.is_some() {
+                    pen += line_run.glyphs.iter().map(|g| g.advance).sum::<f32>();
+                    continue;
+                }
+                let upright = segment.placement == GlyphPlacement::Upright;
+                // Upright glyphs are sideways glyphs in an unrotated run, as in DirectWrite. Going
+                // up, the run is right-to-left so that it still starts from the pen.
+                let (orientation, bidi_level) = match upright {
+                    true => (GlyphOrientation::Angle0, bottom_to_top as u8),
+                    false => (line_orientation, line_run.level.number()),
+                };
+                let advance_direction = if upright && bottom_to_top { -1.0 } else { 1.0 };
+                let mut run = GlyphRun {
+                    font: segment.face,
+                    font_size: segment.font_size,
+                    baseline_origin_x: origin_x + dir_x * pen,
+                    baseline_origin_y: origin_y + dir_y * pen,
+                    glyph_ids: line_run.glyphs.iter().map(|g| g.glyph_id).collect(),
+                    advances: line_run.glyphs.iter().map(|g| g.advance).collect(),
+                    offsets: line_run
+                        .glyphs
+                        .iter()
+                        .map(|g| GlyphOffset {
+                            advance_offset: advance_direction * g.offset_x,
+                            ascender_offset: g.offset_y,
+                        })
+                        .collect(),
+                    orientation,
+                    is_sideways: upright,
+                    path_angle: 0.0,
+                    bidi_level,
+                    text_position: self.text[..line_run.range.start].encode_utf16().count(),
+                    source_text: String::from(&self.text[line_run.range.clone()]),
+                    cluster_map: cluster_map(&self.text, line_run),
+                    drawing_effect: DrawingEffect::from_style(&segment.style),
+                };
+                let advance = run.total_advance();
+                // Right-to-left runs start from their far end, as with DirectWrite
+                if run.is_right_to_left() && !upright {
+                    run.baseline_origin_x += dir_x * advance;
+                    run.baseline_origin_y += dir_y * advance;
+                }
+                pen += advance;
+                runs.push(run);
+            }
+        }
+        runs
+    }
+
+    /// Decoration lines along every run, like DirectWrite draws underlines and strikethroughs.
+    /// Offsets and thicknesses come from the font unless the style sets them; trailing
+    /// whitespace is not decorated.
+    pub(crate) fn decorations(&self, fonts: &FontCollection) -> Vec<Decoration> {
+        let mut decorations = Vec::new();
+        for line in self.lines.iter() {
+            let ((origin_x, origin_y), (dir_x, dir_y), orientation) = self.line_origin(line);
+            let mut pen = 0.0;
+            for line_run in line.runs.iter() {
+                let run_start = pen;
+                let advance: f32 = line_run.glyphs.iter().map(|g| g.advance).sum();
+                pen += advance;
+
+                let segment = &self.segments[line_run.segment];
+                if segment.object.is_some() {
+                    continue;
+                }
+                let Some(effect) = DrawingEffect::from_style(&segment.style) else {
+                    continue;
+                };
+                let Some(decoration) = effect.decoration.as_ref() else {
+                    continue;
+                };
+                let upright = segment.placement == GlyphPlacement::Upright;
+                let (start, width) = visible_extent(line, line_run, upright, run_start);
+                if width <= 0.0 {
+                    continue;
+                }
+
+                let face = fonts.face(segment.face);
+                let scale = segment.font_size / face.units_per_em() as f32;
+                // Vertical lines centre the em box on the central baseline, like `shape` does
+                let shift = match is_vertical(&self.writing_mode) {
+                    true => (face.ascender() + face.descender()) as f32 / 2.0 * scale,
+                    false => 0.0,
+                };
+                let kinds = [
+                    (decoration.line.underline, DecorationKind::Underline),
+                    (decoration.line.overline, DecorationKind::Overline),
+                    (decoration.line.line_through, DecorationKind::LineThrough),
+                ];
+                for (_, kind) in kinds.into_iter().filter(|(set, _)| *set) {
+                    let (offset, thickness) = font_decoration_metrics(&face, kind);
+                    let offset = match (kind, decoration.underline_offset) {
+                        (DecorationKind::Underline, Some(offset)) => offset,
+                        _ => offset * scale,
+                    };
+                    decorations.push(Decoration {
+                        kind,
+                        origin_x: origin_x + dir_x * start,
+                        origin_y: origin_y + dir_y * start,
+                        orientation,
+                        width,
+                        offset: offset + shift,
+                        thickness: decoration.thickness.unwrap_or(thickness * scale),
+                        style: decoration.style,
+                        color: effect.decoration_color(),
+                    });
+                }
+            }
+        }
+        decorations
+    }
+
+    /// Rectangles behind the text of styles with a background color, covering the line box
+    /// without trailing whitespace. Runs next to each other on a line with the same background
+    /// share a rectangle.
+    pub(crate) fn backgrounds(&self) -> Vec<Background> {
+        let mut backgrounds = Vec::new();
+        for line in self.lines.iter() {
+            // The background being grown, with its inline start and end
+            let mut current: Option<(TextBackground, f32, f32)> = None;
+            let mut pen = 0.0;
+            for line_run in line.runs.iter() {
+                let run_start = pen;
+                pen += line_run.glyphs.iter().map(|g| g.advance).sum::<f32>();
+
+                let segment = &self.segments[line_run.segment];
+                let upright = segment.placement == GlyphPlacement::Upright;
+                let (start, width) = visible_extent(line, line_run, upright, run_start);
+                let background = TextBackground::from_style(&segment.style).filter(|_| width > 0.0);
+                if let (Some((last, _, end)), Some(next)) = (current.as_mut(), background.as_ref())
+                {
+                    if last == next && (*end - start).abs() < 0.01 {
+                        *end = start + width;
+                        continue;
+                    }
+                }
+                if let Some(piece) = current.take() {
+                    backgrounds.push(self.line_background(line, piece));
+                }
+                current = background.map(|background| (background, start, start + width));
+            }
+            if let Some(piece) = current {
+                backgrounds.push(self.line_background(line, piece));
+            }
+        }
+        backgrounds
+    }
+
+    /// Background of the inline extent `start..end` of a line, across its whole line box
+    fn line_background(
+        &self,
+        line: &LayoutLine,
+        (background, start, end): (TextBackground, f32, f32),
+    ) -> Background {
+        let ((origin_x, origin_y), _, orientation) = self.line_origin(line);
+        let (x, y, width, height) = match orientation {
+            GlyphOrientation::Angle90 => (
+                origin_x + line.baseline - line.height,
+                origin_y + start,
+                line.height,
+                end - start,
+            ),
+            GlyphOrientation::Angle270 => (
+                origin_x - line.baseline,
+                origin_y - end,
+                line.height,
+                end - start,
+            ),
+            _ => (
+                origin_x + start,
+                origin_y - line.baseline,
+                end - start,
+                line.height,
+            ),
+        };
+        background.around(x, y, width, height)
+    }
+
+    /// Inline objects with their top left corners. They stay upright in vertical lines, centred
+    /// on the central baseline.
+    pub(crate) fn objects(&self) -> Vec<PlacedObject> {
+        let mut objects = Vec::new();
+        for line in self.lines.iter() {
+            let ((origin_x, origin_y), _, orientation) = self.line_origin(line);
+            let mut pen = 0.0;
+            for line_run in line.runs.iter() {
+                let start = pen;
+                pen += line_run.glyphs.iter().map(|g| g.advance).sum::<f32>();
+                let Some(metrics) = self.segments[line_run.segment].object else {
+                    continue;
+                };
+                let object = &self.objects[metrics.index];
+                let (x, y) = match orientation {
+                    GlyphOrientation::Angle90 => (origin_x - object.width / 2.0, origin_y + start),
+                    GlyphOrientation::Angle270 => (
+                        origin_x - object.width / 2.0,
+                        origin_y - start - object.height,
+                    ),
+                    _ => (origin_x + start, origin_y - object.baseline()),
+                };
+                objects.push(PlacedObject {
+                    object: object.clone(),
+                    x,
+                    y,
+                });
+            }
+        }
+        objects
+    }
+}
+
+/// Inline start and size of a run starting at `run_start`, leaving out the trailing whitespace
+/// of its line. Right-to-left runs end on the left.
+fn visible_extent(
+    line: &LayoutLine,
+    line_run: &LineRun,
+    upright: bool,
+    run_start: f32,
+) -> (f32, f32) {
+    let advance: f32 = line_run.glyphs.iter().map(|g| g.advance).sum();
+    let trailing: f32 = line_run
+        .glyphs
+        .iter()
+        .filter(|g| g.cluster >= line.visible_end)
+        .map(|g| g.advance)
+        .sum();
+    let start = match line_run.level.is_rtl() && !upright {
+        true => run_start + trailing,
+        false => run_start,
+    };
+    (start, advance - trailing)
+}
+
+fn is_vertical(writing_mode: &WritingMode) -> bool {
+    matches!(
+        writing_mode,
+        WritingMode::TbLr | WritingMode::TbRl | WritingMode::BtLr | WritingMode::BtRl
+    )
+}
+
+/// Where the free inline space goes, 0 putting it all after the line
+pub(crate) fn alignment_factor(frame: &DocumentFrame) -> f32 {
+    let factor = match frame.text_align {
+        TextAlign::Left | TextAlign::Justify => 0.0,
+        TextAlign::Center => 0.5,
+        TextAlign::Right => 1.0,
+    };
+    match frame.writing_mode {
+        WritingMode::RlTb | WritingMode::RlBt => 1.0 - factor,
+        _ => factor,
+    }
+}
+
+/// Base embedding level of every paragraph, from the reading direction of the writing mode
+fn paragraph_level(writing_mode: &WritingMode) -> Level {
+    match writing_mode {
+        WritingMode::RlTb | WritingMode::RlBt => Level::rtl(),
+        _ => Level::ltr(),
+    }
+}
+
+fn is_line_separator(ch: char) -> bool {
+    matches!(
+        ch,
+        '\n' | '\r' | '\u{0b}' | '\u{0c}' | '\u{85}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+/// Decode the analyzer's UTF-16 text, returning the UTF-8 offset of every UTF-16 position
+fn decode_utf16(text: &[u16]) -> (String, Vec<usize>) {
+    let decoded = String::from_utf16_lossy(text);
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    for (i, ch) in decoded.char_indices() {
+        for _ in 0..ch.len_utf16() {
+            offsets.push(i);
+        }
+    }
+    offsets.push(decoded.len());
+    (decoded, offsets)
+}
+
+fn build_segments(
+    fonts: &FontCollection,
+    text: &str,
+    utf8_offsets: &[usize],
+    style_runs: &[StyleRun],
+) -> Vec<TextSegment> {
+    let default_style = TextStyle::default();
+    let mut segments = Vec::new();
+    let mut cursor = 0;
+    for style_run in style_runs.iter() {
+        if style_run.wch_end <= style_run.wch_start {
+            continue;
+        }
+        let start = utf8_offsets[style_run.wch_start].max(cursor);
+        let end = utf8_offsets[style_run.wch_end];
+        if end <= start {
+            continue;
+        }
+        if start > cursor {
+            push_segments(&mut segments, fonts, text, cursor..start, &default_style);
+        }
+        push_segments(&mut segments, fonts, text, start..end, &style_run.style);
+        cursor = end;
+    }
+    if cursor < text.len() || segments.is_empty() {
+        push_segments(
+            &mut segments,
+            fonts,
+            text,
+            cursor..text.len(),
+            &default_style,
+        );
+    }
+    segments
+}
+
+/// Add the segments of a style run, one for each face font fallback picks
+fn push_segments(
+    segments: &mut Vec<TextSegment>,
+    fonts: &FontCollection,
+    text: &str,
+    range: Range<usize>,
+    style: &TextStyle,
+) {
+    let pieces = itemize_fonts(fonts, text, range.clone(), style, DEFAULT_FONT_FAMILY);
+    if pieces.is_empty() {
+        // Empty runs still provide the metrics of empty lines
+        let family = style
+            .font_family
+            .as_ref()
+            .and_then(|list| list.first())
+            .unwrap_or(DEFAULT_FONT_FAMILY);
+        let face = fonts.resolve(&FontQuery::from_style(style, family), DEFAULT_FONT_FAMILY);
+        segments.push(new_segment(range, face, style));
+    }
+    for (range, face) in pieces {
+        segments.push(new_segment(range, face, style));
+    }
+}
+
+fn new_segment(range: Range<usize>, face: FontId, style: &TextStyle) -> TextSegment {
+    TextSegment {
+        range,
+        face,
+        font_size: style.font_size.unwrap_or(DEFAULT_FONT_SIZE),
+        style: style.clone(),
+        level: Level::ltr(),
+        placement: GlyphPlacement::Horizontal,
+        object: None,
+    }
+}
+
+/// Split segments where the resolved embedding level changes, so each one shapes in one direction
+fn split_at_levels(segments: Vec<TextSegment>, levels: &[Level]) -> Vec<TextSegment> {
+    let mut split = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let mut start = segment.range.start;
+        for b in segment.range.clone() {
+            if b > start && levels[b] != levels[start] {
+                split.push(TextSegment {
+                    range: start..b,
+                    style: segment.style.clone(),
+                    level: levels[start],
+                    ..segment
+                });
+                start = b;
+            }
+        }
+        let level = levels.get(start).copied().unwrap_or(segment.level);
+        split.push(TextSegment {
+            range: start..segment.range.end,
+            level,
+            ..segment
+        });
+    }
+    split
+}
+
+/// Split the segments of a vertical line where glyphs change between upright and sideways
+fn split_at_orientation(
+    segments: Vec<TextSegment>,
+    text: &str,
+    text_orientation: &TextOrientation,
+) -> Vec<TextSegment> {
+    let mut split = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let mut pieces: Vec<(Range<usize>, GlyphPlacement)> = Vec::new();
+        for cluster in clusters(text, segment.range.clone()) {
+            let placement = placement_of(&text[cluster.clone()], text_orientation);
+            match pieces.last_mut() {
+                Some((range, last)) if *last == placement => range.end = cluster.end,
+                _ => pieces.push((cluster, placement)),
+            }
+        }
+        if pieces.is_empty() {
+            pieces.push((segment.range.clone(), GlyphPlacement::Sideways));
+        }
+        for (range, placement) in pieces {
+            split.push(TextSegment {
+                range,
+                style: segment.style.clone(),
+                placement,
+                ..segment
+            });
+        }
+    }
+    split
+}
+
+/// Give the object replacement character of every inline object a segment of its own. Objects
+/// advance by their width, or by their height in vertical lines.
+fn split_at_objects(
+    segments: Vec<TextSegment>,
+    utf8_offsets: &[usize],
+    object_runs: &[ObjectRun],
+    vertical: bool,
+) -> Vec<TextSegment> {
+    let objects: Vec<(usize, ObjectMetrics)> = object_runs
+        .iter()
+        .enumerate()
+        .map(|(index, object_run)| {
+            let object = &object_run.object;
+            let metrics = match vertical {
+                true => ObjectMetrics {
+                    index,
+                    advance: object.height,
+                    ascent: object.width / 2.0,
+                    descent: object.width / 2.0,
+                },
+                false => ObjectMetrics {
+                    index,
+                    advance: object.width,
+                    ascent: object.baseline(),
+                    descent: object.height - object.baseline(),
+                },
+            };
+            (utf8_offsets[object_run.wch_position], metrics)
+        })
+        .collect();
+    if objects.is_empty() {
+        return segments;
+    }
+
+    let mut split = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let mut start = segment.range.start;
+        for &(position, metrics) in objects.iter() {
+            let end = position + OBJECT_REPLACEMENT_CHARACTER.len_utf8();
+            if position < segment.range.start || end > segment.range.end {
+                continue;
+            }
+            if position > start {
+                split.push(TextSegment {
+                    range: start..position,
+                    style: segment.style.clone(),
+                    ..segment
+                });
+            }
+            split.push(TextSegment {
+                range: position..end,
+                style: segment.style.clone(),
+                object: Some(metrics),
+                ..segment
+            });
+            start = end;
+        }
+        if start < segment.range.end {
+            split.push(TextSegment {
+                range: start..segment.range.end,
+                ..segment
+            });
+        }
+    }
+    split
+}
+
+/// Placement of a cluster in a vertical line, from the `Vertical_Orientation` of its base
+/// (UAX #50) under `text-orientation: mixed`. Characters that are transformed or rotated (Tr),
+/// such as brackets, are turned sideways: their rotated glyphs are the usual fallback for the
+/// vertical forms.
+fn placement_of(cluster: &str, text_orientation: &TextOrientation) -> GlyphPlacement {
+    let upright = match text_orientation {
+        TextOrientation::Upright => true,
+        TextOrientation::Sideways => false,
+        TextOrientation::Mixed => cluster.chars().next().is_some_and(|ch| {
+            matches!(
+                char_orientation(ch),
+                Orientation::Upright | Orientation::TransformedOrUpright
+            )
+        }),
+    };
+    match upright {
+        true => GlyphPlacement::Upright,
+        false => GlyphPlacement::Sideways,
+    }
+}
+
+/// Shape a range of a segment. Glyphs come back in logical order, with right-to-left offsets
+/// measured leftwards, like those in `DWRITE_GLYPH_RUN`. Upright segments are shaped top to
+/// bottom, which applies `vert`, and measured with the `vmtx` and `VORG` metrics.
+/// The rustybuzz faces a layout shapes with, each created once with its variations set
+struct ShapingFaces<'a> {
+    fonts: &'a FontCollection,
+    faces: HashMap<FontId, Option<rustybuzz::Face<'a>>>,
+}
+
+impl<'a> ShapingFaces<'a> {
+    fn new(fonts: &'a FontCollection) -> Self {
+        Self {
+            fonts,
+            faces: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, id: FontId) -> Option<&rustybuzz::Face<'a>> {
+        let fonts = self.fonts;
+        self.faces
+            .entry(id)
+            .or_insert_with(|| {
+                let (data, index) = fonts.face_data(id);
+                let mut face = rustybuzz::Face::from_slice(data, index)?;
+                let variations: Vec<Variation> = fonts
+                    .variations(id)
+                    .into_iter()
+                    .map(|(tag, value)| Variation { tag, value })
+                    .collect();
+                face.set_variations(&variations);
+                Some(face)
+            })
+            .as_ref()
+    }
+}
+
+fn shape(
+    faces: &mut ShapingFaces,
+    text: &str,
+    range: Range<usize>,
+    segment: &TextSegment,
+    level: Level,
+) -> Vec<ShapedGlyph> {
+    if let Some(object) = segment.object {
+        return vec![ShapedGlyph {
+            glyph_id: 0,
+            cluster: range.start,
+            advance: object.advance,
+            offset_x: 0.0,
+            offset_y: 0.0,
+        }];
+    }
+    let Some(face) = faces.get(segment.face) else {
+        return Vec::new();
+    };
+    let style = &segment.style;
+
+    let spacing = TextSpacing::from_style(style, segment.font_size);
+    let disabled = spacing
+        .iter()
+        .flat_map(|spacing| spacing.disabled_features(style))
+        .map(|feature| (feature, 0));
+    let features: Vec<Feature> = style
+        .font_feature_settings
+        .iter()
+        .map(|(feature, parameter)| (feature.as_str(), *parameter))
+        .chain(disabled)
+        .map(|(feature, parameter)| {
+            Feature::new(Tag::from_bytes_lossy(feature.as_bytes()), parameter, ..)
+        })
+        .collect();
+
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(&text[range.clone()]);
+    buffer.set_pre_context(&text[..range.start]);
+    buffer.set_post_context(&text[range.end..]);
+    let upright = segment.placement == GlyphPlacement::Upright;
+    let rtl = level.is_rtl() && !upright;
+    buffer.set_direction(if upright {
+        Direction::TopToBottom
+    } else if rtl {
+        Direction::RightToLeft
+    } else {
+        Direction::LeftToRight
+    });
+    let lang = style.lang.as_deref().unwrap_or(DEFAULT_LOCALE);
+    if let Ok(language) = lang.parse::<Language>() {
+        buffer.set_language(language);
+    }
+    buffer.guess_segment_properties();
+
+    let output = rustybuzz::shape(face, &features, buffer);
+    let scale = segment.font_size / face.units_per_em() as f32;
+    let direction = if rtl { -1.0 } else { 1.0 };
+    // Sideways glyphs in vertical lines have their em box centred on the central baseline
+    let centering = match segment.placement {
+        GlyphPlacement::Sideways => (face.ascender() + face.descender()) as f32 / 2.0,
+        _ => 0.0,
+    };
+    let mut glyphs: Vec<ShapedGlyph> = output
+        .glyph_infos()
+        .iter()
+        .zip(output.glyph_positions().iter())
+        .map(|(info, pos)| {
+            let glyph_id = info.glyph_id as u16;
+            let cluster = range.start + info.cluster as usize;
+            if upright {
+                // rustybuzz measures offsets from the vertical origin; keep only the adjustments
+                // on top of it, turned into the frame of the sideways glyph
+                let (origin_x, origin_y) = vertical_origin(face, glyph_id);
+                let shift_x = pos.x_offset + origin_x;
+                let shift_y = pos.y_offset + origin_y;
+                ShapedGlyph {
+                    glyph_id,
+                    cluster,
+                    advance: -pos.y_advance as f32 * scale,
+                    offset_x: -shift_y as f32 * scale,
+                    offset_y: shift_x as f32 * scale,
+                }
+            } else {
+                ShapedGlyph {
+                    glyph_id,
+                    cluster,
+                    advance: pos.x_advance as f32 * scale,
+                    offset_x: direction * pos.x_offset as f32 * scale,
+                    offset_y: (pos.y_offset as f32 - centering) * scale,
+                }
+            }
+        })
+        .collect();
+    // Spacing goes after the last glyph of every cluster, which is its leftmost one when the
+    // glyphs are right to left
+    if let Some(spacing) = spacing {
+        for i in 0..glyphs.len() {
+            let cluster = glyphs[i].cluster;
+            if glyphs
+                .get(i + 1)
+                .is_some_and(|next| next.cluster == cluster)
+            {
+                continue;
+            }
+            if let Some(ch) = text[cluster..].chars().next() {
+                glyphs[i].advance += spacing.after(ch);
+            }
+        }
+    }
+    if rtl {
+        glyphs.reverse();
+    }
+    glyphs
+}
+
+/// Greedy line breaking at the given opportunities; words wider than a line are broken at clusters
+fn break_lines(
+    text: &str,
+    opportunities: &[(usize, BreakOpportunity)],
+    advances: &[f32],
+    cluster_starts: &[bool],
+    max_inline: f32,
+) -> Vec<LineSpan> {
+    let width_of = |r: Range<usize>| -> f32 { advances[r].iter().sum() };
+    let mut spans = Vec::new();
+    let mut line_start = 0;
+    let mut line_width = 0.0;
+    let mut word_start = 0;
+
+    for &(pos, opportunity) in opportunities.iter() {
+        let content_end = word_start + text[word_start..pos].trim_end().len();
+        let content_width = width_of(word_start..content_end);
+
+        if line_start < word_start && line_width + content_width > max_inline {
+            spans.push(LineSpan {
+                range: line_start..word_start,
+                hard_break: false,
+            });
+            line_start = word_start;
+            line_width = 0.0;
+        }
+
+        if line_start == word_start && content_width > max_inline {
+            let mut width = 0.0;
+            for (i, _) in text[word_start..content_end].char_indices() {
+                let b = word_start + i;
+                if b > line_start && cluster_starts[b] && width + advances[b] > max_inline {
+                    spans.push(LineSpan {
+                        range: line_start..b,
+                        hard_break: false,
+                    });
+                    line_start = b;
+                    width = 0.0;
+                }
+                width += advances[b];
+            }
+            line_width = width + width_of(content_end..pos);
+        } else {
+            line_width += width_of(word_start..pos);
+        }
+
+        if opportunity == BreakOpportunity::Mandatory {
+            spans.push(LineSpan {
+                range: line_start..pos,
+                hard_break: true,
+            });
+            line_start = pos;
+            line_width = 0.0;
+        }
+        word_start = pos;
+    }
+
+    if spans.is_empty() || text.ends_with(is_line_separator) {
+        spans.push(LineSpan {
+            range: text.len()..text.len(),
+            hard_break: true,
+        });
+    }
+    spans
+}
+
+fn layout_line(
+    fonts: &FontCollection,
+    faces: &mut ShapingFaces,
+    text: &str,
+    segments: &[TextSegment],
+    bidi: &BidiInfo,
+    span: &LineSpan,
+    frame: &DocumentFrame,
+) -> LayoutLine {
+    let start = span.range.start;
+    let content_end = start
+        + text[span.range.clone()]
+            .trim_end_matches(is_line_separator)
+            .len();
+    let visible_end = start + text[span.range.clone()].trim_end().len();
+
+    let mut ascent: f32 = 0.0;
+    let mut descent: f32 = 0.0;
+    let mut line_gap: f32 = 0.0;
+    let mut has_metrics = false;
+
+    for segment in segments.iter() {
+        let overlaps = segment.range.start < content_end && start < segment.range.end;
+        let touches_empty_line =
+            start == content_end && segment.range.start <= start && start <= segment.range.end;
+        if !overlaps && (has_metrics || !touches_empty_line) {
+            continue;
+        }
+        let (asc, desc, gap) = face_metrics(fonts, segment);
+        ascent = ascent.max(asc);
+        descent = descent.max(desc);
+        line_gap = line_gap.max(gap);
+        has_metrics = true;
+    }
+
+    if !has_metrics {
+        if let Some(segment) = segments.last() {
+            (ascent, descent, line_gap) = face_metrics(fonts, segment);
+        }
+    }
+
+    let mut runs = Vec::new();
+    // Paragraph separators that do not end a line, like U+001C, leave several paragraphs on it
+    for paragraph in bidi.paragraphs.iter() {
+        let line = paragraph.range.start.max(start)..paragraph.range.end.min(content_end);
+        if line.is_empty() {
+            continue;
+        }
+        let (levels, visual_runs) = bidi.visual_runs(paragraph, line);
+        for visual_run in visual_runs {
+            let level = levels[visual_run.start];
+            let mut pieces = Vec::new();
+            for (i, segment) in segments.iter().enumerate() {
+                let range = segment.range.start.max(visual_run.start)
+                    ..segment.range.end.min(visual_run.end);
+                if !range.is_empty() {
+                    pieces.push((i, range));
+                }
+            }
+            if level.is_rtl() {
+                pieces.reverse();
+            }
+            for (i, range) in pieces {
+                let glyphs = shape(faces, text, range.clone(), &segments[i], level);
+                runs.push(LineRun {
+                    segment: i,
+                    range,
+                    level,
+                    glyphs,
+                });
+            }
+        }
+    }
+
+    let width = runs
+        .iter()
+        .flat_map(|run| run.glyphs.iter())
+        .filter(|g| g.cluster < visible_end)
+        .map(|g| g.advance)
+        .sum();
+
+    let height = frame.line_height * (ascent + descent + line_gap);
+    // Vertical lines use the central baseline
+    let baseline = match is_vertical(&frame.writing_mode) {
+        true => height / 2.0,
+        false => frame.line_height * frame.baseline_offset * ascent,
+    };
+    LayoutLine {
+        runs,
+        visible_end,
+        width,
+        start: 0.0,
+        top: 0.0,
+        height,
+        baseline,
+    }
+}
+
+/// For every UTF-16 code unit of a run, the index of the first glyph of its cluster
+fn cluster_map(text: &str, line_run: &LineRun) -> Vec<u16> {
+    let glyphs = &line_run.glyphs;
+    let mut map = Vec::new();
+    let mut last = 0;
+    for (i, ch) in text[line_run.range.clone()].char_indices() {
+        let b = line_run.range.start + i;
+        while last + 1 < glyphs.len() && glyphs[last + 1].cluster <= b {
+            last += 1;
+        }
+        let mut first = last;
+        while first > 0 && glyphs[first - 1].cluster == glyphs[last].cluster {
+            first -= 1;
+        }
+        for _ in 0..ch.len_utf16() {
+            map.push(first as u16);
+        }
+    }
+    map
+}
+
+fn face_metrics(fonts: &FontCollection, segment: &TextSegment) -> (f32, f32, f32) {
+    if let Some(object) = segment.object {
+        return (object.ascent, object.descent, 0.0);
+    }
+    let face = fonts.face(segment.face);
+    let scale = segment.font_size / face.units_per_em() as f32;
+    (
+        face.ascender() as f32 * scale,
+        -(face.descender() as f32) * scale,
+        face.line_gap() as f32 * scale,
+    )
+}
+
+/// Distribute the free inline space over the inter-word spaces of a line
+fn justify_line(text: &str, line: &mut LayoutLine, inline_extent: f32) {
+    let visible_end = line.visible_end;
+    let is_space = |g: &ShapedGlyph| {
+        g.cluster < visible_end && text[g.cluster..].starts_with(char::is_whitespace)
+    };
+    let spaces = line
+        .runs
+        .iter()
+        .flat_map(|run| run.glyphs.iter())
+        .filter(|g| is_space(g))
+        .count();
+    let free = inline_extent - line.width;
+    if spaces == 0 || free <= 0.0 {
+        return;
+    }
+    let extra = free / spaces as f32;
+    for run in line.runs.iter_mut() {
+        for glyph in run.glyphs.iter_mut() {
+            if is_space(glyph) {
+                glyph.advance += extra;
+            }
+        }
+    }
+    line.width = inline_extent;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::document::Document;
+
+    const TEST_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data");
+
+    fn test_document(writing_mode: &str, width: f32, text: &str) -> Document {
+        serde_json::from_value(serde_json::json!({
+            "fontFiles": [format!("{TEST_DATA}/DejaVuSans.ttf")],
+            "frames": [{
+                "right": width,
+                "writing-mode": writing_mode,
+                "contents": [{ "font-family": "DejaVu Sans", "font-size": 24 }, text],
+            }],
+        }))
+        .unwrap()
+    }
+
+    fn lay_out(document: &Document) -> TextLayout {
+        lay_out_with(&FontCollection::load(document).unwrap(), document)
+    }
+
+    fn lay_out_with(fonts: &FontCollection, document: &Document) -> TextLayout {
+        let frame = &document.frames[0];
+        let mut analyzer = DocumentAnalyzer::new();
+        analyzer.analyze(&frame.contents);
+        TextLayout::new(fonts, &analyzer, document.width, document.height, frame)
+    }
+
+    /// The resolved level of every character, `None` for the ones on no line
+    fn char_levels(layout: &TextLayout) -> Vec<Option<u8>> {
+        let starts: Vec<usize> = layout.text.char_indices().map(|(b, _)| b).collect();
+        let mut levels = vec![None; starts.len()];
+        for run in layout.lines.iter().flat_map(|line| line.runs.iter()) {
+            for (i, _) in layout.text[run.range.clone()].char_indices() {
+                let index = starts.binary_search(&(run.range.start + i)).unwrap();
+                levels[index] = Some(run.level.number());
+            }
+        }
+        levels
+    }
+
+    /// Character indices of every line, from left to right
+    fn visual_order(layout: &TextLayout) -> Vec<Vec<usize>> {
+        let starts: Vec<usize> = layout.text.char_indices().map(|(b, _)| b).collect();
+        let mut lines = Vec::new();
+        for line in layout.lines.iter() {
+            let mut order = Vec::new();
+            for run in line.runs.iter() {
+                let mut indices: Vec<usize> = layout.text[run.range.clone()]
+                    .char_indices()
+                    .map(|(i, _)| starts.binary_search(&(run.range.start + i)).unwrap())
+                    .collect();
+                if run.level.is_rtl() {
+                    indices.reverse();
+                }
+                order.extend(indices);
+            }
+            lines.push(order);
+        }
+        lines
+    }
+
+    /// A test input with the levels and order it must come out with. Levels of characters that
+    /// rule X9 removes are `None`, and these characters are left out of the order.
+    struct BidiCase {
+        line: usize,
+        text: String,
+        rtl: bool,
+        levels: Vec<Option<u8>>,
+        order: Vec<usize>,
+    }
+
+    fn parse_levels(field: &str) -> Vec<Option<u8>> {
+        field
+            .split_whitespace()
+            .map(|level| level.parse().ok())
+            .collect()
+    }
+
+    fn parse_order(field: &str) -> Vec<usize> {
+        field
+            .split_whitespace()
+            .map(|index| index.parse().unwrap())
+            .collect()
+    }
+
+    /// Cases of a file in the format of BidiCharacterTest.txt, leaving out the ones with an
+    /// automatic paragraph direction, which writing modes do not have
+    fn character_cases(data: &str) -> Vec<BidiCase> {
+        let mut cases = Vec::new();
+        for (i, line) in data.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(';').collect();
+            let rtl = match fields[1] {
+                "0" => false,
+                "1" => true,
+                _ => continue,
+            };
+            let text = fields[0]
+                .split_whitespace()
+                .map(|cp| char::from_u32(u32::from_str_radix(cp, 16).unwrap()).unwrap())
+                .collect();
+            cases.push(BidiCase {
+                line: i + 1,
+                text,
+                rtl,
+                levels: parse_levels(fields[3]),
+                order: parse_order(fields[4]),
+            });
+        }
+        cases
+    }
+
+    /// A character of each bidi class of BidiTest.txt
+    fn class_char(class: &str) -> char {
+        match class {
+            "L" => 'a',
+            "R" => '\u{05d0}',
+            "AL" => '\u{0627}',
+            "EN" => '1',
+            "ES" => '+',
+            "ET" => '$',
+            "AN" => '\u{0661}',
+            "CS" => ',',
+            "NSM" => '\u{0300}',
+            "BN" => '\u{00ad}',
+            // Not a line separator, which would end the line before the reordering
+            "B" => '\u{1c}',
+            "S" => '\t',
+            "WS" => ' ',
+            "ON" => '!',
+            "LRE" => '\u{202a}',
+            "LRO" => '\u{202d}',
+            "RLE" => '\u{202b}',
+            "RLO" => '\u{202e}',
+            "PDF" => '\u{202c}',
+            "LRI" => '\u{2066}',
+            "RLI" => '\u{2067}',
+            "FSI" => '\u{2068}',
+            "PDI" => '\u{2069}',
+            _ => panic!("unknown bidi class {class}"),
+        }
+    }
+
+    /// Cases of BidiTest.txt, one for each of the left-to-right and right-to-left paragraph
+    /// levels a line is run with
+    fn class_cases(data: &str) -> Vec<BidiCase> {
+        let mut cases = Vec::new();
+        let mut levels = Vec::new();
+        let mut order = Vec::new();
+        for (i, line) in data.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if let Some(field) = line.strip_prefix("@Levels:") {
+                levels = parse_levels(field);
+            } else if let Some(field) = line.strip_prefix("@Reorder:") {
+                order = parse_order(field);
+            } else if let Some((classes, bitset)) = line.split_once(';') {
+                let text: String = classes.split_whitespace().map(class_char).collect();
+                let bitset: u8 = bitset.trim().parse().unwrap();
+                for (bit, rtl) in [(2, false), (4, true)] {
+                    if bitset & bit != 0 {
+                        cases.push(BidiCase {
+                            line: i + 1,
+                            text: text.clone(),
+                            rtl,
+                            levels: levels.clone(),
+                            order: order.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        cases
+    }
+
+    /// Where the layout of a case differs from what it must come out with
+    fn check_case(fonts: &FontCollection, case: &BidiCase) -> Option<String> {
+        let writing_mode = if case.rtl { "rl-tb" } else { "lr-tb" };
+        let layout = lay_out_with(fonts, &test_document(writing_mode, 1e6, &case.text));
+        let levels = char_levels(&layout);
+        let order: Vec<usize> = visual_order(&layout)
+            .concat()
+            .into_iter()
+            .filter(|&i| case.levels[i].is_some())
+            .collect();
+        let levels_match = levels
+            .iter()
+            .zip(case.levels.iter())
+            .all(|(level, expected)| expected.is_none() || level == expected);
+        match levels_match && order == case.order {
+            true => None,
+            false => Some(format!(
+                "line {}, {:?} {}: levels {:?}, order {:?}",
+                case.line, case.text, writing_mode, levels, order
+            )),
+        }
+    }
+
+    fn check_cases(cases: &[BidiCase]) {
+        let fonts = FontCollection::load(&test_document("lr-tb", 1e6, "")).unwrap();
+        let failures: Vec<String> = cases
+            .iter()
+            .filter_map(|case| check_case(&fonts, case))
+            .collect();
+        assert!(
+            failures.is_empty(),
+            "{} of {} cases fail:\n{}",
+            failures.len(),
+            cases.len(),
+            failures[..failures.len().min(20)].join("\n")
+        );
+    }
+
+    fn test_file(name: &str) -> String {
+        let path = Path::new(TEST_DATA).join(name);
+        fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+    }
+
+    #[test]
+    fn reorders_hand_written_cases() {
+        let cases = character_cases(&test_file("bidi-character-cases.txt"));
+        assert!(!cases.is_empty());
+        check_cases(&cases);
+    }
+
+    // The Unicode 8.0.0 conformance files; the bidi classes they use have not changed since
+    #[test]
+    fn reorders_bidi_character_test() {
+        let cases = character_cases(&test_file("BidiCharacterTest.txt"));
+        assert!(cases.len() > 90_000);
+        check_cases(&cases);
+    }
+
+    #[test]
+    fn reorders_bidi_test() {
+        let cases = class_cases(&test_file("BidiTest.txt"));
+        assert!(cases.len() > 400_000);
+        check_cases(&cases);
+    }
+
+    #[test]
+    fn lines_reorder_on_their_own() {
+        // One word per line: each line keeps its own characters, its trailing space at the
+        // paragraph level
+        let layout = lay_out(&test_document(
+            "lr-tb",
+            60.0,
+            "\u{5d0}\u{5d1}\u{5d2} \u{5d3}\u{5d4}\u{5d5}",
+        ));
+        assert_eq!(visual_order(&layout), [vec![2, 1, 0, 3], vec![6, 5, 4]]);
+        assert_eq!(char_levels(&layout), [1, 1, 1, 0, 1, 1, 1].map(Some));
+
+        let layout = lay_out(&test_document("rl-tb", 60.0, "abc def"));
+        assert_eq!(visual_order(&layout), [vec![3, 0, 1, 2], vec![4, 5, 6]]);
+        assert_eq!(char_levels(&layout), [2, 2, 2, 1, 2, 2, 2].map(Some));
+    }
+
+    #[test]
+    fn paragraph_separators_inside_a_line_start_a_new_paragraph() {
+        // U+001C separates paragraphs without ending the line
+        let layout = lay_out(&test_document("lr-tb", 1e6, "\u{5d0}\u{5d1}\u{1c}ab"));
+        assert_eq!(layout.lines.len(), 1);
+        assert_eq!(visual_order(&layout), [vec![1, 0, 2, 3, 4]]);
+    }
+
+    #[test]
+    fn right_to_left_runs_start_from_their_far_end() {
+        for (writing_mode, text) in [
+            ("lr-tb", "abc \u{5d0}\u{5d1}\u{5d2} 12 def"),
+            ("rl-tb", "\u{5d0}\u{5d1} abc \u{5d2} 12"),
+        ] {
+            let layout = lay_out(&test_document(writing_mode, 1e6, text));
+            let runs = layout.glyph_runs();
+            assert!(runs.iter().any(|run| run.is_right_to_left()));
+            // Runs come in visual order and sit side by side from the start of the line
+            let mut pen = layout.lines[0].start;
+            for run in runs.iter() {
+                let left = match run.is_right_to_left() {
+                    true => run.baseline_origin_x - run.total_advance(),
+                    false => run.baseline_origin_x,
+                };
+                assert!((left - pen).abs() < 1e-3, "{writing_mode} {text:?}");
+                pen = left + run.total_advance();
+            }
+        }
+    }
+}
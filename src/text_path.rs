@@ -0,0 +1,530 @@
+use svg::node::element::path::{Command, Data, Position};
+
+use crate::document::{DocumentFrame, TextPath, TextPathSide, WritingMode};
+use crate::document_analyzer::LayoutBounds;
+use crate::error::Result;
+use crate::glyph_run::{join_bounds, GlyphFrame, GlyphOutlineSource, GlyphRun};
+use crate::text_layout::alignment_factor;
+
+// Straight pieces that every curve of a path is flattened into
+const CURVE_STEPS: usize = 32;
+
+/// Parse SVG path data into the polylines of its subpaths, `None` when it does not parse
+pub(crate) fn flatten_path(d: &str) -> Option<Vec<Vec<(f32, f32)>>> {
+    let data = Data::parse(d).ok()?;
+    let mut flattener = PathFlattener::default();
+    for command in data.iter() {
+        flattener.command(command)?;
+    }
+    Some(
+        flattener
+            .subpaths
+            .into_iter()
+            .filter(|subpath| subpath.len() > 1)
+            .collect(),
+    )
+}
+
+#[derive(Default)]
+struct PathFlattener {
+    subpaths: Vec<Vec<(f32, f32)>>,
+    current: (f32, f32),
+    start: (f32, f32),
+    /// Control point that a smooth curve reflects, when the last segment was a curve of its kind
+    last_quad: Option<(f32, f32)>,
+    last_cubic: Option<(f32, f32)>,
+}
+
+impl PathFlattener {
+    fn command(&mut self, command: &Command) -> Option<()> {
+        match command {
+            Command::Move(position, params) => {
+                for (i, p) in groups(params, 2)?.enumerate() {
+                    let to = self.point(position, p[0], p[1]);
+                    match i {
+                        0 => self.move_to(to),
+                        _ => self.line_to(to)?,
+                    }
+                }
+            }
+            Command::Line(position, params) => {
+                for p in groups(params, 2)? {
+                    self.line_to(self.point(position, p[0], p[1]))?;
+                }
+            }
+            Command::HorizontalLine(position, params) => {
+                for p in groups(params, 1)? {
+                    let x = self.point(position, p[0], 0.0).0;
+                    self.line_to((x, self.current.1))?;
+                }
+            }
+            Command::VerticalLine(position, params) => {
+                for p in groups(params, 1)? {
+                    let y = self.point(position, 0.0, p[0]).1;
+                    self.line_to((self.current.0, y))?;
+                }
+            }
+            Command::QuadraticCurve(position, params) => {
+                for p in groups(params, 4)? {
+                    let control = self.point(position, p[0], p[1]);
+                    self.quad_to(control, self.point(position, p[2], p[3]))?;
+                }
+            }
+            Command::SmoothQuadraticCurve(position, params) => {
+                for p in groups(params, 2)? {
+                    let control = self.reflect(self.last_quad);
+                    self.quad_to(control, self.point(position, p[0], p[1]))?;
+                }
+            }
+            Command::CubicCurve(position, params) => {
+                for p in groups(params, 6)? {
+                    let control1 = self.point(position, p[0], p[1]);
+                    let control2 = self.point(position, p[2], p[3]);
+                    self.cubic_to(control1, control2, self.point(position, p[4], p[5]))?;
+                }
+            }
+            Command::SmoothCubicCurve(position, params) => {
+                for p in groups(params, 4)? {
+                    let control1 = self.reflect(self.last_cubic);
+                    let control2 = self.point(position, p[0], p[1]);
+                    self.cubic_to(control1, control2, self.point(position, p[2], p[3]))?;
+                }
+            }
+            Command::EllipticalArc(position, params) => {
+                for p in groups(params, 7)? {
+                    let to = self.point(position, p[5], p[6]);
+                    self.arc_to(p[0], p[1], p[2], p[3] != 0.0, p[4] != 0.0, to)?;
+                }
+            }
+            Command::Close => {
+                self.line_to(self.start)?;
+                // Whatever follows starts a new subpath from the same point
+                self.move_to(self.start);
+            }
+        }
+        Some(())
+    }
+
+    /// A point of a command, relative to the current point when the command is
+    fn point(&self, position: &Position, x: f32, y: f32) -> (f32, f32) {
+        match position {
+            Position::Absolute => (x, y),
+            Position::Relative => (self.current.0 + x, self.current.1 + y),
+        }
+    }
+
+    /// The last control point mirrored around the current point, or the current point itself
+    fn reflect(&self, control: Option<(f32, f32)>) -> (f32, f32) {
+        let (x, y) = self.current;
+        control.map_or(self.current, |(cx, cy)| (2.0 * x - cx, 2.0 * y - cy))
+    }
+
+    fn move_to(&mut self, to: (f32, f32)) {
+        self.subpaths.push(vec![to]);
+        self.current = to;
+        self.start = to;
+        self.last_quad = None;
+        self.last_cubic = None;
+    }
+
+    /// `None` when no subpath has been started
+    fn line_to(&mut self, to: (f32, f32)) -> Option<()> {
+        self.subpaths.last_mut()?.push(to);
+        self.current = to;
+        self.last_quad = None;
+        self.last_cubic = None;
+        Some(())
+    }
+
+    fn quad_to(&mut self, control: (f32, f32), to: (f32, f32)) -> Option<()> {
+        let from = self.current;
+        for step in 1..=CURVE_STEPS {
+            let t = step as f32 / CURVE_STEPS as f32;
+            let mt = 1.0 - t;
+            let [a, b, c] = [mt * mt, 2.0 * mt * t, t * t];
+            let x = a * from.0 + b * control.0 + c * to.0;
+            let y = a * from.1 + b * control.1 + c * to.1;
+            self.line_to((x, y))?;
+        }
+        self.last_quad = Some(control);
+        Some(())
+    }
+
+    fn cubic_to(
+        &mut self,
+        control1: (f32, f32),
+        control2: (f32, f32),
+        to: (f32, f32),
+    ) -> Option<()> {
+        let from = self.current;
+        for step in 1..=CURVE_STEPS {
+            let t = step as f32 / CURVE_STEPS as f32;
+            let mt = 1.0 - t;
+            let [a, b, c, d] = [mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t];
+            let x = a * from.0 + b * control1.0 + c * control2.0 + d * to.0;
+            let y = a * from.1 + b * control1.1 + c * control2.1 + d * to.1;
+            self.line_to((x, y))?;
+        }
+        self.last_cubic = Some(control2);
+        Some(())
+    }
+
+    /// An elliptical arc, turned from its end points to its center as the SVG implementation
+    /// notes do. Radii too small to reach the end point are scaled up.
+    fn arc_to(
+        &mut self,
+        rx: f32,
+        ry: f32,
+        x_axis_rotation: f32,
+        large_arc: bool,
+        sweep: bool,
+        to: (f32, f32),
+    ) -> Option<()> {
+        let from = self.current;
+        let (mut rx, mut ry) = (rx.abs(), ry.abs());
+        if from == to {
+            return Some(());
+        }
+        if rx == 0.0 || ry == 0.0 {
+            return self.line_to(to);
+        }
+        let (sin_phi, cos_phi) = x_axis_rotation.to_radians().sin_cos();
+        let (dx, dy) = ((from.0 - to.0) / 2.0, (from.1 - to.1) / 2.0);
+        let x1 = cos_phi * dx + sin_phi * dy;
+        let y1 = -sin_phi * dx + cos_phi * dy;
+        let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+        if lambda > 1.0 {
+            rx *= lambda.sqrt();
+            ry *= lambda.sqrt();
+        }
+        let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+        let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+        let sign = if large_arc == sweep { -1.0 } else { 1.0 };
+        let coefficient = sign * (numerator / denominator).max(0.0).sqrt();
+        let (cx1, cy1) = (coefficient * rx * y1 / ry, -coefficient * ry * x1 / rx);
+        let cx = cos_phi * cx1 - sin_phi * cy1 + (from.0 + to.0) / 2.0;
+        let cy = sin_phi * cx1 + cos_phi * cy1 + (from.1 + to.1) / 2.0;
+
+        let start_angle = ((y1 - cy1) / ry).atan2((x1 - cx1) / rx);
+        let end_angle = ((-y1 - cy1) / ry).atan2((-x1 - cx1) / rx);
+        let mut sweep_angle = end_angle - start_angle;
+        if sweep && sweep_angle < 0.0 {
+            sweep_angle += std::f32::consts::TAU;
+        } else if !sweep && sweep_angle > 0.0 {
+            sweep_angle -= std::f32::consts::TAU;
+        }
+        let steps = (sweep_angle.abs() / std::f32::consts::TAU * 4.0 * CURVE_STEPS as f32)
+            .ceil()
+            .max(1.0) as usize;
+        for step in 1..steps {
+            let angle = start_angle + sweep_angle * step as f32 / steps as f32;
+            let (sin, cos) = angle.sin_cos();
+            let x = cx + rx * cos * cos_phi - ry * sin * sin_phi;
+            let y = cy + rx * cos * sin_phi + ry * sin * cos_phi;
+            self.line_to((x, y))?;
+        }
+        // End exactly where the arc was asked to
+        self.line_to(to)
+    }
+}
+
+/// Parameters of a command taken `size` at a time, `None` when they do not divide evenly
+fn groups(params: &[f32], size: usize) -> Option<std::slice::ChunksExact<'_, f32>> {
+    (!params.is_empty() && params.len().is_multiple_of(size)).then(|| params.chunks_exact(size))
+}
+
+/// Straight pieces of a path laid end to end. The gaps between subpaths take no length.
+struct PathMeasure {
+    pieces: Vec<PathPiece>,
+    length: f32,
+}
+
+struct PathPiece {
+    from: (f32, f32),
+    to: (f32, f32),
+    /// Distance from the start of the path
+    start: f32,
+}
+
+impl PathMeasure {
+    /// Pieces of the subpaths, taken backwards on the right side
+    fn new(subpaths: &[Vec<(f32, f32)>], side: TextPathSide) -> Self {
+        let mut subpaths = subpaths.to_vec();
+        if side == TextPathSide::Right {
+            subpaths.reverse();
+            subpaths.iter_mut().for_each(|subpath| subpath.reverse());
+        }
+        let mut pieces = Vec::new();
+        let mut length = 0.0;
+        for pair in subpaths.iter().flat_map(|subpath| subpath.windows(2)) {
+            let (from, to) = (pair[0], pair[1]);
+            let piece_length = (to.0 - from.0).hypot(to.1 - from.1);
+            if piece_length > 0.0 {
+                pieces.push(PathPiece {
+                    from,
+                    to,
+                    start: length,
+                });
+                length += piece_length;
+            }
+        }
+        Self { pieces, length }
+    }
+
+    /// Point at a distance along the path, and the direction of the path there in degrees
+    /// clockwise; `None` off either end
+    fn at(&self, distance: f32) -> Option<(f32, f32, f32)> {
+        if !(0.0..=self.length).contains(&distance) {
+            return None;
+        }
+        let index = self
+            .pieces
+            .partition_point(|piece| piece.start <= distance)
+            .checked_sub(1)?;
+        let PathPiece { from, to, start } = self.pieces[index];
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let t = ((distance - start) / dx.hypot(dy)).min(1.0);
+        Some((from.0 + t * dx, from.1 + t * dy, dy.atan2(dx).to_degrees()))
+    }
+}
+
+/// Bend the laid out lines of a frame onto its path, as SVG `<textPath>` does. Every cluster
+/// is placed by the middle of its advance, turned to the tangent there, and left out when that
+/// falls off the path. Lines after the first follow the path at their distance from it.
+pub(crate) fn place_on_path(
+    glyph_frame: &mut GlyphFrame,
+    frame: &DocumentFrame,
+    text_path: &TextPath,
+    bounds: &LayoutBounds,
+    outlines: &dyn GlyphOutlineSource,
+) -> Result<()> {
+    if !matches!(
+        frame.writing_mode,
+        WritingMode::LrTb | WritingMode::RlTb | WritingMode::LrBt | WritingMode::RlBt
+    ) {
+        eprintln!("warning: text-path only applies to horizontal writing modes");
+        return Ok(());
+    }
+    if !(glyph_frame.backgrounds.is_empty()
+        && glyph_frame.decorations.is_empty()
+        && glyph_frame.objects.is_empty())
+    {
+        eprintln!(
+            "warning: text on a path is drawn without backgrounds, decorations or inline objects"
+        );
+        glyph_frame.backgrounds.clear();
+        glyph_frame.decorations.clear();
+        glyph_frame.objects.clear();
+    }
+    let Some(baseline) = glyph_frame.runs.first().map(|run| run.baseline_origin_y) else {
+        return Ok(());
+    };
+
+    let measure = PathMeasure::new(&text_path.d.subpaths, text_path.side);
+    let start = text_path.start_offset.resolve(measure.length);
+    let anchor = bounds.left + alignment_factor(frame) * bounds.width;
+    let mut runs = Vec::new();
+    for run in glyph_frame.runs.iter() {
+        for (mut cluster, middle) in split_clusters(run) {
+            let Some((x, y, angle)) = measure.at(start + middle - anchor) else {
+                continue;
+            };
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let dx = cluster.baseline_origin_x - middle;
+            let dy = cluster.baseline_origin_y - baseline;
+            cluster.baseline_origin_x = x + cos * dx - sin * dy;
+            cluster.baseline_origin_y = y + sin * dx + cos * dy;
+            cluster.path_angle = angle;
+            runs.push(cluster);
+        }
+    }
+
+    // The path is drawn from the top left corner of the frame box, not where the lines were
+    glyph_frame.offset_x = frame.left.unwrap_or(0.0);
+    glyph_frame.offset_y = frame.top.unwrap_or(0.0);
+    glyph_frame.runs = runs;
+    let mut ink_bounds = None;
+    for run in glyph_frame.runs.iter() {
+        ink_bounds = join_bounds(ink_bounds, run.ink_bounds(glyph_frame, outlines)?);
+    }
+    let (x, y) = (glyph_frame.offset_x, glyph_frame.offset_y);
+    glyph_frame.bounds = ink_bounds.unwrap_or([x, y, x, y]);
+    Ok(())
+}
+
+/// Every cluster of a run as a run of its own, with the middle of its advance along the line
+fn split_clusters(run: &GlyphRun) -> Vec<(GlyphRun, f32)> {
+    let direction = if run.is_right_to_left() { -1.0 } else { 1.0 };
+    let mut pen = 0.0;
+    let mut text_position = run.text_position;
+    let mut clusters = Vec::new();
+    for (glyphs, text) in run.clusters() {
+        let units = text.encode_utf16().count();
+        let advance: f32 = run.advances[glyphs.clone()].iter().sum();
+        let origin_x = run.baseline_origin_x + direction * pen;
+        if !glyphs.is_empty() {
+            let cluster = GlyphRun {
+                font: run.font,
+                font_size: run.font_size,
+                baseline_origin_x: origin_x,
+                baseline_origin_y: run.baseline_origin_y,
+                glyph_ids: run.glyph_ids[glyphs.clone()].to_vec(),
+                advances: run.advances[glyphs.clone()].to_vec(),
+                offsets: run.offsets.get(glyphs).unwrap_or_default().to_vec(),
+                orientation: run.orientation,
+                is_sideways: run.is_sideways,
+                path_angle: run.path_angle,
+                bidi_level: run.bidi_level,
+                text_position,
+                source_text: text,
+                cluster_map: vec![0; units],
+                drawing_effect: run.drawing_effect.clone(),
+            };
+            clusters.push((cluster, origin_x + direction * advance / 2.0));
+        }
+        pen += advance;
+        text_position += units;
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glyph_run::test_outlines::Squares;
+
+    const CIRCLE: &str = "M 100 0 A 100 100 0 0 1 -100 0 A 100 100 0 0 1 100 0";
+
+    fn assert_near(actual: (f32, f32), expected: (f32, f32), tolerance: f32) {
+        assert!(
+            (actual.0 - expected.0).abs() < tolerance && (actual.1 - expected.1).abs() < tolerance,
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    /// Glyph origins and tangent angles of `advances` set from the left edge of a frame on
+    /// `text_path`, which is JSON
+    fn place(text_path: &str, advances: &[f32]) -> Vec<(f32, f32, f32)> {
+        let json = format!(r#"{{"text-path": {}, "contents": ""}}"#, text_path);
+        let frame: DocumentFrame = serde_json::from_str(&json).unwrap();
+        let run = GlyphRun {
+            font_size: 1.0,
+            baseline_origin_y: 20.0,
+            glyph_ids: vec![1; advances.len()],
+            advances: advances.to_vec(),
+            source_text: "x".repeat(advances.len()),
+            cluster_map: (0..advances.len() as u16).collect(),
+            ..GlyphRun::default()
+        };
+        let mut glyph_frame = GlyphFrame {
+            runs: vec![run],
+            ..GlyphFrame::default()
+        };
+        let bounds = LayoutBounds {
+            width: advances.iter().sum(),
+            height: 20.0,
+            ..LayoutBounds::default()
+        };
+        let text_path = frame.text_path.as_ref().unwrap();
+        place_on_path(&mut glyph_frame, &frame, text_path, &bounds, &Squares).unwrap();
+        glyph_frame
+            .runs
+            .iter()
+            .map(|run| (run.baseline_origin_x, run.baseline_origin_y, run.path_angle))
+            .collect()
+    }
+
+    #[test]
+    fn paths_flatten_into_polylines() {
+        assert_eq!(
+            flatten_path("M 0 0 L 10 0 L 10 10 M 20 20 l 5 0"),
+            Some(vec![
+                vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)],
+                vec![(20.0, 20.0), (25.0, 20.0)],
+            ])
+        );
+        // The point left after closing the subpath starts nothing
+        assert_eq!(
+            flatten_path("m 1 1 h 5 v 5 z"),
+            Some(vec![vec![(1.0, 1.0), (6.0, 1.0), (6.0, 6.0), (1.0, 1.0)]])
+        );
+        let circle = flatten_path(CIRCLE).unwrap();
+        assert_eq!(circle.len(), 1);
+        for (x, y) in circle[0].iter() {
+            assert!((x.hypot(*y) - 100.0).abs() < 1e-3, "{x} {y}");
+        }
+        assert_eq!(circle[0].last(), Some(&(100.0, 0.0)));
+        for d in ["L 10 10", "M 0", "M 0 0 Q 1 1", "X 1 2"] {
+            assert_eq!(flatten_path(d), None, "{d:?}");
+        }
+    }
+
+    #[test]
+    fn glyphs_follow_a_straight_path() {
+        let placed = place(r#"{"d": "M 0 100 L 100 200"}"#, &[10.0, 10.0, 10.0]);
+        let (sin, cos) = 45f32.to_radians().sin_cos();
+        assert_eq!(placed.len(), 3);
+        for (i, (x, y, angle)) in placed.into_iter().enumerate() {
+            let distance = 10.0 * i as f32;
+            assert_near((x, y), (distance * cos, 100.0 + distance * sin), 1e-3);
+            assert!((angle - 45.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn glyphs_follow_a_circle_by_the_middle_of_their_advance() {
+        let advances = [20.0, 20.0, 20.0, 20.0];
+        let placed = place(&format!(r#"{{"d": "{}"}}"#, CIRCLE), &advances);
+        assert_eq!(placed.len(), 4);
+        for (i, (x, y, angle)) in placed.into_iter().enumerate() {
+            // The path starts on the right of the circle and runs clockwise
+            let middle = 20.0 * i as f32 + 10.0;
+            let theta = middle / 100.0;
+            let tangent = theta + std::f32::consts::FRAC_PI_2;
+            // Within what flattening the arc into chords turns the tangent by
+            assert!((angle - tangent.to_degrees()).abs() < 1.5, "{angle}");
+            let origin = (
+                100.0 * theta.cos() - 10.0 * tangent.cos(),
+                100.0 * theta.sin() - 10.0 * tangent.sin(),
+            );
+            assert_near((x, y), origin, 0.5);
+        }
+    }
+
+    #[test]
+    fn start_offset_moves_the_anchor_along_the_path() {
+        let path = r#""d": "M 0 0 L 100 0""#;
+        for (start_offset, first) in [("20", 20.0), (r#""20px""#, 20.0), (r#""50%""#, 50.0)] {
+            let text_path = format!(r#"{{{path}, "start-offset": {start_offset}}}"#);
+            let placed = place(&text_path, &[10.0, 10.0]);
+            assert_eq!(placed, [(first, 0.0, 0.0), (first + 10.0, 0.0, 0.0)]);
+        }
+    }
+
+    #[test]
+    fn the_right_side_runs_the_path_backwards() {
+        let placed = place(r#"{"d": "M 0 0 L 100 0", "side": "right"}"#, &[10.0, 10.0]);
+        assert_eq!(placed.len(), 2);
+        assert_near((placed[0].0, placed[0].1), (100.0, 0.0), 1e-3);
+        assert_near((placed[1].0, placed[1].1), (90.0, 0.0), 1e-3);
+        assert!(placed
+            .iter()
+            .all(|glyph| (glyph.2.abs() - 180.0).abs() < 1e-3));
+    }
+
+    #[test]
+    fn glyphs_off_the_ends_of_the_path_are_left_out() {
+        // Middles at 85, 95 and 105 on a path 100 long
+        let placed = place(
+            r#"{"d": "M 0 0 L 100 0", "start-offset": 80}"#,
+            &[10.0, 10.0, 10.0],
+        );
+        assert_eq!(placed, [(80.0, 0.0, 0.0), (90.0, 0.0, 0.0)]);
+        // Middles at -5 and 5
+        let placed = place(
+            r#"{"d": "M 0 0 L 100 0", "start-offset": -10}"#,
+            &[10.0, 10.0],
+        );
+        assert_eq!(placed, [(0.0, 0.0, 0.0)]);
+    }
+}
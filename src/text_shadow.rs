@@ -0,0 +1,241 @@
+use tiny_skia::{FillRule, Mask, Stroke, Transform};
+
+use crate::css_gradient::split_top_level;
+use crate::error::Result;
+use crate::glyph_run::{GlyphFrame, GlyphOutlineSource, GlyphRun, TextShadow};
+use crate::path::build_path;
+use crate::text_stroke::skia_line_join;
+
+/// Parse a `text-shadow` list, `None` when one of its shadows does not parse. `none` has none.
+pub(crate) fn parse_text_shadows(css: &str) -> Option<Vec<TextShadow>> {
+    if css.trim().eq_ignore_ascii_case("none") {
+        return Some(Vec::new());
+    }
+    let shadows = split_top_level(css, |ch| ch == ',');
+    if shadows.is_empty() {
+        return None;
+    }
+    shadows.into_iter().map(parse_text_shadow).collect()
+}
+
+/// Two offsets and an optional blur radius, with a color before or after them
+fn parse_text_shadow(css: &str) -> Option<TextShadow> {
+    let tokens = split_top_level(css, char::is_whitespace);
+    // Lengths are in px, and only zero may leave out the unit
+    let parse_length = |token: &str| match token {
+        "0" => Some(0.0),
+        _ => token.strip_suffix("px")?.parse::<f32>().ok(),
+    };
+    let (colors, lengths): (Vec<&str>, Vec<&str>) = tokens
+        .iter()
+        .partition(|token| parse_length(token).is_none());
+    let color = match colors[..] {
+        [] => None,
+        [color] if tokens.first() == Some(&color) || tokens.last() == Some(&color) => {
+            Some(csscolorparser::parse(color).ok()?)
+        }
+        _ => return None,
+    };
+    let lengths: Vec<f32> = lengths
+        .iter()
+        .flat_map(|token| parse_length(token))
+        .collect();
+    match lengths[..] {
+        [offset_x, offset_y] => Some(TextShadow {
+            offset_x,
+            offset_y,
+            blur: 0.0,
+            color,
+        }),
+        [offset_x, offset_y, blur] if blur >= 0.0 => Some(TextShadow {
+            offset_x,
+            offset_y,
+            blur,
+            color,
+        }),
+        _ => None,
+    }
+}
+
+/// Coverage of the shadow of a run, blurred, at `scale` pixels per DIP. The top left pixel of
+/// the mask is at `left`, `top` in pixels of the canvas.
+pub(crate) struct ShadowMask {
+    pub(crate) mask: Mask,
+    pub(crate) left: i32,
+    pub(crate) top: i32,
+}
+
+/// Rasterize the glyph outlines of a run and their stroke, moved by the shadow offset and
+/// blurred like CSS does, with a standard deviation of half the blur radius
+pub(crate) fn shadow_mask(
+    frame: &GlyphFrame,
+    glyph_run: &GlyphRun,
+    outlines: &dyn GlyphOutlineSource,
+    shadow: &TextShadow,
+    scale: f32,
+) -> Result<Option<ShadowMask>> {
+    let Some([x0, y0, x1, y1]) = glyph_run.ink_bounds(frame, outlines)? else {
+        return Ok(None);
+    };
+    let stroke = glyph_run
+        .drawing_effect
+        .as_ref()
+        .and_then(|effect| effect.stroke.as_ref());
+    let sigma = shadow.blur / 2.0;
+    // A blur spreads about three standard deviations, and miter joins reach past the stroke
+    let margin = 3.0 * sigma + stroke.map_or(0.0, |stroke| stroke.width * 2.0) + 1.0;
+    let left = ((x0 + shadow.offset_x - margin) * scale).floor() as i32;
+    let top = ((y0 + shadow.offset_y - margin) * scale).floor() as i32;
+    let right = ((x1 + shadow.offset_x + margin) * scale).ceil() as i32;
+    let bottom = ((y1 + shadow.offset_y + margin) * scale).ceil() as i32;
+    let Some(mut mask) = Mask::new((right - left) as u32, (bottom - top) as u32) else {
+        return Ok(None);
+    };
+
+    let scalar = outlines.units_per_em(glyph_run.font) / glyph_run.font_size;
+    let skia_stroke = stroke.map(|stroke| Stroke {
+        width: stroke.width * scalar,
+        line_join: skia_line_join(stroke.line_join),
+        ..Stroke::default()
+    });
+    let run_transform = Transform::from_translate(-left as f32, -top as f32)
+        .pre_scale(scale, scale)
+        .pre_translate(
+            glyph_run.baseline_origin_x + frame.offset_x + shadow.offset_x,
+            glyph_run.baseline_origin_y + frame.offset_y + shadow.offset_y,
+        )
+        .pre_rotate(glyph_run.rotate_angle())
+        .pre_scale(1.0 / scalar, 1.0 / scalar);
+    for (glyph_id, (x, y)) in glyph_run.glyph_ids.iter().zip(glyph_run.glyph_positions()) {
+        let segments = outlines.glyph_path(glyph_run.font, *glyph_id, glyph_run.is_sideways)?;
+        let Some(path) = build_path(&segments) else {
+            continue;
+        };
+        let transform = run_transform.pre_translate(x * scalar, y * scalar);
+        mask.fill_path(&path, FillRule::Winding, true, transform);
+        if let Some(outline) = skia_stroke.as_ref().and_then(|s| path.stroke(s, 1.0)) {
+            mask.fill_path(&outline, FillRule::Winding, true, transform);
+        }
+    }
+    blur_mask(&mut mask, sigma * scale);
+    Ok(Some(ShadowMask { mask, left, top }))
+}
+
+/// Approximate a gaussian blur with three box blurs in each direction
+fn blur_mask(mask: &mut Mask, sigma: f32) {
+    if sigma < 0.5 {
+        return;
+    }
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let data = mask.data_mut();
+    let mut line = Vec::new();
+    for radius in box_radii(sigma) {
+        for y in 0..height {
+            box_blur_line(data, y * width, 1, width, radius, &mut line);
+        }
+        for x in 0..width {
+            box_blur_line(data, x, width, height, radius, &mut line);
+        }
+    }
+}
+
+/// Radii of three box blurs that add up to a gaussian blur of `sigma`
+fn box_radii(sigma: f32) -> [usize; 3] {
+    let ideal = (4.0 * sigma * sigma + 1.0).sqrt();
+    let mut lower = ideal.floor() as i32;
+    if lower % 2 == 0 {
+        lower -= 1;
+    }
+    let lower_count = ((12.0 * sigma * sigma - (3 * lower * lower + 12 * lower + 9) as f32)
+        / (-4 * lower - 4) as f32)
+        .round() as usize;
+    let radius = |i: usize| match i < lower_count {
+        true => (lower as usize - 1) / 2,
+        false => (lower as usize).div_ceil(2),
+    };
+    [radius(0), radius(1), radius(2)]
+}
+
+/// Average every value of a line with `radius` values on both sides, counting values past the
+/// ends as zero. The line has `len` values, `stride` apart from `start`.
+fn box_blur_line(
+    data: &mut [u8],
+    start: usize,
+    stride: usize,
+    len: usize,
+    radius: usize,
+    line: &mut Vec<u8>,
+) {
+    line.clear();
+    line.extend((0..len).map(|i| data[start + i * stride]));
+    let width = 2 * radius as u32 + 1;
+    let mut sum: u32 = line[..radius.min(len)].iter().map(|&v| v as u32).sum();
+    for i in 0..len {
+        if i + radius < len {
+            sum += line[i + radius] as u32;
+        }
+        data[start + i * stride] = ((sum + width / 2) / width) as u8;
+        if i >= radius {
+            sum -= line[i - radius] as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::TextShadowList;
+
+    #[test]
+    fn shadows_take_their_color_before_or_after_the_lengths() {
+        let shadows = parse_text_shadows("2px 3px red, blue -1px 0 4px").unwrap();
+        assert_eq!(shadows.len(), 2);
+        assert_eq!(
+            (shadows[0].offset_x, shadows[0].offset_y, shadows[0].blur),
+            (2.0, 3.0, 0.0)
+        );
+        assert_eq!(shadows[0].color, csscolorparser::parse("red").ok());
+        assert_eq!(
+            (shadows[1].offset_x, shadows[1].offset_y, shadows[1].blur),
+            (-1.0, 0.0, 4.0)
+        );
+        assert_eq!(parse_text_shadows("none"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn malformed_shadows_fail_the_whole_list() {
+        for css in [
+            "2px red blue",
+            "2px",
+            "1px 2px 3px 4px",
+            "1px 2px -3px",
+            "1px red 2px",
+        ] {
+            assert_eq!(parse_text_shadows(css), None, "{css:?}");
+            assert!(TextShadowList::try_from(format!("1px 1px, {css}")).is_err());
+        }
+        assert!(TextShadowList::try_from(String::new()).is_err());
+    }
+
+    #[test]
+    fn lengths_need_a_unit_unless_zero() {
+        for css in ["1 2px", "1px 2", "1px 2px 3", "1em 2px", "red 1px -1"] {
+            assert_eq!(parse_text_shadows(css), None, "{css:?}");
+            assert!(TextShadowList::try_from(String::from(css)).is_err());
+        }
+        let shadows = parse_text_shadows("0 0 2px").unwrap();
+        assert_eq!((shadows[0].offset_x, shadows[0].offset_y), (0.0, 0.0));
+    }
+
+    #[test]
+    fn shadow_lists_round_trip_through_css() {
+        for css in [
+            "none",
+            "1px 2px 3px #ff0000",
+            "1px 2px 0px, 0px 0px 4px #0000ff80",
+        ] {
+            let list = TextShadowList::try_from(String::from(css)).unwrap();
+            assert_eq!(String::from(list), css);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use crate::document::{SpacingLength, TextStyle};
+
+// Ligatures that CSS leaves out of letter-spaced text; required ligatures stay
+const OPTIONAL_LIGATURES: [&str; 4] = ["liga", "clig", "dlig", "hlig"];
+
+/// `letter-spacing` and `word-spacing` of a style, resolved in DIPs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct TextSpacing {
+    pub(crate) letter: f32,
+    pub(crate) word: f32,
+}
+
+impl TextSpacing {
+    /// Spacing of a style set at `font_size`; `None` when it adds no space
+    pub(crate) fn from_style(style: &TextStyle, font_size: f32) -> Option<Self> {
+        let resolve = |length: Option<SpacingLength>| length.map_or(0.0, |l| l.resolve(font_size));
+        let spacing = Self {
+            letter: resolve(style.letter_spacing),
+            word: resolve(style.word_spacing),
+        };
+        (spacing.letter != 0.0 || spacing.word != 0.0).then_some(spacing)
+    }
+
+    /// Space after a cluster that starts with `ch`
+    pub(crate) fn after(&self, ch: char) -> f32 {
+        match is_word_separator(ch) {
+            true => self.letter + self.word,
+            false => self.letter,
+        }
+    }
+
+    /// Optional ligatures to turn off, leaving those that `font-feature-settings` sets
+    pub(crate) fn disabled_features<'a>(
+        &self,
+        style: &'a TextStyle,
+    ) -> impl Iterator<Item = &'static str> + 'a {
+        let letter_spaced = self.letter != 0.0;
+        OPTIONAL_LIGATURES
+            .into_iter()
+            .filter(move |tag| letter_spaced && !style.font_feature_settings.contains_key(*tag))
+    }
+}
+
+/// Characters that `word-spacing` applies to, as listed by CSS Text
+pub(crate) fn is_word_separator(ch: char) -> bool {
+    matches!(
+        ch,
+        ' ' | '\u{a0}' | '\u{1361}' | '\u{10100}' | '\u{10101}' | '\u{1039f}' | '\u{1091f}'
+    )
+}
@@ -0,0 +1,30 @@
+use tiny_skia::{LineJoin, Stroke};
+
+use crate::document::StrokeLineJoin;
+use crate::path::{build_path, path_segments, PathSegment};
+
+pub(crate) fn skia_line_join(line_join: StrokeLineJoin) -> LineJoin {
+    match line_join {
+        StrokeLineJoin::Miter => LineJoin::Miter,
+        StrokeLineJoin::Round => LineJoin::Round,
+        StrokeLineJoin::Bevel => LineJoin::Bevel,
+    }
+}
+
+/// The area a stroke of `width` along a path covers, as a path to fill for consumers that cannot
+/// stroke. Coordinates stay those of `path`; an empty path has no outline.
+pub(crate) fn stroke_outline(
+    path: &[PathSegment],
+    width: f32,
+    line_join: StrokeLineJoin,
+) -> Vec<PathSegment> {
+    let stroke = Stroke {
+        width,
+        line_join: skia_line_join(line_join),
+        ..Stroke::default()
+    };
+    match build_path(path).and_then(|path| path.stroke(&stroke, 1.0)) {
+        Some(outline) => path_segments(&outline),
+        None => Vec::new(),
+    }
+}